@@ -0,0 +1,279 @@
+//! Android clipboard backend, going through JNI to [`android.content.ClipboardManager`].
+//!
+//! The Java VM and the application context are taken from [`ndk_context`], which is set up
+//! by `android-activity`/`ndk-glue` based apps.
+//!
+//! Android clips are not mime-addressed the same way X11 selections are. We only support the
+//! two clip kinds that every app understands: plain text, and URIs.
+//!
+//! [`android.content.ClipboardManager`]: https://developer.android.com/reference/android/content/ClipboardManager
+
+use std::ffi::CString;
+
+use jni::objects::{GlobalRef, JObject, JString, JValue};
+use jni::{JNIEnv, JavaVM};
+
+use crate::error::{ClipboxError, ErrorContext, Phase, ResultExt};
+use crate::selection::Selection;
+
+/// Label given to clips we create. Android shows it in some clipboard UIs.
+const CLIP_LABEL: &str = "clipbox";
+
+/// Java exceptions included, since we don't catch any
+impl From<jni::errors::Error> for ClipboxError {
+    fn from(value: jni::errors::Error) -> Self {
+        Self::Protocol(Box::new(value))
+    }
+}
+
+pub struct AndroidClipboard {
+    vm: JavaVM,
+    context: GlobalRef,
+}
+
+impl AndroidClipboard {
+    pub fn init() -> Result<Self, ClipboxError> {
+        let android_context = ndk_context::android_context();
+        let init = |err: jni::errors::Error| ClipboxError::Init(err.to_string());
+
+        // SAFETY: ndk_context hands us pointers to the process' JavaVM and Android context,
+        // which stay valid for the lifetime of the app.
+        let vm = unsafe { JavaVM::from_raw(android_context.vm().cast()).map_err(init)? };
+
+        let context = {
+            let env = vm.attach_current_thread().map_err(init)?;
+            let context = unsafe { JObject::from_raw(android_context.context().cast()) };
+            env.new_global_ref(context).map_err(init)?
+        };
+
+        Ok(Self { vm, context })
+    }
+
+    /// Runs `f` with the clipboard manager, in its own JNI local frame
+    /// so that we don't leak local references on long-lived threads.
+    fn with_clipboard_manager<T>(
+        &self,
+        f: impl FnOnce(&mut JNIEnv, &JObject) -> Result<T, ClipboxError>,
+    ) -> Result<T, ClipboxError> {
+        let mut env = self.vm.attach_current_thread()?;
+
+        env.with_local_frame(16, |env| {
+            let service = env.new_string("clipboard")?;
+            let manager = env
+                .call_method(
+                    self.context.as_obj(),
+                    "getSystemService",
+                    "(Ljava/lang/String;)Ljava/lang/Object;",
+                    &[JValue::Object(&service)],
+                )?
+                .l()?;
+
+            f(env, &manager)
+        })
+    }
+
+    /// Gets the first item of the primary clip
+    fn get_primary_clip_item<'local>(
+        env: &mut JNIEnv<'local>,
+        manager: &JObject,
+    ) -> Result<JObject<'local>, ClipboxError> {
+        let clip = env
+            .call_method(
+                manager,
+                "getPrimaryClip",
+                "()Landroid/content/ClipData;",
+                &[],
+            )?
+            .l()?;
+
+        if clip.is_null() {
+            return Err(ClipboxError::NotAvailable);
+        }
+
+        let item_count = env.call_method(&clip, "getItemCount", "()I", &[])?.i()?;
+        if item_count == 0 {
+            return Err(ClipboxError::NotAvailable);
+        }
+
+        let item = env
+            .call_method(
+                &clip,
+                "getItemAt",
+                "(I)Landroid/content/ClipData$Item;",
+                &[JValue::Int(0)],
+            )?
+            .l()?;
+
+        Ok(item)
+    }
+
+    fn set_primary_clip(
+        env: &mut JNIEnv,
+        manager: &JObject,
+        clip: &JObject,
+    ) -> Result<(), ClipboxError> {
+        env.call_method(
+            manager,
+            "setPrimaryClip",
+            "(Landroid/content/ClipData;)V",
+            &[JValue::Object(clip)],
+        )?;
+
+        Ok(())
+    }
+
+    /// Calls `toString()` on a Java object and converts the result to a Rust string
+    fn java_to_string(env: &mut JNIEnv, object: &JObject) -> Result<String, ClipboxError> {
+        let string = env
+            .call_method(object, "toString", "()Ljava/lang/String;", &[])?
+            .l()?;
+
+        Ok(env.get_string(&JString::from(string))?.into())
+    }
+
+    /// Whether there's nothing to paste, for example to grey out a Paste menu item
+    pub fn is_empty(&self) -> bool {
+        let has_clip = self.with_clipboard_manager(|env, manager| {
+            Ok(env
+                .call_method(manager, "hasPrimaryClip", "()Z", &[])?
+                .z()?)
+        });
+
+        !has_clip.unwrap_or(false)
+    }
+
+    /// The MIME types of the current clip, like `text/plain` or `text/uri-list`
+    pub fn get_targets(&self) -> Result<Vec<CString>, ClipboxError> {
+        self.with_clipboard_manager(|env, manager| {
+            let description = env
+                .call_method(
+                    manager,
+                    "getPrimaryClipDescription",
+                    "()Landroid/content/ClipDescription;",
+                    &[],
+                )?
+                .l()?;
+
+            if description.is_null() {
+                return Ok(Vec::new());
+            }
+
+            let count = env
+                .call_method(&description, "getMimeTypeCount", "()I", &[])?
+                .i()?;
+
+            let mut targets = Vec::new();
+            for i in 0..count {
+                let mime = env
+                    .call_method(
+                        &description,
+                        "getMimeType",
+                        "(I)Ljava/lang/String;",
+                        &[JValue::Int(i)],
+                    )?
+                    .l()?;
+
+                let mime: String = env.get_string(&JString::from(mime))?.into();
+                targets.extend(CString::new(mime).ok());
+            }
+
+            Ok(targets)
+        })
+        .context(|| ErrorContext::new(Phase::ListingTargets).selection(Selection::Clipboard))
+    }
+
+    /// Gets the current clip as text.
+    ///
+    /// Non-text clips (URIs, intents) are coerced to text by Android itself.
+    pub fn get_text(&self) -> Result<String, ClipboxError> {
+        self.with_clipboard_manager(|env, manager| {
+            let item = Self::get_primary_clip_item(env, manager)?;
+
+            let text = env
+                .call_method(
+                    &item,
+                    "coerceToText",
+                    "(Landroid/content/Context;)Ljava/lang/CharSequence;",
+                    &[JValue::Object(self.context.as_obj())],
+                )?
+                .l()?;
+
+            Self::java_to_string(env, &text)
+        })
+        .context(pasting)
+    }
+
+    pub fn set_text(&self, text: &str) -> Result<(), ClipboxError> {
+        self.with_clipboard_manager(|env, manager| {
+            let label = env.new_string(CLIP_LABEL)?;
+            let text = env.new_string(text)?;
+
+            let clip = env
+                .call_static_method(
+                    "android/content/ClipData",
+                    "newPlainText",
+                    "(Ljava/lang/CharSequence;Ljava/lang/CharSequence;)Landroid/content/ClipData;",
+                    &[JValue::Object(&label), JValue::Object(&text)],
+                )?
+                .l()?;
+
+            Self::set_primary_clip(env, manager, &clip)
+        })
+        .context(copying)
+    }
+
+    /// Gets the URI of the current clip (for example `content://...` or `https://...`)
+    pub fn get_uri(&self) -> Result<String, ClipboxError> {
+        self.with_clipboard_manager(|env, manager| {
+            let item = Self::get_primary_clip_item(env, manager)?;
+
+            let uri = env
+                .call_method(&item, "getUri", "()Landroid/net/Uri;", &[])?
+                .l()?;
+
+            if uri.is_null() {
+                return Err(ClipboxError::NotAvailable);
+            }
+
+            Self::java_to_string(env, &uri)
+        })
+        .context(pasting)
+    }
+
+    pub fn set_uri(&self, uri: &str) -> Result<(), ClipboxError> {
+        self.with_clipboard_manager(|env, manager| {
+            let label = env.new_string(CLIP_LABEL)?;
+            let uri = env.new_string(uri)?;
+
+            let uri = env
+                .call_static_method(
+                    "android/net/Uri",
+                    "parse",
+                    "(Ljava/lang/String;)Landroid/net/Uri;",
+                    &[JValue::Object(&uri)],
+                )?
+                .l()?;
+
+            let clip = env
+                .call_static_method(
+                    "android/content/ClipData",
+                    "newRawUri",
+                    "(Ljava/lang/CharSequence;Landroid/net/Uri;)Landroid/content/ClipData;",
+                    &[JValue::Object(&label), JValue::Object(&uri)],
+                )?
+                .l()?;
+
+            Self::set_primary_clip(env, manager, &clip)
+        })
+        .context(copying)
+    }
+}
+
+/// What pasting from the clipboard was about, for errors
+fn pasting() -> ErrorContext {
+    ErrorContext::new(Phase::Pasting).selection(Selection::Clipboard)
+}
+
+fn copying() -> ErrorContext {
+    ErrorContext::new(Phase::Copying).selection(Selection::Clipboard)
+}
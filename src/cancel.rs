@@ -0,0 +1,27 @@
+//! Giving up on long operations from elsewhere, like a GUI aborting a huge paste.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Cancels the operations it's handed to once [`cancel`](Self::cancel) is called, from any
+/// thread. Clones share the same state.
+///
+/// Operations notice within a few dozen milliseconds, and fail with a `Cancelled` error.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,545 @@
+use std::cell::{OnceCell, RefCell};
+use std::ffi::CStr;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::sync::mpsc::Receiver;
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "png")]
+use crate::convert::ImageData;
+use crate::convert::{ClipboardContent, FileOperation, Utf8Decoding};
+use crate::error::{ClipboxError, ErrorContext, Phase, ResultExt};
+use crate::event::ClipboardEvent;
+use crate::format::ClipboardFormat;
+use crate::selection::Selection;
+use crate::transform::{Pipeline, Stage};
+
+#[cfg(feature = "async")]
+use crate::linux::x11::ChangeStream;
+use crate::linux::x11::{
+    format_target, BackgroundCopy, ChangeEvent, Offer, SelectionData, SelectionReader,
+    TargetBundle, Timeouts, WatchGuard, Watcher, X11Clipboard,
+};
+
+/// How long a lazy clipboard waits before trying to connect again after a failure
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// How long dropping the clipboard waits for the clipboard manager to save what we copied
+const HAND_OFF_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long the clipboard manager gets to take `CLIPBOARD` over once we handed it off
+const TAKE_OVER_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// How many times we hand what we copied to a clipboard manager that doesn't take it over
+const HAND_OFF_ATTEMPTS: u32 = 2;
+
+/// The system clipboard, without any of the platform-specific plumbing.
+///
+/// This works on the clipboard most apps use (`CLIPBOARD` on X11).
+///
+/// Copying returns right away: what we copy is served from a background thread, until another
+/// app copies something or the process exits. Dropping the clipboard hands what we copied
+/// over to the clipboard manager if there's one, so that it survives us; without a manager,
+/// or if the manager doesn't take it over, the thread keeps serving it.
+/// [`Clipboard::shutdown`] winds all of that down by a deadline instead.
+pub struct Clipboard {
+    x11: OnceCell<X11Clipboard>,
+    /// When we last failed to connect, and why
+    last_failure: RefCell<Option<(Instant, String)>>,
+    /// Kept here too so that they survive a lazy connection
+    timeouts: Timeouts,
+    /// What we copied last, if its thread may still be serving it
+    background: RefCell<Option<BackgroundCopy>>,
+    /// What that thread serves, to copy it again if the clipboard manager doesn't take it over
+    copied: RefCell<Option<TargetBundle>>,
+    /// What text goes through when copied or pasted, see [`Clipboard::set_transforms`]
+    transforms: Pipeline,
+}
+
+impl Clipboard {
+    /// Connects to the display right away
+    pub fn new() -> Result<Self, ClipboxError> {
+        Ok(Self {
+            x11: OnceCell::from(X11Clipboard::init()?),
+            last_failure: RefCell::new(None),
+            timeouts: Timeouts::default(),
+            background: RefCell::new(None),
+            copied: RefCell::new(None),
+            transforms: Pipeline::new(),
+        })
+    }
+
+    /// Only connects to the display the first time the clipboard is used.
+    ///
+    /// This never fails, which is handy for apps that may run headless or never touch the
+    /// clipboard. If connecting fails, operations fail with a [`ClipboxError::Init`] and the
+    /// connection is retried on a later use (at most once per second). Nothing retries in the
+    /// background: an [`X11Clipboard`] can't move between threads, so the one using the
+    /// clipboard has to connect it.
+    pub fn lazy() -> Self {
+        Self {
+            x11: OnceCell::new(),
+            last_failure: RefCell::new(None),
+            timeouts: Timeouts::default(),
+            background: RefCell::new(None),
+            copied: RefCell::new(None),
+            transforms: Pipeline::new(),
+        }
+    }
+
+    /// Whether we're connected to the display already
+    pub fn is_connected(&self) -> bool {
+        self.x11.get().is_some()
+    }
+
+    pub fn timeouts(&self) -> Timeouts {
+        self.timeouts
+    }
+
+    /// Sets how long operations wait for the display and other apps, see [`Timeouts`]
+    pub fn set_timeouts(&mut self, timeouts: Timeouts) {
+        self.timeouts = timeouts;
+
+        if let Some(x11) = self.x11.get_mut() {
+            x11.set_timeouts(timeouts);
+        }
+    }
+
+    pub fn transforms(&self) -> &Pipeline {
+        &self.transforms
+    }
+
+    /// Runs the text we copy with [`Clipboard::set_text`] and paste with [`Clipboard::get_text`]
+    /// through `pipeline`, see [`X11Clipboard::set_transforms`]
+    pub fn set_transforms(&mut self, pipeline: Pipeline) {
+        self.transforms = pipeline;
+    }
+
+    fn connect(&self) -> Result<&X11Clipboard, ClipboxError> {
+        if let Some(x11) = self.x11.get() {
+            return Ok(x11);
+        }
+
+        if let Some((when, err)) = &*self.last_failure.borrow() {
+            if when.elapsed() < RETRY_DELAY {
+                return Err(ClipboxError::Init(err.clone()));
+            }
+        }
+
+        match X11Clipboard::init() {
+            Ok(mut x11) => {
+                x11.set_timeouts(self.timeouts);
+                *self.last_failure.borrow_mut() = None;
+                Ok(self.x11.get_or_init(|| x11))
+            }
+            Err(err) => {
+                let err = match err {
+                    ClipboxError::Init(err) => err,
+                    err => err.to_string(),
+                };
+                *self.last_failure.borrow_mut() = Some((Instant::now(), err.clone()));
+                Err(ClipboxError::Init(err))
+            }
+        }
+    }
+
+    fn paste<T>(
+        &self,
+        f: impl FnOnce(&X11Clipboard) -> Result<T, ClipboxError>,
+    ) -> Result<T, ClipboxError> {
+        let x11 = self.connect()?;
+        f(x11).context(|| pasting(None))
+    }
+
+    /// Like [`Clipboard::paste`], for a single target we know
+    fn paste_as<T>(
+        &self,
+        target: &CStr,
+        f: impl FnOnce(&X11Clipboard) -> Result<T, ClipboxError>,
+    ) -> Result<T, ClipboxError> {
+        let x11 = self.connect()?;
+        f(x11).context(|| pasting(Some(target)))
+    }
+
+    fn copy(
+        &self,
+        selection: Selection,
+        target: Option<&CStr>,
+        f: impl FnOnce(&X11Clipboard) -> Result<(), ClipboxError>,
+    ) -> Result<(), ClipboxError> {
+        let x11 = self.connect()?;
+        f(x11).context(|| ErrorContext {
+            target: target.map(|target| target.to_string_lossy().into_owned()),
+            ..ErrorContext::new(Phase::Copying).selection(selection)
+        })
+    }
+
+    fn copy_in_background(
+        &self,
+        bundle: TargetBundle,
+        ttl: Option<Duration>,
+    ) -> Result<(), ClipboxError> {
+        // Copies with a TTL are emptied rather than handed off
+        let copied = ttl.is_none().then(|| bundle.clone());
+        let copy = self
+            .connect()?
+            .copy_in_background(&[Selection::Clipboard], bundle, ttl)
+            .context(|| ErrorContext::new(Phase::Copying).selection(Selection::Clipboard))?;
+
+        // The previous thread lost the selection to the new one, it's winding down by itself
+        self.background.replace(Some(copy));
+        self.copied.replace(copied);
+        Ok(())
+    }
+
+    /// Hands what the background thread serves to the clipboard manager by `deadline`, then
+    /// makes sure the manager took `CLIPBOARD` over once the thread is gone. If it didn't, we
+    /// copy it again and retry, and once out of attempts, keep serving it from a new thread
+    /// (for as long as the process lives) if `keep_serving`.
+    fn hand_off(
+        &self,
+        x11: &X11Clipboard,
+        mut copy: BackgroundCopy,
+        deadline: Instant,
+        keep_serving: bool,
+    ) -> Result<(), ClipboxError> {
+        let copied = self.copied.take();
+
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            copy.shutdown(deadline, true)?;
+
+            if x11.wait_for_clipboard_owner(Instant::now() + TAKE_OVER_TIMEOUT) {
+                return Ok(());
+            }
+
+            let retry = attempts < HAND_OFF_ATTEMPTS && Instant::now() < deadline;
+            let Some(bundle) = copied.as_ref().filter(|_| retry || keep_serving) else {
+                return Err(ClipboxError::Protocol(
+                    "The clipboard manager didn't take the clipboard over".into(),
+                ));
+            };
+
+            copy = x11
+                .copy_in_background(&[Selection::Clipboard], bundle.clone(), None)
+                .context(|| ErrorContext::new(Phase::Copying).selection(Selection::Clipboard))?;
+
+            if !retry {
+                // Dropping it leaves the thread serving, since nobody else will
+                return Ok(());
+            }
+        }
+    }
+
+    /// The underlying X11 clipboard, for everything this API doesn't cover (yet).
+    /// This connects to the display if we aren't yet.
+    pub fn x11(&self) -> Result<&X11Clipboard, ClipboxError> {
+        self.connect()
+    }
+
+    /// Stops serving what we copied by `deadline` and waits for the background thread, for apps
+    /// that want to exit cleanly rather than leave it to the end of the process. Transfers in
+    /// progress get to finish until then, and with `hand_off`, the clipboard manager gets what
+    /// we copied if there's one. Otherwise it's emptied.
+    ///
+    /// Handing off fails if the manager doesn't take `CLIPBOARD` over after two attempts.
+    /// Checking that it did can take up to half a second past `deadline`. See
+    /// [`BackgroundCopy::shutdown`] for the other errors.
+    pub fn shutdown(mut self, deadline: Instant, hand_off: bool) -> Result<(), ClipboxError> {
+        let Some(copy) = self.background.get_mut().take() else {
+            return Ok(());
+        };
+
+        let x11 = self.connect()?;
+        if hand_off && copy.is_owner(x11, Selection::Clipboard) && x11.has_clipboard_manager() {
+            self.hand_off(x11, copy, deadline, false)
+        } else {
+            copy.shutdown(deadline, hand_off)
+        }
+    }
+
+    /// Subscribes to what happens while we serve the clipboard (requests, transfers...)
+    pub fn subscribe(&self) -> Result<Receiver<ClipboardEvent>, ClipboxError> {
+        Ok(self.x11()?.subscribe())
+    }
+
+    /// Gets the clipboard contents in their best-typed form (files, image, HTML, text...)
+    pub fn get_content(&self) -> Result<ClipboardContent, ClipboxError> {
+        self.paste(|x11| x11.get_content(Selection::Clipboard))
+    }
+
+    /// Gets the clipboard contents as the first of the preferred targets on offer,
+    /// along with the target that was picked
+    pub fn get_preferring<'p>(
+        &self,
+        preferences: &[&'p CStr],
+    ) -> Result<(&'p CStr, Vec<u8>), ClipboxError> {
+        self.paste(|x11| x11.get_selection_preferring(Selection::Clipboard, preferences))
+    }
+
+    /// Gets the clipboard contents as a typed value: `clipboard.get::<Html>()?`
+    pub fn get<F: ClipboardFormat>(&self) -> Result<F, ClipboxError> {
+        let target = format_target::<F>();
+        let (_, data) = self.get_preferring(&[&target])?;
+        F::decode(&data).map_err(ClipboxError::Format)
+    }
+
+    /// Copies a typed value into the clipboard, as its format's MIME type
+    pub fn set<F: ClipboardFormat>(&self, value: F) -> Result<(), ClipboxError> {
+        self.offer().format(&value).set()
+    }
+
+    /// Whether the clipboard can be pasted as `target`, without fetching any data
+    pub fn has_target(&self, target: &CStr) -> bool {
+        self.connect()
+            .is_ok_and(|x11| x11.has_target(Selection::Clipboard, target))
+    }
+
+    /// Whether there's nothing to paste, for example to grey out a Paste menu item
+    pub fn is_empty(&self) -> bool {
+        self.connect()
+            .map_or(true, |x11| x11.is_empty(Selection::Clipboard))
+    }
+
+    /// Captures every target of the clipboard with its data, see [`X11Clipboard::snapshot`]
+    pub fn snapshot(&self) -> Result<TargetBundle, ClipboxError> {
+        self.paste(|x11| x11.snapshot(Selection::Clipboard))
+    }
+
+    /// Gets the clipboard contents as `target`, without copying them when possible.
+    /// See [`X11Clipboard::get_selection_data`].
+    pub fn get_data(&self, target: &CStr) -> Result<SelectionData<'_>, ClipboxError> {
+        let x11 = self.connect()?;
+        x11.get_selection_data(Selection::Clipboard, target)
+            .context(|| pasting(Some(target)))
+    }
+
+    /// Writes the clipboard contents as `target` into `writer`, returning how many bytes it got
+    pub fn get_into(&self, target: &CStr, writer: impl Write) -> Result<usize, ClipboxError> {
+        self.paste_as(target, |x11| {
+            x11.get_selection_into(Selection::Clipboard, target, writer)
+        })
+    }
+
+    /// Gets the clipboard contents as `target`, as a stream rather than all at once
+    pub fn get_reader(&self, target: &CStr) -> Result<SelectionReader<'_>, ClipboxError> {
+        let x11 = self.connect()?;
+        x11.get_selection_reader(Selection::Clipboard, target)
+            .context(|| pasting(Some(target)))
+    }
+
+    /// Gets the clipboard contents as text, failing if they aren't valid UTF-8
+    pub fn get_text(&self) -> Result<String, ClipboxError> {
+        self.get_text_with(Utf8Decoding::Strict)
+    }
+
+    /// Gets the clipboard contents as text, choosing what happens to invalid UTF-8
+    pub fn get_text_with(&self, decoding: Utf8Decoding) -> Result<String, ClipboxError> {
+        let text = self.paste(|x11| x11.get_text_with(Selection::Clipboard, decoding))?;
+        Ok(self.transforms.apply(Stage::Paste, &text))
+    }
+
+    /// Copies text into the clipboard
+    pub fn set_text(&self, text: &str) -> Result<(), ClipboxError> {
+        self.offer()
+            .text(&self.transforms.apply(Stage::Copy, text))
+            .set()
+    }
+
+    /// Copies text into the clipboard, and empties it once `ttl` is over unless something else
+    /// was copied in the meantime. Handy for passwords, see [`X11Clipboard::set_text_with_ttl`].
+    pub fn set_text_with_ttl(&self, text: &str, ttl: Duration) -> Result<(), ClipboxError> {
+        Offer::new(move |bundle| self.copy_in_background(bundle.clone(), Some(ttl)))
+            .text(&self.transforms.apply(Stage::Copy, text))
+            .set()
+    }
+
+    /// Gets the clipboard contents as HTML, escaping plain text if there's no HTML
+    pub fn get_html(&self) -> Result<String, ClipboxError> {
+        self.paste(|x11| x11.get_html(Selection::Clipboard))
+    }
+
+    /// Copies HTML into the clipboard, with a plain text version for apps that don't do HTML
+    pub fn set_html(&self, html: &str, alt_text: &str) -> Result<(), ClipboxError> {
+        self.offer().html(html).text(alt_text).set()
+    }
+
+    /// Gets the clipboard contents as RTF, converting plain text if there's no RTF
+    pub fn get_rtf(&self) -> Result<String, ClipboxError> {
+        self.paste(|x11| x11.get_rtf(Selection::Clipboard))
+    }
+
+    /// Copies RTF into the clipboard, with a plain text version for apps that don't do RTF
+    pub fn set_rtf(&self, rtf: &str, alt_text: &str) -> Result<(), ClipboxError> {
+        self.offer().rtf(rtf).text(alt_text).set()
+    }
+
+    /// Copies formatted text as HTML, RTF and plain text at once
+    pub fn set_rich_text(&self, html: &str, rtf: &str, alt_text: &str) -> Result<(), ClipboxError> {
+        self.offer().html(html).rtf(rtf).text(alt_text).set()
+    }
+
+    /// Gets the files copied from a file manager
+    pub fn get_files(&self) -> Result<Vec<PathBuf>, ClipboxError> {
+        self.paste(|x11| x11.get_files(Selection::Clipboard))
+    }
+
+    /// Gets the files copied from a file manager, and whether they were copied or cut
+    pub fn get_files_with_operation(&self) -> Result<(FileOperation, Vec<PathBuf>), ClipboxError> {
+        self.paste(|x11| x11.get_files_with_operation(Selection::Clipboard))
+    }
+
+    /// Copies (or cuts) files so that they can be pasted in a file manager
+    pub fn set_files(
+        &self,
+        paths: &[PathBuf],
+        operation: FileOperation,
+    ) -> Result<(), ClipboxError> {
+        self.offer().files(paths, operation).set()
+    }
+
+    /// Empties a selection, for example to wipe a copied password
+    pub fn clear(&self, selection: Selection) -> Result<(), ClipboxError> {
+        self.copy(selection, None, |x11| x11.clear(selection))?;
+
+        // Our background thread just got the selection taken away, reap it
+        let copy = self
+            .background
+            .borrow_mut()
+            .take_if(|copy| copy.selections().contains(&selection));
+        if copy.is_some() {
+            self.copied.take();
+        }
+
+        match copy {
+            Some(copy) => copy.wait(),
+            None => Ok(()),
+        }
+    }
+
+    /// Calls `on_change` from a background thread whenever the clipboard changes, until the
+    /// returned guard is dropped. See [`X11Clipboard::watch_with`].
+    pub fn watch(
+        &self,
+        on_change: impl FnMut(ChangeEvent) + Send + 'static,
+    ) -> Result<WatchGuard, ClipboxError> {
+        self.connect()?
+            .watch_with(&[Selection::Clipboard], on_change)
+    }
+
+    /// Every change of the clipboard, as a blocking iterator: `for change in clipboard.changes()?`.
+    ///
+    /// This waits on our own connection, so copies made while iterating have to be served
+    /// from the background (which they are by default).
+    pub fn changes(&self) -> Result<Watcher<'_>, ClipboxError> {
+        Ok(self.connect()?.watch(&[Selection::Clipboard]))
+    }
+
+    /// Every change of the clipboard, as a stream: `while let Some(change) = stream.next().await`.
+    /// See [`X11Clipboard::change_stream`].
+    #[cfg(feature = "async")]
+    pub fn change_stream(&self) -> Result<ChangeStream, ClipboxError> {
+        self.connect()?.change_stream(&[Selection::Clipboard])
+    }
+
+    /// Whether what we copied is still on the selection, for example to copy it again only
+    /// once another app replaced it
+    pub fn is_owner(&self, selection: Selection) -> bool {
+        let Ok(x11) = self.connect() else {
+            return false;
+        };
+
+        let background = self.background.borrow();
+        x11.is_owner(selection)
+            || background
+                .as_ref()
+                .is_some_and(|copy| copy.is_owner(x11, selection))
+    }
+
+    /// Starts building a multi-format copy:
+    /// `clipboard.offer().text(...).html(...).set()?`
+    pub fn offer(&self) -> Offer<'_> {
+        Offer::new(move |bundle| self.set_many(bundle))
+    }
+
+    /// Copies data read from a stream as `target`, see [`X11Clipboard::set_selection_from`].
+    /// Unlike other copies, this one blocks while serving, since the stream isn't ours to move.
+    pub fn set_from(&self, target: &CStr, reader: impl Read) -> Result<(), ClipboxError> {
+        self.copy(Selection::Clipboard, Some(target), |x11| {
+            x11.set_selection_from(Selection::Clipboard, target, reader)
+        })
+    }
+
+    /// Offers targets whose data is only rendered when asked for,
+    /// see [`X11Clipboard::offer_with`]. This one blocks while serving too.
+    pub fn offer_with(
+        &self,
+        targets: &[&CStr],
+        provider: impl FnMut(&CStr) -> Vec<u8>,
+    ) -> Result<(), ClipboxError> {
+        self.copy(Selection::Clipboard, None, |x11| {
+            x11.offer_with(Selection::Clipboard, targets, provider)
+        })
+    }
+
+    /// Copies several representations of the same content at once
+    pub fn set_many(&self, bundle: &TargetBundle) -> Result<(), ClipboxError> {
+        self.copy_in_background(bundle.clone(), None)
+    }
+
+    /// Gets the SVG source of the clipboard contents
+    pub fn get_svg(&self) -> Result<String, ClipboxError> {
+        self.paste(|x11| x11.get_svg(Selection::Clipboard))
+    }
+
+    /// Copies an SVG image into the clipboard, see [`X11Clipboard::set_svg`]
+    pub fn set_svg(&self, svg: &str) -> Result<(), ClipboxError> {
+        self.offer().svg(svg).set()
+    }
+
+    /// Gets the clipboard contents as a decoded image
+    #[cfg(feature = "png")]
+    pub fn get_image(&self) -> Result<ImageData, ClipboxError> {
+        self.paste(|x11| x11.get_image(Selection::Clipboard))
+    }
+
+    /// Copies an image into the clipboard (as `image/png`)
+    #[cfg(feature = "png")]
+    pub fn set_image(&self, image: &ImageData) -> Result<(), ClipboxError> {
+        self.offer().image(image).set()
+    }
+
+    /// Copies an image from the `image` crate into the clipboard
+    #[cfg(feature = "image")]
+    pub fn set_dynamic_image(&self, image: &image::DynamicImage) -> Result<(), ClipboxError> {
+        let image = image.to_rgba8();
+
+        self.set_image(&ImageData {
+            width: image.width(),
+            height: image.height(),
+            rgba: image.into_raw(),
+        })
+    }
+}
+
+/// What pasting from the clipboard was about, for errors
+fn pasting(target: Option<&CStr>) -> ErrorContext {
+    ErrorContext {
+        target: target.map(|target| target.to_string_lossy().into_owned()),
+        ..ErrorContext::new(Phase::Pasting).selection(Selection::Clipboard)
+    }
+}
+
+impl Drop for Clipboard {
+    fn drop(&mut self) {
+        let (Some(x11), Some(copy)) = (self.x11.get(), self.background.get_mut().take()) else {
+            return;
+        };
+
+        // Without a manager, the thread is all that keeps the copy alive
+        if copy.is_owner(x11, Selection::Clipboard) && x11.has_clipboard_manager() {
+            let _ = self.hand_off(x11, copy, Instant::now() + HAND_OFF_TIMEOUT, true);
+        }
+    }
+}
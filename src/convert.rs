@@ -0,0 +1,904 @@
+//! Conversions between the formats clipboard data usually comes in.
+
+use std::borrow::Cow;
+#[cfg(any(feature = "png", feature = "image", feature = "svg"))]
+use std::error::Error;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::string::FromUtf8Error;
+
+/// The form an application wants clipboard contents in, regardless of the targets on offer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DesiredForm {
+    /// UTF-8 text
+    PlainText,
+    /// HTML markup
+    Html,
+    /// Rich Text Format, what office suites exchange
+    Rtf,
+    /// Decoded RGBA pixels
+    Image,
+}
+
+/// Clipboard contents converted to a [`DesiredForm`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Converted {
+    Text(String),
+    Html(String),
+    Rtf(String),
+    Image(ImageData),
+}
+
+/// Clipboard contents in the best-typed form they're available in, see `get_content`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClipboardContent {
+    Text(String),
+    Html(String),
+    Image(ImageData),
+    Files(Vec<PathBuf>),
+    /// Anything else, as the raw bytes of a target
+    Other(String, Vec<u8>),
+}
+
+/// A MIME type like `text/plain;charset=utf-8`, normalized to lowercase
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Mime(String);
+
+impl Mime {
+    /// Parses a MIME type, or returns `None` if it doesn't look like `type/subtype[;params]`
+    pub fn parse(mime: &str) -> Option<Self> {
+        let mime = mime.trim().to_ascii_lowercase();
+        let essence = mime.split(';').next().unwrap_or_default().trim_end();
+        let (ty, subtype) = essence.split_once('/')?;
+
+        // RFC 2045 tokens, roughly
+        let is_token = |part: &str| {
+            !part.is_empty()
+                && part
+                    .bytes()
+                    .all(|b| b.is_ascii_graphic() && !b"()<>@,;:\\\"/[]?=".contains(&b))
+        };
+
+        match is_token(ty) && is_token(subtype) {
+            true => Some(Self(mime)),
+            false => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// The type and subtype without parameters, like `text/plain`
+    pub fn essence(&self) -> &str {
+        self.0.split(';').next().unwrap_or_default().trim_end()
+    }
+
+    /// The top-level type, like `text`
+    pub fn type_(&self) -> &str {
+        self.essence().split('/').next().unwrap_or_default()
+    }
+
+    pub fn subtype(&self) -> &str {
+        self.essence().split('/').nth(1).unwrap_or_default()
+    }
+
+    /// Gets a parameter like `charset`, without its quotes
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.0.split(';').skip(1).find_map(|param| {
+            let (key, value) = param.split_once('=')?;
+            (key.trim() == name).then(|| value.trim().trim_matches('"'))
+        })
+    }
+}
+
+impl fmt::Display for Mime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// A decoded image, 8 bits per channel RGBA, row by row.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageData {
+    pub width: u32,
+    pub height: u32,
+    pub rgba: Vec<u8>,
+}
+
+#[cfg(feature = "image")]
+impl From<image::DynamicImage> for ImageData {
+    fn from(image: image::DynamicImage) -> Self {
+        let image = image.into_rgba8();
+
+        Self {
+            width: image.width(),
+            height: image.height(),
+            rgba: image.into_raw(),
+        }
+    }
+}
+
+#[cfg(feature = "image")]
+impl TryFrom<ImageData> for image::DynamicImage {
+    type Error = ImageSizeMismatchError;
+
+    fn try_from(data: ImageData) -> Result<Self, Self::Error> {
+        let (width, height, len) = (data.width, data.height, data.rgba.len());
+
+        image::RgbaImage::from_raw(width, height, data.rgba)
+            .map(image::DynamicImage::ImageRgba8)
+            .ok_or(ImageSizeMismatchError { width, height, len })
+    }
+}
+
+/// The RGBA buffer of an [`ImageData`] is too small for its dimensions
+#[cfg(feature = "image")]
+#[derive(Debug)]
+pub struct ImageSizeMismatchError {
+    pub width: u32,
+    pub height: u32,
+    pub len: usize,
+}
+
+#[cfg(feature = "image")]
+impl Error for ImageSizeMismatchError {}
+
+#[cfg(feature = "image")]
+impl fmt::Display for ImageSizeMismatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "A {}x{} RGBA image needs {} bytes, but the buffer only has {}",
+            self.width,
+            self.height,
+            self.width as usize * self.height as usize * 4,
+            self.len
+        )
+    }
+}
+
+/// What to do with text that claims to be UTF-8 but isn't
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Utf8Decoding {
+    /// Fail with an error
+    #[default]
+    Strict,
+    /// Replace invalid sequences with U+FFFD
+    Lossy,
+}
+
+impl Utf8Decoding {
+    pub fn decode(self, bytes: Vec<u8>) -> Result<String, FromUtf8Error> {
+        match (self, String::from_utf8(bytes)) {
+            (Self::Lossy, Err(err)) => Ok(String::from_utf8_lossy(err.as_bytes()).into_owned()),
+            (_, result) => result,
+        }
+    }
+}
+
+/// Decodes Latin-1 text (what the X11 `STRING` target is made of).
+pub fn latin1_to_string(bytes: &[u8]) -> String {
+    bytes.iter().map(|&b| b as char).collect()
+}
+
+/// Decodes `COMPOUND_TEXT`, the ISO 2022-based encoding old X11 apps use for text.
+///
+/// ASCII and Latin-1 are understood, as well as UTF-8 segments. Characters from any other
+/// character set (legacy CJK encodings, mostly) come out as U+FFFD.
+pub fn compound_text_to_string(bytes: &[u8]) -> String {
+    /// A character set designated to GL or GR
+    #[derive(Clone, Copy)]
+    enum Charset {
+        /// ASCII for GL, the right half of Latin-1 for GR
+        Default,
+        /// Some other set, with this many bytes per character
+        Unknown(usize),
+    }
+
+    let mut gl = Charset::Default;
+    let mut gr = Charset::Default;
+    let mut text = String::with_capacity(bytes.len());
+
+    let mut i = 0;
+    while i < bytes.len() {
+        let b = bytes[i];
+
+        match b {
+            0x1b => {
+                // ESC, intermediate bytes, then a final byte
+                let intermediates = bytes[i + 1..]
+                    .iter()
+                    .take_while(|b| (0x20..=0x2f).contains(*b))
+                    .count();
+                let Some(&last) = bytes.get(i + 1 + intermediates) else {
+                    break;
+                };
+                let intermediates = &bytes[i + 1..i + 1 + intermediates];
+                i += 2 + intermediates.len();
+
+                match (intermediates, last) {
+                    (b"(", b'B' | b'J') => gl = Charset::Default,
+                    (b"(", _) => gl = Charset::Unknown(1),
+                    (b"-", b'A') => gr = Charset::Default,
+                    (b"-", _) => gr = Charset::Unknown(1),
+                    (b"$(", _) | (b"$", _) => gl = Charset::Unknown(2),
+                    (b"$)", _) => gr = Charset::Unknown(2),
+                    // UTF-8, until ESC % @
+                    (b"%", b'G') => {
+                        let end = bytes[i..]
+                            .windows(3)
+                            .position(|w| w == b"\x1b%@")
+                            .map_or(bytes.len(), |pos| i + pos);
+                        text.push_str(&String::from_utf8_lossy(&bytes[i..end]));
+                        i = (end + 3).min(bytes.len());
+                    }
+                    // Extended segment: its length on two bytes, then the encoding name and STX
+                    (b"%/", _) => {
+                        let (Some(&m), Some(&l)) = (bytes.get(i), bytes.get(i + 1)) else {
+                            break;
+                        };
+                        let len = (m as usize & 0x7f) * 128 + (l as usize & 0x7f);
+                        let segment =
+                            &bytes[(i + 2).min(bytes.len())..(i + 2 + len).min(bytes.len())];
+                        i += 2 + len;
+
+                        let (name, data) = match segment.iter().position(|&b| b == 0x02) {
+                            Some(stx) => (&segment[..stx], &segment[stx + 1..]),
+                            None => (segment, &[][..]),
+                        };
+
+                        match name.to_ascii_lowercase().as_slice() {
+                            b"iso10646-1" | b"utf-8" => {
+                                text.push_str(&String::from_utf8_lossy(data))
+                            }
+                            b"iso8859-1" => text.push_str(&latin1_to_string(data)),
+                            _ => text.push(char::REPLACEMENT_CHARACTER),
+                        }
+                    }
+                    // Nothing else changes what the text means
+                    _ => {}
+                }
+
+                continue;
+            }
+            // CSI, for text direction: skip up to the final byte
+            0x9b => {
+                let len = bytes[i + 1..]
+                    .iter()
+                    .position(|b| (0x40..=0x7e).contains(b))
+                    .map_or(bytes.len() - i, |pos| pos + 2);
+                i += len;
+                continue;
+            }
+            // Spaces are spaces in every set
+            b'\t' | b'\n' | b' ' => text.push(b as char),
+            0x21..=0x7e => match gl {
+                Charset::Default => text.push(b as char),
+                Charset::Unknown(n) => {
+                    text.push(char::REPLACEMENT_CHARACTER);
+                    // Don't swallow an escape sequence if a character is cut short
+                    i += bytes[i..]
+                        .iter()
+                        .take(n)
+                        .take_while(|b| (0x21..=0x7e).contains(*b))
+                        .count();
+                    continue;
+                }
+            },
+            0xa0..=0xff => match gr {
+                Charset::Default => text.push(b as char),
+                Charset::Unknown(n) => {
+                    text.push(char::REPLACEMENT_CHARACTER);
+                    i += bytes[i..]
+                        .iter()
+                        .take(n)
+                        .take_while(|&&b| b >= 0xa0)
+                        .count();
+                    continue;
+                }
+            },
+            // Other control characters aren't allowed
+            _ => {}
+        }
+
+        i += 1;
+    }
+
+    text
+}
+
+/// Decodes HTML coming from the clipboard.
+///
+/// It's usually UTF-8, but Firefox and Chromium put UTF-16 in there (with a byte order mark)
+/// when copying from a web page.
+pub fn html_from_bytes(bytes: &[u8]) -> String {
+    let utf16 = |bytes: &[u8], from_bytes: fn([u8; 2]) -> u16| {
+        let units = bytes
+            .chunks_exact(2)
+            .map(|unit| from_bytes([unit[0], unit[1]]));
+        char::decode_utf16(units)
+            .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+            .collect()
+    };
+
+    match bytes {
+        [0xff, 0xfe, rest @ ..] => utf16(rest, u16::from_le_bytes),
+        [0xfe, 0xff, rest @ ..] => utf16(rest, u16::from_be_bytes),
+        [0xef, 0xbb, 0xbf, rest @ ..] => String::from_utf8_lossy(rest).into_owned(),
+        _ => String::from_utf8_lossy(bytes).into_owned(),
+    }
+}
+
+/// Escapes text so that it can be embedded in HTML as-is.
+pub fn text_to_html(text: &str) -> String {
+    let mut html = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '&' => html.push_str("&amp;"),
+            '<' => html.push_str("&lt;"),
+            '>' => html.push_str("&gt;"),
+            '"' => html.push_str("&quot;"),
+            '\'' => html.push_str("&#39;"),
+            '\n' => html.push_str("<br>\n"),
+            c => html.push(c),
+        }
+    }
+
+    html
+}
+
+/// Wraps text into a minimal RTF document.
+///
+/// Non-ASCII characters are written as `\uN?` escapes, so the result is plain ASCII.
+pub fn text_to_rtf(text: &str) -> String {
+    let mut rtf = String::from("{\\rtf1\\ansi\\deff0 ");
+
+    for c in text.chars() {
+        match c {
+            '\\' | '{' | '}' => {
+                rtf.push('\\');
+                rtf.push(c);
+            }
+            '\n' => rtf.push_str("\\par\n"),
+            '\t' => rtf.push_str("\\tab "),
+            '\r' => {}
+            c if c.is_ascii() => rtf.push(c),
+            c => {
+                // RTF wants signed 16-bit code units, with a fallback character after each
+                let mut units = [0; 2];
+                for &unit in c.encode_utf16(&mut units).iter() {
+                    rtf.push_str(&format!("\\u{}?", unit as i16));
+                }
+            }
+        }
+    }
+
+    rtf.push('}');
+    rtf
+}
+
+/// What a file manager should do with copied files when pasting them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FileOperation {
+    #[default]
+    Copy,
+    /// Move the files, they were cut
+    Cut,
+}
+
+impl FileOperation {
+    /// The first line of `x-special/gnome-copied-files`
+    pub fn as_gnome_str(self) -> &'static str {
+        match self {
+            Self::Copy => "copy",
+            Self::Cut => "cut",
+        }
+    }
+
+    /// The contents of `application/x-kde-cutselection`
+    pub fn as_kde_str(self) -> &'static str {
+        match self {
+            Self::Copy => "0",
+            Self::Cut => "1",
+        }
+    }
+}
+
+/// Turns an absolute path into a `file://` URI, percent-encoding what needs to be.
+pub fn path_to_file_uri(path: &Path) -> String {
+    let mut uri = String::from("file://");
+
+    for &byte in path_bytes(path).iter() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                uri.push(byte as char)
+            }
+            _ => uri.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+
+    uri
+}
+
+/// Turns a `file://` URI back into a path. Other kinds of URIs give `None`.
+pub fn file_uri_to_path(uri: &str) -> Option<PathBuf> {
+    let rest = uri.strip_prefix("file://")?;
+
+    // Skip the host, which is either empty or the name of this machine
+    let path = &rest[rest.find('/')?..];
+
+    let mut bytes = Vec::with_capacity(path.len());
+    let mut chars = path.bytes();
+    while let Some(byte) = chars.next() {
+        if byte != b'%' {
+            bytes.push(byte);
+            continue;
+        }
+
+        let hex = [chars.next()?, chars.next()?];
+        let hex = std::str::from_utf8(&hex).ok()?;
+        bytes.push(u8::from_str_radix(hex, 16).ok()?);
+    }
+
+    Some(path_from_bytes(bytes))
+}
+
+/// Formats paths as a `text/uri-list` ([RFC 2483](https://www.rfc-editor.org/rfc/rfc2483#section-5)).
+pub fn paths_to_uri_list(paths: &[PathBuf]) -> String {
+    let mut list = String::new();
+
+    for path in paths {
+        list.push_str(&path_to_file_uri(path));
+        list.push_str("\r\n");
+    }
+
+    list
+}
+
+/// Parses the local files of a `text/uri-list`, ignoring comments and non-file URIs.
+pub fn uri_list_to_paths(list: &str) -> Vec<PathBuf> {
+    list.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(file_uri_to_path)
+        .collect()
+}
+
+#[cfg(unix)]
+fn path_bytes(path: &Path) -> Cow<'_, [u8]> {
+    use std::os::unix::ffi::OsStrExt;
+    Cow::Borrowed(path.as_os_str().as_bytes())
+}
+
+#[cfg(not(unix))]
+fn path_bytes(path: &Path) -> Cow<'_, [u8]> {
+    Cow::Owned(path.to_string_lossy().into_owned().into_bytes())
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes: Vec<u8>) -> PathBuf {
+    use std::os::unix::ffi::OsStringExt;
+    PathBuf::from(std::ffi::OsString::from_vec(bytes))
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: Vec<u8>) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// Converts HTML to plain text.
+///
+/// This is not a real HTML parser, just enough to get readable text out of what browsers and
+/// editors put in the clipboard: tags are dropped (along with `<script>` and `<style>` contents),
+/// block elements become line breaks and common entities are decoded.
+pub fn html_to_text(html: &str) -> String {
+    let mut text = String::with_capacity(html.len());
+    let mut rest = html;
+    let mut skip_until: Option<&str> = None;
+
+    while !rest.is_empty() {
+        if let Some(closing_tag) = skip_until {
+            match find_ignore_ascii_case(rest, closing_tag) {
+                Some(i) => rest = &rest[i..],
+                None => break,
+            }
+            skip_until = None;
+        }
+
+        match rest.find(['<', '&']) {
+            Some(i) => {
+                push_collapsed(&mut text, &rest[..i]);
+                rest = &rest[i..];
+            }
+            None => {
+                push_collapsed(&mut text, rest);
+                break;
+            }
+        }
+
+        if rest.starts_with('&') {
+            let (decoded, len) = decode_entity(rest);
+            text.push_str(&decoded);
+            rest = &rest[len..];
+            continue;
+        }
+
+        let tag;
+        (tag, rest) = match rest[1..].find('>') {
+            Some(i) => (&rest[1..1 + i], &rest[2 + i..]),
+            None => (&rest[1..], ""),
+        };
+
+        let name = tag
+            .trim_start_matches('/')
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .next()
+            .unwrap_or("")
+            .to_ascii_lowercase();
+
+        match name.as_str() {
+            "script" if !tag.starts_with('/') => skip_until = Some("</script"),
+            "style" if !tag.starts_with('/') => skip_until = Some("</style"),
+            "br" => text.push('\n'),
+            "p" | "div" | "li" | "tr" | "h1" | "h2" | "h3" | "h4" | "h5" | "h6" | "pre"
+            | "blockquote" | "ul" | "ol" | "table"
+                if !text.is_empty() && !text.ends_with('\n') =>
+            {
+                text.push('\n');
+            }
+            "td" | "th" if tag.starts_with('/') => text.push('\t'),
+            _ => {}
+        }
+    }
+
+    text.trim().to_string()
+}
+
+/// Pushes text while collapsing whitespace runs like HTML rendering does.
+fn push_collapsed(text: &mut String, chunk: &str) {
+    for c in chunk.chars() {
+        if c.is_whitespace() {
+            if !text.is_empty() && !text.ends_with([' ', '\n', '\t']) {
+                text.push(' ');
+            }
+        } else {
+            text.push(c);
+        }
+    }
+}
+
+fn find_ignore_ascii_case(haystack: &str, needle: &str) -> Option<usize> {
+    haystack.char_indices().map(|(i, _)| i).find(|&i| {
+        haystack
+            .get(i..i + needle.len())
+            .is_some_and(|s| s.eq_ignore_ascii_case(needle))
+    })
+}
+
+/// Decodes the entity at the start of `s`, returning the decoded text and how much was consumed.
+fn decode_entity(s: &str) -> (String, usize) {
+    let Some(end) = s.find(';').filter(|&end| end <= 10) else {
+        return ("&".to_string(), 1);
+    };
+
+    let entity = &s[1..end];
+    let decoded = match entity {
+        "amp" => Some('&'),
+        "lt" => Some('<'),
+        "gt" => Some('>'),
+        "quot" => Some('"'),
+        "apos" => Some('\''),
+        "nbsp" => Some(' '),
+        _ => match entity.strip_prefix('#') {
+            Some(code) => match code.strip_prefix(['x', 'X']) {
+                Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                None => code.parse().ok(),
+            }
+            .and_then(char::from_u32),
+            None => None,
+        },
+    };
+
+    match decoded {
+        Some(c) => (c.to_string(), end + 1),
+        None => ("&".to_string(), 1),
+    }
+}
+
+#[cfg(feature = "png")]
+#[derive(Debug)]
+pub enum ImageError {
+    Decoding(png::DecodingError),
+    Encoding(png::EncodingError),
+    UnsupportedColorType(png::ColorType),
+}
+
+#[cfg(feature = "png")]
+impl Error for ImageError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Decoding(err) => Some(err),
+            Self::Encoding(err) => Some(err),
+            Self::UnsupportedColorType(_) => None,
+        }
+    }
+}
+
+#[cfg(feature = "png")]
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Decoding(err) => write!(f, "Couldn't decode PNG: {}", err),
+            Self::Encoding(err) => write!(f, "Couldn't encode PNG: {}", err),
+            Self::UnsupportedColorType(ty) => write!(f, "Unsupported PNG color type: {:?}", ty),
+        }
+    }
+}
+
+#[cfg(feature = "png")]
+impl From<png::DecodingError> for ImageError {
+    fn from(value: png::DecodingError) -> Self {
+        Self::Decoding(value)
+    }
+}
+
+#[cfg(feature = "png")]
+impl From<png::EncodingError> for ImageError {
+    fn from(value: png::EncodingError) -> Self {
+        Self::Encoding(value)
+    }
+}
+
+/// Decodes a PNG image into RGBA pixels.
+#[cfg(feature = "png")]
+pub fn decode_png(data: &[u8]) -> Result<ImageData, ImageError> {
+    let mut decoder = png::Decoder::new(data);
+    // Expand palettes and low bit depths, and strip 16-bit channels down to 8 bits
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+
+    let mut reader = decoder.read_info()?;
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf)?;
+    buf.truncate(info.buffer_size());
+
+    let rgba = match info.color_type {
+        png::ColorType::Rgba => buf,
+        png::ColorType::Rgb => buf
+            .chunks_exact(3)
+            .flat_map(|rgb| [rgb[0], rgb[1], rgb[2], 0xff])
+            .collect(),
+        png::ColorType::GrayscaleAlpha => buf
+            .chunks_exact(2)
+            .flat_map(|ga| [ga[0], ga[0], ga[0], ga[1]])
+            .collect(),
+        png::ColorType::Grayscale => buf.iter().flat_map(|&g| [g, g, g, 0xff]).collect(),
+        ty => return Err(ImageError::UnsupportedColorType(ty)),
+    };
+
+    Ok(ImageData {
+        width: info.width,
+        height: info.height,
+        rgba,
+    })
+}
+
+/// Encodes RGBA pixels into a PNG image.
+#[cfg(feature = "png")]
+pub fn encode_png(image: &ImageData) -> Result<Vec<u8>, ImageError> {
+    let mut png = Vec::new();
+
+    let mut encoder = png::Encoder::new(&mut png, image.width, image.height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&image.rgba)?;
+    writer.finish()?;
+
+    Ok(png)
+}
+
+/// Scales an image down so that it fits in `max_width` x `max_height`, keeping its aspect ratio.
+/// Each pixel is the average of those it covers. Images that fit already (or whose buffer
+/// doesn't match their size) are given back as they are.
+pub fn downscale_image(image: ImageData, max_width: u32, max_height: u32) -> ImageData {
+    let (width, height) = (image.width as u64, image.height as u64);
+    if (width <= max_width as u64 && height <= max_height as u64)
+        || image.rgba.len() as u64 != width * height * 4
+    {
+        return image;
+    }
+
+    let scale = f64::min(
+        max_width as f64 / width as f64,
+        max_height as f64 / height as f64,
+    );
+    let new_width = ((width as f64 * scale).round() as u64).clamp(1, width);
+    let new_height = ((height as f64 * scale).round() as u64).clamp(1, height);
+
+    let mut rgba = Vec::with_capacity((new_width * new_height * 4) as usize);
+    for y in 0..new_height {
+        let (top, bottom) = (y * height / new_height, (y + 1) * height / new_height);
+
+        for x in 0..new_width {
+            let (left, right) = (x * width / new_width, (x + 1) * width / new_width);
+
+            // Never empty, since the image only gets smaller
+            let mut sum = [0u64; 4];
+            for src_y in top..bottom {
+                for src_x in left..right {
+                    let i = ((src_y * width + src_x) * 4) as usize;
+                    for (channel, &value) in sum.iter_mut().zip(&image.rgba[i..i + 4]) {
+                        *channel += value as u64;
+                    }
+                }
+            }
+
+            let count = (bottom - top) * (right - left);
+            rgba.extend(sum.map(|channel| (channel / count) as u8));
+        }
+    }
+
+    ImageData {
+        width: new_width as u32,
+        height: new_height as u32,
+        rgba,
+    }
+}
+
+#[cfg(feature = "svg")]
+#[derive(Debug)]
+pub enum SvgError {
+    Parsing(resvg::usvg::Error),
+    /// The SVG has no area to render, or is too big to fit in memory
+    InvalidSize,
+    Encoding(ImageError),
+}
+
+#[cfg(feature = "svg")]
+impl Error for SvgError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Parsing(err) => Some(err),
+            Self::InvalidSize => None,
+            Self::Encoding(err) => Some(err),
+        }
+    }
+}
+
+#[cfg(feature = "svg")]
+impl fmt::Display for SvgError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Parsing(err) => write!(f, "Couldn't parse SVG: {}", err),
+            Self::InvalidSize => write!(f, "The SVG can't be rendered at its size"),
+            Self::Encoding(err) => err.fmt(f),
+        }
+    }
+}
+
+/// Renders an SVG image at its own size into a PNG image.
+#[cfg(feature = "svg")]
+pub fn rasterize_svg(svg: &str) -> Result<Vec<u8>, SvgError> {
+    use resvg::{tiny_skia, usvg};
+
+    let tree = usvg::Tree::from_str(svg, &usvg::Options::default()).map_err(SvgError::Parsing)?;
+
+    let size = tree.size().to_int_size();
+    let mut pixmap =
+        tiny_skia::Pixmap::new(size.width(), size.height()).ok_or(SvgError::InvalidSize)?;
+    resvg::render(&tree, tiny_skia::Transform::default(), &mut pixmap.as_mut());
+
+    // tiny-skia works with premultiplied alpha
+    let rgba = pixmap
+        .pixels()
+        .iter()
+        .flat_map(|pixel| {
+            let color = pixel.demultiply();
+            [color.red(), color.green(), color.blue(), color.alpha()]
+        })
+        .collect();
+
+    let image = ImageData {
+        width: size.width(),
+        height: size.height(),
+        rgba,
+    };
+
+    encode_png(&image).map_err(SvgError::Encoding)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An extended segment of `data` in the `name` encoding
+    fn extended_segment(name: &str, data: &[u8]) -> Vec<u8> {
+        let len = name.len() + 1 + data.len();
+        let mut segment = b"\x1b%/1".to_vec();
+        segment.extend_from_slice(&[0x80 | (len / 128) as u8, 0x80 | (len % 128) as u8]);
+        segment.extend_from_slice(name.as_bytes());
+        segment.push(0x02);
+        segment.extend_from_slice(data);
+        segment
+    }
+
+    #[test]
+    fn compound_text_ascii() {
+        assert_eq!(
+            compound_text_to_string(b"Hello,\tworld!\n"),
+            "Hello,\tworld!\n"
+        );
+        // Other control characters aren't text
+        assert_eq!(compound_text_to_string(b"a\x07b\rc"), "abc");
+    }
+
+    #[test]
+    fn compound_text_latin1_on_gr() {
+        assert_eq!(compound_text_to_string(b"caf\xe9 \xa3"), "café £");
+        // Designating Latin-1 again changes nothing
+        assert_eq!(compound_text_to_string(b"\x1b-A\xfc\x1b(Bber"), "über");
+    }
+
+    #[test]
+    fn compound_text_utf8_segments() {
+        assert_eq!(
+            compound_text_to_string("a\x1b%G日本語\x1b%@b".as_bytes()),
+            "a日本語b"
+        );
+        // Without its end, the rest is UTF-8
+        assert_eq!(compound_text_to_string("\x1b%Gé€".as_bytes()), "é€");
+    }
+
+    #[test]
+    fn compound_text_extended_segments() {
+        let mut bytes = b"<".to_vec();
+        bytes.extend(extended_segment("UTF-8", "日本".as_bytes()));
+        bytes.extend(extended_segment("iso8859-1", b"\xe9"));
+        bytes.extend(extended_segment("big5-0", b"\xa4\xa4"));
+        bytes.push(b'>');
+
+        assert_eq!(compound_text_to_string(&bytes), "<日本é\u{fffd}>");
+    }
+
+    #[test]
+    fn compound_text_unknown_sets() {
+        // Two characters of JIS X 0208 on GL, then ASCII again
+        assert_eq!(
+            compound_text_to_string(b"\x1b$(B\x30\x21\x30\x22\x1b(Bok"),
+            "\u{fffd}\u{fffd}ok"
+        );
+        // The same on GR, with a character cut short by an escape sequence
+        assert_eq!(
+            compound_text_to_string(b"\x1b$)A\xb0\xa1\xb0\x1b-A\xe9"),
+            "\u{fffd}\u{fffd}é"
+        );
+        // A single-byte set on GL
+        assert_eq!(
+            compound_text_to_string(b"\x1b(Iab\x1b(Bc"),
+            "\u{fffd}\u{fffd}c"
+        );
+    }
+
+    #[test]
+    fn compound_text_cut_off_sequences() {
+        assert_eq!(compound_text_to_string(b"abc\x1b"), "abc");
+        assert_eq!(compound_text_to_string(b"abc\x1b$("), "abc");
+        assert_eq!(compound_text_to_string(b"abc\x1b%/1"), "abc");
+        assert_eq!(compound_text_to_string(b"abc\x1b%/1\x80"), "abc");
+        assert_eq!(compound_text_to_string(b"abc\x9b1"), "abc");
+        assert_eq!(compound_text_to_string(b"abc\x1b$(B\x30"), "abc\u{fffd}");
+
+        // A segment longer than what's left
+        let mut bytes = extended_segment("utf-8", b"xyz");
+        bytes.truncate(bytes.len() - 2);
+        assert_eq!(compound_text_to_string(&bytes), "x");
+    }
+
+    #[test]
+    fn compound_text_direction_is_skipped() {
+        assert_eq!(compound_text_to_string(b"a\x9b1]b\x9b]c"), "abc");
+    }
+}
@@ -0,0 +1,191 @@
+//! The error type shared by the whole crate.
+
+use std::error::Error;
+use std::ffi::CStr;
+use std::fmt;
+use std::io;
+
+use crate::selection::Selection;
+
+/// Something that went wrong with the clipboard.
+///
+/// Backends have more detailed errors of their own, which they keep private: they end up as the
+/// [source](Error::source) of the protocol and format variants, and as their message.
+///
+/// Errors can come with what we were doing when they happened (see [`ClipboxError::Context`]),
+/// which their message starts with: `Pasting CLIPBOARD as text/html: Timed out`. Match on
+/// [`ClipboxError::root`] to look past it.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum ClipboxError {
+    /// Couldn't connect to the clipboard (no display, missing system library...)
+    Init(String),
+    /// The platform or the other application didn't do what we expected
+    Protocol(Box<dyn Error + Send + Sync>),
+    /// Waited too long for the platform or the other application
+    Timeout,
+    /// The data isn't what it says it is (invalid UTF-8, broken image...)
+    Format(Box<dyn Error + Send + Sync>),
+    /// Nothing on the clipboard can be converted to what was asked for
+    NotAvailable,
+    /// Couldn't take ownership of the clipboard to copy something
+    Ownership,
+    /// The data is bigger than what we're allowed to copy
+    TooLarge { size: usize, max_size: usize },
+    /// Reading or writing the data on our side failed
+    Io(io::Error),
+    /// Given up on through a [`CancellationToken`](crate::CancellationToken)
+    Cancelled,
+    /// Another error, along with what we were doing when it happened
+    Context {
+        context: ErrorContext,
+        error: Box<ClipboxError>,
+    },
+}
+
+/// What we were doing when an error happened, see [`ClipboxError::Context`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ErrorContext {
+    pub phase: Phase,
+    pub selection: Option<Selection>,
+    /// The target being pasted or copied, by name
+    pub target: Option<String>,
+}
+
+/// The step of an operation an error happened in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Phase {
+    /// Asking the owner what a selection can be pasted as
+    ListingTargets,
+    Pasting,
+    /// Taking a selection over
+    Copying,
+    /// Giving what we copied to those who paste it
+    Serving,
+    /// Running a [`ChangeHook`](crate::linux::x11::ChangeHook)'s command
+    RunningHook,
+}
+
+impl ClipboxError {
+    /// Wraps the error with what we were doing when it happened
+    pub fn context(self, context: ErrorContext) -> Self {
+        Self::Context {
+            context,
+            error: Box::new(self),
+        }
+    }
+
+    /// The error itself, without any context
+    pub fn root(&self) -> &ClipboxError {
+        match self {
+            Self::Context { error, .. } => error.root(),
+            err => err,
+        }
+    }
+
+    /// What we were doing when the error happened, outermost first
+    pub fn contexts(&self) -> impl Iterator<Item = &ErrorContext> {
+        let mut error = self;
+        std::iter::from_fn(move || match error {
+            Self::Context {
+                context,
+                error: inner,
+            } => {
+                error = inner;
+                Some(context)
+            }
+            _ => None,
+        })
+    }
+}
+
+impl ErrorContext {
+    pub fn new(phase: Phase) -> Self {
+        Self {
+            phase,
+            selection: None,
+            target: None,
+        }
+    }
+
+    pub fn selection(mut self, selection: Selection) -> Self {
+        self.selection = Some(selection);
+        self
+    }
+
+    pub fn target(mut self, target: &CStr) -> Self {
+        self.target = Some(target.to_string_lossy().into_owned());
+        self
+    }
+}
+
+/// Adds context to the errors of fallible operations, see [`ClipboxError::context`]
+pub(crate) trait ResultExt<T> {
+    fn context(self, context: impl FnOnce() -> ErrorContext) -> Result<T, ClipboxError>;
+}
+
+impl<T, E: Into<ClipboxError>> ResultExt<T> for Result<T, E> {
+    fn context(self, context: impl FnOnce() -> ErrorContext) -> Result<T, ClipboxError> {
+        self.map_err(|err| err.into().context(context()))
+    }
+}
+
+impl Error for ClipboxError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Protocol(err) | Self::Format(err) => Some(err.as_ref()),
+            Self::Io(err) => Some(err),
+            // The context is only a prefix of the message
+            Self::Context { error, .. } => error.source(),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ClipboxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Init(err) => write!(f, "Couldn't connect to the clipboard: {}", err),
+            Self::Protocol(err) => err.fmt(f),
+            Self::Timeout => write!(f, "Timed out"),
+            Self::Format(err) => err.fmt(f),
+            Self::NotAvailable => write!(f, "The clipboard has nothing in the desired form"),
+            Self::Ownership => write!(f, "Could not become the clipboard owner"),
+            Self::TooLarge { size, max_size } => write!(
+                f,
+                "Payload too large: {} bytes, but the limit is {} bytes",
+                size, max_size
+            ),
+            Self::Io(err) => err.fmt(f),
+            Self::Cancelled => write!(f, "Cancelled"),
+            Self::Context { context, error } => write!(f, "{}: {}", context, error),
+        }
+    }
+}
+
+impl fmt::Display for ErrorContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.phase, f)?;
+        if let Some(selection) = self.selection {
+            write!(f, " {}", selection)?;
+        }
+        if let Some(target) = &self.target {
+            write!(f, " as {}", target)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::ListingTargets => "Listing the targets of",
+            Self::Pasting => "Pasting",
+            Self::Copying => "Copying to",
+            Self::Serving => "Serving",
+            Self::RunningHook => "Running the hook for",
+        })
+    }
+}
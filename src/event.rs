@@ -0,0 +1,35 @@
+/// Something that happened while clipbox was serving a selection.
+///
+/// Selections and targets are given by name (`CLIPBOARD`, `text/html`...),
+/// so that no platform-specific types leak through.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClipboardEvent {
+    /// We became the owner of a selection
+    OwnerChanged { selection: String },
+    /// Another application asked for one of our targets
+    DataRequested {
+        selection: String,
+        target: String,
+        requestor: u64,
+    },
+    /// An incremental transfer progressed by a chunk
+    TransferProgress {
+        target: String,
+        requestor: u64,
+        sent: usize,
+        /// Unknown when streaming
+        total: Option<usize>,
+    },
+    /// We told a requestor we won't convert a selection to one of its targets: the target is
+    /// unavailable, the data is too big...
+    ConversionRefused {
+        selection: String,
+        target: String,
+        requestor: u64,
+        reason: String,
+    },
+    /// Another application took ownership of a selection we had
+    OwnershipLost { selection: String },
+    /// Something went wrong, for example a requestor went away in the middle of a transfer
+    Error { message: String },
+}
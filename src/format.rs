@@ -0,0 +1,137 @@
+//! Typed clipboard formats, to copy and paste values rather than bytes.
+//!
+//! Implement [`ClipboardFormat`] on your own types to give them a MIME type of their own:
+//! apps that know about it get the real thing, and the others just don't see it.
+
+use std::error::Error;
+use std::path::PathBuf;
+
+use crate::convert;
+#[cfg(feature = "png")]
+use crate::convert::ImageData;
+
+/// Why a value couldn't be encoded or decoded
+pub type FormatError = Box<dyn Error + Send + Sync>;
+
+/// A type that can go through the clipboard as a MIME type
+pub trait ClipboardFormat: Sized {
+    /// The MIME type the data is copied as. It can't contain NUL bytes.
+    fn mime() -> &'static str;
+
+    fn encode(&self) -> Result<Vec<u8>, FormatError>;
+
+    fn decode(data: &[u8]) -> Result<Self, FormatError>;
+}
+
+/// UTF-8 text
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Text(pub String);
+
+impl ClipboardFormat for Text {
+    fn mime() -> &'static str {
+        "text/plain;charset=utf-8"
+    }
+
+    fn encode(&self) -> Result<Vec<u8>, FormatError> {
+        Ok(self.0.as_bytes().to_vec())
+    }
+
+    fn decode(data: &[u8]) -> Result<Self, FormatError> {
+        Ok(Self(String::from_utf8(data.to_vec())?))
+    }
+}
+
+/// HTML markup
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Html(pub String);
+
+impl ClipboardFormat for Html {
+    fn mime() -> &'static str {
+        "text/html"
+    }
+
+    fn encode(&self) -> Result<Vec<u8>, FormatError> {
+        Ok(self.0.as_bytes().to_vec())
+    }
+
+    fn decode(data: &[u8]) -> Result<Self, FormatError> {
+        Ok(Self(convert::html_from_bytes(data)))
+    }
+}
+
+/// Rich Text Format
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rtf(pub String);
+
+impl ClipboardFormat for Rtf {
+    fn mime() -> &'static str {
+        "text/rtf"
+    }
+
+    fn encode(&self) -> Result<Vec<u8>, FormatError> {
+        Ok(self.0.as_bytes().to_vec())
+    }
+
+    fn decode(data: &[u8]) -> Result<Self, FormatError> {
+        // Some writers embed raw bytes from their code page
+        Ok(Self(String::from_utf8_lossy(data).into_owned()))
+    }
+}
+
+/// An SVG image, as its source
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Svg(pub String);
+
+impl ClipboardFormat for Svg {
+    fn mime() -> &'static str {
+        "image/svg+xml"
+    }
+
+    fn encode(&self) -> Result<Vec<u8>, FormatError> {
+        Ok(self.0.as_bytes().to_vec())
+    }
+
+    fn decode(data: &[u8]) -> Result<Self, FormatError> {
+        Ok(Self(String::from_utf8(data.to_vec())?))
+    }
+}
+
+/// Files, as a `text/uri-list`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UriList(pub Vec<PathBuf>);
+
+impl ClipboardFormat for UriList {
+    fn mime() -> &'static str {
+        "text/uri-list"
+    }
+
+    fn encode(&self) -> Result<Vec<u8>, FormatError> {
+        Ok(convert::paths_to_uri_list(&self.0).into_bytes())
+    }
+
+    fn decode(data: &[u8]) -> Result<Self, FormatError> {
+        Ok(Self(convert::uri_list_to_paths(&String::from_utf8_lossy(
+            data,
+        ))))
+    }
+}
+
+/// An image, as a PNG
+#[cfg(feature = "png")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Png(pub ImageData);
+
+#[cfg(feature = "png")]
+impl ClipboardFormat for Png {
+    fn mime() -> &'static str {
+        "image/png"
+    }
+
+    fn encode(&self) -> Result<Vec<u8>, FormatError> {
+        Ok(convert::encode_png(&self.0)?)
+    }
+
+    fn decode(data: &[u8]) -> Result<Self, FormatError> {
+        Ok(Self(convert::decode_png(data)?))
+    }
+}
@@ -0,0 +1,589 @@
+//! Remembering what was copied, for clipboard managers and "paste from history" menus.
+//!
+//! A [`ClipboardHistory`] is fed the changes a [watcher](crate::linux::x11::Watcher) notices,
+//! fetching the new contents of each one. It keeps the latest entries only, newest first, and
+//! any of them can be copied back. How many and how big they can be is up to its
+//! [limits](HistoryLimits).
+//!
+//! Histories live in memory, unless they're [opened](ClipboardHistory::open) from a file that
+//! keeps them across restarts. Sensitive entries are never written to that file, they're gone
+//! once the history is.
+//!
+//! Each entry says where it came from (machine, app, time, sensitivity) in its
+//! [provenance](HistoryEntry::provenance).
+//!
+//! With the `image` feature, image entries come with a [thumbnail](HistoryEntry::thumbnail)
+//! for pickers to show.
+
+use std::collections::VecDeque;
+use std::ffi::CString;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::clipboard::Clipboard;
+use crate::convert::ClipboardContent;
+#[cfg(feature = "image")]
+use crate::convert::ImageData;
+use crate::error::{ClipboxError, ErrorContext, Phase, ResultExt};
+use crate::linux::session::Session;
+use crate::linux::x11::{ChangeEvent, OwnerInfo, TargetBundle, X11Clipboard};
+use crate::selection::Selection;
+
+#[cfg(feature = "dbus")]
+mod gpaste;
+mod limits;
+mod provenance;
+mod search;
+mod sensitive;
+mod store;
+#[cfg(feature = "image")]
+mod thumbnails;
+
+#[cfg(feature = "dbus")]
+pub use gpaste::{GPaste, GPasteError, GPasteItem, GPasteItemKind, GPASTE_NAME};
+pub use limits::{EvictionPolicy, HistoryLimits};
+pub use provenance::{local_host_name, Provenance};
+pub use search::HistorySearch;
+pub use sensitive::SensitivityRules;
+pub use store::default_path;
+use store::{HistoryStore, Record};
+#[cfg(feature = "image")]
+pub use thumbnails::DEFAULT_THUMBNAIL_SIZE;
+
+/// How many entries a history keeps by default
+pub const DEFAULT_CAPACITY: usize = 100;
+
+/// Something that was copied
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistoryEntry {
+    /// Unique within its history, never reused
+    pub id: u64,
+    /// The selection it was copied on
+    pub selection: Selection,
+    pub content: ClipboardContent,
+    /// Where it came from, when, and whether it looks sensitive (see [`SensitivityRules`])
+    pub provenance: Provenance,
+    /// Kept however many entries come after it, and found first by searches.
+    /// See [`ClipboardHistory::set_pinned`].
+    pub pinned: bool,
+    /// A small version of the image, for images. See [`ClipboardHistory::set_thumbnail_size`].
+    #[cfg(feature = "image")]
+    pub thumbnail: Option<ImageData>,
+}
+
+/// A bounded history of clipboard contents, see the [module docs](self)
+#[derive(Debug)]
+pub struct ClipboardHistory {
+    /// Newest first
+    entries: VecDeque<HistoryEntry>,
+    limits: HistoryLimits,
+    next_id: u64,
+    /// Where every change is written to, if the history is kept on disk
+    store: Option<HistoryStore>,
+    sensitivity: SensitivityRules,
+    /// Nothing is recorded while it's inactive, see [`ClipboardHistory::set_pause_when_inactive`]
+    session: Option<Session>,
+    /// How many pixels new thumbnails have on their longest side
+    #[cfg(feature = "image")]
+    thumbnail_size: u32,
+}
+
+impl ClipboardHistory {
+    /// Keeps at most `capacity` entries (at least one) besides the pinned ones, forgetting the
+    /// least recently used ones first. See [`ClipboardHistory::set_limits`] for more limits.
+    pub fn new(capacity: usize) -> Self {
+        let limits = HistoryLimits::new(capacity);
+
+        Self {
+            entries: VecDeque::with_capacity(limits.max_entries),
+            limits,
+            next_id: 0,
+            store: None,
+            sensitivity: SensitivityRules::default(),
+            session: Session::current(),
+            #[cfg(feature = "image")]
+            thumbnail_size: DEFAULT_THUMBNAIL_SIZE,
+        }
+    }
+
+    /// Loads a history kept in a file, which then keeps every change made to it but the
+    /// sensitive entries. The file is created if it doesn't exist, readable by our user only.
+    ///
+    /// Whatever was damaged in the file (say, by a crash while writing to it) is dropped, and
+    /// the rest is loaded. Writing to the file later on can fail without the history failing:
+    /// the file only catches up on the next write, check [`ClipboardHistory::flush`] for that.
+    pub fn open(path: impl Into<PathBuf>, capacity: usize) -> io::Result<Self> {
+        let (store, records) = HistoryStore::open(path.into())?;
+
+        let mut history = Self::new(capacity);
+        for record in records {
+            history.replay(record);
+        }
+
+        history.store = Some(store);
+        // Logs from before sensitive entries were kept out of them can still have some
+        let sensitive = history.iter().any(|entry| entry.provenance.sensitive);
+        if sensitive || history.needs_compaction() {
+            history.compact()?;
+        }
+
+        Ok(history)
+    }
+
+    /// Loads the history kept in the [default file](default_path)
+    pub fn open_default(capacity: usize) -> io::Result<Self> {
+        let path = default_path().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                "no XDG_DATA_HOME or HOME to keep it in",
+            )
+        })?;
+
+        Self::open(path, capacity)
+    }
+
+    /// The file the history is kept in, if any
+    pub fn path(&self) -> Option<&Path> {
+        self.store.as_ref().map(HistoryStore::path)
+    }
+
+    /// Rewrites the file with only the entries as they are now, dropping everything else the
+    /// file remembers. This happens by itself once it gets too long.
+    pub fn compact(&mut self) -> io::Result<()> {
+        let entries = self.entries.iter().rev();
+        match &mut self.store {
+            Some(store) => store.rewrite(
+                self.next_id,
+                entries.filter(|entry| !entry.provenance.sensitive),
+            ),
+            None => Ok(()),
+        }
+    }
+
+    /// Makes sure the file has caught up with every change, if some couldn't be written
+    pub fn flush(&mut self) -> io::Result<()> {
+        match self.needs_compaction() {
+            true => self.compact(),
+            false => Ok(()),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.limits.max_entries
+    }
+
+    /// Changes how many entries are kept, evicting some if there are too many
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.limits.max_entries = capacity.max(1);
+        self.evict();
+    }
+
+    pub fn limits(&self) -> &HistoryLimits {
+        &self.limits
+    }
+
+    /// Changes how big the history can get, evicting entries if it's over the new limits
+    /// (including those too big to be added now). Images already there aren't scaled down.
+    pub fn set_limits(&mut self, limits: HistoryLimits) {
+        self.limits = HistoryLimits {
+            max_entries: limits.max_entries.max(1),
+            ..limits
+        };
+        self.evict();
+    }
+
+    pub fn sensitivity_rules(&self) -> &SensitivityRules {
+        &self.sensitivity
+    }
+
+    /// Changes how new entries are found to be sensitive. Entries already there stay as they
+    /// are, see [`ClipboardHistory::set_sensitive`] for those.
+    pub fn set_sensitivity_rules(&mut self, rules: SensitivityRules) {
+        self.sensitivity = rules;
+    }
+
+    #[cfg(feature = "image")]
+    pub fn thumbnail_size(&self) -> u32 {
+        self.thumbnail_size
+    }
+
+    /// Changes how many pixels thumbnails have on their longest side, [`DEFAULT_THUMBNAIL_SIZE`]
+    /// by default. Entries already there keep the thumbnail they have.
+    #[cfg(feature = "image")]
+    pub fn set_thumbnail_size(&mut self, size: u32) {
+        self.thumbnail_size = size.max(1);
+    }
+
+    /// Whether [`ClipboardHistory::record`] records nothing while our login session isn't the
+    /// active one (another user switched to theirs), which it does by default. See
+    /// [`Session::is_active`].
+    pub fn set_pause_when_inactive(&mut self, pause: bool) {
+        self.session = pause.then(Session::current).flatten();
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Adds what was just copied, giving the id of its entry, or `None` if it's too big for
+    /// the [limits](HistoryLimits) (images are scaled down first).
+    ///
+    /// Copying the same content as the latest entry again only brings that one up to date.
+    pub fn push(
+        &mut self,
+        selection: Selection,
+        content: ClipboardContent,
+        source: Option<OwnerInfo>,
+    ) -> Option<u64> {
+        self.expire_sensitive();
+
+        let content = self.limits.shrink(content)?;
+        let mut provenance = Provenance::new(source);
+        provenance.sensitive = self
+            .sensitivity
+            .is_sensitive(&content, provenance.app.as_ref());
+
+        if let Some(latest) = self.entries.front_mut() {
+            if latest.content == content {
+                let latest_provenance = &mut latest.provenance;
+                latest.selection = selection;
+                latest_provenance.copied_at = provenance.copied_at;
+                if provenance.app.is_some() {
+                    latest_provenance.host = provenance.host;
+                    latest_provenance.app = provenance.app;
+                }
+                let was_sensitive = latest_provenance.sensitive;
+                latest_provenance.sensitive |= provenance.sensitive;
+
+                let id = latest.id;
+                match (was_sensitive, latest_provenance.sensitive) {
+                    (false, false) => self.persist(|entries| store::encode_put(&entries[0])),
+                    (false, true) => self.forget_on_disk(),
+                    (true, _) => {}
+                }
+                return Some(id);
+            }
+        }
+
+        #[cfg(feature = "image")]
+        let thumbnail = thumbnails::thumbnail(&content, self.thumbnail_size);
+
+        let id = self.next_id;
+        self.next_id += 1;
+
+        self.entries.push_front(HistoryEntry {
+            id,
+            selection,
+            content,
+            provenance,
+            pinned: false,
+            #[cfg(feature = "image")]
+            thumbnail,
+        });
+        self.persist_latest();
+        self.evict();
+
+        // It can be what goes first, when it's the largest one
+        self.position(id).map(|_| id)
+    }
+
+    /// Fetches the contents a watcher noticed a change of, and adds them. Gives the id of the
+    /// entry, or `None` if there's nothing to add: the selection was cleared, its owner says
+    /// it's a secret (see [`X11Clipboard::is_secret`]), it's too big, our session is inactive
+    /// (see [`ClipboardHistory::set_pause_when_inactive`]), or we own it (add what you copy
+    /// yourself with [`ClipboardHistory::push`]).
+    pub fn record(
+        &mut self,
+        clipboard: &X11Clipboard,
+        change: &ChangeEvent,
+    ) -> Result<Option<u64>, ClipboxError> {
+        if change.owner.is_none() || clipboard.is_owner(change.selection) {
+            return Ok(None);
+        }
+
+        if self
+            .session
+            .as_ref()
+            .is_some_and(|session| !session.is_active())
+        {
+            return Ok(None);
+        }
+
+        let secret = match &change.targets {
+            Some(targets) => {
+                clipboard.offers_secret(change.selection, targets.iter().map(CString::as_c_str))
+            }
+            None => clipboard.is_secret(change.selection),
+        };
+        if secret {
+            return Ok(None);
+        }
+
+        let content = clipboard
+            .get_content(change.selection)
+            .context(|| ErrorContext::new(Phase::Pasting).selection(change.selection))?;
+        let source = clipboard.selection_owner_info(change.selection);
+        Ok(self.push(change.selection, content, source))
+    }
+
+    /// What was copied last
+    pub fn latest(&self) -> Option<&HistoryEntry> {
+        self.entries.front()
+    }
+
+    pub fn get(&self, id: u64) -> Option<&HistoryEntry> {
+        self.entries.iter().find(|entry| entry.id == id)
+    }
+
+    /// The entries, newest first
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &HistoryEntry> + ExactSizeIterator {
+        self.entries.iter()
+    }
+
+    pub fn remove(&mut self, id: u64) -> Option<HistoryEntry> {
+        let index = self.position(id)?;
+        let entry = self.entries.remove(index);
+        self.persist(|_| store::encode_remove(id));
+        entry
+    }
+
+    /// Forgets every entry but the pinned ones
+    pub fn clear(&mut self) {
+        self.entries.retain(|entry| entry.pinned);
+        self.persist(|_| store::encode_clear());
+    }
+
+    /// Pins or unpins an entry, giving whether there's such an entry. Pinned entries don't
+    /// count towards the limits, so unpinning one can make others go.
+    pub fn set_pinned(&mut self, id: u64, pinned: bool) -> bool {
+        let Some(index) = self.position(id) else {
+            return false;
+        };
+
+        if self.entries[index].pinned != pinned {
+            self.entries[index].pinned = pinned;
+            self.persist(|_| store::encode_pin(id, pinned));
+            self.evict();
+        }
+
+        true
+    }
+
+    /// Marks an entry sensitive or not, when the heuristics got it wrong. Gives whether there's
+    /// such an entry.
+    ///
+    /// An entry made sensitive is rewritten out of the file right away, one that isn't anymore
+    /// is written to it.
+    pub fn set_sensitive(&mut self, id: u64, sensitive: bool) -> bool {
+        let Some(index) = self.position(id) else {
+            return false;
+        };
+
+        if self.entries[index].provenance.sensitive != sensitive {
+            self.entries[index].provenance.sensitive = sensitive;
+            match sensitive {
+                true => self.forget_on_disk(),
+                false => self.persist(|entries| store::encode_put(&entries[index])),
+            }
+        }
+
+        true
+    }
+
+    /// Forgets the sensitive entries copied longer ago than the rules
+    /// [keep them](SensitivityRules::expire_after), pinned ones aside. Gives how many went.
+    ///
+    /// This happens whenever something is added, call it by [`ClipboardHistory::next_expiry`]
+    /// too so that they don't stay until the next copy.
+    pub fn expire_sensitive(&mut self) -> usize {
+        let Some(max_age) = self.sensitivity.expire_after else {
+            return 0;
+        };
+
+        let now = SystemTime::now();
+        let len = self.entries.len();
+        // They're never written to the file, there's nothing to remove from it
+        self.entries
+            .retain(|entry| expires(entry, max_age).is_none_or(|expiry| expiry > now));
+
+        len - self.entries.len()
+    }
+
+    /// When the next sensitive entry expires, if one ever does
+    pub fn next_expiry(&self) -> Option<SystemTime> {
+        let max_age = self.sensitivity.expire_after?;
+        self.entries
+            .iter()
+            .filter_map(|entry| expires(entry, max_age))
+            .min()
+    }
+
+    /// The pinned entries, newest first
+    pub fn pinned(&self) -> impl DoubleEndedIterator<Item = &HistoryEntry> {
+        self.entries.iter().filter(|entry| entry.pinned)
+    }
+
+    /// Copies an entry back to the clipboard, making it the latest one again.
+    /// Gives [`ClipboxError::NotAvailable`] if there's no such entry (anymore).
+    pub fn restore(&mut self, id: u64, clipboard: &Clipboard) -> Result<(), ClipboxError> {
+        let index = self.position(id).ok_or(ClipboxError::NotAvailable)?;
+
+        let bundle = TargetBundle::from_content(&self.entries[index].content)?;
+        if bundle.is_empty() {
+            return Err(ClipboxError::NotAvailable);
+        }
+        clipboard.set_many(&bundle)?;
+
+        // The watcher will see it come back, and find it's the latest already
+        if let Some(mut entry) = self.entries.remove(index) {
+            entry.selection = Selection::Clipboard;
+            entry.provenance.copied_at = SystemTime::now();
+            self.entries.push_front(entry);
+            self.persist_latest();
+        }
+
+        Ok(())
+    }
+
+    fn position(&self, id: u64) -> Option<usize> {
+        self.entries.iter().position(|entry| entry.id == id)
+    }
+
+    /// Forgets entries as the eviction policy says until the history is within its limits,
+    /// pinned ones aside. Entries too big to be added go first.
+    fn evict(&mut self) {
+        loop {
+            let (count, total_size) = self
+                .entries
+                .iter()
+                .filter(|entry| !entry.pinned)
+                .fold((0, 0), |(count, size), entry| {
+                    (count + 1, size + entry.size())
+                });
+
+            let too_big = self
+                .entries
+                .iter()
+                .position(|entry| !entry.pinned && !self.limits.fits(entry.size()));
+
+            let victim = match too_big {
+                Some(index) => index,
+                None if self.limits.exceeded(count, total_size) => {
+                    match self.limits.eviction.victim(&self.entries) {
+                        Some(index) => index,
+                        None => break,
+                    }
+                }
+                None => break,
+            };
+
+            if let Some(entry) = self.entries.remove(victim) {
+                self.persist(|_| store::encode_remove(entry.id));
+            }
+        }
+    }
+
+    /// Writes a change to the file, if there's one. The record is only encoded then, since
+    /// it can be a whole image.
+    fn persist(&mut self, record: impl FnOnce(&VecDeque<HistoryEntry>) -> Vec<u8>) {
+        if self.store.is_none() {
+            return;
+        }
+
+        // Failures leave the store dirty, which the next change (or a flush) fixes
+        let _ = match self.needs_compaction() {
+            true => self.compact(),
+            false => {
+                let record = record(&self.entries);
+                self.store
+                    .as_mut()
+                    .map_or(Ok(()), |store| store.append(&record))
+            }
+        };
+    }
+
+    /// Writes the latest entry to the file, unless it's sensitive
+    fn persist_latest(&mut self) {
+        if self
+            .entries
+            .front()
+            .is_some_and(|entry| !entry.provenance.sensitive)
+        {
+            self.persist(|entries| store::encode_put(&entries[0]));
+        }
+    }
+
+    /// Rewrites the file without the sensitive entries, so that what was written of them before
+    /// doesn't stay in it. A failure leaves the store dirty, like in [`Self::persist`].
+    fn forget_on_disk(&mut self) {
+        let _ = self.compact();
+    }
+
+    fn needs_compaction(&self) -> bool {
+        // Pinned entries can make the history bigger than its capacity
+        let size = self.entries.len().max(self.limits.max_entries);
+        self.store
+            .as_ref()
+            .is_some_and(|store| store.needs_compaction(size))
+    }
+
+    /// Applies what the file remembers
+    fn replay(&mut self, record: Record) {
+        match record {
+            Record::Put(entry) => {
+                self.next_id = self.next_id.max(entry.id + 1);
+                if let Some(index) = self.position(entry.id) {
+                    self.entries.remove(index);
+                }
+
+                self.entries.push_front(*entry);
+                self.evict();
+            }
+            Record::Remove(id) => {
+                if let Some(index) = self.position(id) {
+                    self.entries.remove(index);
+                }
+            }
+            Record::Clear => self.entries.retain(|entry| entry.pinned),
+            Record::Pin(id, pinned) => {
+                if let Some(index) = self.position(id) {
+                    self.entries[index].pinned = pinned;
+                }
+                self.evict();
+            }
+            Record::Sensitive(id, sensitive) => {
+                if let Some(index) = self.position(id) {
+                    self.entries[index].provenance.sensitive = sensitive;
+                }
+            }
+            Record::NextId(next_id) => self.next_id = self.next_id.max(next_id),
+        }
+    }
+}
+
+/// When an entry expires, if it's sensitive and not pinned
+fn expires(entry: &HistoryEntry, max_age: Duration) -> Option<SystemTime> {
+    (entry.provenance.sensitive && !entry.pinned)
+        .then(|| entry.provenance.copied_at.checked_add(max_age))
+        .flatten()
+}
+
+impl Default for ClipboardHistory {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
+}
+
+impl<'a> IntoIterator for &'a ClipboardHistory {
+    type Item = &'a HistoryEntry;
+    type IntoIter = std::collections::vec_deque::Iter<'a, HistoryEntry>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.iter()
+    }
+}
@@ -0,0 +1,239 @@
+//! Getting along with GPaste, GNOME's clipboard manager, through its D-Bus API.
+//!
+//! When a GPaste daemon is running, it records the clipboard already: two histories of the
+//! same copies aren't much use. A [`GPaste`] can tell whether it's
+//! [tracking](GPaste::is_tracking) (leave recording to it then, and read its
+//! [history](GPaste::history)), merge its history into ours with
+//! [`ClipboardHistory::merge_gpaste`], or hand ours over to it with
+//! [`ClipboardHistory::export_to_gpaste`].
+//!
+//! GPaste only speaks text over D-Bus: images and passwords it keeps can't be read back, and
+//! images we keep can't be given to it.
+
+use std::error::Error;
+use std::fmt;
+use std::path::PathBuf;
+
+use zbus::blocking::{Connection, Proxy};
+
+use super::{ClipboardHistory, HistoryEntry};
+use crate::convert::{self, ClipboardContent};
+use crate::selection::Selection;
+
+/// The name GPaste goes by on the session bus
+pub const GPASTE_NAME: &str = "org.gnome.GPaste";
+
+const GPASTE_PATH: &str = "/org/gnome/GPaste";
+
+/// The interface of GPaste 3.30 and later
+const GPASTE_INTERFACE: &str = "org.gnome.GPaste2";
+
+/// A GPaste daemon on the session bus, see the [module docs](self)
+pub struct GPaste {
+    proxy: Proxy<'static>,
+}
+
+/// Something in GPaste's history
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GPasteItem {
+    /// What GPaste knows it by
+    pub uuid: String,
+    /// Its text, or a description of it for images and passwords
+    pub value: String,
+    pub kind: GPasteItemKind,
+}
+
+/// What kind of item GPaste says it is
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GPasteItemKind {
+    Text,
+    /// Files, one path per line
+    Uris,
+    Image,
+    Password,
+    /// Something newer GPastes have
+    Other(String),
+}
+
+#[derive(Debug)]
+pub enum GPasteError {
+    /// There's no GPaste daemon on the session bus
+    NotRunning,
+    DBus(zbus::Error),
+}
+
+impl GPaste {
+    /// Connects to the GPaste daemon that's already running, without starting one.
+    /// Gives [`GPasteError::NotRunning`] if there's none.
+    pub fn connect() -> Result<Self, GPasteError> {
+        let connection = Connection::session()?;
+
+        let bus = Proxy::new(
+            &connection,
+            "org.freedesktop.DBus",
+            "/org/freedesktop/DBus",
+            "org.freedesktop.DBus",
+        )?;
+        let running: bool = bus.call("NameHasOwner", &(GPASTE_NAME,))?;
+        if !running {
+            return Err(GPasteError::NotRunning);
+        }
+
+        let proxy = Proxy::new(&connection, GPASTE_NAME, GPASTE_PATH, GPASTE_INTERFACE)?;
+        Ok(Self { proxy })
+    }
+
+    /// Whether GPaste records the clipboard right now, in which case it's best not to record
+    /// it ourselves
+    pub fn is_tracking(&self) -> Result<bool, GPasteError> {
+        Ok(self.proxy.get_property("Active")?)
+    }
+
+    /// Starts or stops GPaste's recording
+    pub fn set_tracking(&self, tracking: bool) -> Result<(), GPasteError> {
+        Ok(self.proxy.call("Track", &(tracking,))?)
+    }
+
+    /// The items of GPaste's current history, newest first
+    pub fn history(&self) -> Result<Vec<GPasteItem>, GPasteError> {
+        let items: Vec<(String, String)> = self.proxy.call("GetHistory", &())?;
+
+        items
+            .into_iter()
+            .map(|(uuid, value)| {
+                let kind: String = self.proxy.call("GetElementKind", &(&uuid,))?;
+                let kind = match kind.as_str() {
+                    "Text" => GPasteItemKind::Text,
+                    "Uris" => GPasteItemKind::Uris,
+                    "Image" => GPasteItemKind::Image,
+                    "Password" => GPasteItemKind::Password,
+                    _ => GPasteItemKind::Other(kind),
+                };
+
+                Ok(GPasteItem { uuid, value, kind })
+            })
+            .collect()
+    }
+
+    /// Adds text to GPaste's history, which also copies it
+    pub fn add(&self, text: &str) -> Result<(), GPasteError> {
+        Ok(self.proxy.call("Add", &(text,))?)
+    }
+
+    /// Adds a password, which GPaste shows by its name only
+    pub fn add_password(&self, name: &str, password: &str) -> Result<(), GPasteError> {
+        Ok(self.proxy.call("AddPassword", &(name, password))?)
+    }
+
+    /// Copies an item of GPaste's history again
+    pub fn select(&self, uuid: &str) -> Result<(), GPasteError> {
+        Ok(self.proxy.call("Select", &(uuid,))?)
+    }
+
+    pub fn delete(&self, uuid: &str) -> Result<(), GPasteError> {
+        Ok(self.proxy.call("Delete", &(uuid,))?)
+    }
+
+    /// Adds one of our entries to GPaste's history: text as it is, HTML as its text, files as
+    /// their paths and sensitive entries as passwords. Gives whether it could, which it can't
+    /// for images and raw data.
+    pub fn add_entry(&self, entry: &HistoryEntry) -> Result<bool, GPasteError> {
+        let text = match &entry.content {
+            ClipboardContent::Text(text) => text.clone(),
+            ClipboardContent::Html(html) => convert::html_to_text(html),
+            ClipboardContent::Files(paths) => paths
+                .iter()
+                .map(|path| path.to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("\n"),
+            ClipboardContent::Image(_) | ClipboardContent::Other(..) => return Ok(false),
+        };
+
+        match entry.provenance.sensitive {
+            true => self.add_password(&format!("clipbox {}", entry.id), &text)?,
+            false => self.add(&text)?,
+        }
+
+        Ok(true)
+    }
+}
+
+impl GPasteItem {
+    /// What the item holds, if GPaste gives it away: text, or files
+    pub fn content(&self) -> Option<ClipboardContent> {
+        match self.kind {
+            GPasteItemKind::Text => Some(ClipboardContent::Text(self.value.clone())),
+            GPasteItemKind::Uris => Some(ClipboardContent::Files(
+                self.value
+                    .lines()
+                    .filter(|line| !line.is_empty())
+                    .map(|line| convert::file_uri_to_path(line).unwrap_or_else(|| line.into()))
+                    .collect::<Vec<PathBuf>>(),
+            )),
+            _ => None,
+        }
+    }
+}
+
+impl ClipboardHistory {
+    /// Adds GPaste's items that we don't have yet, giving how many. They come in oldest first,
+    /// as if they were just copied, so they end up newer than the entries we had.
+    pub fn merge_gpaste(&mut self, gpaste: &GPaste) -> Result<usize, GPasteError> {
+        let mut merged = 0;
+
+        for item in gpaste.history()?.into_iter().rev() {
+            let Some(content) = item.content() else {
+                continue;
+            };
+
+            if self.entries.iter().any(|entry| entry.content == content) {
+                continue;
+            }
+
+            if self.push(Selection::Clipboard, content, None).is_some() {
+                merged += 1;
+            }
+        }
+
+        Ok(merged)
+    }
+
+    /// Adds our entries to GPaste's history, oldest first so that their order is kept, giving
+    /// how many it took (see [`GPaste::add_entry`]). Each one is copied on the way, and the
+    /// newest one stays on the clipboard.
+    pub fn export_to_gpaste(&self, gpaste: &GPaste) -> Result<usize, GPasteError> {
+        let mut exported = 0;
+
+        for entry in self.entries.iter().rev() {
+            if gpaste.add_entry(entry)? {
+                exported += 1;
+            }
+        }
+
+        Ok(exported)
+    }
+}
+
+impl Error for GPasteError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::NotRunning => None,
+            Self::DBus(err) => Some(err),
+        }
+    }
+}
+
+impl fmt::Display for GPasteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotRunning => write!(f, "GPaste isn't running"),
+            Self::DBus(err) => write!(f, "Couldn't talk to GPaste: {}", err),
+        }
+    }
+}
+
+impl From<zbus::Error> for GPasteError {
+    fn from(value: zbus::Error) -> Self {
+        Self::DBus(value)
+    }
+}
@@ -0,0 +1,115 @@
+//! How big a history can get, and which entries go first once it's too big.
+
+use std::collections::VecDeque;
+
+use super::{HistoryEntry, DEFAULT_CAPACITY};
+use crate::convert::{self, ClipboardContent};
+
+/// Which entries go first when a history is over its limits. Pinned entries never go.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EvictionPolicy {
+    /// The ones copied (or restored) the longest ago
+    #[default]
+    LeastRecentlyUsed,
+    /// The ones that were first copied the longest ago, even if they were copied again since
+    OldestFirst,
+    /// The biggest ones, see [`HistoryEntry::size`]
+    LargestFirst,
+}
+
+/// The limits of a history, see [`ClipboardHistory::set_limits`]. Only the number of entries
+/// is limited by default.
+///
+/// Pinned entries don't count towards any of them.
+///
+/// [`ClipboardHistory::set_limits`]: super::ClipboardHistory::set_limits
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistoryLimits {
+    /// How many entries are kept (at least one)
+    pub max_entries: usize,
+    /// Bigger entries aren't added at all. Images are scaled down before they're measured.
+    pub max_entry_size: Option<usize>,
+    /// How big the entries can be all together
+    pub max_total_size: Option<usize>,
+    pub eviction: EvictionPolicy,
+    /// Images are scaled down to fit in that many pixels on each side before they're added,
+    /// keeping their aspect ratio
+    pub max_image_size: Option<(u32, u32)>,
+}
+
+impl Default for HistoryLimits {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
+}
+
+impl HistoryLimits {
+    /// Only `max_entries` entries, evicting the least recently used ones
+    pub fn new(max_entries: usize) -> Self {
+        Self {
+            max_entries: max_entries.max(1),
+            max_entry_size: None,
+            max_total_size: None,
+            eviction: EvictionPolicy::default(),
+            max_image_size: None,
+        }
+    }
+
+    /// Gets content ready to be added, giving `None` if it's too big even then
+    pub(super) fn shrink(&self, content: ClipboardContent) -> Option<ClipboardContent> {
+        let content = match (content, self.max_image_size) {
+            (ClipboardContent::Image(image), Some((max_width, max_height))) => {
+                ClipboardContent::Image(convert::downscale_image(image, max_width, max_height))
+            }
+            (content, _) => content,
+        };
+
+        self.fits(content_size(&content)).then_some(content)
+    }
+
+    /// Whether an entry that big can be kept
+    pub(super) fn fits(&self, size: usize) -> bool {
+        self.max_entry_size.is_none_or(|max| size <= max)
+    }
+
+    /// Whether unpinned entries that many and that big together are too much
+    pub(super) fn exceeded(&self, count: usize, total_size: usize) -> bool {
+        count > self.max_entries || self.max_total_size.is_some_and(|max| total_size > max)
+    }
+}
+
+impl EvictionPolicy {
+    /// The index of the entry to evict next, if there are unpinned ones
+    pub(super) fn victim(self, entries: &VecDeque<HistoryEntry>) -> Option<usize> {
+        let mut unpinned = entries
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| !entry.pinned);
+
+        // Entries are newest first, so the last of equally big ones is the oldest
+        let victim = match self {
+            Self::LeastRecentlyUsed => unpinned.next_back(),
+            Self::OldestFirst => unpinned.min_by_key(|(_, entry)| entry.id),
+            Self::LargestFirst => unpinned.max_by_key(|(_, entry)| entry.size()),
+        };
+
+        victim.map(|(index, _)| index)
+    }
+}
+
+impl HistoryEntry {
+    /// Roughly how many bytes the content takes: the length of text and HTML, of the RGBA
+    /// pixels of images, of the paths of files and of raw data
+    pub fn size(&self) -> usize {
+        content_size(&self.content)
+    }
+}
+
+fn content_size(content: &ClipboardContent) -> usize {
+    match content {
+        ClipboardContent::Text(text) | ClipboardContent::Html(text) => text.len(),
+        ClipboardContent::Image(image) => image.rgba.len(),
+        ClipboardContent::Files(paths) => paths.iter().map(|path| path.as_os_str().len()).sum(),
+        ClipboardContent::Other(_, data) => data.len(),
+    }
+}
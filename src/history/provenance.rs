@@ -0,0 +1,92 @@
+//! Where something that was copied came from, so that users can always tell: which machine,
+//! which app, when, and whether it looked sensitive.
+
+use std::ffi::{c_char, c_int, CStr};
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::linux::x11::OwnerInfo;
+
+/// Longer host names are cut short, see `sysconf(_SC_HOST_NAME_MAX)`
+const MAX_HOST_NAME_SIZE: usize = 256;
+
+// `gethostname` is in libc on every platform we support
+extern "C" {
+    fn gethostname(name: *mut c_char, len: usize) -> c_int;
+}
+
+/// Where something that was copied came from, see the [module docs](self)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Provenance {
+    /// The machine it was copied on: the one its app runs on if it says, this one otherwise
+    pub host: Option<String>,
+    /// The app it was copied from, if it says. Its window is 0 once loaded from a file.
+    pub app: Option<OwnerInfo>,
+    /// When it was copied, or last copied again
+    pub copied_at: SystemTime,
+    /// Looks like a password, a card number... see [`SensitivityRules`](super::SensitivityRules)
+    pub sensitive: bool,
+}
+
+impl Provenance {
+    /// Something `app` copied just now, or that we copied ourselves without one
+    pub fn new(app: Option<OwnerInfo>) -> Self {
+        let host = app
+            .as_ref()
+            .and_then(|app| app.machine.clone())
+            .or_else(local_host_name);
+
+        Self {
+            host,
+            app,
+            copied_at: SystemTime::now(),
+            sensitive: false,
+        }
+    }
+
+    /// The name of the app, by the class part of its `WM_CLASS` or else by its window title
+    pub fn app_name(&self) -> Option<&str> {
+        let app = self.app.as_ref()?;
+        app.class.as_deref().or(app.name.as_deref())
+    }
+}
+
+/// Like `firefox on laptop at 14:03:27 UTC, sensitive`
+impl fmt::Display for Provenance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} on {}",
+            self.app_name().unwrap_or("unknown app"),
+            self.host.as_deref().unwrap_or("unknown host"),
+        )?;
+
+        if let Ok(since_epoch) = self.copied_at.duration_since(UNIX_EPOCH) {
+            let secs = since_epoch.as_secs() % 86400;
+            write!(
+                f,
+                " at {:02}:{:02}:{:02} UTC",
+                secs / 3600,
+                secs / 60 % 60,
+                secs % 60
+            )?;
+        }
+
+        match self.sensitive {
+            true => write!(f, ", sensitive"),
+            false => Ok(()),
+        }
+    }
+}
+
+/// The name of this machine, as `hostname` says
+pub fn local_host_name() -> Option<String> {
+    let mut name = [0 as c_char; MAX_HOST_NAME_SIZE + 1];
+    if unsafe { gethostname(name.as_mut_ptr(), MAX_HOST_NAME_SIZE) } != 0 {
+        return None;
+    }
+
+    // The last byte stays a NUL even if the name was cut short
+    let name = unsafe { CStr::from_ptr(name.as_ptr()) };
+    Some(name.to_string_lossy().into_owned()).filter(|name| !name.is_empty())
+}
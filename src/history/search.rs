@@ -0,0 +1,396 @@
+//! Finding entries again, for pickers and "paste from history" menus.
+
+use std::borrow::Cow;
+use std::time::SystemTime;
+
+use super::{ClipboardHistory, HistoryEntry};
+use crate::convert::{self, ClipboardContent, Mime};
+use crate::selection::Selection;
+
+/// The entries of a history matching a query and some filters, pinned ones first, then newest
+/// first. Get one from [`ClipboardHistory::search`], narrow it down, then iterate over it.
+///
+/// The query is matched against the text of entries, case-insensitively: text itself, HTML
+/// without its tags, and the paths of files. An empty query matches every entry, even
+/// those without text, so that the filters can be used on their own.
+#[derive(Debug, Clone)]
+pub struct HistorySearch<'a> {
+    history: &'a ClipboardHistory,
+    /// Lowercase
+    query: String,
+    fuzzy: bool,
+    /// Any of them, if there are any. `None` for patterns that didn't parse.
+    mimes: Vec<Option<Mime>>,
+    since: Option<SystemTime>,
+    until: Option<SystemTime>,
+    selection: Option<Selection>,
+    /// Lowercase
+    app: Option<String>,
+    sensitive: Option<bool>,
+}
+
+impl<'a> HistorySearch<'a> {
+    pub(super) fn new(history: &'a ClipboardHistory, query: &str) -> Self {
+        Self {
+            history,
+            query: query.to_lowercase(),
+            fuzzy: false,
+            mimes: Vec::new(),
+            since: None,
+            until: None,
+            selection: None,
+            app: None,
+            sensitive: None,
+        }
+    }
+
+    /// Matches the letters of the query in order but not necessarily next to each other, like
+    /// `invc` for "invoice". Results then come best match first.
+    pub fn fuzzy(mut self, fuzzy: bool) -> Self {
+        self.fuzzy = fuzzy;
+        self
+    }
+
+    /// Only entries of this MIME type, which can be a pattern like `text/*`. Calling this again
+    /// allows more types, and invalid patterns match nothing.
+    ///
+    /// See [`HistoryEntry::mime`] for the type of each entry.
+    pub fn mime(mut self, pattern: &str) -> Self {
+        self.mimes.push(Mime::parse(pattern));
+        self
+    }
+
+    /// Only entries copied at that time or later
+    pub fn since(mut self, time: SystemTime) -> Self {
+        self.since = Some(time);
+        self
+    }
+
+    /// Only entries copied before that time
+    pub fn until(mut self, time: SystemTime) -> Self {
+        self.until = Some(time);
+        self
+    }
+
+    pub fn selection(mut self, selection: Selection) -> Self {
+        self.selection = Some(selection);
+        self
+    }
+
+    /// Only entries copied from this app, by its `WM_CLASS` (like `firefox`) or its window
+    /// name, case-insensitively
+    pub fn app(mut self, app: &str) -> Self {
+        self.app = Some(app.to_lowercase());
+        self
+    }
+
+    /// Only sensitive entries, or only the others. The query still looks at the text of
+    /// sensitive entries, so leave them out when the UI masks them.
+    pub fn sensitive(mut self, sensitive: bool) -> Self {
+        self.sensitive = Some(sensitive);
+        self
+    }
+
+    /// Whether an entry passes the filters and matches the query
+    pub fn matches(&self, entry: &HistoryEntry) -> bool {
+        self.passes_filters(entry) && self.score(entry).is_some()
+    }
+
+    /// The matching entries, pinned ones first, then newest first (or best first when fuzzy)
+    pub fn results(&self) -> Vec<&'a HistoryEntry> {
+        let mut results = self
+            .history
+            .iter()
+            .filter(|entry| self.passes_filters(entry))
+            .filter_map(|entry| Some((self.score(entry)?, entry)))
+            .collect::<Vec<_>>();
+
+        // Stable, so newer entries stay first among equally good matches
+        if self.fuzzy {
+            results.sort_by_key(|&(score, _)| std::cmp::Reverse(score));
+        }
+        results.sort_by_key(|&(_, entry)| !entry.pinned);
+
+        results.into_iter().map(|(_, entry)| entry).collect()
+    }
+
+    fn passes_filters(&self, entry: &HistoryEntry) -> bool {
+        let mime_matches = self.mimes.is_empty()
+            || entry.mime().is_some_and(|mime| {
+                self.mimes
+                    .iter()
+                    .flatten()
+                    .any(|pattern| mime_matches(&mime, pattern))
+            });
+
+        let app_matches = self.app.as_deref().is_none_or(|app| {
+            entry.provenance.app.as_ref().is_some_and(|source| {
+                [&source.class, &source.instance, &source.name]
+                    .into_iter()
+                    .flatten()
+                    .any(|name| name.to_lowercase() == app)
+            })
+        });
+
+        mime_matches
+            && app_matches
+            && self
+                .sensitive
+                .is_none_or(|sensitive| entry.provenance.sensitive == sensitive)
+            && self
+                .since
+                .is_none_or(|since| entry.provenance.copied_at >= since)
+            && self
+                .until
+                .is_none_or(|until| entry.provenance.copied_at < until)
+            && self
+                .selection
+                .is_none_or(|selection| entry.selection == selection)
+    }
+
+    /// How well the entry matches the query, if it does at all
+    fn score(&self, entry: &HistoryEntry) -> Option<u32> {
+        if self.query.is_empty() {
+            return Some(0);
+        }
+
+        let text = entry.text()?.to_lowercase();
+        match self.fuzzy {
+            true => fuzzy_score(&text, &self.query),
+            false => text.contains(&self.query).then_some(0),
+        }
+    }
+}
+
+impl<'a> IntoIterator for HistorySearch<'a> {
+    type Item = &'a HistoryEntry;
+    type IntoIter = std::vec::IntoIter<&'a HistoryEntry>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.results().into_iter()
+    }
+}
+
+impl ClipboardHistory {
+    /// Searches the entries, see [`HistorySearch`]
+    pub fn search(&self, query: &str) -> HistorySearch<'_> {
+        HistorySearch::new(self, query)
+    }
+}
+
+impl HistoryEntry {
+    /// The MIME type of the content: `text/plain`, `text/html`, `image/png` (what images are
+    /// copied back as), `text/uri-list` for files, and the target for anything else if it's a
+    /// MIME type at all
+    pub fn mime(&self) -> Option<Mime> {
+        match &self.content {
+            ClipboardContent::Text(_) => Mime::parse("text/plain"),
+            ClipboardContent::Html(_) => Mime::parse("text/html"),
+            ClipboardContent::Image(_) => Mime::parse("image/png"),
+            ClipboardContent::Files(_) => Mime::parse("text/uri-list"),
+            ClipboardContent::Other(target, _) => Mime::parse(target),
+        }
+    }
+
+    /// The text searches look at, if the content has any
+    pub fn text(&self) -> Option<Cow<'_, str>> {
+        match &self.content {
+            ClipboardContent::Text(text) => Some(Cow::Borrowed(text)),
+            ClipboardContent::Html(html) => Some(Cow::Owned(convert::html_to_text(html))),
+            ClipboardContent::Files(paths) => Some(Cow::Owned(
+                paths
+                    .iter()
+                    .map(|path| path.to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            )),
+            ClipboardContent::Image(_) | ClipboardContent::Other(..) => None,
+        }
+    }
+
+    /// What a UI can show of the text: itself, or as many dots as it has characters (up to 12)
+    /// if the entry is sensitive
+    pub fn masked_text(&self) -> Option<Cow<'_, str>> {
+        let text = self.text()?;
+        match self.provenance.sensitive {
+            true => Some(Cow::Owned("•".repeat(text.chars().count().min(12)))),
+            false => Some(text),
+        }
+    }
+}
+
+/// Whether the type matches a pattern, where `*` stands for any type or subtype
+fn mime_matches(mime: &Mime, pattern: &Mime) -> bool {
+    let matches = |part: &str, pattern: &str| pattern == "*" || part == pattern;
+    matches(mime.type_(), pattern.type_()) && matches(mime.subtype(), pattern.subtype())
+}
+
+/// Scores a fuzzy match of the query in the text, if all of its letters are there in order.
+/// Letters right after the previous one (or starting a word) count for more.
+fn fuzzy_score(text: &str, query: &str) -> Option<u32> {
+    let mut score = 0;
+    let mut query = query.chars().peekable();
+    let mut previous: Option<char> = None;
+    let mut matched_previous = false;
+
+    for c in text.chars() {
+        let Some(&wanted) = query.peek() else {
+            break;
+        };
+
+        let matched = c == wanted;
+        if matched {
+            query.next();
+            score += 1;
+
+            if matched_previous {
+                score += 4;
+            }
+            if previous.is_none_or(|previous| !previous.is_alphanumeric()) {
+                score += 2;
+            }
+        }
+
+        matched_previous = matched;
+        previous = Some(c);
+    }
+
+    query.peek().is_none().then_some(score)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    use super::*;
+
+    /// A history with these contents, the last one being the newest, copied a second apart
+    fn history(contents: Vec<ClipboardContent>) -> ClipboardHistory {
+        let mut history = ClipboardHistory::new(10);
+        for content in contents {
+            history.push(Selection::Clipboard, content, None).unwrap();
+        }
+
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        for (age, entry) in history.entries.iter_mut().rev().enumerate() {
+            entry.provenance.copied_at = start + Duration::from_secs(age as u64);
+        }
+        history
+    }
+
+    fn text(text: &str) -> ClipboardContent {
+        ClipboardContent::Text(text.to_owned())
+    }
+
+    fn ids(results: Vec<&HistoryEntry>) -> Vec<u64> {
+        results.into_iter().map(|entry| entry.id).collect()
+    }
+
+    #[test]
+    fn fuzzy_letters_have_to_be_in_order() {
+        assert!(fuzzy_score("invoice", "invc").is_some());
+        assert!(fuzzy_score("invoice", "cvni").is_none());
+        assert!(fuzzy_score("inv", "invoice").is_none());
+        assert_eq!(fuzzy_score("anything", ""), Some(0));
+    }
+
+    #[test]
+    fn fuzzy_prefers_runs_and_word_starts() {
+        // The first letter starts a word, the next two follow it
+        assert_eq!(fuzzy_score("abc", "abc"), Some(13));
+        // Each letter starts a word, none follows another
+        assert_eq!(fuzzy_score("a_b_c", "abc"), Some(9));
+        assert_eq!(fuzzy_score("xaxbxc", "abc"), Some(3));
+
+        let run = fuzzy_score("the invoice", "inv").unwrap();
+        let scattered = fuzzy_score("in a video", "inv").unwrap();
+        assert!(run > scattered);
+    }
+
+    #[test]
+    fn plain_queries_match_text_case_insensitively() {
+        let history = history(vec![
+            text("Invoice #42"),
+            ClipboardContent::Html("<b>INVOICE</b> draft".to_owned()),
+            ClipboardContent::Files(vec![PathBuf::from("/home/me/invoices/march.pdf")]),
+            text("in voice"),
+            ClipboardContent::Other("application/json".to_owned(), b"invoice".to_vec()),
+        ]);
+
+        assert_eq!(ids(history.search("invoice").results()), [2, 1, 0]);
+        // Except for those without text, every entry matches an empty query
+        assert_eq!(ids(history.search("").results()), [4, 3, 2, 1, 0]);
+    }
+
+    #[test]
+    fn mime_patterns() {
+        let history = history(vec![
+            text("plain"),
+            ClipboardContent::Html("<p>html</p>".to_owned()),
+            ClipboardContent::Other("application/json".to_owned(), b"{}".to_vec()),
+            ClipboardContent::Files(vec![PathBuf::from("/tmp/file")]),
+        ]);
+
+        assert_eq!(ids(history.search("").mime("text/*").results()), [3, 1, 0]);
+        assert_eq!(ids(history.search("").mime("text/html").results()), [1]);
+        assert_eq!(
+            ids(history
+                .search("")
+                .mime("text/html")
+                .mime("*/json")
+                .results()),
+            [2, 1]
+        );
+        assert!(history.search("").mime("not a mime").results().is_empty());
+    }
+
+    #[test]
+    fn time_filters() {
+        let history = history(vec![text("0"), text("1"), text("2"), text("3")]);
+        let copied_at = |id| history.get(id).unwrap().provenance.copied_at;
+
+        assert_eq!(
+            ids(history.search("").since(copied_at(2)).results()),
+            [3, 2]
+        );
+        assert_eq!(
+            ids(history.search("").until(copied_at(2)).results()),
+            [1, 0]
+        );
+        assert_eq!(
+            ids(history
+                .search("")
+                .since(copied_at(1))
+                .until(copied_at(3))
+                .results()),
+            [2, 1]
+        );
+    }
+
+    #[test]
+    fn pinned_entries_come_first() {
+        let mut history = history(vec![text("note a"), text("note b"), text("note c")]);
+        history.set_pinned(0, true);
+
+        assert_eq!(ids(history.search("note").results()), [0, 2, 1]);
+        assert!(history.search("note").matches(history.get(0).unwrap()));
+    }
+
+    #[test]
+    fn fuzzy_results_come_best_first() {
+        let mut history = history(vec![
+            text("the invoice"),
+            text("in a video"),
+            text("invite"),
+            text("nothing"),
+        ]);
+
+        // Newer ones first among equal matches
+        assert_eq!(ids(history.search("inv").fuzzy(true).results()), [2, 0, 1]);
+
+        // Pinned ones still come before better matches
+        history.set_pinned(1, true);
+        assert_eq!(ids(history.search("inv").fuzzy(true).results()), [1, 2, 0]);
+    }
+}
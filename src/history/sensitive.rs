@@ -0,0 +1,327 @@
+//! Guessing which entries hold something sensitive, to mask them or let them
+//! [expire](SensitivityRules::expire_after).
+//!
+//! Password managers don't always say what they copy is a secret, and people copy card numbers
+//! from their notes. None of this is exact: it's meant to err on the side of masking.
+
+use std::mem;
+use std::time::Duration;
+
+use crate::convert::ClipboardContent;
+use crate::linux::x11::OwnerInfo;
+
+/// Password managers by `WM_CLASS`, lowercase
+const PASSWORD_MANAGERS: &[&str] = &[
+    "keepassxc",
+    "keepass2",
+    "keepass",
+    "bitwarden",
+    "1password",
+    "enpass",
+    "lastpass",
+    "proton pass",
+    "proton-pass",
+    "seahorse",
+    "gnome-keyring",
+    "kwalletmanager5",
+    "gopass",
+    "qtpass",
+];
+
+/// Which heuristics mark an entry sensitive, see [`ClipboardHistory::set_sensitivity_rules`].
+/// They're all on by default, and sensitive entries don't expire.
+///
+/// [`ClipboardHistory::set_sensitivity_rules`]: super::ClipboardHistory::set_sensitivity_rules
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SensitivityRules {
+    /// A single word mixing at least three of lowercase, uppercase, digits and symbols (other
+    /// than `_`, `-` and `.`)
+    pub passwords: bool,
+    /// Card numbers, with a valid Luhn checksum
+    pub payment_cards: bool,
+    /// IBANs, with valid check digits
+    pub ibans: bool,
+    /// Apps whose copies are all sensitive, by the class or instance part of their `WM_CLASS`
+    /// (case-insensitively). The default list has the usual password managers.
+    pub apps: Vec<String>,
+    /// How long sensitive entries are kept once copied, pinned ones aside. See
+    /// [`ClipboardHistory::expire_sensitive`](super::ClipboardHistory::expire_sensitive).
+    pub expire_after: Option<Duration>,
+}
+
+impl Default for SensitivityRules {
+    fn default() -> Self {
+        Self {
+            passwords: true,
+            payment_cards: true,
+            ibans: true,
+            apps: PASSWORD_MANAGERS
+                .iter()
+                .map(|app| app.to_string())
+                .collect(),
+            expire_after: None,
+        }
+    }
+}
+
+impl SensitivityRules {
+    /// Nothing is ever sensitive
+    pub fn none() -> Self {
+        Self {
+            passwords: false,
+            payment_cards: false,
+            ibans: false,
+            apps: Vec::new(),
+            expire_after: None,
+        }
+    }
+
+    pub fn is_sensitive(&self, content: &ClipboardContent, source: Option<&OwnerInfo>) -> bool {
+        let from_app = source.is_some_and(|source| {
+            [&source.class, &source.instance]
+                .into_iter()
+                .flatten()
+                .any(|name| self.apps.iter().any(|app| app.eq_ignore_ascii_case(name)))
+        });
+
+        let text = match content {
+            ClipboardContent::Text(text) | ClipboardContent::Html(text) => text.as_str(),
+            _ => return from_app,
+        };
+
+        from_app
+            || (self.passwords && looks_like_password(text))
+            || (self.payment_cards && has_payment_card(text))
+            || (self.ibans && has_iban(text))
+    }
+}
+
+/// One word of 8 to 64 characters, mixing enough kinds of them to not be a regular word,
+/// number or identifier
+fn looks_like_password(text: &str) -> bool {
+    let text = text.trim();
+    let len = text.chars().count();
+
+    if !(8..=64).contains(&len) || text.chars().any(char::is_whitespace) {
+        return false;
+    }
+
+    // Those mix kinds of characters too
+    if text.contains("://") || text.starts_with('/') || text.starts_with("~/") {
+        return false;
+    }
+
+    let kinds = [
+        text.chars().any(|c| c.is_lowercase()),
+        text.chars().any(|c| c.is_uppercase()),
+        text.chars().any(|c| c.is_ascii_digit()),
+        // Not counting what identifiers and file names have
+        text.chars()
+            .any(|c| !c.is_alphanumeric() && !['_', '-', '.'].contains(&c)),
+    ];
+
+    kinds.into_iter().filter(|&kind| kind).count() >= 3
+}
+
+/// Whether there are 13 to 19 digits in a row (maybe grouped by spaces or dashes) that pass the
+/// Luhn check, like card numbers do
+fn has_payment_card(text: &str) -> bool {
+    let mut digits = Vec::new();
+    let mut separated = false;
+
+    // Something else at the end, to check the last number
+    for c in text.chars().chain(['\0']) {
+        match c {
+            '0'..='9' => {
+                digits.push(c as u8 - b'0');
+                separated = false;
+            }
+            ' ' | '-' if !separated && !digits.is_empty() => separated = true,
+            _ => {
+                if (13..=19).contains(&digits.len()) && luhn(&digits) {
+                    return true;
+                }
+
+                digits.clear();
+                separated = false;
+            }
+        }
+    }
+
+    false
+}
+
+fn luhn(digits: &[u8]) -> bool {
+    let sum: u32 = digits
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, &digit)| match i % 2 {
+            0 => digit as u32,
+            _ => [0, 2, 4, 6, 8, 1, 3, 5, 7, 9][digit as usize],
+        })
+        .sum();
+
+    sum.is_multiple_of(10)
+}
+
+/// Whether there's an IBAN with valid check digits, either in one piece or in groups of four
+/// like it's usually written
+fn has_iban(text: &str) -> bool {
+    text.split(|c: char| !c.is_ascii_alphanumeric() && c != ' ')
+        .flat_map(|part| iban_candidates(part.split(' ').filter(|word| !word.is_empty())))
+        .any(|candidate| is_iban(&candidate))
+}
+
+/// Words that could be IBANs, and the groups of four following one that starts like an IBAN
+/// glued together
+fn iban_candidates<'a>(words: impl Iterator<Item = &'a str>) -> Vec<String> {
+    let mut candidates = Vec::new();
+    let mut group = String::new();
+
+    for word in words {
+        candidates.push(word.to_string());
+
+        if word.len() == 4 && starts_like_iban(word) {
+            candidates.push(mem::take(&mut group));
+            group.push_str(word);
+        } else if !group.is_empty() && word.len() <= 4 {
+            group.push_str(word);
+            // A shorter group is the last one
+            if word.len() < 4 {
+                candidates.push(mem::take(&mut group));
+            }
+        } else {
+            candidates.push(mem::take(&mut group));
+        }
+    }
+
+    candidates.push(group);
+    candidates.retain(|candidate| !candidate.is_empty());
+    candidates
+}
+
+/// A country code and check digits
+fn starts_like_iban(word: &str) -> bool {
+    let bytes = word.as_bytes();
+    bytes.len() >= 4
+        && bytes[..2].iter().all(u8::is_ascii_uppercase)
+        && bytes[2..4].iter().all(u8::is_ascii_digit)
+}
+
+/// A country code, check digits and 11 to 30 letters or digits, the whole of which gives 1
+/// modulo 97 once the first four characters are moved to the end
+fn is_iban(candidate: &str) -> bool {
+    let bytes = candidate.as_bytes();
+    if !(15..=34).contains(&bytes.len())
+        || !starts_like_iban(candidate)
+        || !bytes
+            .iter()
+            .all(|b| b.is_ascii_digit() || b.is_ascii_uppercase())
+    {
+        return false;
+    }
+
+    let remainder = bytes[4..]
+        .iter()
+        .chain(&bytes[..4])
+        .fold(0, |remainder: u32, &b| match b {
+            b'0'..=b'9' => (remainder * 10 + (b - b'0') as u32) % 97,
+            // Letters are two digits, A being 10
+            _ => (remainder * 100 + (b - b'A' + 10) as u32) % 97,
+        });
+
+    remainder == 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(text: &str) -> ClipboardContent {
+        ClipboardContent::Text(text.to_owned())
+    }
+
+    fn app(class: &str) -> OwnerInfo {
+        OwnerInfo {
+            window: 0,
+            instance: None,
+            class: Some(class.to_owned()),
+            name: None,
+            pid: None,
+            machine: None,
+        }
+    }
+
+    #[test]
+    fn passwords_mix_kinds_of_characters() {
+        assert!(looks_like_password("hunter2-Hunter"));
+        assert!(looks_like_password("  c0rrect#horse  "));
+        assert!(!looks_like_password("Sh0rt!"));
+        assert!(!looks_like_password("password"));
+        assert!(!looks_like_password("my_variable_name2"));
+        assert!(!looks_like_password("Two Words4!"));
+    }
+
+    #[test]
+    fn paths_and_urls_are_not_passwords() {
+        assert!(!looks_like_password("https://Example.com/a1"));
+        assert!(!looks_like_password("/usr/lib/Xorg-1.so"));
+        assert!(!looks_like_password("~/Documents/Tax2024.pdf"));
+        assert!(!looks_like_password("Cargo.lock"));
+    }
+
+    #[test]
+    fn card_numbers_pass_the_luhn_check() {
+        assert!(has_payment_card("4111111111111111"));
+        assert!(has_payment_card("card: 5500 0000 0000 0004, exp 12/30"));
+        assert!(has_payment_card("3782-822463-10005"));
+        // One digit off
+        assert!(!has_payment_card("4111111111111112"));
+        assert!(!has_payment_card("5500 0000 0000 0005"));
+    }
+
+    #[test]
+    fn other_numbers_are_not_cards() {
+        // Too short, too long, and separators that don't group digits
+        assert!(!has_payment_card("4242424242"));
+        assert!(!has_payment_card("41111111111111110000"));
+        assert!(!has_payment_card("4111  1111 1111 1111"));
+        assert!(!has_payment_card("call 555-0100 or 555-0199"));
+    }
+
+    #[test]
+    fn ibans_have_valid_check_digits() {
+        assert!(has_iban("GB82WEST12345698765432"));
+        assert!(has_iban("IBAN: GB82 WEST 1234 5698 7654 32."));
+        assert!(has_iban("DE89 3704 0044 0532 0130 00"));
+        assert!(!has_iban("GB83WEST12345698765432"));
+        assert!(!has_iban("GB82 WEST 1234 5698 7654 33"));
+    }
+
+    #[test]
+    fn other_codes_are_not_ibans() {
+        assert!(!has_iban("AB12"));
+        assert!(!has_iban(
+            "see /srv/GB82WEST/backup and https://example.com/DE89"
+        ));
+        assert!(!has_iban("gb82west12345698765432"));
+    }
+
+    #[test]
+    fn rules_can_be_turned_off() {
+        let rules = SensitivityRules::default();
+        assert!(rules.is_sensitive(&text("4111111111111111"), None));
+        assert!(rules.is_sensitive(&text("anything"), Some(&app("KeePassXC"))));
+        assert!(!rules.is_sensitive(&text("anything"), Some(&app("firefox"))));
+
+        let rules = SensitivityRules {
+            payment_cards: false,
+            ..SensitivityRules::default()
+        };
+        assert!(!rules.is_sensitive(&text("4111111111111111"), None));
+
+        let rules = SensitivityRules::none();
+        assert!(!rules.is_sensitive(&text("hunter2-Hunter"), Some(&app("keepassxc"))));
+    }
+}
@@ -0,0 +1,743 @@
+//! Keeping a history on disk, as an append-only log of what happened to it.
+//!
+//! The log is a header followed by records, each one framed by its length and a checksum.
+//! Loading replays the records up to the first one that doesn't check out (a write the
+//! process died in the middle of, usually) and cuts the rest off. Once the log gets much longer
+//! than the history, it's compacted by writing the entries as they are to a new log, which
+//! replaces the old one.
+//!
+//! Logs (and the directory they're made in) are only readable by their user, since copies
+//! are often things like messages or addresses.
+
+use std::ffi::OsStr;
+use std::fs::{self, DirBuilder, File, OpenOptions, Permissions};
+use std::io::{self, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, UNIX_EPOCH};
+
+use super::{HistoryEntry, Provenance};
+use crate::convert::{ClipboardContent, ImageData};
+use crate::linux::x11::OwnerInfo;
+use crate::selection::Selection;
+
+/// What every log starts with, the last byte being the format version
+const MAGIC: &[u8] = b"clipbox-history\x01";
+
+/// Anything bigger is a broken length rather than a huge copy
+const MAX_RECORD_SIZE: usize = 1 << 30;
+
+/// How many records past the size of the history the log can have before it's compacted
+const COMPACTION_SLACK: usize = 64;
+
+/// The names of the custom selections loaded so far, leaked once each
+static CUSTOM_SELECTIONS: Mutex<Vec<&'static str>> = Mutex::new(Vec::new());
+
+/// Permissions of the logs, and of the directories made for them
+const FILE_MODE: u32 = 0o600;
+const DIR_MODE: u32 = 0o700;
+
+/// What happened to a history, as read back from its log
+pub(super) enum Record {
+    /// An entry was added, or changed and moved to the front.
+    Put(Box<HistoryEntry>),
+    Remove(u64),
+    Clear,
+    /// The ids below this one were used already, so that compacting doesn't forget it
+    NextId(u64),
+    Pin(u64, bool),
+    /// Only in logs from before sensitive entries were kept out of them
+    Sensitive(u64, bool),
+}
+
+/// The log of a history
+#[derive(Debug)]
+pub(super) struct HistoryStore {
+    path: PathBuf,
+    file: File,
+    /// How many records are in the log
+    records: usize,
+    /// A write failed, so the log may be missing records or end with half of one
+    dirty: bool,
+}
+
+/// Where histories are kept by default: `$XDG_DATA_HOME/clipbox/history`, or
+/// `~/.local/share/clipbox/history` without it
+pub fn default_path() -> Option<PathBuf> {
+    let data_home = std::env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .or_else(|| {
+            let home = std::env::var_os("HOME")?;
+            Some(Path::new(&home).join(".local").join("share"))
+        })?;
+
+    Some(data_home.join("clipbox").join("history"))
+}
+
+impl HistoryStore {
+    /// Opens the log (creating it if needed), giving what it remembers.
+    ///
+    /// A file that isn't a history log is put aside as `<path>.corrupt` instead of being
+    /// overwritten, and a new log takes its place.
+    pub(super) fn open(path: PathBuf) -> io::Result<(Self, Vec<Record>)> {
+        if let Some(parent) = path.parent() {
+            DirBuilder::new()
+                .recursive(true)
+                .mode(DIR_MODE)
+                .create(parent)?;
+        }
+
+        let mut bytes = match fs::read(&path) {
+            Ok(bytes) => bytes,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(err) => return Err(err),
+        };
+
+        if !bytes.is_empty() && !bytes.starts_with(MAGIC) {
+            fs::rename(&path, with_suffix(&path, ".corrupt"))?;
+            bytes.clear();
+        }
+
+        let mut records = Vec::new();
+        let mut frames = 0;
+        let mut end = MAGIC.len().min(bytes.len());
+        while let Some((payload, next)) = next_frame(&bytes, end) {
+            // Records we don't know are from a newer version, they're skipped but kept
+            if let Some(record) = decode_record(payload) {
+                records.push(record);
+            }
+
+            frames += 1;
+            end = next;
+        }
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .mode(FILE_MODE)
+            .open(&path)?;
+        // Logs written before they were made private
+        if file.metadata()?.permissions().mode() & 0o077 != 0 {
+            file.set_permissions(Permissions::from_mode(FILE_MODE))?;
+        }
+
+        if bytes.is_empty() {
+            file.write_all(MAGIC)?;
+        } else if end < bytes.len() {
+            // Whatever comes after the first broken record
+            file.set_len(end as u64)?;
+        }
+
+        let store = Self {
+            path,
+            file,
+            records: frames,
+            dirty: false,
+        };
+        Ok((store, records))
+    }
+
+    pub(super) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Whether the log should be rewritten rather than appended to
+    pub(super) fn needs_compaction(&self, capacity: usize) -> bool {
+        self.dirty || self.records > capacity.saturating_mul(2) + COMPACTION_SLACK
+    }
+
+    pub(super) fn append(&mut self, payload: &[u8]) -> io::Result<()> {
+        let result = self.file.write_all(&frame(payload));
+        match result {
+            Ok(()) => self.records += 1,
+            Err(_) => self.dirty = true,
+        }
+
+        result
+    }
+
+    /// Replaces the log with one that only has the entries, oldest first
+    pub(super) fn rewrite<'a>(
+        &mut self,
+        next_id: u64,
+        entries: impl Iterator<Item = &'a HistoryEntry>,
+    ) -> io::Result<()> {
+        let result = self.try_rewrite(next_id, entries);
+        self.dirty = result.is_err();
+        result
+    }
+
+    fn try_rewrite<'a>(
+        &mut self,
+        next_id: u64,
+        entries: impl Iterator<Item = &'a HistoryEntry>,
+    ) -> io::Result<()> {
+        let temp_path = with_suffix(&self.path, ".tmp");
+        // One left behind by a crash could have other permissions, which opening it keeps
+        let _ = fs::remove_file(&temp_path);
+        let temp = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(FILE_MODE)
+            .open(&temp_path)?;
+        let mut temp = io::BufWriter::new(temp);
+
+        temp.write_all(MAGIC)?;
+        temp.write_all(&frame(&encode_next_id(next_id)))?;
+
+        let mut records = 1;
+        for entry in entries {
+            temp.write_all(&frame(&encode_put(entry)))?;
+            records += 1;
+        }
+
+        // The new log has to be all there before it replaces the old one
+        let temp = temp.into_inner().map_err(io::IntoInnerError::into_error)?;
+        temp.sync_all()?;
+        drop(temp);
+        fs::rename(&temp_path, &self.path)?;
+
+        self.file = OpenOptions::new().append(true).open(&self.path)?;
+        self.records = records;
+        Ok(())
+    }
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+/// Frames a record as its length, its checksum and itself
+fn frame(payload: &[u8]) -> Vec<u8> {
+    let mut framed = Vec::with_capacity(8 + payload.len());
+    framed.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    framed.extend_from_slice(&checksum(payload).to_le_bytes());
+    framed.extend_from_slice(payload);
+    framed
+}
+
+/// The record framed at `start`, and where the next one starts, if it checks out
+fn next_frame(bytes: &[u8], start: usize) -> Option<(&[u8], usize)> {
+    let mut reader = Reader::new(bytes.get(start..)?);
+    let len = reader.u32()? as usize;
+    let expected = reader.u32()?;
+
+    if len > MAX_RECORD_SIZE {
+        return None;
+    }
+
+    let payload = reader.take(len)?;
+    (checksum(payload) == expected).then_some((payload, start + 8 + len))
+}
+
+/// FNV-1a, which is plenty to notice torn writes and flipped bits
+fn checksum(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c9dc5, |hash, &byte| {
+        (hash ^ byte as u32).wrapping_mul(0x01000193)
+    })
+}
+
+// Encoding records
+
+mod tag {
+    pub const PUT: u8 = 0;
+    pub const REMOVE: u8 = 1;
+    pub const CLEAR: u8 = 2;
+    pub const NEXT_ID: u8 = 3;
+    pub const PIN: u8 = 4;
+    pub const SENSITIVE: u8 = 5;
+
+    pub const TEXT: u8 = 0;
+    pub const HTML: u8 = 1;
+    pub const IMAGE: u8 = 2;
+    pub const FILES: u8 = 3;
+    pub const OTHER: u8 = 4;
+}
+
+pub(super) fn encode_put(entry: &HistoryEntry) -> Vec<u8> {
+    let mut writer = Writer(vec![tag::PUT]);
+    writer.u64(entry.id);
+    writer.str(entry.selection.name());
+
+    let provenance = &entry.provenance;
+    let copied_at = provenance
+        .copied_at
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    writer.u64(copied_at.as_secs());
+    writer.u32(copied_at.subsec_nanos());
+
+    match &provenance.app {
+        Some(source) => {
+            // Windows don't outlive the session, so they're not kept
+            writer.u8(1);
+            writer.opt_str(source.instance.as_deref());
+            writer.opt_str(source.class.as_deref());
+            writer.opt_str(source.name.as_deref());
+            match source.pid {
+                Some(pid) => {
+                    writer.u8(1);
+                    writer.u32(pid);
+                }
+                None => writer.u8(0),
+            }
+        }
+        None => writer.u8(0),
+    }
+
+    match &entry.content {
+        ClipboardContent::Text(text) => {
+            writer.u8(tag::TEXT);
+            writer.str(text);
+        }
+        ClipboardContent::Html(html) => {
+            writer.u8(tag::HTML);
+            writer.str(html);
+        }
+        ClipboardContent::Image(image) => {
+            writer.u8(tag::IMAGE);
+            writer.u32(image.width);
+            writer.u32(image.height);
+            writer.bytes(&image.rgba);
+        }
+        ClipboardContent::Files(paths) => {
+            writer.u8(tag::FILES);
+            writer.u32(paths.len() as u32);
+            for path in paths {
+                writer.bytes(path.as_os_str().as_bytes());
+            }
+        }
+        ClipboardContent::Other(target, data) => {
+            writer.u8(tag::OTHER);
+            writer.str(target);
+            writer.bytes(data);
+        }
+    }
+
+    writer.u8(entry.pinned as u8);
+    writer.u8(provenance.sensitive as u8);
+
+    // Always there, so that whatever comes after can be found without the `image` feature
+    #[cfg(feature = "image")]
+    let thumbnail = entry.thumbnail.as_ref();
+    #[cfg(not(feature = "image"))]
+    let thumbnail = None::<&ImageData>;
+    match thumbnail {
+        Some(thumbnail) => {
+            writer.u8(1);
+            writer.u32(thumbnail.width);
+            writer.u32(thumbnail.height);
+            writer.bytes(&thumbnail.rgba);
+        }
+        None => writer.u8(0),
+    }
+
+    writer.opt_str(provenance.host.as_deref());
+    writer.opt_str(
+        provenance
+            .app
+            .as_ref()
+            .and_then(|source| source.machine.as_deref()),
+    );
+
+    writer.0
+}
+
+pub(super) fn encode_remove(id: u64) -> Vec<u8> {
+    let mut writer = Writer(vec![tag::REMOVE]);
+    writer.u64(id);
+    writer.0
+}
+
+pub(super) fn encode_clear() -> Vec<u8> {
+    vec![tag::CLEAR]
+}
+
+pub(super) fn encode_pin(id: u64, pinned: bool) -> Vec<u8> {
+    let mut writer = Writer(vec![tag::PIN]);
+    writer.u64(id);
+    writer.u8(pinned as u8);
+    writer.0
+}
+
+fn encode_next_id(next_id: u64) -> Vec<u8> {
+    let mut writer = Writer(vec![tag::NEXT_ID]);
+    writer.u64(next_id);
+    writer.0
+}
+
+fn decode_record(payload: &[u8]) -> Option<Record> {
+    let mut reader = Reader::new(payload);
+
+    match reader.u8()? {
+        tag::PUT => decode_entry(&mut reader).map(|entry| Record::Put(Box::new(entry))),
+        tag::REMOVE => reader.u64().map(Record::Remove),
+        tag::CLEAR => Some(Record::Clear),
+        tag::NEXT_ID => reader.u64().map(Record::NextId),
+        tag::PIN => Some(Record::Pin(reader.u64()?, reader.u8()? != 0)),
+        tag::SENSITIVE => Some(Record::Sensitive(reader.u64()?, reader.u8()? != 0)),
+        _ => None,
+    }
+}
+
+fn decode_entry(reader: &mut Reader) -> Option<HistoryEntry> {
+    let id = reader.u64()?;
+    let selection = selection_named(reader.str()?);
+
+    let secs = reader.u64()?;
+    let nanos = reader.u32()?;
+    let copied_at = UNIX_EPOCH.checked_add(Duration::new(secs, nanos))?;
+
+    let mut source = match reader.u8()? {
+        0 => None,
+        _ => Some(OwnerInfo {
+            window: 0,
+            instance: reader.opt_str()?,
+            class: reader.opt_str()?,
+            name: reader.opt_str()?,
+            pid: match reader.u8()? {
+                0 => None,
+                _ => Some(reader.u32()?),
+            },
+            machine: None,
+        }),
+    };
+
+    let content = match reader.u8()? {
+        tag::TEXT => ClipboardContent::Text(reader.str()?),
+        tag::HTML => ClipboardContent::Html(reader.str()?),
+        tag::IMAGE => ClipboardContent::Image(ImageData {
+            width: reader.u32()?,
+            height: reader.u32()?,
+            rgba: reader.bytes()?.to_vec(),
+        }),
+        tag::FILES => {
+            let count = reader.u32()?;
+            let paths = (0..count)
+                .map(|_| Some(PathBuf::from(OsStr::from_bytes(reader.bytes()?))))
+                .collect::<Option<Vec<_>>>()?;
+            ClipboardContent::Files(paths)
+        }
+        tag::OTHER => ClipboardContent::Other(reader.str()?, reader.bytes()?.to_vec()),
+        _ => return None,
+    };
+
+    // Only there since pinning and sensitivity were added
+    let pinned = reader.u8().is_some_and(|pinned| pinned != 0);
+    let sensitive = reader.u8().is_some_and(|sensitive| sensitive != 0);
+
+    // Only there since thumbnails were added, they're made again for older entries
+    let thumbnail = match reader.u8() {
+        Some(0) | None => None,
+        Some(_) => Some(ImageData {
+            width: reader.u32()?,
+            height: reader.u32()?,
+            rgba: reader.bytes()?.to_vec(),
+        }),
+    };
+    #[cfg(feature = "image")]
+    let thumbnail = thumbnail.or_else(|| {
+        super::thumbnails::thumbnail(&content, super::thumbnails::DEFAULT_THUMBNAIL_SIZE)
+    });
+    #[cfg(not(feature = "image"))]
+    let _ = thumbnail;
+
+    // Only there since provenance was added
+    let host = reader.opt_str().flatten();
+    let machine = reader.opt_str().flatten();
+    if let Some(source) = &mut source {
+        source.machine = machine;
+    }
+
+    Some(HistoryEntry {
+        id,
+        selection,
+        content,
+        provenance: Provenance {
+            host,
+            app: source,
+            copied_at,
+            sensitive,
+        },
+        pinned,
+        #[cfg(feature = "image")]
+        thumbnail,
+    })
+}
+
+fn selection_named(name: String) -> Selection {
+    match name.as_str() {
+        "CLIPBOARD" => Selection::Clipboard,
+        "PRIMARY" => Selection::Primary,
+        "SECONDARY" => Selection::Secondary,
+        _ => Selection::Custom(intern(name)),
+    }
+}
+
+/// A `'static` copy of a custom selection name. Those are rare enough to leak, but every entry
+/// copied on one would leak its own without this.
+fn intern(name: String) -> &'static str {
+    let mut names = CUSTOM_SELECTIONS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+    match names.iter().find(|&&known| known == name) {
+        Some(known) => known,
+        None => {
+            let name: &'static str = Box::leak(name.into_boxed_str());
+            names.push(name);
+            name
+        }
+    }
+}
+
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn u8(&mut self, value: u8) {
+        self.0.push(value);
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.u32(bytes.len() as u32);
+        self.0.extend_from_slice(bytes);
+    }
+
+    fn str(&mut self, string: &str) {
+        self.bytes(string.as_bytes());
+    }
+
+    fn opt_str(&mut self, string: Option<&str>) {
+        match string {
+            Some(string) => {
+                self.u8(1);
+                self.str(string);
+            }
+            None => self.u8(0),
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if len > self.bytes.len() {
+            return None;
+        }
+
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Some(taken)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.take(4)?.try_into().ok()?))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.take(8)?.try_into().ok()?))
+    }
+
+    fn bytes(&mut self) -> Option<&'a [u8]> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+
+    fn str(&mut self) -> Option<String> {
+        String::from_utf8(self.bytes()?.to_vec()).ok()
+    }
+
+    fn opt_str(&mut self) -> Option<Option<String>> {
+        match self.u8()? {
+            0 => Some(None),
+            _ => self.str().map(Some),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    /// A directory of its own for each test, removed when it's done
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new() -> Self {
+            static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+            let name = format!(
+                "clipbox-store-{}-{}",
+                std::process::id(),
+                COUNT.fetch_add(1, Ordering::Relaxed)
+            );
+            let dir = std::env::temp_dir().join(name);
+            let _ = fs::remove_dir_all(&dir);
+            Self(dir)
+        }
+
+        fn log(&self) -> PathBuf {
+            self.0.join("clipbox").join("history")
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn entry(id: u64, text: &str) -> HistoryEntry {
+        HistoryEntry {
+            id,
+            selection: Selection::Clipboard,
+            content: ClipboardContent::Text(text.to_owned()),
+            provenance: Provenance::new(None),
+            pinned: false,
+            #[cfg(feature = "image")]
+            thumbnail: None,
+        }
+    }
+
+    fn text_of(record: &Record) -> Option<(u64, &str)> {
+        match record {
+            Record::Put(entry) => match &entry.content {
+                ClipboardContent::Text(text) => Some((entry.id, text)),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn records_round_trip() {
+        let dir = TempDir::new();
+        let (mut store, records) = HistoryStore::open(dir.log()).unwrap();
+        assert!(records.is_empty());
+
+        let mut first = entry(0, "first");
+        first.selection = Selection::Custom("MY_SELECTION");
+        store.append(&encode_put(&first)).unwrap();
+        store.append(&encode_put(&entry(1, "second"))).unwrap();
+        store.append(&encode_pin(1, true)).unwrap();
+        store.append(&encode_remove(0)).unwrap();
+        store.append(&encode_clear()).unwrap();
+        drop(store);
+
+        let (_, records) = HistoryStore::open(dir.log()).unwrap();
+        assert_eq!(records.len(), 5);
+        match &records[0] {
+            Record::Put(entry) => assert_eq!(**entry, first),
+            _ => panic!("expected the first entry"),
+        }
+        assert_eq!(text_of(&records[1]), Some((1, "second")));
+        assert!(matches!(records[2], Record::Pin(1, true)));
+        assert!(matches!(records[3], Record::Remove(0)));
+        assert!(matches!(records[4], Record::Clear));
+    }
+
+    #[test]
+    fn torn_last_record_is_cut_off() {
+        let dir = TempDir::new();
+        let (mut store, _) = HistoryStore::open(dir.log()).unwrap();
+        store.append(&encode_put(&entry(0, "kept"))).unwrap();
+        let intact = fs::metadata(dir.log()).unwrap().len();
+
+        // A crash halfway through writing the next one
+        let torn = frame(&encode_put(&entry(1, "torn")));
+        store.file.write_all(&torn[..torn.len() / 2]).unwrap();
+        drop(store);
+
+        let (mut store, records) = HistoryStore::open(dir.log()).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(text_of(&records[0]), Some((0, "kept")));
+        assert_eq!(fs::metadata(dir.log()).unwrap().len(), intact);
+
+        // And what comes after lands right after the intact one
+        store.append(&encode_remove(0)).unwrap();
+        drop(store);
+        let (_, records) = HistoryStore::open(dir.log()).unwrap();
+        assert!(matches!(records[..], [Record::Put(_), Record::Remove(0)]));
+    }
+
+    #[test]
+    fn foreign_file_is_put_aside() {
+        let dir = TempDir::new();
+        fs::create_dir_all(dir.log().parent().unwrap()).unwrap();
+        fs::write(dir.log(), "not a history").unwrap();
+
+        let (_, records) = HistoryStore::open(dir.log()).unwrap();
+        assert!(records.is_empty());
+        assert_eq!(
+            fs::read(with_suffix(&dir.log(), ".corrupt")).unwrap(),
+            b"not a history"
+        );
+        assert_eq!(fs::read(dir.log()).unwrap(), MAGIC);
+    }
+
+    #[test]
+    fn compaction_keeps_next_id() {
+        let dir = TempDir::new();
+        let (mut store, _) = HistoryStore::open(dir.log()).unwrap();
+        for id in 0..8 {
+            store.append(&encode_put(&entry(id, "old"))).unwrap();
+        }
+
+        store.rewrite(8, [entry(7, "kept")].iter()).unwrap();
+        assert!(!with_suffix(&dir.log(), ".tmp").exists());
+        drop(store);
+
+        let (_, records) = HistoryStore::open(dir.log()).unwrap();
+        assert_eq!(records.len(), 2);
+        assert!(matches!(records[0], Record::NextId(8)));
+        assert_eq!(text_of(&records[1]), Some((7, "kept")));
+    }
+
+    #[test]
+    fn logs_are_private() {
+        let dir = TempDir::new();
+        let (mut store, _) = HistoryStore::open(dir.log()).unwrap();
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+
+        assert_eq!(mode(&dir.log()), FILE_MODE);
+        assert_eq!(mode(dir.log().parent().unwrap()), DIR_MODE);
+
+        store.rewrite(0, [].iter()).unwrap();
+        assert_eq!(mode(&dir.log()), FILE_MODE);
+    }
+
+    #[test]
+    fn custom_selections_are_leaked_once() {
+        let first = selection_named("MY_SELECTION".to_owned());
+        let second = selection_named("MY_SELECTION".to_owned());
+        assert!(std::ptr::eq(first.name(), second.name()));
+        assert_eq!(selection_named("PRIMARY".to_owned()), Selection::Primary);
+    }
+}
@@ -0,0 +1,36 @@
+//! Small versions of the images in a history, so that pickers can show them without going
+//! through the whole image.
+
+use crate::convert::{ClipboardContent, ImageData};
+
+/// How many pixels thumbnails have on their longest side by default
+pub const DEFAULT_THUMBNAIL_SIZE: u32 = 128;
+
+/// The thumbnail of an image, fitting in `size` x `size` pixels and keeping its aspect ratio.
+/// Images that fit already are their own thumbnail, and content that isn't an image (or whose
+/// buffer doesn't match its size) has none.
+pub(super) fn thumbnail(content: &ClipboardContent, size: u32) -> Option<ImageData> {
+    let ClipboardContent::Image(image) = content else {
+        return None;
+    };
+
+    let size = size.max(1);
+    if image.width <= size && image.height <= size {
+        return Some(image.clone());
+    }
+
+    let rgba = image::RgbaImage::from_raw(image.width, image.height, image.rgba.clone())?;
+
+    // Rounded, but never down to nothing
+    let (width, height) = (image.width as u64, image.height as u64);
+    let longest = width.max(height);
+    let new_width = ((width * size as u64 + longest / 2) / longest).max(1) as u32;
+    let new_height = ((height * size as u64 + longest / 2) / longest).max(1) as u32;
+
+    let thumbnail = image::imageops::thumbnail(&rgba, new_width, new_height);
+    Some(ImageData {
+        width: thumbnail.width(),
+        height: thumbnail.height(),
+        rgba: thumbnail.into_raw(),
+    })
+}
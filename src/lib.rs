@@ -1 +1,59 @@
+#[cfg(all(target_os = "android", feature = "android"))]
+pub mod android;
+#[cfg(any(
+    target_os = "linux",
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "openbsd",
+    target_os = "netbsd"
+))]
+mod clipboard;
+#[cfg(any(
+    target_os = "linux",
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "openbsd",
+    target_os = "netbsd"
+))]
+pub mod history;
+#[cfg(any(
+    target_os = "linux",
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "openbsd",
+    target_os = "netbsd"
+))]
 pub mod linux;
+// Only the X11 backend drives the protocol state machines for now
+#[cfg(any(
+    target_os = "linux",
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "openbsd",
+    target_os = "netbsd"
+))]
+mod protocol;
+#[cfg(target_arch = "wasm32")]
+pub mod web;
+
+pub mod cancel;
+pub mod convert;
+pub mod error;
+pub mod event;
+pub mod format;
+pub mod selection;
+pub mod transform;
+
+pub use cancel::CancellationToken;
+#[cfg(any(
+    target_os = "linux",
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "openbsd",
+    target_os = "netbsd"
+))]
+pub use clipboard::Clipboard;
+pub use error::ClipboxError;
+pub use selection::Selection;
+#[cfg(target_arch = "wasm32")]
+pub use web::WebClipboard;
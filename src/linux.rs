@@ -1 +1,62 @@
+use std::env;
+
+#[cfg(feature = "dbus")]
+pub mod dbus;
+pub mod doctor;
+pub mod session;
 pub mod x11;
+
+/// Environment variable used to force a specific backend (only `x11` for now)
+pub const BACKEND_ENV_VAR: &str = "CLIPBOX_BACKEND";
+
+/// Clipboard backends available on Linux (and the BSDs)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Backend {
+    X11,
+}
+
+impl Backend {
+    /// Picks the backend to use for the current session.
+    ///
+    /// Clipbox only has an X11 backend for now. Under XWayland, it only sees what XWayland
+    /// mediates between X11 and Wayland clients, see [`x11::X11Clipboard::is_xwayland`].
+    ///
+    /// This can be overridden with the `CLIPBOX_BACKEND` environment variable.
+    pub fn detect() -> Self {
+        Self::from_env().unwrap_or(Self::X11)
+    }
+
+    /// Checks at runtime whether this backend can be used on this machine.
+    pub fn availability(self) -> Availability {
+        match self {
+            Self::X11 => x11::probe(),
+        }
+    }
+
+    /// Reads the backend forced through `CLIPBOX_BACKEND`, if any.
+    pub fn from_env() -> Option<Self> {
+        let backend = env::var(BACKEND_ENV_VAR).ok()?;
+
+        match backend.to_ascii_lowercase().as_str() {
+            "x11" => Some(Self::X11),
+            _ => None,
+        }
+    }
+}
+
+/// Whether a backend can be used, as found out by [`Backend::availability`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Availability {
+    Available,
+    /// The system library couldn't be loaded (the error message is included)
+    LibraryMissing(String),
+    /// The library is there, but we can't connect to a display server
+    NoDisplay,
+}
+
+impl Availability {
+    pub fn is_available(&self) -> bool {
+        *self == Self::Available
+    }
+}
@@ -0,0 +1,237 @@
+//! The clipboard as a D-Bus service, for scripts and desktop components that aren't written in
+//! Rust.
+//!
+//! The service goes by [`SERVICE_NAME`] on the session bus, with a single object at
+//! [`OBJECT_PATH`] implementing the `org.clipbox.Clipboard1` interface:
+//!
+//! - `Get(s selection, s target) -> ay`: the selection's data as `target`
+//! - `Set(s selection, a(say) offers)`: copies each `(target, data)` on the selection, in that
+//!   order of preference
+//! - `ListTargets(s selection) -> as`: what the selection can be pasted as
+//! - `Changed(s selection, as targets)`: a signal for each new owner of `CLIPBOARD` or
+//!   `PRIMARY` (no targets once it's cleared)
+//!
+//! Selections go by their name, like `CLIPBOARD` or `PRIMARY` (an empty name is `CLIPBOARD`).
+//! Everything happens on a [`ClipboardThread`], which serves what's copied through `Set` for
+//! as long as it runs:
+//!
+//! ```sh
+//! busctl --user call org.clipbox.Clipboard1 /org/clipbox/Clipboard1 \
+//!     org.clipbox.Clipboard1 ListTargets s CLIPBOARD
+//! ```
+
+use std::ffi::CString;
+use std::sync::mpsc::RecvTimeoutError;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use zbus::blocking::{connection, Connection};
+use zbus::fdo;
+use zbus::interface;
+use zbus::object_server::SignalEmitter;
+
+use super::x11::{ClipboardHandle, ClipboardThread, TargetBundle, TargetOrder};
+use crate::cancel::CancellationToken;
+use crate::error::ClipboxError;
+use crate::selection::Selection;
+
+/// The well-known name of the service on the session bus
+pub const SERVICE_NAME: &str = "org.clipbox.Clipboard1";
+
+/// Where the clipboard object lives
+pub const OBJECT_PATH: &str = "/org/clipbox/Clipboard1";
+
+/// The selections `Changed` is emitted for
+const WATCHED: &[Selection] = &[Selection::Clipboard, Selection::Primary];
+
+/// How many changes wait for the signal thread, when the bus is slower than the clipboard
+const MAX_PENDING_CHANGES: usize = 64;
+
+/// How often the signal thread checks whether it should stop
+const STOP_CHECK_INTERVAL: Duration = Duration::from_millis(50);
+
+/// The `org.clipbox.Clipboard1` interface, see the [module docs](self). Serve it on a
+/// connection of your own with [`ClipboardService::new`], or let [`DbusService`] take care of
+/// everything.
+pub struct ClipboardService {
+    handle: ClipboardHandle,
+}
+
+/// The clipboard served on the session bus, with `Changed` signals. Dropping it gives up the
+/// name and stops emitting signals.
+pub struct DbusService {
+    connection: Connection,
+    /// Keeps the event thread alive, if we started it
+    thread: Option<ClipboardThread>,
+    stop: CancellationToken,
+    signals: Option<JoinHandle<()>>,
+}
+
+impl ClipboardService {
+    pub fn new(handle: ClipboardHandle) -> Self {
+        Self { handle }
+    }
+}
+
+#[interface(name = "org.clipbox.Clipboard1")]
+impl ClipboardService {
+    #[zbus(name = "Get")]
+    fn get_data(&self, selection: &str, target: &str) -> fdo::Result<Vec<u8>> {
+        let selection = parse_selection(selection)?;
+        let target = parse_target(target)?;
+
+        self.handle.get(selection, &target).map_err(failed)
+    }
+
+    #[zbus(name = "Set")]
+    fn set_data(&self, selection: &str, offers: Vec<(String, Vec<u8>)>) -> fdo::Result<()> {
+        let selection = parse_selection(selection)?;
+
+        let mut bundle = TargetBundle::new().order(TargetOrder::AsAdded);
+        for (target, data) in offers {
+            bundle = bundle.with(&parse_target(&target)?, data);
+        }
+
+        if bundle.is_empty() {
+            return Err(fdo::Error::InvalidArgs("nothing to copy".to_string()));
+        }
+
+        self.handle.set(&[selection], bundle).map_err(failed)
+    }
+
+    fn list_targets(&self, selection: &str) -> fdo::Result<Vec<String>> {
+        let selection = parse_selection(selection)?;
+        let targets = self.handle.targets(selection).map_err(failed)?;
+
+        Ok(targets
+            .into_iter()
+            .map(|target| target.to_string_lossy().into_owned())
+            .collect())
+    }
+
+    #[zbus(signal)]
+    async fn changed(
+        emitter: &SignalEmitter<'_>,
+        selection: &str,
+        targets: Vec<String>,
+    ) -> zbus::Result<()>;
+}
+
+impl DbusService {
+    /// Starts a clipboard thread and serves it on the session bus
+    pub fn start() -> Result<Self, ClipboxError> {
+        let thread = ClipboardThread::spawn()?;
+        let mut service = Self::with_handle(thread.handle())?;
+        service.thread = Some(thread);
+        Ok(service)
+    }
+
+    /// Serves a clipboard thread that's already running on the session bus. Fails with
+    /// [`ClipboxError::Init`] if we can't get on the bus or someone else has the name.
+    pub fn with_handle(handle: ClipboardHandle) -> Result<Self, ClipboxError> {
+        let connection = connection::Builder::session()
+            .and_then(|builder| builder.name(SERVICE_NAME))
+            .and_then(|builder| {
+                builder.serve_at(OBJECT_PATH, ClipboardService::new(handle.clone()))
+            })
+            .and_then(|builder| builder.build())
+            .map_err(|err| ClipboxError::Init(format!("Couldn't serve on D-Bus: {}", err)))?;
+
+        let stop = CancellationToken::new();
+
+        // Without XFixes there's just no signal
+        let signals = match handle.watch_bounded(WATCHED, MAX_PENDING_CHANGES)? {
+            Some(changes) => {
+                let (connection, stop) = (connection.clone(), stop.clone());
+                let thread = thread::Builder::new()
+                    .name("clipbox-dbus".to_string())
+                    .spawn(move || {
+                        while !stop.is_cancelled() {
+                            let change = match changes.recv_timeout(STOP_CHECK_INTERVAL) {
+                                Ok(change) => change,
+                                Err(RecvTimeoutError::Timeout) => continue,
+                                Err(RecvTimeoutError::Disconnected) => return,
+                            };
+
+                            // The event thread already asked other owners. Ours come without
+                            // targets, it lists what it serves for them.
+                            let targets = match (change.owner, change.targets) {
+                                (None, _) => Vec::new(),
+                                (Some(_), Some(targets)) if !targets.is_empty() => targets,
+                                (Some(_), _) => {
+                                    handle.targets(change.selection).unwrap_or_default()
+                                }
+                            };
+
+                            let targets = targets
+                                .into_iter()
+                                .map(|target| target.to_string_lossy().into_owned())
+                                .collect();
+
+                            let _ = emit_changed(&connection, change.selection, targets);
+                        }
+                    })
+                    .map_err(ClipboxError::Io)?;
+
+                Some(thread)
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            connection,
+            thread: None,
+            stop,
+            signals,
+        })
+    }
+
+    /// The connection the service is on, to serve more objects on it
+    pub fn connection(&self) -> &Connection {
+        &self.connection
+    }
+}
+
+impl Drop for DbusService {
+    fn drop(&mut self) {
+        self.stop.cancel();
+        if let Some(signals) = self.signals.take() {
+            let _ = signals.join();
+        }
+    }
+}
+
+fn emit_changed(
+    connection: &Connection,
+    selection: Selection,
+    targets: Vec<String>,
+) -> zbus::Result<()> {
+    let emitter = SignalEmitter::new(connection.inner(), OBJECT_PATH)?;
+    zbus::block_on(ClipboardService::changed(
+        &emitter,
+        selection.name(),
+        targets,
+    ))
+}
+
+/// Selections by name, `CLIPBOARD` when there's none
+fn parse_selection(name: &str) -> fdo::Result<Selection> {
+    match name.to_ascii_uppercase().as_str() {
+        "" | "CLIPBOARD" => Ok(Selection::Clipboard),
+        "PRIMARY" => Ok(Selection::Primary),
+        "SECONDARY" => Ok(Selection::Secondary),
+        _ => Err(fdo::Error::InvalidArgs(format!(
+            "unknown selection {}",
+            name
+        ))),
+    }
+}
+
+fn parse_target(target: &str) -> fdo::Result<CString> {
+    CString::new(target)
+        .map_err(|_| fdo::Error::InvalidArgs(format!("target {:?} has a NUL byte", target)))
+}
+
+fn failed(err: ClipboxError) -> fdo::Error {
+    fdo::Error::Failed(err.to_string())
+}
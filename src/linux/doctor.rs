@@ -0,0 +1,161 @@
+//! Environment diagnostics, to paste into bug reports.
+//!
+//! Clipboard bugs on Linux mostly depend on what's running around us (display server,
+//! clipboard manager, XWayland...), so [`run`] collects all of that in one [`Report`].
+
+use std::env;
+use std::fmt;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::x11::{atom_names, ServeUntil, X11Clipboard};
+use super::{Availability, Backend};
+use crate::selection::Selection;
+
+/// Private selection used for the round-trip test, so that we don't clobber the user's clipboard
+const ROUND_TRIP_SELECTION: Selection = Selection::Custom("CLIPBOX_DOCTOR");
+const ROUND_TRIP_PAYLOAD: &str = "clipbox doctor round-trip 日本語";
+
+/// Everything [`run`] found out about the environment
+#[derive(Debug, Clone)]
+pub struct Report {
+    pub backend: Backend,
+    pub availability: Availability,
+    /// `XDG_SESSION_TYPE`
+    pub session_type: Option<String>,
+    /// `DISPLAY`
+    pub display: Option<String>,
+    /// `WAYLAND_DISPLAY`
+    pub wayland_display: Option<String>,
+    /// Only there if we could connect to an X server
+    pub x11: Option<X11Report>,
+}
+
+/// What we found out about the X server
+#[derive(Debug, Clone)]
+pub struct X11Report {
+    pub xwayland: bool,
+    pub xfixes: bool,
+    pub clipboard_manager: bool,
+    /// In bytes
+    pub max_request_size: usize,
+    /// How long copying and pasting between two connections took, or why it failed
+    pub round_trip: Result<Duration, String>,
+}
+
+/// Inspects the environment and runs a quick copy/paste round trip.
+///
+/// The round trip goes through a private selection, the clipboard is left untouched.
+pub fn run() -> Report {
+    let availability = Backend::X11.availability();
+
+    let x11 = match availability {
+        Availability::Available => X11Clipboard::init().ok().map(|clipboard| X11Report {
+            xwayland: clipboard.is_xwayland(),
+            xfixes: clipboard.has_xfixes(),
+            clipboard_manager: clipboard.has_clipboard_manager(),
+            max_request_size: clipboard.max_request_size(),
+            round_trip: round_trip(),
+        }),
+        _ => None,
+    };
+
+    Report {
+        backend: Backend::detect(),
+        availability,
+        session_type: env::var("XDG_SESSION_TYPE").ok(),
+        display: env::var("DISPLAY").ok(),
+        wayland_display: env::var("WAYLAND_DISPLAY").ok(),
+        x11,
+    }
+}
+
+/// Copies from one connection and pastes from another one
+fn round_trip() -> Result<Duration, String> {
+    let start = Instant::now();
+
+    let owner = thread::spawn(move || -> Result<(), String> {
+        let mut clipboard = X11Clipboard::init().map_err(|err| err.to_string())?;
+        // Don't hang the report if the requestor never lets go
+        clipboard.set_serve_until(ServeUntil::Deadline(start + Duration::from_secs(2)));
+        clipboard
+            .set_selection(
+                ROUND_TRIP_SELECTION,
+                atom_names::UTF8_STRING,
+                ROUND_TRIP_PAYLOAD.as_bytes(),
+            )
+            .map_err(|err| err.to_string())
+    });
+
+    let requestor = X11Clipboard::init().map_err(|err| err.to_string())?;
+
+    // The owner might not have taken the selection yet, give it a few tries
+    let mut pasted = Err("the selection never got an owner".to_string());
+    for _ in 0..10 {
+        thread::sleep(Duration::from_millis(10));
+
+        pasted = requestor
+            .get_selection(ROUND_TRIP_SELECTION, atom_names::UTF8_STRING)
+            .map_err(|err| err.to_string());
+
+        if pasted.is_ok() {
+            break;
+        }
+    }
+
+    let elapsed = start.elapsed();
+
+    // Taking the selection away is what tells the owner it's done
+    let _ = requestor.clear(ROUND_TRIP_SELECTION);
+    let pasted = pasted?;
+
+    owner
+        .join()
+        .map_err(|_| "the owner thread panicked".to_string())??;
+
+    match pasted == ROUND_TRIP_PAYLOAD.as_bytes() {
+        true => Ok(elapsed),
+        false => Err(format!(
+            "pasted {:?} instead of {:?}",
+            String::from_utf8_lossy(&pasted),
+            ROUND_TRIP_PAYLOAD
+        )),
+    }
+}
+
+fn or_unset(var: &Option<String>) -> &str {
+    var.as_deref().unwrap_or("(unset)")
+}
+
+fn yes_no(value: bool) -> &'static str {
+    match value {
+        true => "yes",
+        false => "no",
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "clipbox {}", env!("CARGO_PKG_VERSION"))?;
+        writeln!(f, "os: {}", env::consts::OS)?;
+        writeln!(f, "session type: {}", or_unset(&self.session_type))?;
+        writeln!(f, "DISPLAY: {}", or_unset(&self.display))?;
+        writeln!(f, "WAYLAND_DISPLAY: {}", or_unset(&self.wayland_display))?;
+        writeln!(f, "detected backend: {:?}", self.backend)?;
+        writeln!(f, "x11: {:?}", self.availability)?;
+
+        let Some(x11) = &self.x11 else {
+            return Ok(());
+        };
+
+        writeln!(f, "xwayland: {}", yes_no(x11.xwayland))?;
+        writeln!(f, "xfixes: {}", yes_no(x11.xfixes))?;
+        writeln!(f, "clipboard manager: {}", yes_no(x11.clipboard_manager))?;
+        writeln!(f, "max request size: {} bytes", x11.max_request_size)?;
+
+        match &x11.round_trip {
+            Ok(elapsed) => writeln!(f, "round trip: ok ({}ms)", elapsed.as_millis()),
+            Err(err) => writeln!(f, "round trip: FAILED ({})", err),
+        }
+    }
+}
@@ -0,0 +1,62 @@
+//! Which login session we're in, and whether it's the one in front of the user.
+//!
+//! On shared machines, several users can be logged in at once, each on a VT of their own.
+//! systemd-logind keeps the state of each session in `/run/systemd/sessions`, which tells
+//! whether it's the active one on its seat. Background work that copies things around, like
+//! [`SelectionSync`](super::x11::SelectionSync) and
+//! [`ClipboardHistory::record`](crate::history::ClipboardHistory::record), pauses while its
+//! session isn't.
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+/// Where logind keeps the state of each session
+const SESSIONS_DIR: &str = "/run/systemd/sessions";
+
+/// What `/proc/self/sessionid` holds outside of any session
+const NO_AUDIT_SESSION: &str = "4294967295";
+
+/// A logind session, see the [module docs](self)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Session {
+    id: String,
+}
+
+impl Session {
+    /// The session we run in, by `XDG_SESSION_ID` or else by our audit session. Gives `None`
+    /// outside of any session, or without logind.
+    pub fn current() -> Option<Self> {
+        let id = env::var("XDG_SESSION_ID")
+            .ok()
+            .or_else(|| fs::read_to_string("/proc/self/sessionid").ok())
+            .map(|id| id.trim().to_string())
+            .filter(|id| !id.is_empty() && id != NO_AUDIT_SESSION)
+            // They're file names
+            .filter(|id| id.bytes().all(|b| b.is_ascii_alphanumeric()))?;
+
+        let session = Self { id };
+        session.state_path().exists().then_some(session)
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Whether the session is the active one on its seat. Sessions logind doesn't know about
+    /// (anymore) are taken to be active, so that nothing pauses for good if it can't tell.
+    pub fn is_active(&self) -> bool {
+        let Ok(state) = fs::read_to_string(self.state_path()) else {
+            return true;
+        };
+
+        match state.lines().find_map(|line| line.strip_prefix("ACTIVE=")) {
+            Some(active) => active.trim() == "1",
+            None => true,
+        }
+    }
+
+    fn state_path(&self) -> PathBuf {
+        PathBuf::from(SESSIONS_DIR).join(&self.id)
+    }
+}
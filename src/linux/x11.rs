@@ -1,14 +1,80 @@
+use std::borrow::Cow;
+use std::cell::{Cell, OnceCell, RefCell};
+use std::collections::{HashMap, VecDeque};
 use std::error::Error;
-use std::ffi::{c_int, c_long, c_ulong, c_void, CStr};
+use std::ffi::{c_int, c_long, c_ulong, c_void, CStr, CString};
 use std::fmt;
+use std::io::{self, Read, Write};
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, RawFd};
+use std::path::PathBuf;
 use std::ptr::{self, NonNull};
+use std::rc::Rc;
+use std::string::FromUtf8Error;
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender, TrySendError};
 use std::time::{Duration, Instant};
 
+use crate::cancel::CancellationToken;
+#[cfg(feature = "svg")]
+use crate::convert::SvgError;
+use crate::convert::{
+    self, ClipboardContent, Converted, DesiredForm, FileOperation, Mime, Utf8Decoding,
+};
+#[cfg(feature = "png")]
+use crate::convert::{ImageData, ImageError};
+use crate::error::ClipboxError;
+use crate::event::ClipboardEvent;
+use crate::format::ClipboardFormat;
+use crate::linux::Availability;
+use crate::protocol::{self, IdleTimeout, IncrReceiver, IncrSender};
+use crate::selection::Selection;
+use crate::transform::{Pipeline, Stage};
+
 use loki_linux::x11::{
     self, errcode, et, prop_mode, property, xevent_mask, Atom, Bool, LibX11, XDisplay, XErrorEvent,
     XEvent, XSelectionEvent, XSelectionRequestEvent, XWindow,
 };
 
+#[cfg(feature = "tokio")]
+mod async_io;
+mod background;
+#[cfg(feature = "calloop")]
+mod calloop_source;
+mod driver;
+mod event_loop;
+mod hooks;
+mod loader;
+mod manager;
+mod owner;
+mod poll;
+mod preview;
+pub mod raw;
+#[cfg(feature = "async")]
+mod stream;
+mod sync;
+mod watch;
+#[cfg(feature = "winit")]
+mod winit_waker;
+mod xfixes;
+mod xlib;
+
+pub use background::BackgroundCopy;
+use background::ShutdownSignal;
+#[cfg(feature = "calloop")]
+pub use calloop_source::ClipboardSource;
+pub use driver::ClipboardDriver;
+pub use event_loop::{ClipboardHandle, ClipboardThread};
+pub use hooks::ChangeHook;
+pub use loader::{set_library_paths, LibraryPaths, LIBX11_ENV_VAR, LIBXFIXES_ENV_VAR};
+pub use owner::OwnerInfo;
+pub use preview::{Preview, MAX_PREVIEW_SIZE};
+#[cfg(feature = "async")]
+pub use stream::ChangeStream;
+pub use sync::{SelectionSync, SyncDirection, DEFAULT_SETTLE_TIME};
+pub use watch::{ChangeEvent, WatchGuard, WatchStrategy, Watcher, DEFAULT_POLL_INTERVAL};
+#[cfg(feature = "winit")]
+pub use winit_waker::WinitClipboard;
+use xlib::LibX11Extras;
+
 pub mod atom_names {
     use std::ffi::CStr;
 
@@ -29,12 +95,33 @@ pub mod atom_names {
     pub const TEXT: &CStr = c"TEXT";
     /// Property type: UTF8 string
     pub const UTF8_STRING: &CStr = c"UTF8_STRING";
+    /// Property type: ISO 2022 text, from old apps
+    pub const COMPOUND_TEXT: &CStr = c"COMPOUND_TEXT";
     /// Property type: targets (list of atoms)
     pub const TARGETS: &CStr = c"TARGETS";
     /// Property type: incremental data fetching
     pub const INCR: &CStr = c"INCR";
     /// Property type: atom
     pub const ATOM: &CStr = c"ATOM";
+    /// Property type: 32-bit integer
+    pub const INTEGER: &CStr = c"INTEGER";
+
+    /// Target: when the owner acquired the selection
+    pub const TIMESTAMP: &CStr = c"TIMESTAMP";
+    /// Target: asks the owner to delete the contents, once they were moved elsewhere
+    pub const DELETE: &CStr = c"DELETE";
+    /// Property type: nothing, for targets that are only there for their side effect
+    pub const NULL: &CStr = c"NULL";
+
+    /// Target: several conversions at once
+    pub const MULTIPLE: &CStr = c"MULTIPLE";
+    /// Property type: pairs of atoms (for `MULTIPLE`)
+    pub const ATOM_PAIR: &CStr = c"ATOM_PAIR";
+
+    /// Owned by the clipboard manager, if there's one running
+    pub const CLIPBOARD_MANAGER: &CStr = c"CLIPBOARD_MANAGER";
+    /// Target: asks the clipboard manager to save the clipboard, before its owner exits
+    pub const SAVE_TARGETS: &CStr = c"SAVE_TARGETS";
 }
 
 /// Some commonly used mime types. They're literally infinite so the list cannot be exclusive.
@@ -46,8 +133,18 @@ pub mod mime_types {
     pub const TEXT_PLAIN: &CStr = c"text/plain";
     pub const TEXT_PLAIN_CHARSET_UTF8: &CStr = c"text/plain;charset=utf-8";
     pub const TEXT_HTML: &CStr = c"text/html";
+    pub const TEXT_RTF: &CStr = c"text/rtf";
+    pub const APPLICATION_RTF: &CStr = c"application/rtf";
+    pub const TEXT_URI_LIST: &CStr = c"text/uri-list";
+    /// What GNOME file managers (and Thunar, and most others) use to copy files
+    pub const GNOME_COPIED_FILES: &CStr = c"x-special/gnome-copied-files";
+    /// What KDE uses to tell cut files from copied ones, along with `text/uri-list`
+    pub const KDE_CUT_SELECTION: &CStr = c"application/x-kde-cutselection";
+    /// What password managers (KeePassXC and friends) offer as `secret` when copying a password
+    pub const KDE_PASSWORD_MANAGER_HINT: &CStr = c"x-kde-passwordManagerHint";
 
     pub const IMAGE_PNG: &CStr = c"image/png";
+    pub const IMAGE_SVG: &CStr = c"image/svg+xml";
     pub const IMAGE_JPG: &CStr = c"image/jpg";
     pub const IMAGE_JPEG: &CStr = c"image/jpeg";
 }
@@ -84,8 +181,26 @@ unsafe fn intern_atom(x: &LibX11, display: NonNull<XDisplay>, name: &CStr) -> At
     (x.XInternAtom)(display.as_ptr(), name.as_ptr() as _, 0)
 }
 
-unsafe fn get_atom_name(x: &LibX11, display: NonNull<XDisplay>, atom: Atom) -> &CStr {
-    CStr::from_ptr((x.XGetAtomName)(display.as_ptr(), atom))
+/// Atoms we interned or looked up the name of already, so that we only ask the X server once
+#[derive(Debug, Default)]
+struct AtomCache {
+    atoms: RefCell<HashMap<CString, Atom>>,
+    names: RefCell<HashMap<Atom, CString>>,
+}
+
+impl AtomCache {
+    fn atom(&self, name: &CStr) -> Option<Atom> {
+        self.atoms.borrow().get(name).copied()
+    }
+
+    fn name(&self, atom: Atom) -> Option<CString> {
+        self.names.borrow().get(&atom).cloned()
+    }
+
+    fn insert(&self, name: &CStr, atom: Atom) {
+        self.atoms.borrow_mut().insert(name.to_owned(), atom);
+        self.names.borrow_mut().insert(atom, name.to_owned());
+    }
 }
 
 #[derive(Debug)]
@@ -114,7 +229,8 @@ struct XWindowProperty<'a> {
     pub format: c_int,
     pub nitems: c_ulong,
     pub bytes_remaining: c_ulong,
-    pub data: NonNull<c_void>,
+    /// Xlib doesn't always allocate anything for empty properties
+    pub data: Option<NonNull<c_void>>,
 }
 
 impl<'a> XWindowProperty<'a> {
@@ -132,6 +248,11 @@ impl<'a> XWindowProperty<'a> {
 
     /// Writes this property into a vec
     fn write_into_vec<T>(self, buf: &mut Vec<T>) -> Result<(), PropertyInvalidFormatError> {
+        // Empty is empty, whatever the format
+        let Some(data) = self.data.filter(|_| self.nitems > 0) else {
+            return Ok(());
+        };
+
         self.check_format_compatible::<T>()?;
 
         let prev_len = buf.len();
@@ -142,7 +263,7 @@ impl<'a> XWindowProperty<'a> {
             // SAFETY: we trust Xlib that the source is aligned and valid,
             // and `buf.reserve` ensures that we have usable space to write them.
             ptr::copy_nonoverlapping(
-                self.data.as_ptr().cast::<T>().cast_const(),
+                data.as_ptr().cast::<T>().cast_const(),
                 buf.as_mut_ptr().add(prev_len),
                 n_items,
             );
@@ -155,19 +276,87 @@ impl<'a> XWindowProperty<'a> {
         Ok(())
     }
 
+    /// Borrows the data of this 8-bit property, without copying it
+    fn as_bytes(&self) -> Result<&[u8], PropertyInvalidFormatError> {
+        let Some(data) = self.data.filter(|_| self.nitems > 0) else {
+            return Ok(&[]);
+        };
+
+        self.check_format_compatible::<u8>()?;
+
+        // SAFETY: Xlib allocated `nitems` bytes for an 8-bit property, freed when we're dropped
+        Ok(unsafe { std::slice::from_raw_parts(data.as_ptr().cast::<u8>(), self.nitems as usize) })
+    }
+
     /// Converts this property into a vec
     fn into_vec<T>(self) -> Result<Vec<T>, PropertyInvalidFormatError> {
         let mut prop = Vec::new();
         self.write_into_vec(&mut prop)?;
         Ok(prop)
     }
+
+    /// Converts this 32-bit property into a vec.
+    ///
+    /// Xlib hands out 32-bit items as C `long`s, which are 64 bits wide on 64-bit platforms,
+    /// so they can't just be copied like the other formats.
+    fn into_vec32(self) -> Result<Vec<u32>, PropertyInvalidFormatError> {
+        let Some(data) = self.data.filter(|_| self.nitems > 0) else {
+            return Ok(Vec::new());
+        };
+
+        if self.format != 32 {
+            return Err(PropertyInvalidFormatError {
+                wanted: 32,
+                actual: self.format as u8,
+            });
+        }
+
+        // SAFETY: Xlib allocated `nitems` longs for a 32-bit property
+        let items = unsafe {
+            std::slice::from_raw_parts(
+                data.as_ptr().cast::<c_ulong>().cast_const(),
+                self.nitems as usize,
+            )
+        };
+
+        Ok(items.iter().map(|&item| item as u32).collect())
+    }
+}
+
+/// A property of our window that one transfer replies into,
+/// see [`X11Clipboard::reply_property`]
+struct ReplyProperty<'a> {
+    clipboard: &'a X11Clipboard,
+    atom: Atom,
+}
+
+impl<'a> ReplyProperty<'a> {
+    fn read(&self) -> Result<XWindowProperty<'a>, GetSelectionError> {
+        self.clipboard
+            .get_window_property(self.clipboard.window, self.atom)
+    }
+
+    /// Reads about the first `max_bytes` of the property only, the rest being left in
+    /// `bytes_remaining`
+    fn read_prefix(&self, max_bytes: usize) -> Result<XWindowProperty<'a>, GetSelectionError> {
+        self.clipboard
+            .get_window_property_prefix(self.clipboard.window, self.atom, max_bytes)
+    }
+}
+
+impl<'a> Drop for ReplyProperty<'a> {
+    fn drop(&mut self) {
+        self.clipboard.free_reply_property(self.atom);
+    }
 }
 
 impl<'a> Drop for XWindowProperty<'a> {
     fn drop(&mut self) {
-        unsafe {
-            // The data is free \o/
-            (self.x11.XFree)(self.data.as_ptr());
+        if let Some(data) = self.data {
+            unsafe {
+                // The data is free \o/
+                (self.x11.XFree)(data.as_ptr());
+            }
         }
     }
 }
@@ -194,541 +383,3919 @@ unsafe extern "C" fn x11_error_handler(_display: *mut XDisplay, event: *mut XErr
     0
 }
 
-pub struct X11Clipboard {
-    x: LibX11,
-    display: NonNull<XDisplay>,
-    window: XWindow,
-    atoms: Atoms,
-    max_request_size: usize,
+fn load_extras() -> Result<LibX11Extras, ClipboxError> {
+    LibX11Extras::open()
+        .ok_or_else(|| ClipboxError::Init("libX11 is missing functions we need".to_string()))
 }
 
-impl X11Clipboard {
-    pub fn init() -> Result<Self, Box<dyn Error>> {
-        unsafe {
-            let x = LibX11::new()?;
-
-            (x.XSetErrorHandler)(Some(x11_error_handler));
-
-            // Open the default X11 display
-            let display = (x.XOpenDisplay)(std::ptr::null());
-            let display = NonNull::new(display).ok_or("cannot open display :(")?;
-
-            let root = (x.XDefaultRootWindow)(display.as_ptr());
-
-            // Create a window to trap events
-            let window = (x.XCreateSimpleWindow)(display.as_ptr(), root, 0, 0, 1, 1, 0, 0, 0);
-
-            // Select property change events
-            (x.XSelectInput)(display.as_ptr(), window, xevent_mask::PROPERTY_CHANGE);
-
-            let atoms = Atoms {
-                primary: intern_atom(&x, display, atom_names::PRIMARY),
-                secondary: intern_atom(&x, display, atom_names::SECONDARY),
-                clipboard: intern_atom(&x, display, atom_names::CLIPBOARD),
-                clipbox: intern_atom(&x, display, atom_names::CLIPBOX),
-                clipbox_dummy: intern_atom(&x, display, atom_names::CLIPBOX_DUMMY),
-                string: intern_atom(&x, display, atom_names::STRING),
-                text: intern_atom(&x, display, atom_names::TEXT),
-                utf8_string: intern_atom(&x, display, atom_names::UTF8_STRING),
-                targets: intern_atom(&x, display, atom_names::TARGETS),
-                incr: intern_atom(&x, display, atom_names::INCR),
-                atom: intern_atom(&x, display, atom_names::ATOM),
-            };
-
-            let max_request_size = (x.XMaxRequestSize)(display.as_ptr()) as usize;
+/// Checks that libX11 can be loaded and that we can connect to the default display
+pub(crate) fn probe() -> Availability {
+    let x = match loader::load_x11() {
+        Ok(x) => x,
+        Err(err) => return Availability::LibraryMissing(err),
+    };
 
-            Ok(Self {
-                x,
-                display,
-                window,
-                atoms,
-                max_request_size,
-            })
+    unsafe {
+        let display = (x.XOpenDisplay)(std::ptr::null());
+        if display.is_null() {
+            return Availability::NoDisplay;
         }
-    }
 
-    unsafe fn next_event(&self) -> XEvent {
-        let mut xevent = XEvent { type_id: 0 };
-        (self.x.XNextEvent)(self.display.as_ptr(), &mut xevent);
-        xevent
+        (x.XCloseDisplay)(display);
     }
 
-    /// Tries to get the next event before the timeout.
-    /// It will look for pending events every 100µs.
-    unsafe fn next_event_timeout(&self, timeout: Duration) -> Option<XEvent> {
-        let start = Instant::now();
-        loop {
-            let pending = (self.x.XPending)(self.display.as_ptr());
+    Availability::Available
+}
 
-            if pending == 0 {
-                let elapsed = start.elapsed();
-                if elapsed > timeout {
-                    return None;
-                }
+/// How long to wait for the X server and other clients. `None` waits forever.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timeouts {
+    /// For the owner to answer a conversion request (including `TARGETS`)
+    pub conversion: Option<Duration>,
+    /// For each chunk of an INCR transfer, when sending as well as receiving
+    pub incr_chunk: Option<Duration>,
+    /// For the X server to give us the timestamp needed to acquire a selection
+    pub ownership: Option<Duration>,
+}
 
-                print!(
-                    "\x1b[2K\rWaiting for next event... {}µs",
-                    elapsed.as_micros()
-                );
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            conversion: Some(Duration::from_secs(5)),
+            incr_chunk: Some(Duration::from_secs(5)),
+            ownership: Some(Duration::from_secs(1)),
+        }
+    }
+}
 
-                std::thread::sleep(Duration::from_micros(100));
-                continue;
-            }
+/// How hard we try to become a selection owner when another client takes it at the same time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OwnershipRetry {
+    /// How many more times we try after losing, each time with a fresh timestamp
+    pub retries: u32,
+    /// How long we wait before the first retry. It doubles after each one.
+    pub backoff: Duration,
+}
 
-            println!("\x1b[2K\rPending: {}", pending);
-            break;
+impl Default for OwnershipRetry {
+    fn default() -> Self {
+        Self {
+            retries: 2,
+            backoff: Duration::from_millis(10),
         }
-
-        Some(self.next_event())
     }
+}
 
-    /// Get a compliant timestamp for selection requests
-    ///
-    /// # Convention
-    ///
-    /// *Clients attempting to acquire a selection must set the time value of the
-    /// `SetSelectionOwner` request to the timestamp of the event triggering the
-    /// acquisition attempt, not to `CurrentTime`. A zero-length append to a property
-    /// is a way to obtain a timestamp for this purpose; the timestamp is in the
-    /// corresponding `PropertyNotify` event.*
+/// When a copy is over, which is when [`X11Clipboard::set_selection`] and friends return
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ServeUntil {
+    /// Once another client takes the selection
+    #[default]
+    OwnershipLost,
+    /// At that time, or once the selection is lost if that comes first.
+    /// Transfers still going on are abandoned.
+    Deadline(Instant),
+    /// Once nothing happened for that long (or the selection is lost)
+    Idle(Duration),
+    /// Right away: a background thread serves the selection until it's lost, see
+    /// [`X11Clipboard::set_many_in_background`].
     ///
-    /// [ICCCM - Acquiring Selection Ownership](https://tronche.com/gui/x/icccm/sec-2.html#s-2.1)
-    unsafe fn get_compliant_timestamp(&self) -> c_ulong {
-        // Send dummy change property request to obtain a timestamp from its resulting event
-        // This is because it is disincentivized to use CurrentTime when sending a ConvertSelection request
-        (self.x.XChangeProperty)(
-            self.display.as_ptr(),
-            self.window,
-            self.atoms.clipbox_dummy,
-            self.atoms.string,
-            8,
-            prop_mode::APPEND,
-            std::ptr::null(),
-            0,
-        );
-
-        loop {
-            let xevent = self.next_event();
-
-            if xevent.type_id == et::PROPERTY_NOTIFY {
-                let xevent = xevent.xproperty;
+    /// Streams and lazy targets can't be handed to another thread, so they're served until
+    /// the selection is lost instead.
+    Background,
+}
 
-                if xevent.atom == self.atoms.clipbox_dummy {
-                    return xevent.time;
-                }
-            }
-        }
-    }
+/// A selection another client got before us, see [`X11Clipboard::acquire`]
+struct LostRace {
+    selection: Selection,
+    owner: Option<XWindow>,
 }
 
-#[derive(Debug)]
-pub enum GetSelectionError {
-    BadSelection,
-    SelectionLost,
-    GetPropertyFailed(i32),
-    NoDataInProperty,
-    PropertyInvalidFormat(PropertyInvalidFormatError),
+/// What we copied to `CLIPBOARD`, kept for the clipboard manager
+struct SavedClipboard {
+    /// When we became the owner, for `TIMESTAMP`
+    acquired_at: c_ulong,
+    targets: Vec<(Atom, Vec<u8>)>,
 }
 
-impl Error for GetSelectionError {
-    fn source(&self) -> Option<&(dyn Error + 'static)> {
-        match self {
-            Self::PropertyInvalidFormat(err) => Some(err),
-            _ => None,
-        }
-    }
+/// Where [`X11Clipboard::emit`] sends events
+enum Subscriber {
+    Unbounded(Sender<ClipboardEvent>),
+    Bounded(SyncSender<ClipboardEvent>),
 }
 
-impl fmt::Display for GetSelectionError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+impl Subscriber {
+    /// Gives whether it's still listening. A full bounded channel misses the event.
+    fn send(&self, event: ClipboardEvent) -> bool {
         match self {
-            Self::BadSelection => write!(f, "Received notification for a selection that wasn't requested. The X server is probably mad"),
-            Self::SelectionLost => write!(f, "Selection lost (probably a bug with the clipbox library, how did that happen?)"),
-            Self::GetPropertyFailed(status) => write!(f, "Couldn't get property (error code: {})", status),
-            Self::NoDataInProperty => write!(f, "No data in our dedicated X11 property (how even)"),
-            Self::PropertyInvalidFormat(err) => err.fmt(f),
+            Self::Unbounded(sender) => sender.send(event).is_ok(),
+            Self::Bounded(sender) => {
+                !matches!(sender.try_send(event), Err(TrySendError::Disconnected(_)))
+            }
         }
     }
 }
 
-impl From<PropertyInvalidFormatError> for GetSelectionError {
-    fn from(value: PropertyInvalidFormatError) -> Self {
-        Self::PropertyInvalidFormat(value)
-    }
+/// A connection to an X server, with a hidden window to paste through and another one to copy
+/// from. Events for the owner window that come while we're pasting are kept for when we serve
+/// again, so pasting doesn't make requestors of what we copied wait forever.
+///
+/// All the unsafe Xlib calls stay inside this module: they're sound as long as `display` is
+/// valid, which holds for the clipboard's whole life. Only the constructors that take someone
+/// else's display are `unsafe`, since we can't check that for them.
+pub struct X11Clipboard {
+    x: LibX11,
+    /// What loki-linux may not bind, see [`xlib`]
+    xlib: LibX11Extras,
+    display: NonNull<XDisplay>,
+    /// Whether we opened the display ourselves and have to close it
+    owns_display: bool,
+    /// Hidden window we paste into
+    window: XWindow,
+    /// Hidden window that owns what we copy, so that pasting and serving don't get in each
+    /// other's way
+    owner_window: XWindow,
+    /// Events for the owner window that came while we were pasting, still to be handled
+    deferred_events: RefCell<VecDeque<XEvent>>,
+    atoms: Atoms,
+    max_request_size: usize,
+    /// See [`X11Clipboard::set_incr_chunk_size`]
+    incr_chunk_size: Option<usize>,
+    payload_limit: Option<PayloadLimit>,
+    atom_cache: AtomCache,
+    timeouts: Cell<Timeouts>,
+    /// See [`X11Clipboard::with_cancellation`]
+    cancellation: RefCell<Option<CancellationToken>>,
+    ownership_retry: OwnershipRetry,
+    serve_until: Cell<ServeUntil>,
+    /// Copies served by background threads, see [`ServeUntil::Background`]
+    background_copies: RefCell<Vec<BackgroundCopy>>,
+    subscribers: RefCell<Vec<Subscriber>>,
+    /// What we last copied to `CLIPBOARD`, to hand it to the clipboard manager when dropped
+    saved_clipboard: RefCell<Option<SavedClipboard>>,
+    delete_handler: Option<Box<DeleteHandler>>,
+    /// What text goes through when copied or pasted, see [`X11Clipboard::set_transforms`]
+    transforms: Pipeline,
+    /// Properties of our window that no transfer is replying into, see [`ReplyProperty`]
+    free_reply_properties: RefCell<VecDeque<Atom>>,
+    reply_property_count: Cell<usize>,
+    /// Loaded the first time we watch a selection
+    xfixes: OnceCell<Option<xfixes::XFixes>>,
+    /// Set on the connections of background copies, see [`BackgroundCopy::shutdown`]
+    shutdown: Option<ShutdownSignal>,
 }
 
-// Paste (get selection)
 impl X11Clipboard {
-    unsafe fn get_selection_event(
-        &self,
-        atom_selection: Atom,
-        atom_target: Atom,
-    ) -> Result<XSelectionEvent, GetSelectionError> {
-        let when_everything_started = self.get_compliant_timestamp();
+    /// Connects to the default X display (the one in the `DISPLAY` environment variable)
+    pub fn init() -> Result<Self, ClipboxError> {
+        Self::open(None)
+    }
 
-        // Send a ConvertSelection request
-        (self.x.XConvertSelection)(
-            self.display.as_ptr(),
-            atom_selection,
-            atom_target,
-            self.atoms.clipbox,
-            self.window,
-            when_everything_started,
-        );
+    /// Connects to a specific X display, for example `":1"` or `"host:0.0"`
+    pub fn init_with_display_name(display_name: &CStr) -> Result<Self, ClipboxError> {
+        Self::open(Some(display_name))
+    }
 
-        let xevent = loop {
-            let xevent = self.next_event();
+    fn open(display_name: Option<&CStr>) -> Result<Self, ClipboxError> {
+        unsafe {
+            let x = loader::load_x11().map_err(ClipboxError::Init)?;
+            let xlib = load_extras()?;
 
-            if xevent.type_id == et::SELECTION_NOTIFY {
-                let xevent = xevent.xselection;
+            (x.XSetErrorHandler)(Some(x11_error_handler));
 
-                if xevent.requestor == self.window
-                    && xevent.selection == atom_selection
-                    && xevent.target == atom_target
-                {
-                    break xevent;
-                } else {
-                    return Err(GetSelectionError::BadSelection);
-                }
-            }
-        };
+            // Open the X11 display (null means the default one)
+            let display_name = display_name.map_or(std::ptr::null(), CStr::as_ptr);
+            let display = (x.XOpenDisplay)(display_name);
+            let display = NonNull::new(display)
+                .ok_or_else(|| ClipboxError::Init("cannot open display :(".to_string()))?;
 
-        if xevent.property == 0 {
-            return Err(GetSelectionError::SelectionLost);
+            Ok(Self::with_display(x, xlib, display, true))
         }
+    }
 
-        Ok(xevent)
+    /// Reuses an already open X display connection, for example the one of a winit or SDL window.
+    ///
+    /// Clipbox creates its own hidden window on that connection and only cares about events
+    /// targeting it, but it still reads them from the shared event queue while copying or
+    /// pasting. The application shouldn't pump events from another thread at the same time.
+    ///
+    /// The display is not closed when the clipboard is dropped, and the application's X error
+    /// handler is left untouched.
+    ///
+    /// # Safety
+    ///
+    /// `display` must be a valid, open Xlib display that outlives the returned clipboard.
+    pub unsafe fn from_display(display: NonNull<XDisplay>) -> Result<Self, ClipboxError> {
+        let x = loader::load_x11().map_err(ClipboxError::Init)?;
+        Ok(Self::with_display(x, load_extras()?, display, false))
     }
 
-    fn get_clipbox_property(&self) -> Result<XWindowProperty, GetSelectionError> {
-        let mut ty: Atom = 0;
-        let mut format: c_int = 8;
-        let mut nitems: c_ulong = 0;
-        let mut bytes_remaining: c_ulong = 0;
-        let mut data: *mut c_void = std::ptr::null_mut();
+    /// Reuses the display connection from a [`raw_window_handle`] display handle.
+    ///
+    /// Only Xlib handles are supported, as we're an Xlib client.
+    ///
+    /// # Safety
+    ///
+    /// The handle must point to a valid, open Xlib display that outlives the returned clipboard.
+    /// See [`X11Clipboard::from_display`].
+    #[cfg(feature = "raw-window-handle")]
+    pub unsafe fn from_raw_display_handle(
+        handle: raw_window_handle::RawDisplayHandle,
+    ) -> Result<Self, ClipboxError> {
+        let raw_window_handle::RawDisplayHandle::Xlib(handle) = handle else {
+            return Err(ClipboxError::Init(
+                "only Xlib display handles are supported".to_string(),
+            ));
+        };
 
-        let status = unsafe {
-            let long_offset: c_long = 0;
-            let long_length: c_long = c_long::MAX;
-            let delete: Bool = x11::bool::FALSE;
-            let req_type: Atom = 0;
+        let display = handle.display.ok_or_else(|| {
+            ClipboxError::Init("the Xlib display handle has no display".to_string())
+        })?;
+        Self::from_display(display.cast())
+    }
 
-            (self.x.XGetWindowProperty)(
-                self.display.as_ptr(),
-                self.window,
-                self.atoms.clipbox,
-                long_offset,
-                long_length,
-                delete,
-                req_type,
-                &mut ty,
-                &mut format,
-                &mut nitems,
-                &mut bytes_remaining,
-                &mut data,
-            )
+    unsafe fn with_display(
+        x: LibX11,
+        xlib: LibX11Extras,
+        display: NonNull<XDisplay>,
+        owns_display: bool,
+    ) -> Self {
+        let root = (x.XDefaultRootWindow)(display.as_ptr());
+
+        // Create a window to trap events
+        let window = (x.XCreateSimpleWindow)(display.as_ptr(), root, 0, 0, 1, 1, 0, 0, 0);
+
+        // Select property change events
+        (x.XSelectInput)(display.as_ptr(), window, xevent_mask::PROPERTY_CHANGE);
+
+        // And another one to own selections (selection events don't need to be selected)
+        let owner_window = (x.XCreateSimpleWindow)(display.as_ptr(), root, 0, 0, 1, 1, 0, 0, 0);
+
+        let atoms = Atoms {
+            primary: intern_atom(&x, display, atom_names::PRIMARY),
+            secondary: intern_atom(&x, display, atom_names::SECONDARY),
+            clipboard: intern_atom(&x, display, atom_names::CLIPBOARD),
+            clipbox: intern_atom(&x, display, atom_names::CLIPBOX),
+            clipbox_dummy: intern_atom(&x, display, atom_names::CLIPBOX_DUMMY),
+            string: intern_atom(&x, display, atom_names::STRING),
+            text: intern_atom(&x, display, atom_names::TEXT),
+            utf8_string: intern_atom(&x, display, atom_names::UTF8_STRING),
+            targets: intern_atom(&x, display, atom_names::TARGETS),
+            incr: intern_atom(&x, display, atom_names::INCR),
+            atom: intern_atom(&x, display, atom_names::ATOM),
         };
 
-        if status != errcode::SUCCESS {
-            return Err(GetSelectionError::GetPropertyFailed(status));
+        // Servers with the BIG-REQUESTS extension take way bigger requests than they advertise
+        let max_request_size = match (xlib.XExtendedMaxRequestSize)(display.as_ptr()) {
+            0 => (x.XMaxRequestSize)(display.as_ptr()),
+            size => size,
+        } as usize;
+
+        Self {
+            x,
+            xlib,
+            display,
+            owns_display,
+            window,
+            owner_window,
+            deferred_events: RefCell::new(VecDeque::new()),
+            xfixes: OnceCell::new(),
+            atoms,
+            max_request_size,
+            incr_chunk_size: None,
+            payload_limit: None,
+            atom_cache: AtomCache::default(),
+            timeouts: Cell::new(Timeouts::default()),
+            cancellation: RefCell::new(None),
+            ownership_retry: OwnershipRetry::default(),
+            serve_until: Cell::new(ServeUntil::default()),
+            background_copies: RefCell::new(Vec::new()),
+            subscribers: RefCell::new(Vec::new()),
+            saved_clipboard: RefCell::new(None),
+            delete_handler: None,
+            transforms: Pipeline::new(),
+            free_reply_properties: RefCell::new(VecDeque::new()),
+            reply_property_count: Cell::new(0),
+            shutdown: None,
         }
+    }
 
-        let Some(data) = NonNull::new(data) else {
-            return Err(GetSelectionError::NoDataInProperty);
-        };
+    pub fn timeouts(&self) -> Timeouts {
+        self.timeouts.get()
+    }
 
-        Ok(XWindowProperty {
-            x11: &self.x,
-            ty,
-            format,
-            nitems,
-            bytes_remaining,
-            data,
-        })
+    pub fn set_timeouts(&mut self, timeouts: Timeouts) {
+        self.timeouts.set(timeouts);
     }
 
-    pub fn get_targets(&self, selection: &CStr) -> Result<Vec<&CStr>, GetSelectionError> {
-        unsafe {
-            let atom_selection = intern_atom(&self.x, self.display, selection);
-            self.get_selection_event(atom_selection, self.atoms.targets)?
-        };
+    pub fn ownership_retry(&self) -> OwnershipRetry {
+        self.ownership_retry
+    }
 
-        let clipbox_prop = self.get_clipbox_property()?;
+    /// Sets how we retry taking a selection when another client wins the race for it
+    pub fn set_ownership_retry(&mut self, retry: OwnershipRetry) {
+        self.ownership_retry = retry;
+    }
 
-        let targets = clipbox_prop
-            .into_vec::<u32>()?
-            .into_iter()
-            .filter(|&atom| atom != 0)
-            .map(|atom| unsafe { get_atom_name(&self.x, self.display, atom as Atom) })
-            .collect::<Vec<_>>();
+    /// Runs `f` with different timeouts, for a single call
+    pub fn with_timeouts<T>(&self, timeouts: Timeouts, f: impl FnOnce(&Self) -> T) -> T {
+        let previous = self.timeouts.replace(timeouts);
+        let result = f(self);
+        self.timeouts.set(previous);
+        result
+    }
 
-        Ok(targets)
+    /// Runs `f`, giving up on whatever it waits for as soon as `token` is cancelled: pastes
+    /// (INCR transfers included) fail with a `Cancelled` error, and serving stops.
+    ///
+    /// The tokio API doesn't need this, dropping its futures cancels them.
+    pub fn with_cancellation<T>(&self, token: &CancellationToken, f: impl FnOnce(&Self) -> T) -> T {
+        let previous = self.cancellation.replace(Some(token.clone()));
+        let result = f(self);
+        self.cancellation.replace(previous);
+        result
     }
 
-    pub fn get_selection(
-        &self,
-        selection: &CStr,
-        target: &CStr,
-    ) -> Result<Vec<u8>, GetSelectionError> {
-        if target == atom_names::TARGETS {
-            panic!(concat!(
-                "TARGETS is a special selection target, this method doesn't support it.",
-                " Try X11Clipboard::get_targets instead!"
-            ));
+    fn is_cancelled(&self) -> bool {
+        self.cancellation
+            .borrow()
+            .as_ref()
+            .is_some_and(CancellationToken::is_cancelled)
+    }
+
+    /// Why a paste stopped waiting
+    fn gave_up_pasting(&self) -> GetSelectionError {
+        match self.is_cancelled() {
+            true => GetSelectionError::Cancelled,
+            false => GetSelectionError::Timeout,
         }
+    }
 
-        unsafe {
-            let atom_selection = intern_atom(&self.x, self.display, selection);
-            let atom_target = intern_atom(&self.x, self.display, target);
-            self.get_selection_event(atom_selection, atom_target)?
-        };
+    /// Why a copy stopped waiting
+    fn gave_up_copying(&self) -> SetSelectionError {
+        match self.is_cancelled() {
+            true => SetSelectionError::Cancelled,
+            false => SetSelectionError::Timeout,
+        }
+    }
+
+    /// Subscribes to the events happening while this clipboard serves selections
+    pub fn subscribe(&self) -> Receiver<ClipboardEvent> {
+        let (sender, receiver) = mpsc::channel();
+        self.subscribers
+            .borrow_mut()
+            .push(Subscriber::Unbounded(sender));
+        receiver
+    }
 
-        let clipbox_prop = self.get_clipbox_property()?;
+    /// Like [`X11Clipboard::subscribe`], but at most `capacity` events wait to be read: newer
+    /// ones are dropped until the receiver catches up
+    pub fn subscribe_bounded(&self, capacity: usize) -> Receiver<ClipboardEvent> {
+        let (sender, receiver) = mpsc::sync_channel(capacity);
+        self.subscribers
+            .borrow_mut()
+            .push(Subscriber::Bounded(sender));
+        receiver
+    }
 
-        if clipbox_prop.ty == self.atoms.incr {
-            // We got an INCR atom, fetch property incrementally
-            let mut data = Vec::new();
+    /// Sends an event to every subscriber. The event is only built if anyone is listening.
+    fn emit(&self, event: impl FnOnce() -> ClipboardEvent) {
+        let mut subscribers = self.subscribers.borrow_mut();
+        if subscribers.is_empty() {
+            return;
+        }
 
-            loop {
-                unsafe {
-                    // First delete the INCR property
-                    (self.x.XDeleteProperty)(
-                        self.display.as_ptr(),
-                        self.window,
-                        self.atoms.clipbox,
-                    );
-
-                    // Waiting for a `PropertyNotify` with the state argument `NewValue`
-                    loop {
-                        let xevent = self.next_event();
-
-                        if xevent.type_id == et::PROPERTY_NOTIFY {
-                            let xevent = xevent.xproperty;
-
-                            if xevent.state == property::NEW_VALUE {
-                                break;
-                            }
-                        }
-                    }
+        let event = event();
+        // Forget about subscribers who dropped their receiver
+        subscribers.retain(|subscriber| subscriber.send(event.clone()));
+    }
 
-                    let clipbox_prop = self.get_clipbox_property()?;
+    /// Gets the atom for a name, creating it if needed. Atoms are cached.
+    fn intern(&self, name: &CStr) -> Atom {
+        if let Some(atom) = self.atom_cache.atom(name) {
+            return atom;
+        }
 
-                    if clipbox_prop.nitems == 0 {
-                        break;
-                    }
+        let atom = unsafe { intern_atom(&self.x, self.display, name) };
+        if atom != 0 {
+            self.atom_cache.insert(name, atom);
+        }
 
-                    clipbox_prop.write_into_vec(&mut data)?;
-                }
-            }
+        atom
+    }
 
-            Ok(data)
-        } else {
-            Ok(clipbox_prop.into_vec()?)
+    fn selection_atom(&self, selection: Selection) -> Atom {
+        match selection {
+            Selection::Clipboard => self.atoms.clipboard,
+            Selection::Primary => self.atoms.primary,
+            Selection::Secondary => self.atoms.secondary,
+            Selection::Custom(name) => {
+                let name = CString::new(name).expect("selection names can't contain NUL bytes");
+                self.intern(&name)
+            }
         }
     }
-}
 
-#[derive(Debug)]
-pub enum SetSelectionError {
-    NotOwner,
-}
+    /// Interns a MIME type ahead of time, to use it as a target later without asking the X server.
+    ///
+    /// Returns its atom, or `None` if it isn't a valid MIME type.
+    pub fn register_mime(&self, mime: &str) -> Option<Atom> {
+        let mime = Mime::parse(mime)?;
+        let name = CString::new(mime.as_str()).ok()?;
+        Some(self.intern(&name))
+    }
 
-impl Error for SetSelectionError {}
+    /// Gets the name of an atom, or `None` if the atom doesn't exist
+    fn atom_name(&self, atom: Atom) -> Option<CString> {
+        if atom == 0 {
+            return None;
+        }
 
-impl fmt::Display for SetSelectionError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            Self::NotOwner => write!(f, "Could not become the selection owner"),
+        if let Some(name) = self.atom_cache.name(atom) {
+            return Some(name);
         }
+
+        let owned = unsafe {
+            let name = (self.x.XGetAtomName)(self.display.as_ptr(), atom);
+            if name.is_null() {
+                return None;
+            }
+
+            let owned = CStr::from_ptr(name).to_owned();
+            (self.x.XFree)(name.cast());
+            owned
+        };
+
+        self.atom_cache.insert(&owned, atom);
+        Some(owned)
     }
-}
+
+    fn atom_name_lossy(&self, atom: Atom) -> String {
+        match self.atom_name(atom) {
+            Some(name) => name.to_string_lossy().into_owned(),
+            None => format!("<unknown atom {}>", atom),
+        }
+    }
+
+    /// Checks whether the X server supports an extension
+    fn has_extension(&self, name: &CStr) -> bool {
+        let mut major_opcode: c_int = 0;
+        let mut first_event: c_int = 0;
+        let mut first_error: c_int = 0;
+
+        let present = unsafe {
+            (self.xlib.XQueryExtension)(
+                self.display.as_ptr(),
+                name.as_ptr(),
+                &mut major_opcode,
+                &mut first_event,
+                &mut first_error,
+            )
+        };
+
+        present != x11::bool::FALSE
+    }
+
+    /// Whether we're connected to XWayland rather than a real X server.
+    ///
+    /// In that case we can only see selections of other X11 clients, plus whatever
+    /// XWayland decides to mirror from Wayland clients.
+    pub fn is_xwayland(&self) -> bool {
+        self.has_extension(c"XWAYLAND")
+    }
+
+    /// The name of the display we're connected to, like `:0`
+    pub fn display_name(&self) -> CString {
+        unsafe { CStr::from_ptr((self.xlib.XDisplayString)(self.display.as_ptr())) }.to_owned()
+    }
+
+    /// Whether the X server supports XFixes, which lets clients watch selection changes
+    pub fn has_xfixes(&self) -> bool {
+        self.has_extension(c"XFIXES")
+    }
+
+    /// Whether a clipboard manager is running, which keeps the clipboard alive after we exit
+    pub fn has_clipboard_manager(&self) -> bool {
+        unsafe {
+            let atom = self.intern(atom_names::CLIPBOARD_MANAGER);
+            (self.x.XGetSelectionOwner)(self.display.as_ptr(), atom) != 0
+        }
+    }
+
+    /// The biggest request the X server accepts, in bytes
+    pub fn max_request_size(&self) -> usize {
+        // XMaxRequestSize counts in 4-byte units
+        self.max_request_size * 4
+    }
+
+    /// The biggest property we can write in one go, in bytes. Anything bigger is sent
+    /// incrementally.
+    pub fn max_property_size(&self) -> usize {
+        // Taken from this line: https://github.com/quininer/x11-clipboard/blob/704cfd3ebf7297e4cd3b5ef00d2e2527e9b633f2/src/run.rs#L122
+        // The -24 is the size of the ChangeProperty request header, and the Tronche guide does say this:
+        // "The size should be less than the maximum-request-size in the connection handshake".
+        self.max_request_size() - 24
+    }
+
+    /// How much data goes in each chunk of incremental transfers, in bytes
+    pub fn incr_chunk_size(&self) -> usize {
+        let max_size = self.max_property_size();
+        self.incr_chunk_size.unwrap_or(max_size).clamp(1, max_size)
+    }
+
+    fn next_event(&self) -> XEvent {
+        let mut xevent = XEvent { type_id: 0 };
+        unsafe { (self.x.XNextEvent)(self.display.as_ptr(), &mut xevent) };
+        xevent
+    }
+
+    /// Tries to get the next event before the timeout, sleeping until the connection is
+    /// readable in the meantime
+    fn next_event_timeout(&self, timeout: Duration) -> Option<XEvent> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            // Xlib may have read events already, and this also flushes our requests
+            if unsafe { (self.x.XPending)(self.display.as_ptr()) } > 0 {
+                return Some(self.next_event());
+            }
+
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return None;
+            }
+
+            match poll::wait_readable([self.as_raw_fd()], Some(remaining)) {
+                Ok(_) => continue,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                // Better give up than spin on a connection we can't wait on
+                Err(_) => return None,
+            }
+        }
+    }
+
+    /// Gets the next event if it comes before the deadline, or waits forever without one.
+    /// Gives up early once cancelled, see [`X11Clipboard::with_cancellation`].
+    fn next_event_until(&self, deadline: Option<Instant>) -> Option<XEvent> {
+        // How long we may take to notice we're cancelled
+        const CANCEL_CHECK_INTERVAL: Duration = Duration::from_millis(50);
+
+        if self.cancellation.borrow().is_some() {
+            loop {
+                if self.is_cancelled() {
+                    return None;
+                }
+
+                let check_at = Instant::now() + CANCEL_CHECK_INTERVAL;
+                let wake_up = deadline.map_or(check_at, |deadline| deadline.min(check_at));
+                let xevent =
+                    self.next_event_timeout(wake_up.saturating_duration_since(Instant::now()));
+                if xevent.is_some() || deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                    return xevent;
+                }
+            }
+        }
+
+        match deadline {
+            Some(deadline) => {
+                self.next_event_timeout(deadline.saturating_duration_since(Instant::now()))
+            }
+            None => Some(self.next_event()),
+        }
+    }
+
+    /// Gets the next event for pasting if it comes before the deadline. Events for the owner
+    /// window (and for the requestors it serves) are put aside for the owner.
+    fn next_reader_event_until(&self, deadline: Option<Instant>) -> Option<XEvent> {
+        loop {
+            let xevent = self.next_event_until(deadline)?;
+            if let Some(xevent) = self.reader_event(xevent) {
+                return Some(xevent);
+            }
+        }
+    }
+
+    /// Gives back the event if it's for pasting, or puts it aside for the owner
+    fn reader_event(&self, xevent: XEvent) -> Option<XEvent> {
+        if self.is_reader_event(&xevent) {
+            return Some(xevent);
+        }
+
+        self.defer_event(xevent);
+        None
+    }
+
+    /// Whether the event is about pasting: an answer from an owner, or a change of our window
+    fn is_reader_event(&self, xevent: &XEvent) -> bool {
+        unsafe {
+            match xevent.type_id {
+                et::PROPERTY_NOTIFY => xevent.xproperty.window == self.window,
+                et::SELECTION_NOTIFY => xevent.xselection.requestor == self.window,
+                _ => false,
+            }
+        }
+    }
+
+    /// Puts an event aside for the next owner loop or watcher
+    fn defer_event(&self, xevent: XEvent) {
+        // Nobody might ever serve them, so don't keep piling them up. Only selection clears
+        // and answers to pastes can go past that, and there are never many of them.
+        const MAX_DEFERRED_EVENTS: usize = 256;
+
+        // Requestors hang until their request is answered, we have to know about the
+        // selections we lose, and pastes of the driver hang until their owner's answers are
+        // read (see `get_compliant_timestamp`), so everything else goes first
+        let is_expendable = |xevent: &XEvent| {
+            !matches!(
+                unsafe { xevent.type_id },
+                et::SELECTION_REQUEST | et::SELECTION_CLEAR
+            ) && !self.is_reader_event(xevent)
+        };
+
+        let mut deferred = self.deferred_events.borrow_mut();
+        if deferred.len() >= MAX_DEFERRED_EVENTS {
+            match deferred.iter().position(is_expendable) {
+                Some(index) => {
+                    deferred.remove(index);
+                }
+                None if is_expendable(&xevent) => return,
+                None => {
+                    let request = deferred
+                        .iter()
+                        .position(|xevent| unsafe { xevent.type_id } == et::SELECTION_REQUEST)
+                        .and_then(|index| deferred.remove(index));
+
+                    if let Some(request) = request {
+                        let request = unsafe { request.xselectionrequest };
+                        self.refuse_conversion(&request, "too many requests at once");
+                    }
+                }
+            }
+        }
+        deferred.push_back(xevent);
+    }
+
+    /// Gets the next event for serving if it comes before the deadline, starting with the ones
+    /// that came while we were pasting
+    fn next_owner_event_until(&self, deadline: Option<Instant>) -> Option<XEvent> {
+        let deferred = self.deferred_events.borrow_mut().pop_front();
+        deferred.or_else(|| self.next_event_until(deadline))
+    }
+
+    /// Handles the events that already arrived without blocking, and gives how many there were.
+    ///
+    /// This is for apps waiting on the connection in their own event loop (see the [`AsRawFd`]
+    /// impl), whenever it's readable and clipbox isn't busy waiting for events itself. Requests
+    /// are refused right away since nothing serves them, so that requestors don't hang, and
+    /// selection changes are kept for watchers.
+    pub fn process_pending_events(&self) -> usize {
+        let mut processed = 0;
+        while unsafe { (self.x.XPending)(self.display.as_ptr()) } > 0 {
+            let xevent = self.next_event();
+            processed += 1;
+
+            match unsafe { xevent.type_id } {
+                et::SELECTION_REQUEST => {
+                    let request = unsafe { xevent.xselectionrequest };
+                    self.refuse_conversion(&request, "we're not serving anything right now");
+                }
+                et::SELECTION_CLEAR => {
+                    let atom_selection = unsafe { xlib::selection_clear(&xevent).selection };
+                    if atom_selection == self.atoms.clipboard {
+                        self.saved_clipboard.take();
+                    }
+
+                    self.emit(|| ClipboardEvent::OwnershipLost {
+                        selection: self.atom_name_lossy(atom_selection),
+                    });
+                }
+                // Late replies to pastes that gave up on them
+                et::SELECTION_NOTIFY | et::PROPERTY_NOTIFY => {}
+                _ => self.defer_event(xevent),
+            }
+        }
+
+        processed
+    }
+
+    /// Get a compliant timestamp for selection requests
+    ///
+    /// # Convention
+    ///
+    /// *Clients attempting to acquire a selection must set the time value of the
+    /// `SetSelectionOwner` request to the timestamp of the event triggering the
+    /// acquisition attempt, not to `CurrentTime`. A zero-length append to a property
+    /// is a way to obtain a timestamp for this purpose; the timestamp is in the
+    /// corresponding `PropertyNotify` event.*
+    ///
+    /// [ICCCM - Acquiring Selection Ownership](https://tronche.com/gui/x/icccm/sec-2.html#s-2.1)
+    ///
+    /// Gives up after `timeout`.
+    fn get_compliant_timestamp(&self, timeout: Option<Duration>) -> Option<c_ulong> {
+        self.request_timestamp();
+
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            let xevent = self.next_reader_event_until(deadline)?;
+            if let Some(time) = self.timestamp_of(&xevent) {
+                return Some(time);
+            }
+
+            // Pastes can go on alongside, see `ClipboardDriver::get`
+            self.defer_event(xevent);
+        }
+    }
+
+    /// Asks the X server for a timestamp, see [`X11Clipboard::get_compliant_timestamp`]
+    fn request_timestamp(&self) {
+        // Send dummy change property request to obtain a timestamp from its resulting event
+        // This is because it is disincentivized to use CurrentTime when sending a ConvertSelection request
+        unsafe {
+            (self.x.XChangeProperty)(
+                self.display.as_ptr(),
+                self.window,
+                self.atoms.clipbox_dummy,
+                self.atoms.string,
+                8,
+                prop_mode::APPEND,
+                std::ptr::null(),
+                0,
+            );
+        }
+    }
+
+    /// The timestamp in the event, if it answers [`X11Clipboard::request_timestamp`]
+    fn timestamp_of(&self, xevent: &XEvent) -> Option<c_ulong> {
+        if unsafe { xevent.type_id } != et::PROPERTY_NOTIFY {
+            return None;
+        }
+
+        let xevent = unsafe { xevent.xproperty };
+        (xevent.atom == self.atoms.clipbox_dummy).then_some(xevent.time)
+    }
+}
+
+#[derive(Debug)]
+pub(crate) enum GetSelectionError {
+    SelectionLost,
+    GetPropertyFailed(i32),
+    /// The owner said it answered, but didn't write anything (not even an empty property)
+    NoDataInProperty,
+    PropertyInvalidFormat(PropertyInvalidFormatError),
+    InvalidUtf8(FromUtf8Error),
+    #[cfg(feature = "png")]
+    InvalidImage(ImageError),
+    /// See [`Timeouts`]
+    Timeout,
+    /// See [`X11Clipboard::with_cancellation`]
+    Cancelled,
+    /// The X connection couldn't be registered with the async runtime
+    #[cfg(feature = "tokio")]
+    Io(io::Error),
+}
+
+impl Error for GetSelectionError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            #[cfg(feature = "tokio")]
+            Self::Io(err) => Some(err),
+            Self::PropertyInvalidFormat(err) => Some(err),
+            Self::InvalidUtf8(err) => Some(err),
+            #[cfg(feature = "png")]
+            Self::InvalidImage(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for GetSelectionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::SelectionLost => write!(
+                f,
+                "Selection lost (probably a bug with the clipbox library, how did that happen?)"
+            ),
+            Self::GetPropertyFailed(status) => {
+                write!(f, "Couldn't get property (error code: {})", status)
+            }
+            Self::NoDataInProperty => write!(f, "No data in our dedicated X11 property (how even)"),
+            Self::PropertyInvalidFormat(err) => err.fmt(f),
+            Self::InvalidUtf8(err) => write!(f, "The selection isn't valid UTF-8: {}", err),
+            #[cfg(feature = "png")]
+            Self::InvalidImage(err) => err.fmt(f),
+            Self::Timeout => write!(f, "Timed out waiting for the selection owner"),
+            Self::Cancelled => write!(f, "Cancelled while waiting for the selection owner"),
+            #[cfg(feature = "tokio")]
+            Self::Io(err) => write!(f, "Couldn't wait for the X server: {}", err),
+        }
+    }
+}
+
+impl From<PropertyInvalidFormatError> for GetSelectionError {
+    fn from(value: PropertyInvalidFormatError) -> Self {
+        Self::PropertyInvalidFormat(value)
+    }
+}
+
+impl From<FromUtf8Error> for GetSelectionError {
+    fn from(value: FromUtf8Error) -> Self {
+        Self::InvalidUtf8(value)
+    }
+}
+
+#[cfg(feature = "png")]
+impl From<ImageError> for GetSelectionError {
+    fn from(value: ImageError) -> Self {
+        Self::InvalidImage(value)
+    }
+}
+
+impl AsRawFd for X11Clipboard {
+    /// The X connection, which becomes readable when events arrive
+    fn as_raw_fd(&self) -> RawFd {
+        unsafe { (self.xlib.XConnectionNumber)(self.display.as_ptr()) }
+    }
+}
+
+impl AsFd for X11Clipboard {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        // The connection stays open until we're dropped
+        unsafe { BorrowedFd::borrow_raw(self.as_raw_fd()) }
+    }
+}
+
+// Paste (get selection)
+impl X11Clipboard {
+    /// Asks the owner to convert the selection into `atom_property` on our window,
+    /// and waits for its answer
+    fn get_selection_event(
+        &self,
+        atom_selection: Atom,
+        atom_target: Atom,
+        atom_property: Atom,
+    ) -> Result<XSelectionEvent, GetSelectionError> {
+        let timeout = self.timeouts.get().conversion;
+        let when_everything_started = self
+            .get_compliant_timestamp(timeout)
+            .ok_or_else(|| self.gave_up_pasting())?;
+
+        self.convert_selection(
+            atom_selection,
+            atom_target,
+            atom_property,
+            when_everything_started,
+        );
+
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            let xevent = self
+                .next_reader_event_until(deadline)
+                .ok_or_else(|| self.gave_up_pasting())?;
+
+            if let Some(reply) =
+                self.selection_reply(&xevent, atom_selection, atom_target, atom_property)
+            {
+                return reply;
+            }
+        }
+    }
+
+    /// Sends a `ConvertSelection` request, for the owner to answer on our window
+    fn convert_selection(
+        &self,
+        atom_selection: Atom,
+        atom_target: Atom,
+        atom_property: Atom,
+        time: c_ulong,
+    ) {
+        unsafe {
+            (self.x.XConvertSelection)(
+                self.display.as_ptr(),
+                atom_selection,
+                atom_target,
+                atom_property,
+                self.window,
+                time,
+            );
+        }
+    }
+
+    /// The owner's answer in the event, if it's the `SelectionNotify` for our conversion of the
+    /// selection into `atom_property`.
+    ///
+    /// Late answers to conversions we gave up on are ignored, so that they don't fail the
+    /// transfers that came after.
+    fn selection_reply(
+        &self,
+        xevent: &XEvent,
+        atom_selection: Atom,
+        atom_target: Atom,
+        atom_property: Atom,
+    ) -> Option<Result<XSelectionEvent, GetSelectionError>> {
+        if unsafe { xevent.type_id } != et::SELECTION_NOTIFY {
+            return None;
+        }
+
+        let xevent = unsafe { xevent.xselection };
+        if xevent.requestor != self.window {
+            return None;
+        }
+
+        // Refusals don't say which property they're about, the selection and target have to do
+        let converted = xevent.selection == atom_selection && xevent.target == atom_target;
+        if !converted || (xevent.property != 0 && xevent.property != atom_property) {
+            self.discard_stale_reply(xevent.property, atom_property);
+            return None;
+        }
+
+        if xevent.property == 0 {
+            return Some(Err(GetSelectionError::SelectionLost));
+        }
+
+        Some(Ok(xevent))
+    }
+
+    /// Deletes what a late answer left in a property no transfer uses anymore, so that it's
+    /// clean for the next transfer we hand it out to
+    fn discard_stale_reply(&self, property: Atom, atom_property: Atom) {
+        // Our own property may already hold the answer we're waiting for
+        if property == 0 || property == atom_property {
+            return;
+        }
+
+        if self.free_reply_properties.borrow().contains(&property) {
+            unsafe { (self.x.XDeleteProperty)(self.display.as_ptr(), self.window, property) };
+        }
+    }
+
+    /// Gets a property of our window for one transfer to reply into, so that overlapping
+    /// transfers don't overwrite each other. It's handed out again once dropped.
+    fn reply_property(&self) -> ReplyProperty<'_> {
+        ReplyProperty {
+            clipboard: self,
+            atom: self.take_reply_property(),
+        }
+    }
+
+    /// Like [`X11Clipboard::reply_property`], for transfers that outlive a borrow of us. The
+    /// property has to be given back with [`X11Clipboard::free_reply_property`].
+    fn take_reply_property(&self) -> Atom {
+        // The one freed the longest ago, which late answers to its last transfer are the least
+        // likely to still write into
+        let atom = self.free_reply_properties.borrow_mut().pop_front();
+        atom.unwrap_or_else(|| {
+            // Atoms live as long as the X server, so we recycle a few of them rather than
+            // making up new ones forever
+            let count = self.reply_property_count.get();
+            self.reply_property_count.set(count + 1);
+
+            let name = CString::new(format!("CLIPBOX_{}", count)).unwrap();
+            self.intern(&name)
+        })
+    }
+
+    /// Hands a property out again once its transfer is over
+    fn free_reply_property(&self, atom: Atom) {
+        // Leave nothing behind for the next transfer
+        unsafe { (self.x.XDeleteProperty)(self.display.as_ptr(), self.window, atom) };
+        self.free_reply_properties.borrow_mut().push_back(atom);
+    }
+
+    fn get_window_property(
+        &self,
+        window: XWindow,
+        property: Atom,
+    ) -> Result<XWindowProperty<'_>, GetSelectionError> {
+        self.get_window_property_prefix(window, property, usize::MAX)
+    }
+
+    /// Gets at least the first `max_bytes` of a property, rounded up to 4 bytes
+    fn get_window_property_prefix(
+        &self,
+        window: XWindow,
+        property: Atom,
+        max_bytes: usize,
+    ) -> Result<XWindowProperty<'_>, GetSelectionError> {
+        let mut ty: Atom = 0;
+        let mut format: c_int = 8;
+        let mut nitems: c_ulong = 0;
+        let mut bytes_remaining: c_ulong = 0;
+        let mut data: *mut c_void = std::ptr::null_mut();
+
+        let status = unsafe {
+            let long_offset: c_long = 0;
+            // In 32-bit units, whatever the format
+            let long_length = max_bytes.div_ceil(4).min(c_long::MAX as usize) as c_long;
+            let delete: Bool = x11::bool::FALSE;
+            let req_type: Atom = 0;
+
+            (self.x.XGetWindowProperty)(
+                self.display.as_ptr(),
+                window,
+                property,
+                long_offset,
+                long_length,
+                delete,
+                req_type,
+                &mut ty,
+                &mut format,
+                &mut nitems,
+                &mut bytes_remaining,
+                &mut data,
+            )
+        };
+
+        if status != errcode::SUCCESS {
+            return Err(GetSelectionError::GetPropertyFailed(status));
+        }
+
+        let property = XWindowProperty {
+            x11: &self.x,
+            ty,
+            format,
+            nitems,
+            bytes_remaining,
+            data: NonNull::new(data),
+        };
+
+        // A property with no type doesn't exist. An empty one is fine though, it's an empty copy.
+        if property.ty == 0 {
+            return Err(GetSelectionError::NoDataInProperty);
+        }
+
+        Ok(property)
+    }
+
+    /// Gets the targets the owner of the selection can convert it to
+    pub fn get_targets(&self, selection: Selection) -> Result<Vec<TargetInfo>, ClipboxError> {
+        let targets = self
+            .get_target_atoms(selection)?
+            .into_iter()
+            .filter_map(|atom| Some(TargetInfo::new(atom, self.atom_name(atom)?)))
+            .collect::<Vec<_>>();
+
+        Ok(targets)
+    }
+
+    /// The names of the targets of the selection, for lookups
+    fn get_target_names(&self, selection: Selection) -> Result<TargetNames, GetSelectionError> {
+        let names = self
+            .get_target_atoms(selection)?
+            .into_iter()
+            .filter_map(|atom| self.atom_name(atom))
+            .collect::<Vec<_>>();
+
+        Ok(TargetNames(names))
+    }
+
+    fn get_target_atoms(&self, selection: Selection) -> Result<Vec<Atom>, GetSelectionError> {
+        let atom_selection = self.selection_atom(selection);
+        let property = self.reply_property();
+        self.get_selection_event(atom_selection, self.atoms.targets, property.atom)?;
+
+        let atoms = property.read()?.into_vec32()?;
+        Ok(atoms.into_iter().map(|atom| atom as Atom).collect())
+    }
+
+    pub fn get_selection(
+        &self,
+        selection: Selection,
+        target: &CStr,
+    ) -> Result<Vec<u8>, ClipboxError> {
+        Ok(self.get_selection_data(selection, target)?.into_owned())
+    }
+
+    /// Like [`X11Clipboard::get_selection`], giving up once `token` is cancelled
+    pub fn get_selection_cancellable(
+        &self,
+        selection: Selection,
+        target: &CStr,
+        token: &CancellationToken,
+    ) -> Result<Vec<u8>, ClipboxError> {
+        self.with_cancellation(token, |clipboard| {
+            clipboard.get_selection(selection, target)
+        })
+    }
+
+    /// Gets the selection without copying it when possible.
+    ///
+    /// Data that came in a single property read is borrowed straight from Xlib's buffer,
+    /// which is freed with the returned guard. Only INCR transfers are assembled into a `Vec`.
+    pub fn get_selection_data(
+        &self,
+        selection: Selection,
+        target: &CStr,
+    ) -> Result<SelectionData<'_>, ClipboxError> {
+        Ok(self.read_selection(selection, target)?)
+    }
+
+    /// [`X11Clipboard::get_selection_data`], keeping our own error to tell what went wrong
+    fn read_selection(
+        &self,
+        selection: Selection,
+        target: &CStr,
+    ) -> Result<SelectionData<'_>, GetSelectionError> {
+        let (property, clipbox_prop) = self.request_selection(selection, target)?;
+
+        if clipbox_prop.ty == self.atoms.incr {
+            // We got an INCR atom, fetch property incrementally
+            let mut receiver = IncrReceiver::new();
+
+            loop {
+                let chunk = self.next_incr_chunk(&property)?;
+                if receiver.push(&chunk) {
+                    break;
+                }
+            }
+
+            Ok(SelectionData(SelectionDataInner::Owned(receiver.finish())))
+        } else {
+            // Fail now rather than when the data is used
+            clipbox_prop.as_bytes()?;
+            Ok(SelectionData(SelectionDataInner::Borrowed(clipbox_prop)))
+        }
+    }
+
+    /// Gets the selection as a stream, without holding all of it in memory.
+    ///
+    /// Big selections come through INCR transfers, and each chunk is only requested from the
+    /// owner once the previous one was read. Dropping the reader halfway leaves the owner
+    /// waiting until it gives up on us.
+    pub fn get_selection_reader(
+        &self,
+        selection: Selection,
+        target: &CStr,
+    ) -> Result<SelectionReader<'_>, ClipboxError> {
+        let (property, clipbox_prop) = self.request_selection(selection, target)?;
+
+        let (chunk, done) = match clipbox_prop.ty == self.atoms.incr {
+            true => (Vec::new(), false),
+            false => (clipbox_prop.into_vec()?, true),
+        };
+
+        Ok(SelectionReader {
+            property,
+            chunk,
+            pos: 0,
+            done,
+        })
+    }
+
+    /// Gets the selection straight into a writer (a file, a socket, a hasher...),
+    /// returning how many bytes were written.
+    ///
+    /// INCR chunks are written as they arrive, so the selection is never held in memory
+    /// all at once.
+    pub fn get_selection_into(
+        &self,
+        selection: Selection,
+        target: &CStr,
+        mut writer: impl Write,
+    ) -> Result<usize, ClipboxError> {
+        let (property, clipbox_prop) = self.request_selection(selection, target)?;
+
+        if clipbox_prop.ty != self.atoms.incr {
+            let data = clipbox_prop.as_bytes()?;
+            writer.write_all(data).map_err(ClipboxError::Io)?;
+            return Ok(data.len());
+        }
+
+        let mut written = 0;
+        loop {
+            let chunk = self.next_incr_chunk(&property)?;
+            if chunk.is_empty() {
+                break;
+            }
+
+            writer.write_all(&chunk).map_err(ClipboxError::Io)?;
+            written += chunk.len();
+        }
+
+        Ok(written)
+    }
+
+    /// Converts the selection into a property of ours and reads it back.
+    /// For INCR transfers, that's only the beginning: the rest comes through the same property.
+    fn request_selection(
+        &self,
+        selection: Selection,
+        target: &CStr,
+    ) -> Result<(ReplyProperty<'_>, XWindowProperty<'_>), GetSelectionError> {
+        self.request_selection_prefix(selection, target, usize::MAX)
+    }
+
+    /// Like [`X11Clipboard::request_selection`], only reading about `max_bytes` of the property
+    fn request_selection_prefix(
+        &self,
+        selection: Selection,
+        target: &CStr,
+        max_bytes: usize,
+    ) -> Result<(ReplyProperty<'_>, XWindowProperty<'_>), GetSelectionError> {
+        if target == atom_names::TARGETS {
+            panic!(concat!(
+                "TARGETS is a special selection target, this method doesn't support it.",
+                " Try X11Clipboard::get_targets instead!"
+            ));
+        }
+
+        let atom_selection = self.selection_atom(selection);
+        let atom_target = self.intern(target);
+        let property = self.reply_property();
+        self.get_selection_event(atom_selection, atom_target, property.atom)?;
+
+        let clipbox_prop = property.read_prefix(max_bytes)?;
+
+        Ok((property, clipbox_prop))
+    }
+
+    /// Asks for the next chunk of an INCR transfer into its property, and waits for it
+    fn next_incr_chunk(&self, property: &ReplyProperty) -> Result<Vec<u8>, GetSelectionError> {
+        self.wait_for_incr_chunk(property)?;
+        Ok(property.read()?.into_vec::<u8>()?)
+    }
+
+    /// Asks for the next chunk of an INCR transfer, and waits until it's in the property
+    fn wait_for_incr_chunk(&self, property: &ReplyProperty) -> Result<(), GetSelectionError> {
+        // Deleting the property tells the owner we're ready for more
+        unsafe { (self.x.XDeleteProperty)(self.display.as_ptr(), self.window, property.atom) };
+
+        // Waiting for a `PropertyNotify` with the state argument `NewValue`
+        let deadline = self
+            .timeouts
+            .get()
+            .incr_chunk
+            .map(|timeout| Instant::now() + timeout);
+        loop {
+            let xevent = self
+                .next_reader_event_until(deadline)
+                .ok_or_else(|| self.gave_up_pasting())?;
+
+            if self.is_incr_chunk(&xevent, property.atom) {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Whether the event tells us the next chunk of an INCR transfer is in `property`
+    fn is_incr_chunk(&self, xevent: &XEvent, property: Atom) -> bool {
+        if unsafe { xevent.type_id } != et::PROPERTY_NOTIFY {
+            return false;
+        }
+
+        // Other transfers may be going on in other properties
+        let xevent = unsafe { xevent.xproperty };
+        xevent.state == property::NEW_VALUE
+            && xevent.window == self.window
+            && xevent.atom == property
+    }
+}
+
+/// Pasted data, see [`X11Clipboard::get_selection_data`]
+pub struct SelectionData<'a>(SelectionDataInner<'a>);
+
+enum SelectionDataInner<'a> {
+    /// Still in Xlib's buffer, which is always an 8-bit property
+    Borrowed(XWindowProperty<'a>),
+    Owned(Vec<u8>),
+}
+
+impl<'a> SelectionData<'a> {
+    pub fn as_bytes(&self) -> &[u8] {
+        match &self.0 {
+            // Checked when we got it
+            SelectionDataInner::Borrowed(prop) => prop.as_bytes().unwrap_or_default(),
+            SelectionDataInner::Owned(data) => data,
+        }
+    }
+
+    /// The data as text, failing if it isn't valid UTF-8
+    pub fn to_str(&self) -> Result<&str, std::str::Utf8Error> {
+        std::str::from_utf8(self.as_bytes())
+    }
+
+    /// The data as text, only copied if it has to replace invalid UTF-8
+    pub fn to_string_lossy(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(self.as_bytes())
+    }
+
+    /// Whether the data is still in Xlib's buffer
+    pub fn is_borrowed(&self) -> bool {
+        matches!(self.0, SelectionDataInner::Borrowed(_))
+    }
+
+    /// Copies the data out, unless it's owned already
+    pub fn into_owned(self) -> Vec<u8> {
+        match self.0 {
+            SelectionDataInner::Borrowed(prop) => prop.into_vec().unwrap_or_default(),
+            SelectionDataInner::Owned(data) => data,
+        }
+    }
+}
+
+impl<'a> AsRef<[u8]> for SelectionData<'a> {
+    fn as_ref(&self) -> &[u8] {
+        self.as_bytes()
+    }
+}
+
+impl<'a> fmt::Debug for SelectionData<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SelectionData")
+            .field("len", &self.as_bytes().len())
+            .field("borrowed", &self.is_borrowed())
+            .finish()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TargetInfo {
+    pub atom: Atom,
+    pub name: String,
+    /// The MIME type of the data, for MIME targets and the usual X11 text targets
+    pub mime: Option<Mime>,
+}
+
+impl TargetInfo {
+    fn new(atom: Atom, name: CString) -> Self {
+        let name = name
+            .into_string()
+            .unwrap_or_else(|err| err.into_cstring().to_string_lossy().into_owned());
+
+        // X11 predates MIME, its text targets have their own names
+        let mime = match name.as_str() {
+            "UTF8_STRING" => Mime::parse("text/plain;charset=utf-8"),
+            "STRING" => Mime::parse("text/plain;charset=iso-8859-1"),
+            "TEXT" => Mime::parse("text/plain"),
+            _ => Mime::parse(&name),
+        };
+
+        Self { atom, name, mime }
+    }
+}
+
+/// Target names, to check which ones are on offer
+struct TargetNames(Vec<CString>);
+
+impl TargetNames {
+    fn contains(&self, target: &CStr) -> bool {
+        self.0.iter().any(|name| name.as_c_str() == target)
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &CStr> {
+        self.0.iter().map(|name| name.as_c_str())
+    }
+}
+
+/// A selection being pasted chunk by chunk, see [`X11Clipboard::get_selection_reader`]
+pub struct SelectionReader<'a> {
+    /// Where the chunks come in
+    property: ReplyProperty<'a>,
+    chunk: Vec<u8>,
+    /// How much of the chunk was read already
+    pos: usize,
+    /// Whether there are no more chunks to come
+    done: bool,
+}
+
+impl<'a> Read for SelectionReader<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.chunk.len() {
+            if self.done {
+                return Ok(0);
+            }
+
+            let clipboard = self.property.clipboard;
+            self.chunk = clipboard
+                .next_incr_chunk(&self.property)
+                .map_err(io::Error::other)?;
+            self.pos = 0;
+            // An empty chunk marks the end of the transfer
+            self.done = self.chunk.is_empty();
+        }
+
+        let len = buf.len().min(self.chunk.len() - self.pos);
+        buf[..len].copy_from_slice(&self.chunk[self.pos..self.pos + len]);
+        self.pos += len;
+
+        Ok(len)
+    }
+}
+
+// Paste, negotiating the target
+impl X11Clipboard {
+    /// Gets the selection as the first of the preferred targets that the owner offers.
+    ///
+    /// Targets are fetched once, and the target that was picked is returned with the data.
+    pub fn get_selection_preferring<'p>(
+        &self,
+        selection: Selection,
+        preferences: &[&'p CStr],
+    ) -> Result<(&'p CStr, Vec<u8>), ClipboxError> {
+        let targets = self.get_target_names(selection)?;
+
+        let &target = preferences
+            .iter()
+            .find(|target| targets.contains(target))
+            .ok_or(ClipboxError::NotAvailable)?;
+
+        Ok((target, self.get_selection(selection, target)?))
+    }
+
+    /// Whether the selection is currently available as `target`, without fetching any data.
+    ///
+    /// Failing to get the targets counts as not having it.
+    pub fn has_target(&self, selection: Selection, target: &CStr) -> bool {
+        self.get_target_names(selection)
+            .is_ok_and(|targets| targets.contains(target))
+    }
+
+    /// Whether there's nothing to paste from the selection: nobody owns it,
+    /// or its owner doesn't offer any actual data.
+    pub fn is_empty(&self, selection: Selection) -> bool {
+        if self.selection_owner(selection).is_none() {
+            return true;
+        }
+
+        match self.get_target_names(selection) {
+            Ok(targets) => targets.iter().all(is_meta_target),
+            Err(_) => true,
+        }
+    }
+
+    /// Whether the owner says the selection is a secret (likely a password), by offering
+    /// `x-kde-passwordManagerHint` as `secret`. Clipboard managers shouldn't keep those.
+    pub fn is_secret(&self, selection: Selection) -> bool {
+        self.get_target_names(selection)
+            .is_ok_and(|targets| self.offers_secret(selection, targets.iter()))
+    }
+
+    /// See [`X11Clipboard::is_secret`], for targets we got already
+    pub(crate) fn offers_secret<'t>(
+        &self,
+        selection: Selection,
+        mut targets: impl Iterator<Item = &'t CStr>,
+    ) -> bool {
+        let hint = mime_types::KDE_PASSWORD_MANAGER_HINT;
+        targets.any(|target| target == hint)
+            && self
+                .get_selection(selection, hint)
+                .is_ok_and(|data| data.trim_ascii() == b"secret")
+    }
+}
+
+// Paste, everything at once
+impl X11Clipboard {
+    /// Captures every target of the selection along with its data, to archive or re-offer it.
+    ///
+    /// When the owner supports `MULTIPLE`, everything is fetched in a single conversion so that
+    /// the owner can't change in between. Otherwise (and for the targets that need an INCR
+    /// transfer) targets are fetched one by one. Targets the owner refuses, and those that
+    /// aren't made of bytes, are left out.
+    ///
+    /// The bundle keeps the owner's target order, so it can be given to `set_many` as-is.
+    pub fn snapshot(&self, selection: Selection) -> Result<TargetBundle, ClipboxError> {
+        let all_targets = self.get_target_names(selection)?;
+        let targets = all_targets
+            .iter()
+            .filter(|&target| !is_meta_target(target))
+            .collect::<Vec<_>>();
+
+        let mut bundle = TargetBundle::new().order(TargetOrder::AsAdded);
+
+        let mut fetched = Vec::new();
+        if all_targets.contains(atom_names::MULTIPLE) {
+            // If MULTIPLE fails, we can still get everything the slow way
+            fetched = self.get_multiple(selection, &targets).unwrap_or_default();
+        }
+
+        for target in targets {
+            if let Some((_, data)) = fetched.iter().find(|&&(t, _)| t == target) {
+                bundle = bundle.with(target, data.as_slice());
+                continue;
+            }
+
+            match self.read_selection(selection, target) {
+                Ok(data) => bundle = bundle.with(target, data.into_owned()),
+                Err(GetSelectionError::SelectionLost)
+                | Err(GetSelectionError::PropertyInvalidFormat(_)) => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        Ok(bundle)
+    }
+
+    /// Fetches several targets in one `MULTIPLE` conversion.
+    ///
+    /// Targets that were refused or would need an INCR transfer are not returned.
+    fn get_multiple<'t>(
+        &self,
+        selection: Selection,
+        targets: &[&'t CStr],
+    ) -> Result<Vec<(&'t CStr, Vec<u8>)>, GetSelectionError> {
+        let atom_multiple = self.intern(atom_names::MULTIPLE);
+        let atom_pair = self.intern(atom_names::ATOM_PAIR);
+
+        // Each target is converted into its own property, listed next to it in the MULTIPLE property
+        let multiple_property = self.reply_property();
+        let properties = (0..targets.len())
+            .map(|_| self.reply_property())
+            .collect::<Vec<_>>();
+
+        let pairs = targets
+            .iter()
+            .zip(&properties)
+            .flat_map(|(&target, property)| {
+                let atom_target = self.intern(target);
+                [atom_target as c_long, property.atom as c_long]
+            })
+            .collect::<Vec<_>>();
+
+        unsafe {
+            (self.x.XChangeProperty)(
+                self.display.as_ptr(),
+                self.window,
+                multiple_property.atom,
+                atom_pair,
+                32,
+                prop_mode::REPLACE,
+                pairs.as_ptr().cast(),
+                pairs.len() as c_int,
+            );
+
+            let atom_selection = self.selection_atom(selection);
+            self.get_selection_event(atom_selection, atom_multiple, multiple_property.atom)?;
+        }
+
+        // The owner replaced the properties of the targets it refused with None
+        let pairs = multiple_property.read()?.into_vec32()?;
+
+        let mut results = Vec::new();
+        for (&target, pair) in targets.iter().zip(pairs.chunks_exact(2)) {
+            let property = pair[1] as Atom;
+            if property == 0 {
+                continue;
+            }
+
+            let Ok(data) = self.get_window_property(self.window, property) else {
+                continue;
+            };
+
+            if data.ty == self.atoms.incr {
+                continue;
+            }
+
+            if let Ok(data) = data.into_vec::<u8>() {
+                results.push((target, data));
+            }
+        }
+
+        Ok(results)
+    }
+}
+
+/// Targets holding UTF-8 text, in order of preference
+const UTF8_TEXT_TARGETS: &[&CStr] = &[
+    atom_names::UTF8_STRING,
+    mime_types::TEXT_PLAIN_CHARSET_UTF8,
+    mime_types::TEXT_PLAIN,
+];
+
+/// Whether a target is about the selection itself rather than its data (like `TARGETS`)
+fn is_meta_target(target: &CStr) -> bool {
+    [
+        atom_names::TARGETS,
+        atom_names::MULTIPLE,
+        atom_names::TIMESTAMP,
+        atom_names::SAVE_TARGETS,
+        atom_names::DELETE,
+        c"INSERT_SELECTION",
+        c"INSERT_PROPERTY",
+    ]
+    .contains(&target)
+}
+
+/// Whether a target holds the selection's data. The password manager hint is copied along
+/// with the rest (so that clipboard managers saving the copy still see it), but it's not the
+/// data itself.
+fn is_data_target(target: &CStr) -> bool {
+    !is_meta_target(target) && target != mime_types::KDE_PASSWORD_MANAGER_HINT
+}
+
+/// Window ids as given in [`ClipboardEvent`]s. Xlib's are `c_ulong`, which is only 32 bits
+/// wide on some of the targets we build for.
+#[allow(clippy::useless_conversion)]
+fn window_id(window: XWindow) -> u64 {
+    u64::from(window)
+}
+
+/// Targets holding RTF, in order of preference (LibreOffice offers both)
+const RTF_TARGETS: &[&CStr] = &[mime_types::TEXT_RTF, mime_types::APPLICATION_RTF];
+
+// Paste, converting to a desired form
+impl X11Clipboard {
+    /// Gets the selection as text from whichever text target is available
+    fn get_any_text(
+        &self,
+        selection: Selection,
+        targets: &TargetNames,
+        decoding: Utf8Decoding,
+    ) -> Result<Option<String>, ClipboxError> {
+        if let Some(&target) = UTF8_TEXT_TARGETS.iter().find(|t| targets.contains(t)) {
+            let text = self.get_selection(selection, target)?;
+            return Ok(Some(
+                decoding.decode(text).map_err(GetSelectionError::from)?,
+            ));
+        }
+
+        if targets.contains(atom_names::COMPOUND_TEXT) {
+            let text = self.get_selection(selection, atom_names::COMPOUND_TEXT)?;
+            return Ok(Some(convert::compound_text_to_string(&text)));
+        }
+
+        if targets.contains(atom_names::STRING) {
+            let text = self.get_selection(selection, atom_names::STRING)?;
+            return Ok(Some(convert::latin1_to_string(&text)));
+        }
+
+        Ok(None)
+    }
+
+    /// Gets the selection as UTF-8 text, failing if the data isn't valid UTF-8.
+    ///
+    /// Text from old apps is converted to UTF-8 when no UTF-8 target is available:
+    /// `COMPOUND_TEXT` first since it can hold more characters, then Latin-1 `STRING`.
+    pub fn get_text(&self, selection: Selection) -> Result<String, ClipboxError> {
+        self.get_text_with(selection, Utf8Decoding::Strict)
+    }
+
+    /// Gets the selection as UTF-8 text, choosing what happens to invalid UTF-8
+    pub fn get_text_with(
+        &self,
+        selection: Selection,
+        decoding: Utf8Decoding,
+    ) -> Result<String, ClipboxError> {
+        let targets = self.get_target_names(selection)?;
+
+        let text = self
+            .get_any_text(selection, &targets, decoding)?
+            .ok_or(ClipboxError::NotAvailable)?;
+        Ok(self.transforms.apply(Stage::Paste, &text))
+    }
+
+    /// Gets the selection as HTML, preferring `text/html` and otherwise escaping plain text
+    pub fn get_html(&self, selection: Selection) -> Result<String, ClipboxError> {
+        match self.get_as(selection, DesiredForm::Html)? {
+            Converted::Html(html) => Ok(html),
+            _ => Err(ClipboxError::NotAvailable),
+        }
+    }
+
+    /// Gets the selection as RTF, converting plain text if there's no RTF
+    pub fn get_rtf(&self, selection: Selection) -> Result<String, ClipboxError> {
+        match self.get_as(selection, DesiredForm::Rtf)? {
+            Converted::Rtf(rtf) => Ok(rtf),
+            _ => Err(ClipboxError::NotAvailable),
+        }
+    }
+
+    /// Gets the files copied from a file manager
+    pub fn get_files(&self, selection: Selection) -> Result<Vec<PathBuf>, ClipboxError> {
+        Ok(self.get_files_with_operation(selection)?.1)
+    }
+
+    /// Gets the files copied from a file manager, and whether they were copied or cut
+    pub fn get_files_with_operation(
+        &self,
+        selection: Selection,
+    ) -> Result<(FileOperation, Vec<PathBuf>), ClipboxError> {
+        let targets = self.get_target_names(selection)?;
+
+        if targets.contains(mime_types::GNOME_COPIED_FILES) {
+            let files = self.get_selection(selection, mime_types::GNOME_COPIED_FILES)?;
+            let files = String::from_utf8_lossy(&files);
+
+            // The first line says whether the files were copied or cut
+            let (operation, uris) = files.split_once('\n').unwrap_or((&files, ""));
+            let operation = match operation.trim() {
+                "cut" => FileOperation::Cut,
+                _ => FileOperation::Copy,
+            };
+
+            return Ok((operation, convert::uri_list_to_paths(uris)));
+        }
+
+        if targets.contains(mime_types::TEXT_URI_LIST) {
+            let list = self.get_selection(selection, mime_types::TEXT_URI_LIST)?;
+            let paths = convert::uri_list_to_paths(&String::from_utf8_lossy(&list));
+
+            let mut operation = FileOperation::Copy;
+            if targets.contains(mime_types::KDE_CUT_SELECTION) {
+                let cut = self.get_selection(selection, mime_types::KDE_CUT_SELECTION)?;
+                if cut.starts_with(b"1") {
+                    operation = FileOperation::Cut;
+                }
+            }
+
+            return Ok((operation, paths));
+        }
+
+        Err(ClipboxError::NotAvailable)
+    }
+
+    /// Gets the selection as a decoded image, from its `image/png` target
+    #[cfg(feature = "png")]
+    pub fn get_image(&self, selection: Selection) -> Result<ImageData, ClipboxError> {
+        match self.get_as(selection, DesiredForm::Image)? {
+            Converted::Image(image) => Ok(image),
+            _ => Err(ClipboxError::NotAvailable),
+        }
+    }
+
+    /// Gets the SVG source of the selection, from its `image/svg+xml` target
+    pub fn get_svg(&self, selection: Selection) -> Result<String, ClipboxError> {
+        let targets = self.get_target_names(selection)?;
+        if !targets.contains(mime_types::IMAGE_SVG) {
+            return Err(ClipboxError::NotAvailable);
+        }
+
+        let svg = self.get_selection(selection, mime_types::IMAGE_SVG)?;
+        Ok(String::from_utf8(svg).map_err(GetSelectionError::from)?)
+    }
+
+    /// Gets the selection in its best-typed form: files first, then images, HTML and text.
+    ///
+    /// If none of those are on offer, the raw bytes of the first regular target are returned.
+    pub fn get_content(&self, selection: Selection) -> Result<ClipboardContent, ClipboxError> {
+        let targets = self.get_target_names(selection)?;
+
+        let has_files = [mime_types::GNOME_COPIED_FILES, mime_types::TEXT_URI_LIST]
+            .iter()
+            .any(|target| targets.contains(target));
+        if has_files {
+            return Ok(ClipboardContent::Files(self.get_files(selection)?));
+        }
+
+        #[cfg(feature = "png")]
+        if targets.contains(mime_types::IMAGE_PNG) {
+            let png = self.get_selection(selection, mime_types::IMAGE_PNG)?;
+            let image = convert::decode_png(&png).map_err(GetSelectionError::from)?;
+            return Ok(ClipboardContent::Image(image));
+        }
+
+        if targets.contains(mime_types::TEXT_HTML) {
+            let html = self.get_selection(selection, mime_types::TEXT_HTML)?;
+            return Ok(ClipboardContent::Html(convert::html_from_bytes(&html)));
+        }
+
+        if let Some(text) = self.get_any_text(selection, &targets, Utf8Decoding::Lossy)? {
+            return Ok(ClipboardContent::Text(text));
+        }
+
+        let target = targets
+            .iter()
+            .find(|&target| is_data_target(target))
+            .ok_or(ClipboxError::NotAvailable)?;
+
+        let data = self.get_selection(selection, target)?;
+        Ok(ClipboardContent::Other(
+            target.to_string_lossy().into_owned(),
+            data,
+        ))
+    }
+
+    /// Gets the selection in the desired form.
+    ///
+    /// The native target for that form is tried first, and otherwise it's converted from
+    /// whatever else is available (HTML to text, text to HTML, PNG to raw RGBA).
+    pub fn get_as(
+        &self,
+        selection: Selection,
+        form: DesiredForm,
+    ) -> Result<Converted, ClipboxError> {
+        let targets = self.get_target_names(selection)?;
+
+        match form {
+            DesiredForm::PlainText => {
+                if let Some(text) = self.get_any_text(selection, &targets, Utf8Decoding::Lossy)? {
+                    return Ok(Converted::Text(text));
+                }
+
+                if targets.contains(mime_types::TEXT_HTML) {
+                    let html = self.get_selection(selection, mime_types::TEXT_HTML)?;
+                    let text = convert::html_to_text(&convert::html_from_bytes(&html));
+                    return Ok(Converted::Text(text));
+                }
+            }
+            DesiredForm::Html => {
+                if targets.contains(mime_types::TEXT_HTML) {
+                    let html = self.get_selection(selection, mime_types::TEXT_HTML)?;
+                    return Ok(Converted::Html(convert::html_from_bytes(&html)));
+                }
+
+                if let Some(text) = self.get_any_text(selection, &targets, Utf8Decoding::Lossy)? {
+                    return Ok(Converted::Html(convert::text_to_html(&text)));
+                }
+            }
+            DesiredForm::Rtf => {
+                if let Some(&target) = RTF_TARGETS.iter().find(|t| targets.contains(t)) {
+                    // RTF is 7-bit ASCII, but some writers embed raw bytes from their code page
+                    let rtf = self.get_selection(selection, target)?;
+                    return Ok(Converted::Rtf(String::from_utf8_lossy(&rtf).into_owned()));
+                }
+
+                if let Some(text) = self.get_any_text(selection, &targets, Utf8Decoding::Lossy)? {
+                    return Ok(Converted::Rtf(convert::text_to_rtf(&text)));
+                }
+            }
+            DesiredForm::Image =>
+            {
+                #[cfg(feature = "png")]
+                if targets.contains(mime_types::IMAGE_PNG) {
+                    let png = self.get_selection(selection, mime_types::IMAGE_PNG)?;
+                    let image = convert::decode_png(&png).map_err(GetSelectionError::from)?;
+                    return Ok(Converted::Image(image));
+                }
+            }
+        }
+
+        Err(ClipboxError::NotAvailable)
+    }
+}
+
+#[derive(Debug)]
+pub(crate) enum SetSelectionError {
+    /// Another client got the selection, `owner` being its window (if anyone has it)
+    NotOwner {
+        owner: Option<XWindow>,
+    },
+    PayloadTooLarge {
+        size: usize,
+        max_size: usize,
+    },
+    #[cfg(feature = "png")]
+    InvalidImage(ImageError),
+    #[cfg(feature = "svg")]
+    InvalidSvg(SvgError),
+    /// See [`Timeouts`]
+    Timeout,
+    /// See [`X11Clipboard::with_cancellation`]
+    Cancelled,
+    /// The background thread couldn't take over, see [`ServeUntil::Background`]
+    Background(Box<ClipboxError>),
+    /// The X connection couldn't be registered with the async runtime
+    #[cfg(feature = "tokio")]
+    Io(io::Error),
+}
+
+impl Error for SetSelectionError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            #[cfg(feature = "png")]
+            Self::InvalidImage(err) => Some(err),
+            #[cfg(feature = "svg")]
+            Self::InvalidSvg(err) => Some(err),
+            Self::Background(err) => Some(err.as_ref()),
+            #[cfg(feature = "tokio")]
+            Self::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for SetSelectionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotOwner { owner: Some(owner) } => write!(
+                f,
+                "Could not become the selection owner, window {:#x} has it",
+                owner
+            ),
+            Self::NotOwner { owner: None } => write!(f, "Could not become the selection owner"),
+            Self::PayloadTooLarge { size, max_size } => write!(
+                f,
+                "Payload too large: {} bytes, but the limit is {} bytes",
+                size, max_size
+            ),
+            #[cfg(feature = "png")]
+            Self::InvalidImage(err) => err.fmt(f),
+            #[cfg(feature = "svg")]
+            Self::InvalidSvg(err) => err.fmt(f),
+            Self::Timeout => write!(f, "Timed out waiting for the X server"),
+            Self::Cancelled => write!(f, "Cancelled while waiting for the X server"),
+            Self::Background(err) => write!(f, "Couldn't copy in the background: {}", err),
+            #[cfg(feature = "tokio")]
+            Self::Io(err) => write!(f, "Couldn't wait for the X server: {}", err),
+        }
+    }
+}
+
+#[cfg(feature = "png")]
+impl From<ImageError> for SetSelectionError {
+    fn from(value: ImageError) -> Self {
+        Self::InvalidImage(value)
+    }
+}
+
+#[cfg(feature = "svg")]
+impl From<SvgError> for SetSelectionError {
+    fn from(value: SvgError) -> Self {
+        Self::InvalidSvg(value)
+    }
+}
+
+impl From<GetSelectionError> for ClipboxError {
+    fn from(value: GetSelectionError) -> Self {
+        match value {
+            GetSelectionError::Timeout => Self::Timeout,
+            GetSelectionError::Cancelled => Self::Cancelled,
+            #[cfg(feature = "tokio")]
+            GetSelectionError::Io(err) => Self::Io(err),
+            GetSelectionError::InvalidUtf8(_) => Self::Format(Box::new(value)),
+            #[cfg(feature = "png")]
+            GetSelectionError::InvalidImage(_) => Self::Format(Box::new(value)),
+            _ => Self::Protocol(Box::new(value)),
+        }
+    }
+}
+
+impl From<PropertyInvalidFormatError> for ClipboxError {
+    fn from(value: PropertyInvalidFormatError) -> Self {
+        GetSelectionError::from(value).into()
+    }
+}
+
+impl From<SetSelectionError> for ClipboxError {
+    fn from(value: SetSelectionError) -> Self {
+        match value {
+            SetSelectionError::NotOwner { .. } => Self::Ownership,
+            SetSelectionError::PayloadTooLarge { size, max_size } => {
+                Self::TooLarge { size, max_size }
+            }
+            #[cfg(feature = "png")]
+            SetSelectionError::InvalidImage(_) => Self::Format(Box::new(value)),
+            #[cfg(feature = "svg")]
+            SetSelectionError::InvalidSvg(_) => Self::Format(Box::new(value)),
+            SetSelectionError::Timeout => Self::Timeout,
+            SetSelectionError::Cancelled => Self::Cancelled,
+            SetSelectionError::Background(err) => *err,
+            #[cfg(feature = "tokio")]
+            SetSelectionError::Io(err) => Self::Io(err),
+        }
+    }
+}
+
+/// What to do with data that is larger than the payload limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OversizePolicy {
+    /// Refuse to copy, returning [`ClipboxError::TooLarge`]
+    Reject,
+    /// Only copy the first bytes that fit. UTF-8 text is cut on a character boundary.
+    Truncate,
+}
+
+/// Maximum amount of data accepted when copying
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PayloadLimit {
+    pub max_size: usize,
+    pub policy: OversizePolicy,
+}
+
+impl PayloadLimit {
+    /// Applies the limit on data that is about to be copied
+    fn apply<'d>(&self, target: &CStr, data: &'d [u8]) -> Result<&'d [u8], SetSelectionError> {
+        if data.len() <= self.max_size {
+            return Ok(data);
+        }
+
+        match self.policy {
+            OversizePolicy::Reject => Err(SetSelectionError::PayloadTooLarge {
+                size: data.len(),
+                max_size: self.max_size,
+            }),
+            OversizePolicy::Truncate => {
+                let mut end = self.max_size;
+
+                if UTF8_TEXT_TARGETS.contains(&target) {
+                    // Don't leave half a character at the end
+                    if let Err(err) = std::str::from_utf8(&data[..end]) {
+                        if err.error_len().is_none() {
+                            end = err.valid_up_to();
+                        }
+                    }
+                }
+
+                Ok(&data[..end])
+            }
+        }
+    }
+}
+
+/// The order in which a [`TargetBundle`] advertises its targets in `TARGETS`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TargetOrder {
+    /// Richest formats first (images, then rich text, then plain text), since some requestors
+    /// just pick the first target they understand. Targets of equal richness keep their order.
+    #[default]
+    RichestFirst,
+    /// Exactly the order in which the targets were added
+    AsAdded,
+}
+
+/// A set of targets to offer together, from a single selection ownership
+#[derive(Debug, Clone, Default)]
+pub struct TargetBundle {
+    offers: Vec<(CString, Vec<u8>)>,
+    order: TargetOrder,
+}
+
+impl TargetBundle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a target with its data. Adding the same target twice replaces its data.
+    pub fn with(mut self, target: &CStr, data: impl Into<Vec<u8>>) -> Self {
+        let data = data.into();
+
+        match self.offers.iter_mut().find(|(t, _)| t.as_c_str() == target) {
+            Some((_, existing)) => *existing = data,
+            None => self.offers.push((target.to_owned(), data)),
+        }
+
+        self
+    }
+
+    pub fn order(mut self, order: TargetOrder) -> Self {
+        self.order = order;
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.offers.is_empty()
+    }
+
+    /// The data of a target, if it's in the bundle
+    pub fn get(&self, target: &CStr) -> Option<&[u8]> {
+        self.offers
+            .iter()
+            .find(|(t, _)| t.as_c_str() == target)
+            .map(|(_, data)| data.as_slice())
+    }
+
+    /// The targets and their data, in the order they were added
+    pub fn iter(&self) -> impl Iterator<Item = (&CStr, &[u8])> {
+        self.offers
+            .iter()
+            .map(|(target, data)| (target.as_c_str(), data.as_slice()))
+    }
+
+    /// The targets [`X11Clipboard::get_content`] would have gotten the content from, so that
+    /// it can be copied back. Files are copied, not cut.
+    pub fn from_content(content: &ClipboardContent) -> Result<Self, ClipboxError> {
+        let bundle = Self::new();
+
+        Ok(match content {
+            ClipboardContent::Text(text) => bundle.with_text(text),
+            ClipboardContent::Html(html) => bundle
+                .with(mime_types::TEXT_HTML, html.as_str())
+                .with_text(&convert::html_to_text(html)),
+            #[cfg(feature = "png")]
+            ClipboardContent::Image(image) => {
+                let png = convert::encode_png(image).map_err(SetSelectionError::from)?;
+                bundle.with(mime_types::IMAGE_PNG, png)
+            }
+            // Nothing decodes images without PNG support, so this never came from us
+            #[cfg(not(feature = "png"))]
+            ClipboardContent::Image(_) => bundle,
+            ClipboardContent::Files(paths) => bundle.with_files(paths, FileOperation::Copy),
+            ClipboardContent::Other(target, data) => match CString::new(target.as_str()) {
+                Ok(target) => bundle.with(&target, data.as_slice()),
+                Err(_) => bundle,
+            },
+        })
+    }
+
+    /// Adds text as `UTF8_STRING` and `text/plain;charset=utf-8`
+    fn with_text(self, text: &str) -> Self {
+        self.with(atom_names::UTF8_STRING, text)
+            .with(mime_types::TEXT_PLAIN_CHARSET_UTF8, text)
+    }
+
+    /// Adds files in the formats file managers understand, along with their paths as text
+    fn with_files(self, paths: &[PathBuf], operation: FileOperation) -> Self {
+        let paths = paths
+            .iter()
+            .map(|path| std::path::absolute(path).unwrap_or_else(|_| path.clone()))
+            .collect::<Vec<_>>();
+
+        let uris = paths
+            .iter()
+            .map(|path| convert::path_to_file_uri(path))
+            .collect::<Vec<_>>();
+
+        let text = paths
+            .iter()
+            .map(|path| path.to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        self.with(
+            mime_types::GNOME_COPIED_FILES,
+            format!("{}\n{}", operation.as_gnome_str(), uris.join("\n")),
+        )
+        .with(
+            mime_types::TEXT_URI_LIST,
+            convert::paths_to_uri_list(&paths),
+        )
+        .with(mime_types::KDE_CUT_SELECTION, operation.as_kde_str())
+        .with_text(&text)
+    }
+
+    /// The targets and their data, in advertisement order
+    fn ordered(&self) -> Vec<(&CStr, &[u8])> {
+        let mut offers = self
+            .offers
+            .iter()
+            .map(|(target, data)| (target.as_c_str(), data.as_slice()))
+            .collect::<Vec<_>>();
+
+        if self.order == TargetOrder::RichestFirst {
+            offers.sort_by_key(|&(target, _)| std::cmp::Reverse(target_richness(target)));
+        }
+
+        offers
+    }
+}
+
+/// Builds up the formats to copy, then [`set`](Offer::set)s them all from one ownership.
+///
+/// Get one from [`X11Clipboard::offer`] or `Clipboard::offer`.
+pub struct Offer<'a> {
+    bundle: TargetBundle,
+    /// The first thing that went wrong while adding formats, reported by `set`
+    error: Option<ClipboxError>,
+    set_many: Box<SetMany<'a>>,
+}
+
+type SetMany<'a> = dyn FnOnce(&TargetBundle) -> Result<(), ClipboxError> + 'a;
+
+impl<'a> Offer<'a> {
+    pub(crate) fn new(
+        set_many: impl FnOnce(&TargetBundle) -> Result<(), ClipboxError> + 'a,
+    ) -> Self {
+        Self {
+            bundle: TargetBundle::new(),
+            error: None,
+            set_many: Box::new(set_many),
+        }
+    }
+
+    /// Offers plain UTF-8 text
+    pub fn text(mut self, text: &str) -> Self {
+        self.bundle = self.bundle.with_text(text);
+        self
+    }
+
+    pub fn html(mut self, html: &str) -> Self {
+        self.bundle = self.bundle.with(mime_types::TEXT_HTML, html);
+        self
+    }
+
+    pub fn rtf(mut self, rtf: &str) -> Self {
+        self.bundle = self
+            .bundle
+            .with(mime_types::TEXT_RTF, rtf)
+            .with(mime_types::APPLICATION_RTF, rtf);
+        self
+    }
+
+    /// Offers an already encoded PNG image
+    pub fn png(mut self, png: impl Into<Vec<u8>>) -> Self {
+        self.bundle = self.bundle.with(mime_types::IMAGE_PNG, png);
+        self
+    }
+
+    /// Offers RGBA pixels, encoded as PNG
+    #[cfg(feature = "png")]
+    pub fn image(mut self, image: &ImageData) -> Self {
+        match convert::encode_png(image) {
+            Ok(png) => self.bundle = self.bundle.with(mime_types::IMAGE_PNG, png),
+            Err(err) => {
+                self.error
+                    .get_or_insert(SetSelectionError::from(err).into());
+            }
+        }
+        self
+    }
+
+    /// Offers an SVG image, along with a rasterized PNG with the `svg` feature
+    pub fn svg(mut self, svg: &str) -> Self {
+        self.bundle = self.bundle.with(mime_types::IMAGE_SVG, svg);
+
+        #[cfg(feature = "svg")]
+        match convert::rasterize_svg(svg) {
+            Ok(png) => self.bundle = self.bundle.with(mime_types::IMAGE_PNG, png),
+            Err(err) => {
+                self.error
+                    .get_or_insert(SetSelectionError::from(err).into());
+            }
+        }
+        self
+    }
+
+    pub fn files(mut self, paths: &[PathBuf], operation: FileOperation) -> Self {
+        self.bundle = self.bundle.with_files(paths, operation);
+        self
+    }
+
+    /// Offers any target with its raw data
+    pub fn target(mut self, target: &CStr, data: impl Into<Vec<u8>>) -> Self {
+        self.bundle = self.bundle.with(target, data);
+        self
+    }
+
+    /// Offers a typed value, as its format's MIME type
+    pub fn format<F: ClipboardFormat>(mut self, value: &F) -> Self {
+        match value.encode() {
+            Ok(data) => self.bundle = self.bundle.with(&format_target::<F>(), data),
+            Err(err) => {
+                self.error.get_or_insert(ClipboxError::Format(err));
+            }
+        }
+        self
+    }
+
+    pub fn order(mut self, order: TargetOrder) -> Self {
+        self.bundle = self.bundle.order(order);
+        self
+    }
+
+    /// Becomes the owner of the selection and serves everything that was offered
+    pub fn set(self) -> Result<(), ClipboxError> {
+        if let Some(err) = self.error {
+            return Err(err);
+        }
+
+        (self.set_many)(&self.bundle)
+    }
+}
+
+/// The target a format is copied as
+pub(crate) fn format_target<F: ClipboardFormat>() -> CString {
+    CString::new(F::mime()).expect("MIME types can't contain NUL bytes")
+}
+
+/// Roughly how much information a target carries, for [`TargetOrder::RichestFirst`]
+fn target_richness(target: &CStr) -> u8 {
+    let target = target.to_bytes();
+
+    if target.starts_with(b"image/") {
+        4
+    } else if target.starts_with(b"text/html")
+        || target.ends_with(b"/rtf")
+        || target == b"text/uri-list"
+    {
+        3
+    } else if target == atom_names::UTF8_STRING.to_bytes()
+        || target == mime_types::TEXT_PLAIN_CHARSET_UTF8.to_bytes()
+    {
+        1
+    } else if target.starts_with(b"text/plain")
+        || target == atom_names::STRING.to_bytes()
+        || target == atom_names::COMPOUND_TEXT.to_bytes()
+        || target == atom_names::TEXT.to_bytes()
+    {
+        0
+    } else {
+        // Some other mime type or X11 target, probably more specific than plain text
+        2
+    }
+}
+
+/// An incremental transfer to one requestor, see [`X11Clipboard::serve_owned`]
+struct IncrTransfer<'d> {
+    request: XSelectionRequestEvent,
+    sender: IncrSender<'d>,
+    /// When the requestor last asked for a chunk
+    last_activity: Instant,
+}
+
+/// The selections we own, with everything we serve from them
+struct Served<'d> {
+    selections: Vec<(Selection, Atom)>,
+    /// When we acquired it, for `TIMESTAMP`
+    acquired_at: c_ulong,
+    offers: Vec<(Atom, Payload<'d>)>,
+    /// Set once a requestor moved the contents away with `DELETE`
+    deleted: bool,
+}
+
+impl<'d> Served<'d> {
+    /// Which of our selections an atom is
+    fn selection(&self, atom: Atom) -> Option<Selection> {
+        self.selections
+            .iter()
+            .find(|&&(_, atom_selection)| atom_selection == atom)
+            .map(|&(selection, _)| selection)
+    }
+
+    /// The payload of one of our targets, if we can still send it
+    fn payload(&mut self, target: Atom) -> Option<&mut Payload<'d>> {
+        if self.deleted {
+            return None;
+        }
+
+        self.offers
+            .iter_mut()
+            .find(|(atom_target, _)| *atom_target == target)
+            .map(|(_, payload)| payload)
+            .filter(|payload| payload.is_available())
+    }
+}
+
+/// A serving loop in progress, see [`X11Clipboard::serve_owned`]
+struct Serving<'d> {
+    served: Served<'d>,
+    /// Several requestors can paste big data at the same time, each into its own property
+    incr_transfers: HashMap<(XWindow, Atom), IncrTransfer<'d>>,
+    idle: IdleTimeout,
+    until: Option<Instant>,
+    /// Whether we're handing off to the clipboard manager, which tells us once it's done
+    handing_off: bool,
+}
+
+/// The first of two deadlines, `None` being never
+fn earliest(a: Option<Instant>, b: Option<Instant>) -> Option<Instant> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+/// Deletes the source of what we copied, see [`X11Clipboard::set_delete_handler`]
+type DeleteHandler = RefCell<dyn FnMut(Selection)>;
+
+/// Renders the data of a target when someone asks for it, see [`X11Clipboard::offer_with`]
+type Provider<'d> = RefCell<dyn FnMut(&CStr) -> Vec<u8> + 'd>;
+
+/// Where the data of an offered target comes from
+enum Payload<'d> {
+    Bytes(&'d [u8]),
+    /// Owned by whoever serves it, see [`ClipboardThread`]
+    Shared(Rc<[u8]>),
+    /// Can only be read once, so it's taken by the first requestor
+    Stream(Option<&'d mut dyn Read>),
+    /// Rendered on the first request, then kept for the next ones
+    Lazy {
+        target: &'d CStr,
+        provider: &'d Provider<'d>,
+        data: Option<Rc<[u8]>>,
+    },
+}
+
+impl<'d> Payload<'d> {
+    fn is_available(&self) -> bool {
+        match self {
+            Self::Stream(reader) => reader.is_some(),
+            _ => true,
+        }
+    }
+
+    /// Gets the data ready to be sent, rendering it if it's lazy
+    fn prepare(&mut self, limit: Option<&PayloadLimit>) -> Result<(), SetSelectionError> {
+        if let Self::Lazy {
+            target,
+            provider,
+            data: data @ None,
+        } = self
+        {
+            let rendered = (provider.borrow_mut())(target);
+            let rendered = match limit {
+                Some(limit) => limit.apply(target, &rendered)?,
+                None => &rendered,
+            };
+
+            *data = Some(rendered.into());
+        }
+
+        Ok(())
+    }
+
+    /// The data to send, if it's all in memory
+    fn bytes(&self) -> Option<&[u8]> {
+        match self {
+            Self::Bytes(data) => Some(data),
+            Self::Shared(data) => Some(data),
+            Self::Stream(_) => None,
+            Self::Lazy { data, .. } => data.as_deref(),
+        }
+    }
+
+    /// Starts an incremental transfer of this payload
+    fn incr_sender(&mut self, chunk_size: usize) -> IncrSender<'d> {
+        match self {
+            Self::Bytes(data) => IncrSender::new(data, chunk_size),
+            Self::Shared(data) => IncrSender::shared(data.clone(), chunk_size),
+            Self::Stream(reader) => {
+                let reader = reader.take().expect("the stream was already taken");
+                IncrSender::from_reader(reader, chunk_size)
+            }
+            Self::Lazy { data, .. } => {
+                let data = data.clone().expect("lazy data should be prepared first");
+                IncrSender::shared(data, chunk_size)
+            }
+        }
+    }
+}
 
 // Copy (set selection)
 impl X11Clipboard {
+    /// Replies to a selection request, telling the requestor in which property the data is.
+    /// A property of 0 (None) means that the conversion was refused.
+    fn send_selection_notify(&self, request: &XSelectionRequestEvent, property: Atom) {
+        let mut selection_event = XEvent {
+            xselection: XSelectionEvent {
+                type_id: et::SELECTION_NOTIFY,
+                serial: 0,
+                send_event: 1,
+                display: request.display,
+                requestor: request.requestor,
+                selection: request.selection,
+                target: request.target,
+                property,
+                time: request.time,
+            },
+        };
+
+        unsafe {
+            (self.x.XSendEvent)(
+                self.display.as_ptr(),
+                request.requestor,
+                0,
+                0,
+                &mut selection_event,
+            );
+
+            (self.x.XFlush)(self.display.as_ptr());
+        }
+    }
+
+    /// Refuses a selection request the way ICCCM wants it: a `SelectionNotify` echoing the
+    /// requested target, with None as the property. Subscribers learn why.
+    fn refuse_conversion(&self, request: &XSelectionRequestEvent, reason: &str) {
+        self.emit(|| ClipboardEvent::ConversionRefused {
+            selection: self.atom_name_lossy(request.selection),
+            target: self.atom_name_lossy(request.target),
+            requestor: window_id(request.requestor),
+            reason: reason.to_string(),
+        });
+
+        self.send_selection_notify(request, 0);
+    }
+
+    /// Sets how much data goes in each chunk of incremental transfers, in bytes. `None` (the
+    /// default) makes chunks as big as a property can be, which takes the fewest round trips.
+    ///
+    /// Sizes above [`X11Clipboard::max_property_size`] are capped to it.
+    pub fn set_incr_chunk_size(&mut self, size: Option<usize>) {
+        self.incr_chunk_size = size;
+    }
+
+    /// Limits how much data can be copied at once, since everything we copy
+    /// is kept in memory while we serve it. There is no limit by default.
+    pub fn set_payload_limit(&mut self, limit: Option<PayloadLimit>) {
+        self.payload_limit = limit;
+    }
+
+    /// Lets other apps move what we copy rather than only copy it: when a requestor asks us
+    /// to `DELETE` the selection after pasting it, `handler` is called to delete the source
+    /// data, and we stop offering the contents.
+    ///
+    /// Without a handler, `DELETE` is refused. Copies served in the background don't call it.
+    pub fn set_delete_handler(&mut self, handler: impl FnMut(Selection) + 'static) {
+        self.delete_handler = Some(Box::new(RefCell::new(handler)));
+    }
+
+    pub fn transforms(&self) -> &Pipeline {
+        &self.transforms
+    }
+
+    /// Runs the text we copy with [`X11Clipboard::set_text`] and paste with
+    /// [`X11Clipboard::get_text`] through `pipeline`, with [`Stage::Copy`] and [`Stage::Paste`]
+    /// respectively. Other formats are left alone.
+    pub fn set_transforms(&mut self, pipeline: Pipeline) {
+        self.transforms = pipeline;
+    }
+
+    /// When copies are over, see [`ServeUntil`]
+    pub fn serve_until(&self) -> ServeUntil {
+        self.serve_until.get()
+    }
+
+    /// Sets when copies are over, see [`ServeUntil`]
+    pub fn set_serve_until(&mut self, serve_until: ServeUntil) {
+        self.serve_until.set(serve_until);
+    }
+
+    /// Copies data for a single target. This blocks until the copy is over, see
+    /// [`X11Clipboard::set_serve_until`].
+    ///
+    /// Empty data is a regular copy: we still own the selection and advertise the target,
+    /// and requestors get a zero-length property of that type rather than a refusal.
     pub fn set_selection(
         &self,
-        selection: &CStr,
-        target: &CStr,
-        data: &[u8],
-    ) -> Result<(), SetSelectionError> {
-        let when_everything_started = unsafe { self.get_compliant_timestamp() };
+        selection: Selection,
+        target: &CStr,
+        data: &[u8],
+    ) -> Result<(), ClipboxError> {
+        self.serve_selection(&[selection], &[(target, data)])
+    }
+
+    /// Copies the same content in several forms (like `text/plain`, `text/html` and
+    /// `image/png`) from a single ownership. Targets are offered in the order given, and a
+    /// target given twice keeps its last data.
+    ///
+    /// This is [`X11Clipboard::set_many`] without building a [`TargetBundle`].
+    pub fn set_selection_multi(
+        &self,
+        selection: Selection,
+        offers: &[(&CStr, &[u8])],
+    ) -> Result<(), ClipboxError> {
+        let mut unique: Vec<(&CStr, &[u8])> = Vec::with_capacity(offers.len());
+        for &(target, data) in offers {
+            match unique.iter_mut().find(|(t, _)| *t == target) {
+                Some((_, existing)) => *existing = data,
+                None => unique.push((target, data)),
+            }
+        }
+
+        self.serve_selection(&[selection], &unique)
+    }
+
+    /// Starts building a multi-format copy, see [`Offer`]
+    pub fn offer(&self, selection: Selection) -> Offer<'_> {
+        Offer::new(move |bundle| self.set_many(selection, bundle))
+    }
+
+    /// Like [`X11Clipboard::offer`], but the copy goes on several selections at once
+    pub fn offer_on(&self, selections: &[Selection]) -> Offer<'_> {
+        let selections = selections.to_vec();
+        Offer::new(move |bundle| self.set_many_on(&selections, bundle))
+    }
+
+    /// Offers every target of the bundle from a single selection ownership
+    pub fn set_many(
+        &self,
+        selection: Selection,
+        bundle: &TargetBundle,
+    ) -> Result<(), ClipboxError> {
+        self.serve_selection(&[selection], &bundle.ordered())
+    }
+
+    /// Offers every target of the bundle on several selections at once, all served from the
+    /// same event loop. Terminals usually put what they copy on both `CLIPBOARD` and `PRIMARY`.
+    ///
+    /// This keeps serving until the copy is over (see [`ServeUntil`]), or until we lost every
+    /// selection.
+    pub fn set_many_on(
+        &self,
+        selections: &[Selection],
+        bundle: &TargetBundle,
+    ) -> Result<(), ClipboxError> {
+        self.serve_selection(selections, &bundle.ordered())
+    }
+
+    /// Copies text as UTF-8, offering both `UTF8_STRING` and `text/plain;charset=utf-8`
+    pub fn set_text(&self, selection: Selection, text: &str) -> Result<(), ClipboxError> {
+        let text = self.transforms.apply(Stage::Copy, text);
+        self.serve_selection(
+            &[selection],
+            &[
+                (atom_names::UTF8_STRING, text.as_bytes()),
+                (mime_types::TEXT_PLAIN_CHARSET_UTF8, text.as_bytes()),
+            ],
+        )
+    }
+
+    /// Copies text like [`X11Clipboard::set_text`], then empties the selection once `ttl` is
+    /// over if it still has what we copied. That's what password managers do with passwords.
+    ///
+    /// This blocks until then or until we lost the selection, whatever [`ServeUntil`] says (in
+    /// the background, the thread is the one waiting). The text isn't handed to the clipboard
+    /// manager either.
+    pub fn set_text_with_ttl(
+        &self,
+        selection: Selection,
+        text: &str,
+        ttl: Duration,
+    ) -> Result<(), ClipboxError> {
+        let text = self.transforms.apply(Stage::Copy, text);
+        self.serve_selection_with_ttl(&[selection], &TargetBundle::new().with_text(&text), ttl)
+    }
+
+    /// Copies HTML, along with a plain text version for apps that don't understand HTML
+    pub fn set_html(
+        &self,
+        selection: Selection,
+        html: &str,
+        alt_text: &str,
+    ) -> Result<(), ClipboxError> {
+        let bundle = TargetBundle::new()
+            .with(mime_types::TEXT_HTML, html)
+            .with(atom_names::UTF8_STRING, alt_text)
+            .with(mime_types::TEXT_PLAIN_CHARSET_UTF8, alt_text);
+
+        self.set_many(selection, &bundle)
+    }
+
+    /// Copies RTF, along with a plain text version for apps that don't understand RTF
+    pub fn set_rtf(
+        &self,
+        selection: Selection,
+        rtf: &str,
+        alt_text: &str,
+    ) -> Result<(), ClipboxError> {
+        let bundle = TargetBundle::new()
+            .with(mime_types::TEXT_RTF, rtf)
+            .with(mime_types::APPLICATION_RTF, rtf)
+            .with(atom_names::UTF8_STRING, alt_text)
+            .with(mime_types::TEXT_PLAIN_CHARSET_UTF8, alt_text);
+
+        self.set_many(selection, &bundle)
+    }
+
+    /// Copies the same formatted text as HTML, RTF and plain text at once,
+    /// so that browsers, office suites and terminals can all paste it
+    pub fn set_rich_text(
+        &self,
+        selection: Selection,
+        html: &str,
+        rtf: &str,
+        alt_text: &str,
+    ) -> Result<(), ClipboxError> {
+        let bundle = TargetBundle::new()
+            .with(mime_types::TEXT_HTML, html)
+            .with(mime_types::TEXT_RTF, rtf)
+            .with(mime_types::APPLICATION_RTF, rtf)
+            .with(atom_names::UTF8_STRING, alt_text)
+            .with(mime_types::TEXT_PLAIN_CHARSET_UTF8, alt_text);
+
+        self.set_many(selection, &bundle)
+    }
+
+    /// Copies (or cuts) files so that they can be pasted in a file manager.
+    ///
+    /// Relative paths are made absolute against the current directory.
+    pub fn set_files(
+        &self,
+        selection: Selection,
+        paths: &[PathBuf],
+        operation: FileOperation,
+    ) -> Result<(), ClipboxError> {
+        let bundle = TargetBundle::new().with_files(paths, operation);
+        self.set_many(selection, &bundle)
+    }
+
+    /// Copies an image, encoded as `image/png`
+    #[cfg(feature = "png")]
+    pub fn set_image(&self, selection: Selection, image: &ImageData) -> Result<(), ClipboxError> {
+        let png = convert::encode_png(image).map_err(SetSelectionError::from)?;
+        self.set_selection(selection, mime_types::IMAGE_PNG, &png)
+    }
+
+    /// Copies an SVG image. With the `svg` feature, a rasterized PNG is offered along with it
+    /// for apps that don't understand SVG.
+    pub fn set_svg(&self, selection: Selection, svg: &str) -> Result<(), ClipboxError> {
+        #[allow(unused_mut)]
+        let mut bundle = TargetBundle::new().with(mime_types::IMAGE_SVG, svg);
+
+        #[cfg(feature = "svg")]
+        {
+            let png = convert::rasterize_svg(svg).map_err(SetSelectionError::from)?;
+            bundle = bundle.with(mime_types::IMAGE_PNG, png);
+        }
+
+        self.set_many(selection, &bundle)
+    }
+
+    /// Empties the selection, whoever owns it, by setting its owner to `None`.
+    ///
+    /// The current owner is told it lost the selection. Note that clipboard managers usually
+    /// notice and put back what they saved, so this doesn't guarantee the data is gone for good.
+    pub fn clear(&self, selection: Selection) -> Result<(), ClipboxError> {
+        unsafe {
+            let time = self
+                .get_compliant_timestamp(self.timeouts.get().ownership)
+                .ok_or_else(|| self.gave_up_copying())?;
+            let atom_selection = self.selection_atom(selection);
+
+            (self.x.XSetSelectionOwner)(self.display.as_ptr(), atom_selection, 0, time);
+
+            // The server ignores us if someone took the selection after our timestamp
+            let owner = (self.x.XGetSelectionOwner)(self.display.as_ptr(), atom_selection);
+            if owner != 0 {
+                return Err(ClipboxError::Ownership);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Whether we still own the selection, from this connection or from a copy served by a
+    /// background thread. Another app copying something is what usually makes us lose it.
+    pub fn is_owner(&self, selection: Selection) -> bool {
+        if self.selection_owner(selection) == Some(self.owner_window) {
+            return true;
+        }
+
+        let copies = self.background_copies.borrow();
+        copies.iter().any(|copy| copy.is_owner(self, selection))
+    }
+
+    /// The window that owns the selection, if anyone owns it
+    fn selection_owner(&self, selection: Selection) -> Option<XWindow> {
+        let owner = unsafe {
+            (self.x.XGetSelectionOwner)(self.display.as_ptr(), self.selection_atom(selection))
+        };
+
+        Some(owner).filter(|&owner| owner != 0)
+    }
+
+    /// Copies data read from a stream (like a file) without loading all of it in memory.
+    ///
+    /// The data is always sent incrementally, and since a stream can only be read once, only the
+    /// first requestor gets it: later requests are refused. The payload limit doesn't apply.
+    pub fn set_selection_from(
+        &self,
+        selection: Selection,
+        target: &CStr,
+        mut reader: impl Read,
+    ) -> Result<(), ClipboxError> {
+        let atom_target = self.intern(target);
+        Ok(self.serve_payloads(
+            &[selection],
+            vec![(atom_target, Payload::Stream(Some(&mut reader)))],
+            None,
+        )?)
+    }
+
+    /// Offers targets whose data is only produced when a requestor actually asks for it,
+    /// which is handy for expensive conversions.
+    ///
+    /// The provider is called with the requested target, at most once per target.
+    pub fn offer_with(
+        &self,
+        selection: Selection,
+        targets: &[&CStr],
+        provider: impl FnMut(&CStr) -> Vec<u8>,
+    ) -> Result<(), ClipboxError> {
+        let provider = RefCell::new(provider);
+
+        let offers = targets
+            .iter()
+            .map(|&target| {
+                let atom_target = self.intern(target);
+                let payload = Payload::Lazy {
+                    target,
+                    provider: &provider,
+                    data: None,
+                };
+
+                (atom_target, payload)
+            })
+            .collect();
+
+        Ok(self.serve_payloads(&[selection], offers, None)?)
+    }
+
+    /// Becomes the owner of the selections and serves every offered target from that ownership
+    fn serve_selection(
+        &self,
+        selections: &[Selection],
+        offers: &[(&CStr, &[u8])],
+    ) -> Result<(), ClipboxError> {
+        Ok(self.serve_offers(selections, offers, None)?)
+    }
+
+    /// Serves the bundle until `ttl` is over, then empties the selections we still own, see
+    /// [`X11Clipboard::set_text_with_ttl`]
+    fn serve_selection_with_ttl(
+        &self,
+        selections: &[Selection],
+        bundle: &TargetBundle,
+        ttl: Duration,
+    ) -> Result<(), ClipboxError> {
+        Ok(self.serve_offers(selections, &bundle.ordered(), Some(ttl))?)
+    }
+
+    fn serve_offers(
+        &self,
+        selections: &[Selection],
+        offers: &[(&CStr, &[u8])],
+        ttl: Option<Duration>,
+    ) -> Result<(), SetSelectionError> {
+        let offers = offers
+            .iter()
+            .map(|&(target, data)| {
+                let data = match &self.payload_limit {
+                    Some(limit) => limit.apply(target, data)?,
+                    None => data,
+                };
+
+                Ok((target, data))
+            })
+            .collect::<Result<Vec<_>, SetSelectionError>>()?;
+
+        if self.serve_until.get() == ServeUntil::Background {
+            let bundle = offers
+                .iter()
+                .fold(TargetBundle::new(), |bundle, &(target, data)| {
+                    bundle.with(target, data)
+                })
+                .order(TargetOrder::AsAdded);
+
+            let copy = self
+                .copy_in_background(selections, bundle, ttl)
+                .map_err(|err| SetSelectionError::Background(Box::new(err)))?;
+
+            // Forget about the copies that are over
+            let mut copies = self.background_copies.borrow_mut();
+            copies.retain(BackgroundCopy::is_serving);
+            copies.push(copy);
+            return Ok(());
+        }
+
+        let offers = offers
+            .into_iter()
+            .map(|(target, data)| (self.intern(target), Payload::Bytes(data)))
+            .collect();
+
+        self.serve_payloads(selections, offers, ttl)
+    }
+
+    /// Acquires the selections and serves the offers. With a TTL, serving ends once it's over
+    /// and the selections are emptied, rather than when [`ServeUntil`] says.
+    fn serve_payloads(
+        &self,
+        selections: &[Selection],
+        offers: Vec<(Atom, Payload)>,
+        ttl: Option<Duration>,
+    ) -> Result<(), SetSelectionError> {
+        let (when_everything_started, owned) = self.acquire_with_retry(selections)?;
+        let mut serving = self.start_serving(owned.clone(), when_everything_started, offers);
+
+        let Some(ttl) = ttl else {
+            return self.serve_owned(serving);
+        };
+
+        serving.idle = IdleTimeout::new(None);
+        serving.until = Some(Instant::now() + ttl);
+        self.serve_owned(serving)?;
+
+        self.expire(&owned, when_everything_started);
+        Ok(())
+    }
+
+    /// Empties the selections we acquired at `acquired_at`, once what we copied there expired.
+    /// The server ignores this for the ones another client got since, so their copy stays.
+    fn expire(&self, owned: &[(Selection, Atom)], acquired_at: c_ulong) {
+        // Expired means gone, not saved by the clipboard manager when we're dropped
+        self.saved_clipboard.take();
+
+        for &(selection, atom_selection) in owned {
+            if self.selection_owner(selection) != Some(self.owner_window) {
+                continue;
+            }
+
+            unsafe {
+                (self.x.XSetSelectionOwner)(self.display.as_ptr(), atom_selection, 0, acquired_at);
+            }
+        }
+
+        unsafe { (self.x.XFlush)(self.display.as_ptr()) };
+    }
+
+    /// Becomes the owner of every selection, trying again as [`OwnershipRetry`] says. Gives
+    /// when we acquired them.
+    fn acquire_with_retry(
+        &self,
+        selections: &[Selection],
+    ) -> Result<(c_ulong, Vec<(Selection, Atom)>), SetSelectionError> {
+        let timeouts = self.timeouts.get();
+
+        let mut attempt = 0;
+        let mut backoff = self.ownership_retry.backoff;
+        loop {
+            let time = self
+                .get_compliant_timestamp(timeouts.ownership)
+                .ok_or_else(|| self.gave_up_copying())?;
+
+            if let Some(owned) = self.try_acquire(selections, time, attempt)? {
+                return Ok((time, owned));
+            }
+
+            attempt += 1;
+            std::thread::sleep(backoff);
+            backoff *= 2;
+        }
+    }
+
+    /// One attempt at owning the selections, see [`OwnershipRetry`]. Gives `None` when we lost
+    /// the race but can try again.
+    fn try_acquire(
+        &self,
+        selections: &[Selection],
+        time: c_ulong,
+        attempt: u32,
+    ) -> Result<Option<Vec<(Selection, Atom)>>, SetSelectionError> {
+        let LostRace { selection, owner } = match self.acquire(selections, time) {
+            Ok(owned) => return Ok(Some(owned)),
+            Err(lost) => lost,
+        };
+
+        let winner = match owner {
+            Some(owner) => format!("window {:#x}", owner),
+            None => "nobody".to_string(),
+        };
+
+        if attempt == self.ownership_retry.retries {
+            // \(T-T)/
+            self.emit(|| ClipboardEvent::Error {
+                message: format!(
+                    "Could not become the owner of {}, {} has it",
+                    selection, winner
+                ),
+            });
+            return Err(SetSelectionError::NotOwner { owner });
+        }
+
+        self.emit(|| ClipboardEvent::Error {
+            message: format!("Lost {} to {}, trying again", selection, winner),
+        });
+
+        Ok(None)
+    }
+
+    /// Gets everything ready to serve the selections we just acquired, for as long as
+    /// [`ServeUntil`] says
+    fn start_serving<'d>(
+        &self,
+        owned: Vec<(Selection, Atom)>,
+        acquired_at: c_ulong,
+        offers: Vec<(Atom, Payload<'d>)>,
+    ) -> Serving<'d> {
+        for &(selection, _) in &owned {
+            self.emit(|| ClipboardEvent::OwnerChanged {
+                selection: selection.to_string(),
+            });
+        }
 
-        unsafe {
-            let atom_selection = intern_atom(&self.x, self.display, selection);
+        // Keep a copy for the clipboard manager, in case we're dropped while still owning it.
+        // Streams and lazy targets aren't in memory, so they can't be saved.
+        if owned
+            .iter()
+            .any(|&(selection, _)| selection == Selection::Clipboard)
+        {
+            let targets = offers
+                .iter()
+                .filter_map(|(atom_target, payload)| {
+                    Some((*atom_target, payload.bytes()?.to_vec()))
+                })
+                .collect();
 
-            // Become owner of selection
-            (self.x.XSetSelectionOwner)(
-                self.display.as_ptr(),
-                atom_selection,
-                self.window,
-                when_everything_started,
-            );
+            self.saved_clipboard.replace(Some(SavedClipboard {
+                acquired_at,
+                targets,
+            }));
+        }
 
-            // Verify that we did indeed become owner of selection
-            let owner = (self.x.XGetSelectionOwner)(self.display.as_ptr(), atom_selection);
-            if owner != self.window {
-                // \(T-T)/
-                return Err(SetSelectionError::NotOwner);
+        let served = Served {
+            selections: owned,
+            acquired_at,
+            offers,
+            deleted: false,
+        };
+
+        let (idle, until) = match self.serve_until.get() {
+            ServeUntil::OwnershipLost | ServeUntil::Background => (None, None),
+            ServeUntil::Deadline(deadline) => (None, Some(deadline)),
+            ServeUntil::Idle(idle) => (Some(idle), None),
+        };
+
+        Serving {
+            served,
+            incr_transfers: HashMap::new(),
+            idle: IdleTimeout::new(idle),
+            until,
+            handing_off: false,
+        }
+    }
+
+    /// Tries to become the owner of every selection at `time`. If another client is faster,
+    /// gives the selection we didn't get and who has it instead, after giving back the ones we
+    /// did get so that we don't hold on to half a copy.
+    fn acquire(
+        &self,
+        selections: &[Selection],
+        time: c_ulong,
+    ) -> Result<Vec<(Selection, Atom)>, LostRace> {
+        let mut owned = Vec::with_capacity(selections.len());
+        for &selection in selections {
+            let atom_selection = self.selection_atom(selection);
+
+            unsafe {
+                // Become owner of selection
+                (self.x.XSetSelectionOwner)(
+                    self.display.as_ptr(),
+                    atom_selection,
+                    self.owner_window,
+                    time,
+                );
+
+                // Verify that we did indeed become owner of selection
+                let owner = (self.x.XGetSelectionOwner)(self.display.as_ptr(), atom_selection);
+                if owner != self.owner_window {
+                    for &(_, atom_owned) in &owned {
+                        // Set their owner to None, which is ignored if someone took them since
+                        (self.x.XSetSelectionOwner)(self.display.as_ptr(), atom_owned, 0, time);
+                    }
+
+                    return Err(LostRace {
+                        selection,
+                        owner: Some(owner).filter(|&owner| owner != 0),
+                    });
+                }
             }
 
-            let target_atoms = &[
-                self.atoms.targets,
-                intern_atom(&self.x, self.display, target),
-            ];
+            owned.push((selection, atom_selection));
+        }
 
-            const INCR_CHUNK_SIZE: usize = 4096;
-            let mut incr_bytes_sent: usize = 0;
-            let mut incr_start_xevent: Option<XSelectionRequestEvent> = None;
-            loop {
-                let Some(xevent) = self.next_event_timeout(Duration::from_millis(100)) else {
-                    // we're not receiving any event immediately, consider the operation finished
-                    return Ok(());
+        Ok(owned)
+    }
+
+    /// Serves the selections we already own until we lost them all, nothing happened for a
+    /// while, or `until`. When handing off to the clipboard manager, we also stop once it's
+    /// done saving.
+    fn serve_owned(&self, mut serving: Serving) -> Result<(), SetSelectionError> {
+        // How long we may take to notice a shutdown
+        const SHUTDOWN_CHECK_INTERVAL: Duration = Duration::from_millis(50);
+
+        loop {
+            if self.is_shut_down(&serving) {
+                return Ok(());
+            }
+
+            let deadline = self.serve_deadline(&serving);
+            // Shutdowns come from another thread, so we have to check for them now and then
+            let check_at = self
+                .shutdown
+                .as_ref()
+                .map(|_| Instant::now() + SHUTDOWN_CHECK_INTERVAL);
+            let wake_up = match (deadline, check_at) {
+                (Some(deadline), Some(check_at)) => Some(deadline.min(check_at)),
+                (deadline, check_at) => deadline.or(check_at),
+            };
+
+            let over = match self.next_owner_event_until(wake_up) {
+                Some(xevent) => self.serve_event(&mut serving, xevent),
+                None if self.is_cancelled() => true,
+                None if deadline.is_none_or(|deadline| Instant::now() < deadline) => false,
+                None => self.serve_timed_out(&mut serving),
+            };
+
+            if over {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Whether a [shutdown](BackgroundCopy::shutdown) is over for this serving loop: once the
+    /// transfers in progress are done, or given up on at its deadline. Handing off to the
+    /// clipboard manager has that deadline as its `until` instead.
+    fn is_shut_down(&self, serving: &Serving) -> bool {
+        let Some(deadline) = self.shutdown_deadline() else {
+            return false;
+        };
+
+        if serving.handing_off {
+            return false;
+        }
+
+        if serving.incr_transfers.is_empty() {
+            return true;
+        }
+
+        if Instant::now() < deadline {
+            return false;
+        }
+
+        self.emit(|| ClipboardEvent::Error {
+            message: "Shutting down in the middle of a transfer, giving up on it".to_string(),
+        });
+        true
+    }
+
+    fn shutdown_deadline(&self) -> Option<Instant> {
+        self.shutdown.as_ref().and_then(ShutdownSignal::deadline)
+    }
+
+    /// When to stop waiting for the next event while serving
+    fn serve_deadline(&self, serving: &Serving) -> Option<Instant> {
+        // While sending incrementally, give the requestors time to ask for the next chunk
+        let wake_up = match self.timeouts.get().incr_chunk {
+            _ if serving.incr_transfers.is_empty() => serving
+                .idle
+                .remaining()
+                .map(|remaining| Instant::now() + remaining),
+            Some(timeout) => serving
+                .incr_transfers
+                .values()
+                .map(|transfer| transfer.last_activity + timeout)
+                .min(),
+            None => None,
+        };
+
+        // While shutting down, transfers are only waited for until its deadline
+        let until = earliest(serving.until, self.shutdown_deadline());
+        earliest(wake_up, until)
+    }
+
+    /// Handles the deadline passing without any event, returning whether serving is over
+    fn serve_timed_out(&self, serving: &mut Serving) -> bool {
+        if serving.until.is_some_and(|until| Instant::now() >= until) {
+            // Time's up, whatever is still going on
+            return true;
+        }
+
+        // Only transfers with a timeout make us wake up
+        let incr_chunk = self.timeouts.get().incr_chunk;
+        let Some(timeout) = incr_chunk.filter(|_| !serving.incr_transfers.is_empty()) else {
+            // we're not receiving any event immediately, consider the operation finished
+            return true;
+        };
+
+        let now = Instant::now();
+        serving.incr_transfers.retain(|_, transfer| {
+            let alive = now < transfer.last_activity + timeout;
+            if !alive {
+                self.emit(|| ClipboardEvent::Error {
+                    message: "The requestor stopped asking for chunks, giving up".to_string(),
+                });
+            }
+            alive
+        });
+
+        serving.idle.touch();
+        false
+    }
+
+    /// Handles one event while serving, returning whether serving is over
+    fn serve_event(&self, serving: &mut Serving, xevent: XEvent) -> bool {
+        serving.idle.touch();
+
+        unsafe {
+            if xevent.type_id == et::SELECTION_REQUEST {
+                let mut xevent = xevent.xselectionrequest;
+                if xevent.owner != self.owner_window {
+                    return false;
+                }
+
+                let Some(selection) = serving.served.selection(xevent.selection) else {
+                    self.refuse_conversion(&xevent, "we don't own that selection anymore");
+                    return false;
                 };
 
-                if xevent.type_id == et::SELECTION_REQUEST {
-                    let mut xevent = xevent.xselectionrequest;
+                if xevent.time != 0 && xevent.time < serving.served.acquired_at {
+                    // "If the specified time is earlier than the time the selection was
+                    // acquired, the owner should refuse the conversion" (and so do we)
+                    self.refuse_conversion(&xevent, "the request predates our ownership");
+                    return false;
+                }
+
+                // Only the transfers in progress get to finish, and the clipboard manager
+                if self.shutdown_deadline().is_some() && !serving.handing_off {
+                    self.refuse_conversion(&xevent, "we're shutting down");
+                    return false;
+                }
+
+                if xevent.property == 0 {
+                    if xevent.target == self.intern(atom_names::MULTIPLE) {
+                        // The pairs to convert are in the property, there's no guessing them
+                        self.refuse_conversion(&xevent, "MULTIPLE without a property");
+                        return false;
+                    }
 
                     // "If the specified property is None, the requestor is an obsolete client.
                     // Owners are encouraged to support these clients by using the specified target
                     // atom as the property name to be used for the reply."
-                    xevent.property = match xevent.property {
-                        0 => xevent.target,
-                        _ => xevent.property,
-                    };
+                    xevent.property = xevent.target;
+                }
 
-                    if xevent.owner != self.window {
-                        continue;
+                if xevent.target == self.intern(atom_names::MULTIPLE) {
+                    if !self.convert_multiple(&mut serving.served, &xevent) {
+                        self.refuse_conversion(&xevent, "no target pairs to convert");
+                        return false;
                     }
+                } else if let Some(converted) = self.convert_meta_target(
+                    &mut serving.served,
+                    selection,
+                    xevent.requestor,
+                    xevent.target,
+                    xevent.property,
+                ) {
+                    if !converted {
+                        self.refuse_conversion(&xevent, "not supported");
+                        return false;
+                    }
+                } else if let Some(payload) = serving.served.payload(xevent.target) {
+                    self.emit(|| ClipboardEvent::DataRequested {
+                        selection: selection.to_string(),
+                        target: self.atom_name_lossy(xevent.target),
+                        requestor: window_id(xevent.requestor),
+                    });
 
-                    if xevent.selection != atom_selection {
-                        continue;
+                    if let Err(err) = payload.prepare(self.payload_limit.as_ref()) {
+                        // The rendered data is too big
+                        self.refuse_conversion(&xevent, &err.to_string());
+                        return false;
                     }
+                    // Streams always go incrementally, we don't know how big they are
+                    let small_data = payload
+                        .bytes()
+                        .filter(|data| !protocol::needs_incr(data.len(), self.max_property_size()));
 
-                    if target_atoms.contains(&xevent.target) {
-                        if xevent.target == self.atoms.targets {
-                            // Send our available targets
-                            (self.x.XChangeProperty)(
-                                xevent.display,
-                                xevent.requestor,
-                                xevent.property,
-                                self.atoms.atom,
-                                32,
-                                prop_mode::REPLACE,
-                                target_atoms.as_ptr().cast(),
-                                target_atoms.len() as i32,
-                            );
-                        } else if data.len() < self.max_request_size - 24 {
-                            // ^ Taken from this line: https://github.com/quininer/x11-clipboard/blob/704cfd3ebf7297e4cd3b5ef00d2e2527e9b633f2/src/run.rs#L122
-                            // I don't know why it's -24 specifically, but the Tronche guide does say this:
-                            // "The size should be less than the maximum-request-size in the connection handshake".
-
-                            (self.x.XChangeProperty)(
-                                xevent.display,
-                                xevent.requestor,
-                                xevent.property,
-                                xevent.target,
-                                8,
-                                prop_mode::REPLACE,
-                                data.as_ptr().cast(),
-                                data.len() as i32,
-                            );
-                        } else {
-                            // change the attributes of the requestor window against its will (wtf)
-                            (self.x.XSelectInput)(
-                                xevent.display,
-                                xevent.requestor,
-                                xevent_mask::PROPERTY_CHANGE,
-                            );
-
-                            // send data incrementally
-                            (self.x.XChangeProperty)(
-                                xevent.display,
-                                xevent.requestor,
-                                xevent.property,
-                                self.atoms.incr,
-                                32,
-                                prop_mode::REPLACE,
-                                std::ptr::null(),
-                                0,
-                            );
-
-                            incr_start_xevent = Some(xevent);
-                        }
+                    if let Some(data) = small_data {
+                        (self.x.XChangeProperty)(
+                            xevent.display,
+                            xevent.requestor,
+                            xevent.property,
+                            xevent.target,
+                            8,
+                            prop_mode::REPLACE,
+                            data.as_ptr().cast(),
+                            data.len() as i32,
+                        );
                     } else {
-                        // Refuse conversion
-                        xevent.property = 0;
-                    }
+                        // change the attributes of the requestor window against its will (wtf)
+                        // (we also want to know if it's destroyed before the end)
+                        (self.x.XSelectInput)(
+                            xevent.display,
+                            xevent.requestor,
+                            xevent_mask::PROPERTY_CHANGE | xlib::STRUCTURE_NOTIFY_MASK,
+                        );
+
+                        // send data incrementally
+                        (self.x.XChangeProperty)(
+                            xevent.display,
+                            xevent.requestor,
+                            xevent.property,
+                            self.atoms.incr,
+                            32,
+                            prop_mode::REPLACE,
+                            std::ptr::null(),
+                            0,
+                        );
 
-                    let mut selection_event = XEvent {
-                        xselection: XSelectionEvent {
-                            type_id: et::SELECTION_NOTIFY,
-                            serial: 0,
-                            send_event: 1,
-                            display: xevent.display,
-                            requestor: xevent.requestor,
-                            selection: xevent.selection,
-                            target: xevent.target,
-                            property: xevent.property,
-                            time: xevent.time,
-                        },
-                    };
-
-                    (self.x.XSendEvent)(
-                        xevent.display,
-                        xevent.requestor,
-                        0,
-                        0,
-                        &mut selection_event,
-                    );
-
-                    (self.x.XFlush)(self.display.as_ptr());
-                } else if xevent.type_id == et::PROPERTY_NOTIFY {
-                    let xevent = xevent.xproperty;
-                    if xevent.state != property::DELETE {
-                        // Not a Delete - move on
-                        continue;
+                        serving.incr_transfers.insert(
+                            (xevent.requestor, xevent.property),
+                            IncrTransfer {
+                                request: xevent,
+                                sender: payload.incr_sender(self.incr_chunk_size()),
+                                last_activity: Instant::now(),
+                            },
+                        );
                     }
+                } else {
+                    self.refuse_conversion(&xevent, "unavailable target");
+                    return false;
+                }
+
+                self.send_selection_notify(&xevent, xevent.property);
+            } else if xevent.type_id == et::PROPERTY_NOTIFY {
+                let xevent = xevent.xproperty;
+                if xevent.state != property::DELETE {
+                    // Not a Delete - move on
+                    return false;
+                }
 
-                    let Some(xevent) = incr_start_xevent else {
-                        // there's no incremental data to send
-                        continue;
-                    };
+                let key = (xevent.window, xevent.atom);
+                let Some(transfer) = serving.incr_transfers.get_mut(&key) else {
+                    // not one of our transfers, no incremental data to send
+                    return false;
+                };
 
-                    let incr_data_slice = {
-                        let end = (incr_bytes_sent + INCR_CHUNK_SIZE).min(data.len());
-                        &data[incr_bytes_sent..end]
-                    };
+                let xevent = transfer.request;
+                let sender = &mut transfer.sender;
+                if !sender.consumed() {
+                    // Deleted twice, or something else deleted it: we already sent this chunk
+                    return false;
+                }
 
-                    if incr_data_slice.is_empty() {
-                        incr_start_xevent = None;
+                transfer.last_activity = Instant::now();
+                let incr_data_slice = match sender.next_chunk() {
+                    Some(Ok(chunk)) => chunk,
+                    Some(Err(err)) => {
+                        // Better leave the requestor hanging than have it paste truncated data
+                        self.emit(|| ClipboardEvent::Error {
+                            message: format!("Couldn't read the data to send: {}", err),
+                        });
+                        serving.incr_transfers.remove(&key);
+                        return false;
                     }
+                    None => return false,
+                };
+
+                (self.x.XChangeProperty)(
+                    xevent.display,
+                    xevent.requestor,
+                    xevent.property,
+                    xevent.target,
+                    8,
+                    prop_mode::REPLACE,
+                    incr_data_slice.as_ptr().cast(),
+                    incr_data_slice.len() as i32,
+                );
+
+                self.emit(|| ClipboardEvent::TransferProgress {
+                    target: self.atom_name_lossy(xevent.target),
+                    requestor: window_id(xevent.requestor),
+                    sent: sender.sent(),
+                    total: sender.total(),
+                });
+
+                if sender.is_finished() {
+                    serving.incr_transfers.remove(&key);
+                }
+            } else if xevent.type_id == xlib::DESTROY_NOTIFY {
+                let window = xlib::destroy_window(&xevent).window;
+
+                // Writing chunks to a dead window would only get us BadWindow errors
+                let transfers = serving.incr_transfers.len();
+                serving
+                    .incr_transfers
+                    .retain(|&(requestor, _), _| requestor != window);
+
+                if serving.incr_transfers.len() < transfers {
+                    self.emit(|| ClipboardEvent::Error {
+                        message: "The requestor went away in the middle of a transfer".to_string(),
+                    });
+                }
+            } else if xevent.type_id == et::SELECTION_NOTIFY && serving.handing_off {
+                // The clipboard manager answers SAVE_TARGETS once it saved everything it wanted
+                let xevent = xevent.xselection;
+                if xevent.selection == self.intern(atom_names::CLIPBOARD_MANAGER) {
+                    return true;
+                }
+            } else if xevent.type_id == et::SELECTION_CLEAR {
+                let xevent = xlib::selection_clear(&xevent);
+                let Some(selection) = serving.served.selection(xevent.selection) else {
+                    return false;
+                };
+
+                // We gave it back ourselves after losing a race, before owning it again
+                if xevent.time < serving.served.acquired_at {
+                    return false;
+                }
+
+                // No longer our selection \(=_= )\
+                serving
+                    .served
+                    .selections
+                    .retain(|&(_, atom)| atom != xevent.selection);
+                if selection == Selection::Clipboard {
+                    self.saved_clipboard.take();
+                }
+
+                self.emit(|| ClipboardEvent::OwnershipLost {
+                    selection: selection.to_string(),
+                });
+
+                if serving.served.selections.is_empty() {
+                    return true;
+                }
+            }
+
+            false
+        }
+    }
+}
+
+// Clipboard manager handoff
+impl X11Clipboard {
+    /// Hands what we copied to `CLIPBOARD` over to the clipboard manager through `SAVE_TARGETS`,
+    /// so that it survives us. This serves the manager's requests until it's done.
+    fn save_to_clipboard_manager(&self) {
+        let Some(saved) = self.saved_clipboard.take() else {
+            return;
+        };
+
+        let timeouts = self.timeouts.get();
+        let atom_manager = self.intern(atom_names::CLIPBOARD_MANAGER);
+
+        unsafe {
+            let owner = (self.x.XGetSelectionOwner)(self.display.as_ptr(), self.atoms.clipboard);
+            let manager = (self.x.XGetSelectionOwner)(self.display.as_ptr(), atom_manager);
+            if owner != self.owner_window || manager == 0 {
+                // Someone else copied in the meantime, or there's nobody to hand it to
+                return;
+            }
+
+            let time = self
+                .get_compliant_timestamp(timeouts.conversion)
+                .unwrap_or(0);
+
+            // A property of None asks the manager to save every target we offer
+            (self.x.XConvertSelection)(
+                self.display.as_ptr(),
+                atom_manager,
+                self.intern(atom_names::SAVE_TARGETS),
+                0,
+                self.owner_window,
+                time,
+            );
+        }
+
+        let offers = saved
+            .targets
+            .iter()
+            .map(|(atom_target, data)| (*atom_target, Payload::Bytes(data)))
+            .collect();
 
-                    (self.x.XChangeProperty)(
-                        xevent.display,
-                        xevent.requestor,
-                        xevent.property,
-                        xevent.target,
-                        8,
-                        prop_mode::REPLACE,
-                        incr_data_slice.as_ptr().cast(),
-                        incr_data_slice.len() as i32,
-                    );
+        let serving = Serving {
+            served: Served {
+                selections: vec![(Selection::Clipboard, self.atoms.clipboard)],
+                acquired_at: saved.acquired_at,
+                offers,
+                deleted: false,
+            },
+            incr_transfers: HashMap::new(),
+            // The manager converts the targets one after the other, give it time between each
+            idle: IdleTimeout::new(timeouts.conversion),
+            until: self.shutdown_deadline(),
+            handing_off: true,
+        };
+
+        if let Err(err) = self.serve_owned(serving) {
+            self.emit(|| ClipboardEvent::Error {
+                message: format!(
+                    "Couldn't hand the clipboard to the clipboard manager: {}",
+                    err
+                ),
+            });
+        }
+    }
+}
+
+// Copy, targets about the selection itself
+impl X11Clipboard {
+    /// The targets we list in reply to `TARGETS`
+    fn target_atoms(&self, served: &Served) -> Vec<Atom> {
+        let mut target_atoms = vec![
+            self.atoms.targets,
+            self.intern(atom_names::MULTIPLE),
+            self.intern(atom_names::TIMESTAMP),
+        ];
+
+        if self.delete_handler.is_some() {
+            target_atoms.push(self.intern(atom_names::DELETE));
+        }
+
+        if !served.deleted {
+            for &(atom_target, _) in &served.offers {
+                // Offers named after a meta target can't be pasted anyway, we answer for them
+                if !target_atoms.contains(&atom_target) {
+                    target_atoms.push(atom_target);
+                }
+            }
+        }
+
+        target_atoms
+    }
+
+    /// Converts the targets that are about the selection rather than its contents
+    /// (`TARGETS`, `TIMESTAMP` and `DELETE`).
+    ///
+    /// Returns whether the conversion worked, or `None` if `target` isn't one of them.
+    fn convert_meta_target(
+        &self,
+        served: &mut Served,
+        selection: Selection,
+        requestor: XWindow,
+        target: Atom,
+        property: Atom,
+    ) -> Option<bool> {
+        if target == self.atoms.targets {
+            let target_atoms = self.target_atoms(served);
+            self.change_property(requestor, property, self.atoms.atom, &target_atoms);
+        } else if target == self.intern(atom_names::TIMESTAMP) {
+            let atom_integer = self.intern(atom_names::INTEGER);
+            self.change_property(requestor, property, atom_integer, &[served.acquired_at]);
+        } else if target == self.intern(atom_names::DELETE) {
+            let Some(handler) = &self.delete_handler else {
+                return Some(false);
+            };
+
+            // The requestor moved the contents away, they're gone from here too
+            (handler.borrow_mut())(selection);
+            served.deleted = true;
+
+            let atom_null = self.intern(atom_names::NULL);
+            self.change_property::<u8>(requestor, property, atom_null, &[]);
+        } else {
+            return None;
+        }
+
+        Some(true)
+    }
+
+    /// Answers a `MULTIPLE` request: converts every (target, property) pair listed in the
+    /// request's property, then writes the pairs back with `None` for the ones we refused.
+    /// Returns `false` if the request doesn't list any pair.
+    ///
+    /// Entries too big for a single property are refused, as they would each need an INCR
+    /// transfer of their own. Requestors can still ask for them separately.
+    fn convert_multiple(&self, served: &mut Served, request: &XSelectionRequestEvent) -> bool {
+        let Ok(pairs) = self
+            .get_window_property(request.requestor, request.property)
+            .and_then(|prop| Ok(prop.into_vec32()?))
+        else {
+            return false;
+        };
+
+        let mut pairs = pairs.into_iter().map(Atom::from).collect::<Vec<_>>();
+        if pairs.len() < 2 {
+            return false;
+        }
+
+        let Some(selection) = served.selection(request.selection) else {
+            return false;
+        };
 
-                    incr_bytes_sent += incr_data_slice.len();
-                } else if xevent.type_id == et::SELECTION_CLEAR {
-                    // No longer our selection \(=_= )\
-                    return Ok(());
+        for pair in pairs.chunks_exact_mut(2) {
+            let (target, property) = (pair[0], pair[1]);
+
+            let converted = if let Some(converted) =
+                self.convert_meta_target(served, selection, request.requestor, target, property)
+            {
+                converted
+            } else if let Some(payload) = served.payload(target) {
+                self.emit(|| ClipboardEvent::DataRequested {
+                    selection: selection.to_string(),
+                    target: self.atom_name_lossy(target),
+                    requestor: window_id(request.requestor),
+                });
+
+                match payload.prepare(self.payload_limit.as_ref()) {
+                    Ok(()) => {
+                        // Same limit as for a single conversion, see `serve_owned`
+                        let small_data = payload.bytes().filter(|data| {
+                            !protocol::needs_incr(data.len(), self.max_property_size())
+                        });
+
+                        if let Some(data) = small_data {
+                            self.change_property(request.requestor, property, target, data);
+                        }
+                        small_data.is_some()
+                    }
+                    Err(err) => {
+                        self.emit(|| ClipboardEvent::Error {
+                            message: err.to_string(),
+                        });
+                        false
+                    }
                 }
+            } else {
+                false
+            };
+
+            if !converted {
+                self.emit(|| ClipboardEvent::ConversionRefused {
+                    selection: selection.to_string(),
+                    target: self.atom_name_lossy(target),
+                    requestor: window_id(request.requestor),
+                    reason: "refused as part of MULTIPLE".to_string(),
+                });
+                pair[1] = 0;
             }
         }
+
+        let atom_pair = self.intern(atom_names::ATOM_PAIR);
+        self.change_property(request.requestor, request.property, atom_pair, &pairs);
+        true
+    }
+
+    /// Replaces a property on some window, as 8-bit data for bytes and 32-bit data for
+    /// atoms and timestamps
+    fn change_property<T: PropertyItem>(
+        &self,
+        window: XWindow,
+        property: Atom,
+        ty: Atom,
+        items: &[T],
+    ) {
+        unsafe {
+            (self.x.XChangeProperty)(
+                self.display.as_ptr(),
+                window,
+                property,
+                ty,
+                T::FORMAT,
+                prop_mode::REPLACE,
+                items.as_ptr().cast(),
+                items.len() as c_int,
+            );
+        }
     }
 }
 
+/// What can go in a property through [`X11Clipboard::change_property`]
+trait PropertyItem {
+    const FORMAT: c_int;
+}
+
+impl PropertyItem for u8 {
+    const FORMAT: c_int = 8;
+}
+
+/// Xlib wants longs for 32-bit properties
+impl PropertyItem for c_ulong {
+    const FORMAT: c_int = 32;
+}
+
 impl Drop for X11Clipboard {
     fn drop(&mut self) {
+        self.save_to_clipboard_manager();
+
         unsafe {
-            // Disconnect from the X server
-            (self.x.XCloseDisplay)(self.display.as_ptr());
+            if self.owns_display {
+                // Disconnect from the X server
+                (self.x.XCloseDisplay)(self.display.as_ptr());
+            } else {
+                // Not our connection, only clean up after ourselves
+                (self.xlib.XDestroyWindow)(self.display.as_ptr(), self.window);
+                (self.xlib.XDestroyWindow)(self.display.as_ptr(), self.owner_window);
+                (self.x.XFlush)(self.display.as_ptr());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::mem;
+
+    use loki_linux::x11::XPropertyEvent;
+
+    use super::*;
+
+    extern "C" {
+        fn malloc(size: usize) -> *mut c_void;
+    }
+
+    /// What `XGetWindowProperty` gives for an empty property. Xlib may or may not allocate a
+    /// byte for it, `XFree` takes care of that one.
+    fn empty_property(x11: &LibX11, format: c_int, allocated: bool) -> XWindowProperty<'_> {
+        XWindowProperty {
+            x11,
+            ty: 0,
+            format,
+            nitems: 0,
+            bytes_remaining: 0,
+            data: allocated
+                .then(|| NonNull::new(unsafe { malloc(1) }))
+                .flatten(),
+        }
+    }
+
+    /// A clipboard on the display the tests run in, or `None` to skip tests that need one
+    fn connect() -> Option<X11Clipboard> {
+        X11Clipboard::init()
+            .inspect_err(|err| eprintln!("Skipping, no X display: {}", err))
+            .ok()
+    }
+
+    fn property_notify(window: XWindow, atom: Atom, state: c_int) -> XEvent {
+        let mut xevent: XEvent = unsafe { mem::zeroed() };
+        xevent.xproperty = XPropertyEvent {
+            type_id: et::PROPERTY_NOTIFY,
+            window,
+            atom,
+            state,
+            time: 42,
+            ..unsafe { mem::zeroed() }
+        };
+        xevent
+    }
+
+    fn selection_notify(
+        requestor: XWindow,
+        selection: Atom,
+        target: Atom,
+        property: Atom,
+    ) -> XEvent {
+        let mut xevent: XEvent = unsafe { mem::zeroed() };
+        xevent.xselection = XSelectionEvent {
+            type_id: et::SELECTION_NOTIFY,
+            requestor,
+            selection,
+            target,
+            property,
+            time: 42,
+            ..unsafe { mem::zeroed() }
+        };
+        xevent
+    }
+
+    fn serving(idle: Option<Duration>, until: Option<Instant>) -> Serving<'static> {
+        Serving {
+            served: Served {
+                selections: Vec::new(),
+                acquired_at: 0,
+                offers: Vec::new(),
+                deleted: false,
+            },
+            incr_transfers: HashMap::new(),
+            idle: IdleTimeout::new(idle),
+            until,
+            handing_off: false,
         }
     }
+
+    #[test]
+    fn empty_properties_give_empty_vecs() {
+        let x11 = LibX11::new().expect("libX11 isn't installed");
+
+        for allocated in [false, true] {
+            let vec8 = empty_property(&x11, 8, allocated).into_vec::<u8>();
+            assert_eq!(vec8.unwrap(), Vec::<u8>::new());
+
+            let vec16 = empty_property(&x11, 16, allocated).into_vec::<u16>();
+            assert_eq!(vec16.unwrap(), Vec::<u16>::new());
+
+            let vec32 = empty_property(&x11, 32, allocated).into_vec::<u32>();
+            assert_eq!(vec32.unwrap(), Vec::<u32>::new());
+
+            let vec32 = empty_property(&x11, 32, allocated).into_vec32();
+            assert_eq!(vec32.unwrap(), Vec::<u32>::new());
+        }
+    }
+
+    #[test]
+    fn empty_properties_have_any_format() {
+        let x11 = LibX11::new().expect("libX11 isn't installed");
+
+        // Some owners say 8 bits for empty lists of atoms, there's nothing to misread anyway
+        assert!(empty_property(&x11, 8, false)
+            .into_vec::<u32>()
+            .unwrap()
+            .is_empty());
+        assert!(empty_property(&x11, 8, true)
+            .into_vec32()
+            .unwrap()
+            .is_empty());
+        assert!(empty_property(&x11, 32, true)
+            .into_vec::<u8>()
+            .unwrap()
+            .is_empty());
+        assert!(empty_property(&x11, 16, false)
+            .as_bytes()
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn empty_properties_leave_the_vec_alone() {
+        let x11 = LibX11::new().expect("libX11 isn't installed");
+
+        for format in [8, 16, 32] {
+            let mut buf = vec![1u8, 2, 3];
+            empty_property(&x11, format, true)
+                .write_into_vec(&mut buf)
+                .unwrap();
+            assert_eq!(buf, [1, 2, 3]);
+        }
+    }
+
+    #[test]
+    fn deadlines_are_the_earliest_one() {
+        let now = Instant::now();
+        let later = now + Duration::from_secs(1);
+
+        assert_eq!(earliest(None, None), None);
+        assert_eq!(earliest(Some(now), None), Some(now));
+        assert_eq!(earliest(None, Some(later)), Some(later));
+        assert_eq!(earliest(Some(later), Some(now)), Some(now));
+    }
+
+    #[test]
+    fn reader_events_are_about_our_window() {
+        let Some(clipboard) = connect() else { return };
+        let atoms = &clipboard.atoms;
+
+        let ours = property_notify(clipboard.window, atoms.clipbox, property::NEW_VALUE);
+        assert!(clipboard.is_reader_event(&ours));
+        let answer = selection_notify(clipboard.window, atoms.clipboard, atoms.string, 0);
+        assert!(clipboard.is_reader_event(&answer));
+
+        // The owner window's events are for serving
+        let owner = property_notify(clipboard.owner_window, atoms.clipbox, property::NEW_VALUE);
+        assert!(!clipboard.is_reader_event(&owner));
+        let other = selection_notify(clipboard.owner_window, atoms.clipboard, atoms.string, 0);
+        assert!(!clipboard.is_reader_event(&other));
+    }
+
+    #[test]
+    fn timestamps_come_from_the_dummy_property() {
+        let Some(clipboard) = connect() else { return };
+        let atoms = &clipboard.atoms;
+
+        let dummy = property_notify(clipboard.window, atoms.clipbox_dummy, property::NEW_VALUE);
+        assert_eq!(clipboard.timestamp_of(&dummy), Some(42));
+
+        let other = property_notify(clipboard.window, atoms.clipbox, property::NEW_VALUE);
+        assert_eq!(clipboard.timestamp_of(&other), None);
+        let answer = selection_notify(clipboard.window, atoms.clipboard, atoms.string, 0);
+        assert_eq!(clipboard.timestamp_of(&answer), None);
+    }
+
+    #[test]
+    fn selection_replies_answer_our_conversion() {
+        let Some(clipboard) = connect() else { return };
+        let atoms = &clipboard.atoms;
+        let reply = |xevent: &XEvent| {
+            clipboard.selection_reply(xevent, atoms.clipboard, atoms.string, atoms.clipbox)
+        };
+
+        let converted = selection_notify(
+            clipboard.window,
+            atoms.clipboard,
+            atoms.string,
+            atoms.clipbox,
+        );
+        assert!(matches!(reply(&converted), Some(Ok(xevent)) if xevent.property == atoms.clipbox));
+
+        let refused = selection_notify(clipboard.window, atoms.clipboard, atoms.string, 0);
+        assert!(matches!(
+            reply(&refused),
+            Some(Err(GetSelectionError::SelectionLost))
+        ));
+
+        // Late answers to other conversions, and other events, are for someone else
+        let late = selection_notify(clipboard.window, atoms.primary, atoms.string, 0);
+        assert!(reply(&late).is_none());
+        let elsewhere = selection_notify(
+            clipboard.owner_window,
+            atoms.clipboard,
+            atoms.string,
+            atoms.clipbox,
+        );
+        assert!(reply(&elsewhere).is_none());
+        let property = property_notify(clipboard.window, atoms.clipbox, property::NEW_VALUE);
+        assert!(reply(&property).is_none());
+    }
+
+    #[test]
+    fn incr_chunks_are_new_values_of_their_property() {
+        let Some(clipboard) = connect() else { return };
+        let atoms = &clipboard.atoms;
+
+        let chunk = property_notify(clipboard.window, atoms.clipbox, property::NEW_VALUE);
+        assert!(clipboard.is_incr_chunk(&chunk, atoms.clipbox));
+
+        let deleted = property_notify(clipboard.window, atoms.clipbox, property::DELETE);
+        assert!(!clipboard.is_incr_chunk(&deleted, atoms.clipbox));
+        let other = property_notify(clipboard.window, atoms.clipbox_dummy, property::NEW_VALUE);
+        assert!(!clipboard.is_incr_chunk(&other, atoms.clipbox));
+        let owner = property_notify(clipboard.owner_window, atoms.clipbox, property::NEW_VALUE);
+        assert!(!clipboard.is_incr_chunk(&owner, atoms.clipbox));
+    }
+
+    #[test]
+    fn serving_wakes_up_for_the_earliest_deadline() {
+        let Some(clipboard) = connect() else { return };
+        let start = Instant::now();
+
+        assert_eq!(clipboard.serve_deadline(&serving(None, None)), None);
+
+        let until = start + Duration::from_secs(60);
+        assert_eq!(
+            clipboard.serve_deadline(&serving(None, Some(until))),
+            Some(until)
+        );
+
+        let idle = serving(Some(Duration::from_secs(1)), Some(until));
+        let deadline = clipboard.serve_deadline(&idle).unwrap();
+        assert!(deadline > start && deadline <= Instant::now() + Duration::from_secs(1));
+    }
+
+    #[test]
+    fn serving_times_out_without_transfers() {
+        let Some(clipboard) = connect() else { return };
+
+        // Nothing to wait for anymore
+        assert!(clipboard.serve_timed_out(&mut serving(Some(Duration::ZERO), None)));
+
+        let past = Instant::now() - Duration::from_millis(1);
+        assert!(clipboard.serve_timed_out(&mut serving(None, Some(past))));
+    }
 }
@@ -0,0 +1,267 @@
+//! Pasting and copying from a tokio task, without blocking a thread on the X connection.
+//!
+//! The rest of [`X11Clipboard`] waits for the X server in Xlib, so async apps have to give it a
+//! worker thread of its own. Here, the connection's file descriptor is registered with tokio
+//! (through [`AsyncFd`]) and events are only read once it's readable, so the task yields to
+//! the runtime in the meantime.
+//!
+//! The clipboard isn't `Send`, and neither are these futures: run them on a current-thread
+//! runtime or a [`LocalSet`](tokio::task::LocalSet). One operation at a time per clipboard,
+//! too, since each of them registers the connection.
+
+use std::ffi::{c_ulong, CStr};
+use std::io;
+use std::os::fd::{AsRawFd, RawFd};
+use std::time::{Duration, Instant};
+
+use loki_linux::x11::{Atom, XEvent};
+use tokio::io::unix::AsyncFd;
+
+use super::{
+    atom_names, GetSelectionError, Payload, ReplyProperty, SetSelectionError, X11Clipboard,
+};
+use crate::error::ClipboxError;
+use crate::protocol::IncrReceiver;
+use crate::selection::Selection;
+
+/// The X connection of a clipboard, as tokio sees it
+struct AsyncConnection<'a> {
+    clipboard: &'a X11Clipboard,
+    fd: AsyncFd<RawFd>,
+}
+
+impl<'a> AsyncConnection<'a> {
+    fn new(clipboard: &'a X11Clipboard) -> io::Result<Self> {
+        Ok(Self {
+            clipboard,
+            fd: AsyncFd::new(clipboard.as_raw_fd())?,
+        })
+    }
+
+    /// Gets the next event if it comes before the deadline, or waits forever without one.
+    /// Gives up early if the runtime can't watch the connection anymore.
+    async fn next_event_until(&self, deadline: Option<Instant>) -> Option<XEvent> {
+        let clipboard = self.clipboard;
+
+        loop {
+            // Xlib may have read events already, and this also flushes our requests
+            if unsafe { (clipboard.x.XPending)(clipboard.display.as_ptr()) } > 0 {
+                return Some(clipboard.next_event());
+            }
+
+            let readable = self.fd.readable();
+            let mut guard = match deadline {
+                Some(deadline) => {
+                    let deadline = tokio::time::Instant::from_std(deadline);
+                    tokio::time::timeout_at(deadline, readable).await.ok()?
+                }
+                None => readable.await,
+            }
+            .ok()?;
+
+            // XPending does the actual reading
+            guard.clear_ready();
+        }
+    }
+
+    /// See [`X11Clipboard::next_reader_event_until`]
+    async fn next_reader_event_until(&self, deadline: Option<Instant>) -> Option<XEvent> {
+        loop {
+            let xevent = self.next_event_until(deadline).await?;
+            if let Some(xevent) = self.clipboard.reader_event(xevent) {
+                return Some(xevent);
+            }
+        }
+    }
+
+    /// See [`X11Clipboard::next_owner_event_until`]
+    async fn next_owner_event_until(&self, deadline: Option<Instant>) -> Option<XEvent> {
+        let deferred = self.clipboard.deferred_events.borrow_mut().pop_front();
+        match deferred {
+            Some(xevent) => Some(xevent),
+            None => self.next_event_until(deadline).await,
+        }
+    }
+
+    /// See [`X11Clipboard::get_compliant_timestamp`]
+    async fn get_compliant_timestamp(&self, timeout: Option<Duration>) -> Option<c_ulong> {
+        self.clipboard.request_timestamp();
+
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            let xevent = self.next_reader_event_until(deadline).await?;
+            if let Some(time) = self.clipboard.timestamp_of(&xevent) {
+                return Some(time);
+            }
+        }
+    }
+
+    /// See [`X11Clipboard::get_selection_event`]
+    async fn get_selection_event(
+        &self,
+        atom_selection: Atom,
+        atom_target: Atom,
+        atom_property: Atom,
+    ) -> Result<(), GetSelectionError> {
+        let clipboard = self.clipboard;
+
+        let timeout = clipboard.timeouts.get().conversion;
+        let when_everything_started = self
+            .get_compliant_timestamp(timeout)
+            .await
+            .ok_or(GetSelectionError::Timeout)?;
+
+        clipboard.convert_selection(
+            atom_selection,
+            atom_target,
+            atom_property,
+            when_everything_started,
+        );
+
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            let xevent = self
+                .next_reader_event_until(deadline)
+                .await
+                .ok_or(GetSelectionError::Timeout)?;
+
+            if let Some(reply) =
+                clipboard.selection_reply(&xevent, atom_selection, atom_target, atom_property)
+            {
+                return reply.map(|_| ());
+            }
+        }
+    }
+
+    /// See [`X11Clipboard::next_incr_chunk`]
+    async fn next_incr_chunk(
+        &self,
+        property: &ReplyProperty<'_>,
+    ) -> Result<Vec<u8>, GetSelectionError> {
+        let clipboard = self.clipboard;
+
+        // Deleting the property tells the owner we're ready for more
+        unsafe {
+            (clipboard.x.XDeleteProperty)(
+                clipboard.display.as_ptr(),
+                clipboard.window,
+                property.atom,
+            )
+        };
+
+        let deadline = clipboard
+            .timeouts
+            .get()
+            .incr_chunk
+            .map(|timeout| Instant::now() + timeout);
+        loop {
+            let xevent = self
+                .next_reader_event_until(deadline)
+                .await
+                .ok_or(GetSelectionError::Timeout)?;
+
+            if clipboard.is_incr_chunk(&xevent, property.atom) {
+                break;
+            }
+        }
+
+        Ok(property.read()?.into_vec::<u8>()?)
+    }
+}
+
+impl X11Clipboard {
+    /// Gets the selection like [`X11Clipboard::get_selection`], waiting for its owner from
+    /// the async runtime instead of blocking the thread.
+    ///
+    /// This has to run within a tokio runtime with IO and timers enabled.
+    pub async fn get_selection_async(
+        &self,
+        selection: Selection,
+        target: &CStr,
+    ) -> Result<Vec<u8>, ClipboxError> {
+        if target == atom_names::TARGETS {
+            panic!(concat!(
+                "TARGETS is a special selection target, this method doesn't support it.",
+                " Try X11Clipboard::get_targets instead!"
+            ));
+        }
+
+        let connection = AsyncConnection::new(self).map_err(GetSelectionError::Io)?;
+
+        let atom_selection = self.selection_atom(selection);
+        let atom_target = self.intern(target);
+        let property = self.reply_property();
+        connection
+            .get_selection_event(atom_selection, atom_target, property.atom)
+            .await?;
+
+        let clipbox_prop = property.read()?;
+        if clipbox_prop.ty != self.atoms.incr {
+            return Ok(clipbox_prop.into_vec()?);
+        }
+        drop(clipbox_prop);
+
+        // We got an INCR atom, fetch property incrementally
+        let mut receiver = IncrReceiver::new();
+        loop {
+            let chunk = connection.next_incr_chunk(&property).await?;
+            if receiver.push(&chunk) {
+                break;
+            }
+        }
+
+        Ok(receiver.finish())
+    }
+
+    /// Sets the selection like [`X11Clipboard::set_selection`], serving it from the async
+    /// runtime instead of blocking the thread. The future completes when serving is over, as
+    /// [`ServeUntil`](super::ServeUntil) says (`Background` serves from this task too).
+    ///
+    /// This has to run within a tokio runtime with IO and timers enabled.
+    pub async fn set_selection_async(
+        &self,
+        selection: Selection,
+        target: &CStr,
+        data: &[u8],
+    ) -> Result<(), ClipboxError> {
+        let data = match &self.payload_limit {
+            Some(limit) => limit.apply(target, data)?,
+            None => data,
+        };
+
+        let connection = AsyncConnection::new(self).map_err(SetSelectionError::Io)?;
+        let timeouts = self.timeouts.get();
+
+        let mut attempt = 0;
+        let mut backoff = self.ownership_retry.backoff;
+        let (when_everything_started, owned) = loop {
+            let time = connection
+                .get_compliant_timestamp(timeouts.ownership)
+                .await
+                .ok_or(SetSelectionError::Timeout)?;
+
+            if let Some(owned) = self.try_acquire(&[selection], time, attempt)? {
+                break (time, owned);
+            }
+
+            attempt += 1;
+            tokio::time::sleep(backoff).await;
+            backoff *= 2;
+        };
+
+        let offers = vec![(self.intern(target), Payload::Bytes(data))];
+        let mut serving = self.start_serving(owned, when_everything_started, offers);
+
+        loop {
+            let deadline = self.serve_deadline(&serving);
+            let over = match connection.next_owner_event_until(deadline).await {
+                Some(xevent) => self.serve_event(&mut serving, xevent),
+                None => self.serve_timed_out(&mut serving),
+            };
+
+            if over {
+                return Ok(());
+            }
+        }
+    }
+}
@@ -0,0 +1,300 @@
+//! Serving a selection from a thread of its own, so that copying returns right away.
+//!
+//! Owning a selection means answering every app that pastes it, for as long as we own it.
+//! [`X11Clipboard::set_many`] does that on the caller's thread until the copy is over (see
+//! [`ServeUntil`]), which blocks it. Here, a thread with its own connection to the same display
+//! does the serving until another client takes the selection.
+
+use std::panic;
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, OnceLock};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use loki_linux::x11::XWindow;
+
+use super::{ServeUntil, TargetBundle, X11Clipboard};
+use crate::cancel::CancellationToken;
+use crate::error::{ClipboxError, ErrorContext, Phase, ResultExt};
+use crate::event::ClipboardEvent;
+use crate::selection::Selection;
+
+/// How many of its events a background copy keeps until they're read
+const MAX_PENDING_EVENTS: usize = 256;
+
+/// How often we check whether the thread is done handing off
+const HAND_OFF_CHECK_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How late after the deadline of a shutdown the thread may notice it
+const SHUTDOWN_GRACE: Duration = Duration::from_millis(100);
+
+/// A copy being served from a background thread, see [`X11Clipboard::set_many_in_background`].
+///
+/// Dropping it leaves the thread running: it keeps serving until another client takes the
+/// selection or the process exits. Use [`BackgroundCopy::hand_off`] to have a clipboard manager
+/// keep it instead.
+pub struct BackgroundCopy {
+    selections: Vec<Selection>,
+    /// The owner window of the background connection
+    window: XWindow,
+    events: Receiver<ClipboardEvent>,
+    /// Stops the serving, after which the thread hands off to the clipboard manager
+    stop: CancellationToken,
+    shutdown: ShutdownSignal,
+    thread: JoinHandle<Result<(), ClipboxError>>,
+}
+
+/// Asks the thread of a background copy to shut down, see [`BackgroundCopy::shutdown`]. Its
+/// connection checks it while serving.
+#[derive(Debug, Clone, Default)]
+pub(super) struct ShutdownSignal {
+    requested: Arc<OnceLock<ShutdownRequest>>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ShutdownRequest {
+    deadline: Instant,
+    hand_off: bool,
+}
+
+impl ShutdownSignal {
+    /// When the transfers in progress are given up on, once a shutdown was asked for
+    pub(super) fn deadline(&self) -> Option<Instant> {
+        self.requested.get().map(|request| request.deadline)
+    }
+
+    /// Whether we were shut down without handing off to the clipboard manager
+    fn releases(&self) -> bool {
+        self.requested
+            .get()
+            .is_some_and(|request| !request.hand_off)
+    }
+}
+
+impl X11Clipboard {
+    /// Copies every target of the bundle from a background thread, which keeps serving them
+    /// until another client takes the selection.
+    ///
+    /// This returns as soon as the thread owns the selection. The thread opens its own
+    /// connection to the same display, with our timeouts, payload limit and chunk size.
+    pub fn set_many_in_background(
+        &self,
+        selection: Selection,
+        bundle: TargetBundle,
+    ) -> Result<BackgroundCopy, ClipboxError> {
+        self.copy_in_background(&[selection], bundle, None)
+    }
+
+    /// Copies the bundle on every selection from a background thread, see
+    /// [`X11Clipboard::set_many_in_background`]. With a TTL, the thread empties the selections
+    /// once it's over, like [`X11Clipboard::set_text_with_ttl`].
+    pub(crate) fn copy_in_background(
+        &self,
+        selections: &[Selection],
+        bundle: TargetBundle,
+        ttl: Option<Duration>,
+    ) -> Result<BackgroundCopy, ClipboxError> {
+        let display_name = self.display_name();
+        let timeouts = self.timeouts();
+        let payload_limit = self.payload_limit;
+        let incr_chunk_size = self.incr_chunk_size;
+        let ownership_retry = self.ownership_retry;
+
+        let thread_selections = selections.to_vec();
+        let stop = CancellationToken::new();
+        let thread_stop = stop.clone();
+        let shutdown = ShutdownSignal::default();
+        let thread_shutdown = shutdown.clone();
+        let (ready_tx, ready_rx) = mpsc::channel();
+        let thread = thread::Builder::new()
+            .name("clipbox-owner".to_string())
+            .spawn(move || -> Result<(), ClipboxError> {
+                let mut clipboard = X11Clipboard::init_with_display_name(&display_name)?;
+                clipboard.set_timeouts(timeouts);
+                clipboard.set_payload_limit(payload_limit);
+                clipboard.set_incr_chunk_size(incr_chunk_size);
+                clipboard.set_ownership_retry(ownership_retry);
+                // Not `Background`, or we'd spawn threads all the way down
+                clipboard.set_serve_until(ServeUntil::OwnershipLost);
+                clipboard.shutdown = Some(thread_shutdown.clone());
+
+                let events = clipboard.subscribe_bounded(MAX_PENDING_EVENTS);
+                let _ = ready_tx.send((clipboard.owner_window, events));
+                clipboard
+                    .with_cancellation(&thread_stop, |clipboard| match ttl {
+                        Some(ttl) => {
+                            clipboard.serve_selection_with_ttl(&thread_selections, &bundle, ttl)
+                        }
+                        None => clipboard.set_many_on(&thread_selections, &bundle),
+                    })
+                    .context(|| ErrorContext {
+                        selection: thread_selections.first().copied(),
+                        ..ErrorContext::new(Phase::Serving)
+                    })?;
+
+                if thread_shutdown.releases() {
+                    for &selection in &thread_selections {
+                        if clipboard.selection_owner(selection) == Some(clipboard.owner_window) {
+                            // Someone taking it in the meantime is just as good
+                            let _ = clipboard.clear(selection);
+                        }
+                    }
+                }
+
+                // Dropping the clipboard hands `CLIPBOARD` to the manager if we were stopped
+                // while still owning it
+                Ok(())
+            })
+            .map_err(ClipboxError::Io)?;
+
+        // If the thread hangs up before telling us anything, it failed to connect
+        let Ok((window, events)) = ready_rx.recv() else {
+            return Err(join(thread).err().unwrap_or(ClipboxError::Ownership));
+        };
+
+        // Wait until the thread owns every selection
+        let mut pending = selections.len();
+        while pending > 0 {
+            match events.recv() {
+                Ok(ClipboardEvent::OwnerChanged { .. }) => pending -= 1,
+                Ok(_) => continue,
+                // The thread is done before even owning the selection (T-T)
+                Err(_) => return Err(join(thread).err().unwrap_or(ClipboxError::Ownership)),
+            }
+        }
+
+        Ok(BackgroundCopy {
+            selections: selections.to_vec(),
+            window,
+            events,
+            stop,
+            shutdown,
+            thread,
+        })
+    }
+}
+
+impl BackgroundCopy {
+    pub fn selections(&self) -> &[Selection] {
+        &self.selections
+    }
+
+    /// The window the thread owns the selections from, to tell its copies apart in
+    /// [`ChangeEvent`](super::ChangeEvent)s
+    pub fn window(&self) -> XWindow {
+        self.window
+    }
+
+    /// Whether the thread still serves the selections
+    pub fn is_serving(&self) -> bool {
+        !self.thread.is_finished()
+    }
+
+    /// Whether the thread still owns `selection`.
+    ///
+    /// `clipboard` has to be connected to the same display.
+    pub fn is_owner(&self, clipboard: &X11Clipboard, selection: Selection) -> bool {
+        self.is_serving()
+            && self.selections.contains(&selection)
+            && clipboard.selection_owner(selection) == Some(self.window)
+    }
+
+    /// What happens while the thread serves the selection (requests, transfers...). Once a
+    /// few hundred events are waiting to be read, newer ones are dropped.
+    pub fn events(&self) -> &Receiver<ClipboardEvent> {
+        &self.events
+    }
+
+    /// Blocks until other clients took the selections and the thread is done
+    pub fn wait(self) -> Result<(), ClipboxError> {
+        join(self.thread)
+    }
+
+    /// Stops serving and has the thread hand `CLIPBOARD` over to the clipboard manager, if it
+    /// still owns it and there's a manager (see [`X11Clipboard::has_clipboard_manager`]).
+    /// Copies with a TTL are emptied instead.
+    ///
+    /// Waits at most `timeout` for the manager to save it, giving whether the thread is done.
+    /// Otherwise the thread finishes handing off by itself, as long as the process lives.
+    pub fn hand_off(self, timeout: Duration) -> bool {
+        self.stop.cancel();
+
+        let deadline = Instant::now() + timeout;
+        while !self.thread.is_finished() {
+            if Instant::now() >= deadline {
+                return false;
+            }
+
+            thread::sleep(HAND_OFF_CHECK_INTERVAL);
+        }
+
+        // Errors were reported as events already
+        let _ = join(self.thread);
+        true
+    }
+
+    /// Stops serving by `deadline` and waits for the thread to be done, so that the process can
+    /// exit without leaving anyone hanging:
+    ///
+    /// - Transfers in progress get to finish until `deadline`, and are given up on after that.
+    ///   Pastes that come in the meantime are refused.
+    /// - With `hand_off`, `CLIPBOARD` is then handed to the clipboard manager if there's one,
+    ///   which also has to be done by `deadline`. Otherwise, or without a manager, the
+    ///   selections the thread still owns are emptied.
+    ///
+    /// Gives [`ClipboxError::Timeout`] if the thread isn't done by then, leaving it to finish by
+    /// itself as long as the process lives.
+    pub fn shutdown(self, deadline: Instant, hand_off: bool) -> Result<(), ClipboxError> {
+        let _ = self
+            .shutdown
+            .requested
+            .set(ShutdownRequest { deadline, hand_off });
+
+        while !self.thread.is_finished() {
+            if Instant::now() >= deadline + SHUTDOWN_GRACE {
+                return Err(ClipboxError::Timeout);
+            }
+
+            thread::sleep(HAND_OFF_CHECK_INTERVAL);
+        }
+
+        join(self.thread)
+    }
+
+    /// Stops serving, emptying the selections the thread still owns.
+    ///
+    /// `clipboard` has to be connected to the same display.
+    pub fn stop(self, clipboard: &X11Clipboard) -> Result<(), ClipboxError> {
+        for &selection in &self.selections {
+            if self.is_owner(clipboard, selection) {
+                clipboard.clear(selection)?;
+            }
+        }
+
+        self.wait()
+    }
+}
+
+impl X11Clipboard {
+    /// Waits for someone to own `CLIPBOARD` after a background copy handed it off, which the
+    /// clipboard manager does once the copy is gone. Gives whether anyone does by `deadline`.
+    pub(crate) fn wait_for_clipboard_owner(&self, deadline: Instant) -> bool {
+        loop {
+            if self.selection_owner(Selection::Clipboard).is_some() {
+                return true;
+            }
+
+            if Instant::now() >= deadline {
+                return false;
+            }
+
+            thread::sleep(HAND_OFF_CHECK_INTERVAL);
+        }
+    }
+}
+
+fn join(thread: JoinHandle<Result<(), ClipboxError>>) -> Result<(), ClipboxError> {
+    thread
+        .join()
+        .unwrap_or_else(|payload| panic::resume_unwind(payload))
+}
@@ -0,0 +1,192 @@
+//! Driving the clipboard from a [calloop](::calloop) event loop.
+//!
+//! The [`ClipboardSource`] registers the X connection with the loop and dispatches its
+//! [`ClipboardDriver`] whenever it's readable, with a timer for the driver's deadlines. Its
+//! callback gets the changes of the selections it was told to watch, along with the driver to
+//! copy or paste from.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io;
+use std::ops::{Deref, DerefMut};
+use std::os::fd::AsFd;
+use std::rc::Rc;
+use std::time::Instant;
+
+use ::calloop::timer::{TimeoutAction, Timer};
+use ::calloop::{EventSource, Interest, Mode, Poll, PostAction, Readiness, Token, TokenFactory};
+
+use super::{ChangeEvent, ClipboardDriver, X11Clipboard};
+use crate::selection::Selection;
+
+/// A calloop event source for a clipboard, see the [module docs](self).
+///
+/// The loop owns the source once it's inserted: go through a
+/// [`Dispatcher`](::calloop::Dispatcher) to copy from other callbacks. The source notices new
+/// deadlines right before the loop goes to sleep, so copying from anywhere is fine.
+pub struct ClipboardSource {
+    driver: ClipboardDriver,
+    /// What watchers noticed during a dispatch, for the callback
+    changes: Rc<RefCell<VecDeque<ChangeEvent>>>,
+    token: Option<Token>,
+    /// Wakes us up for the driver's deadline, which it's armed with
+    timer: Option<(Timer, Instant)>,
+}
+
+impl ClipboardSource {
+    pub fn new(clipboard: X11Clipboard) -> Self {
+        Self::from_driver(ClipboardDriver::new(clipboard))
+    }
+
+    pub fn from_driver(driver: ClipboardDriver) -> Self {
+        Self {
+            driver,
+            changes: Rc::default(),
+            token: None,
+            timer: None,
+        }
+    }
+
+    /// Hands the changes of the selections to the callback, see [`ClipboardDriver::watch`]
+    pub fn emit_changes(&mut self, selections: &[Selection]) -> bool {
+        let changes = self.changes.clone();
+        self.driver.watch(selections, move |change| {
+            changes.borrow_mut().push_back(change);
+            true
+        })
+    }
+
+    pub fn into_driver(self) -> ClipboardDriver {
+        self.driver
+    }
+
+    /// Whether the timer has to be armed again
+    fn timer_outdated(&self) -> bool {
+        let armed = self.timer.as_ref().map(|&(_, deadline)| deadline);
+        armed != self.driver.deadline() || armed.is_some_and(|deadline| deadline <= Instant::now())
+    }
+
+    fn arm_timer(
+        &mut self,
+        poll: &mut Poll,
+        token_factory: &mut TokenFactory,
+    ) -> ::calloop::Result<()> {
+        self.disarm_timer(poll)?;
+
+        if let Some(deadline) = self.driver.deadline() {
+            let mut timer = Timer::from_deadline(deadline);
+            timer.register(poll, token_factory)?;
+            self.timer = Some((timer, deadline));
+        }
+
+        Ok(())
+    }
+
+    fn disarm_timer(&mut self, poll: &mut Poll) -> ::calloop::Result<()> {
+        match self.timer.take() {
+            Some((mut timer, _)) => timer.unregister(poll),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Deref for ClipboardSource {
+    type Target = ClipboardDriver;
+
+    fn deref(&self) -> &ClipboardDriver {
+        &self.driver
+    }
+}
+
+impl DerefMut for ClipboardSource {
+    fn deref_mut(&mut self) -> &mut ClipboardDriver {
+        &mut self.driver
+    }
+}
+
+impl EventSource for ClipboardSource {
+    type Event = ChangeEvent;
+    type Metadata = ClipboardDriver;
+    type Ret = ();
+    type Error = io::Error;
+
+    const NEEDS_EXTRA_LIFECYCLE_EVENTS: bool = true;
+
+    fn process_events<F>(
+        &mut self,
+        readiness: Readiness,
+        token: Token,
+        mut callback: F,
+    ) -> Result<PostAction, io::Error>
+    where
+        F: FnMut(ChangeEvent, &mut ClipboardDriver),
+    {
+        // It's either the connection or the timer, and we do the same thing in both cases
+        if let Some((timer, _)) = &mut self.timer {
+            timer
+                .process_events(readiness, token, |_, _| TimeoutAction::Drop)
+                .map_err(io::Error::other)?;
+        }
+
+        self.driver.dispatch();
+
+        loop {
+            let change = self.changes.borrow_mut().pop_front();
+            let Some(change) = change else {
+                break;
+            };
+
+            callback(change, &mut self.driver);
+        }
+
+        if self.timer_outdated() {
+            return Ok(PostAction::Reregister);
+        }
+
+        Ok(PostAction::Continue)
+    }
+
+    fn register(
+        &mut self,
+        poll: &mut Poll,
+        token_factory: &mut TokenFactory,
+    ) -> ::calloop::Result<()> {
+        let token = token_factory.token();
+
+        // The connection stays open until the driver is dropped, which calloop only does after
+        // unregistering us
+        unsafe { poll.register(self.driver.as_fd(), Interest::READ, Mode::Level, token)? };
+        self.token = Some(token);
+
+        self.arm_timer(poll, token_factory)
+    }
+
+    fn reregister(
+        &mut self,
+        poll: &mut Poll,
+        token_factory: &mut TokenFactory,
+    ) -> ::calloop::Result<()> {
+        let token = token_factory.token();
+        poll.reregister(self.driver.as_fd(), Interest::READ, Mode::Level, token)?;
+        self.token = Some(token);
+
+        self.arm_timer(poll, token_factory)
+    }
+
+    fn unregister(&mut self, poll: &mut Poll) -> ::calloop::Result<()> {
+        poll.unregister(self.driver.as_fd())?;
+        self.token = None;
+
+        self.disarm_timer(poll)
+    }
+
+    fn before_sleep(&mut self) -> ::calloop::Result<Option<(Readiness, Token)>> {
+        // Copies made from elsewhere since, or events Xlib read ahead: dispatch before sleeping
+        let wake_up = self.timer_outdated() || self.driver.has_pending_events();
+
+        Ok(self
+            .token
+            .filter(|_| wake_up)
+            .map(|token| (Readiness::EMPTY, token)))
+    }
+}
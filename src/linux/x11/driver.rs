@@ -0,0 +1,604 @@
+//! Everything the event thread does, for event loops that already exist.
+//!
+//! A [`ClipboardDriver`] serves its copies, pastes and notices changes whenever it's dispatched,
+//! without ever blocking to wait for events: register its file descriptor with the event loop,
+//! dispatch it when that's readable, and again by its [deadline](ClipboardDriver::deadline). The
+//! [`ClipboardThread`](super::ClipboardThread) is one of these on a thread of its own.
+
+use std::ffi::{c_ulong, CStr, CString};
+use std::mem;
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, RawFd};
+use std::time::{Duration, Instant};
+
+use loki_linux::x11::{et, Atom, XEvent, XWindow};
+
+use super::xfixes::selection_mask;
+use super::xlib;
+use super::{
+    atom_names, ChangeEvent, GetSelectionError, Payload, Serving, SetSelectionError, TargetBundle,
+    X11Clipboard,
+};
+use crate::error::ClipboxError;
+use crate::protocol::IncrReceiver;
+use crate::selection::Selection;
+
+/// What gets told about changes, until it says it's not listening anymore
+type OnChange = Box<dyn FnMut(ChangeEvent) -> bool>;
+
+/// What gets the data once a paste is over, see [`ClipboardDriver::get`]
+type OnPasted = Box<dyn FnOnce(Result<Vec<u8>, ClipboxError>)>;
+
+/// What gets the targets once the owner listed them, see [`ClipboardDriver::targets`]
+type OnTargets = Box<dyn FnOnce(Result<Vec<CString>, ClipboxError>)>;
+
+/// Serves copies and watches selections from someone else's event loop, see the
+/// [module docs](self)
+pub struct ClipboardDriver {
+    clipboard: X11Clipboard,
+    /// Copies we serve, each on its own selections
+    servings: Vec<Serving<'static>>,
+    /// Pastes waiting for their owner, oldest first
+    pastes: Vec<Paste>,
+    watchers: Vec<DriverWatcher>,
+}
+
+/// A paste going on in the driver, driven by the owner's answers
+struct Paste {
+    atom_selection: Atom,
+    atom_target: Atom,
+    /// The property of ours the owner answers into
+    property: Atom,
+    step: PasteStep,
+    /// When we give up on the owner, reset whenever it answers
+    deadline: Option<Instant>,
+    on_done: OnPasteDone,
+}
+
+/// Where a paste is at
+enum PasteStep {
+    /// Waiting for a timestamp to ask the owner with
+    Timestamp,
+    /// Waiting for the owner to answer our `ConvertSelection`
+    Conversion,
+    /// Waiting for the next chunk of an INCR transfer
+    Incr(IncrReceiver),
+}
+
+/// What a paste is for
+enum OnPasteDone {
+    Data(OnPasted),
+    Targets(OnTargets),
+}
+
+/// What the owner sent
+enum Pasted {
+    Data(Vec<u8>),
+    Targets(Vec<Atom>),
+}
+
+/// A watcher living in the driver, see [`ClipboardDriver::watch`]
+struct DriverWatcher {
+    selections: Vec<(Selection, Atom)>,
+    on_change: OnChange,
+}
+
+impl ClipboardDriver {
+    pub fn new(clipboard: X11Clipboard) -> Self {
+        Self {
+            clipboard,
+            servings: Vec::new(),
+            pastes: Vec::new(),
+            watchers: Vec::new(),
+        }
+    }
+
+    /// The clipboard underneath. Setting selections from there blocks until serving is over,
+    /// and pasting keeps us from serving until the owner answers: use [`ClipboardDriver::set`]
+    /// and [`ClipboardDriver::get`] instead.
+    pub fn clipboard(&self) -> &X11Clipboard {
+        &self.clipboard
+    }
+
+    /// Handles every event that's already there, then whatever timed out. This never waits
+    /// for the X server, except to answer the requests it handles and to read what owners
+    /// pasted.
+    pub fn dispatch(&mut self) {
+        // Including what pastes put aside
+        while let Some(xevent) = self.clipboard.next_owner_event_until(Some(Instant::now())) {
+            self.handle_event(xevent);
+        }
+
+        self.handle_timeouts();
+    }
+
+    /// Whether dispatching would handle events right away. Xlib reads events ahead while it
+    /// waits for replies, so they're not always left for the connection to be readable.
+    pub fn has_pending_events(&self) -> bool {
+        let clipboard = &self.clipboard;
+        !clipboard.deferred_events.borrow().is_empty()
+            || unsafe { (clipboard.x.XPending)(clipboard.display.as_ptr()) } > 0
+    }
+
+    /// When to dispatch again even if no events come, if ever
+    pub fn deadline(&self) -> Option<Instant> {
+        let servings = self
+            .servings
+            .iter()
+            .filter_map(|serving| self.clipboard.serve_deadline(serving));
+        let pastes = self.pastes.iter().filter_map(|paste| paste.deadline);
+
+        servings.chain(pastes).min()
+    }
+
+    /// Pastes the selection, handing the data to `on_pasted` from [`ClipboardDriver::dispatch`]
+    /// once its owner sent all of it. If we serve the selection ourselves, that's right away
+    /// unless it's a stream, which we paste from ourselves like any owner.
+    ///
+    /// Other events are handled while the owner takes its time, so several pastes can go on
+    /// at the same time. `TARGETS` can't be pasted this way, [`ClipboardDriver::targets`] lists
+    /// them.
+    pub fn get(
+        &mut self,
+        selection: Selection,
+        target: &CStr,
+        on_pasted: impl FnOnce(Result<Vec<u8>, ClipboxError>) + 'static,
+    ) {
+        if target == atom_names::TARGETS {
+            on_pasted(Err(ClipboxError::Protocol(
+                "TARGETS isn't data to paste, use ClipboardDriver::targets instead".into(),
+            )));
+            return;
+        }
+
+        let atom_target = self.clipboard.intern(target);
+
+        // We'd wait for ourselves to answer otherwise
+        let limit = self.clipboard.payload_limit;
+        if let Some(serving) = self.serving(selection) {
+            let Some(payload) = serving.served.payload(atom_target) else {
+                on_pasted(Err(ClipboxError::NotAvailable));
+                return;
+            };
+
+            // Lazy targets are rendered for us like for anyone asking
+            if let Err(err) = payload.prepare(limit.as_ref()) {
+                on_pasted(Err(err.into()));
+                return;
+            }
+
+            // A stream is only read by answering a request, which ours can be
+            if let Some(data) = payload.bytes() {
+                on_pasted(Ok(data.to_vec()));
+                return;
+            }
+        }
+
+        self.start_paste(
+            selection,
+            atom_target,
+            OnPasteDone::Data(Box::new(on_pasted)),
+        );
+    }
+
+    /// Lists the targets of the selection, handing them to `on_targets` like
+    /// [`ClipboardDriver::get`] does. If we serve the selection, they're the ones we answer
+    /// `TARGETS` with.
+    pub fn targets(
+        &mut self,
+        selection: Selection,
+        on_targets: impl FnOnce(Result<Vec<CString>, ClipboxError>) + 'static,
+    ) {
+        let clipboard = &self.clipboard;
+        let atom_selection = clipboard.selection_atom(selection);
+
+        let serving = self
+            .servings
+            .iter()
+            .find(|serving| serving.served.selection(atom_selection).is_some());
+
+        if let Some(serving) = serving {
+            let targets = clipboard
+                .target_atoms(&serving.served)
+                .into_iter()
+                .filter_map(|atom| clipboard.atom_name(atom))
+                .collect();
+
+            on_targets(Ok(targets));
+            return;
+        }
+
+        let atom_targets = clipboard.atoms.targets;
+        self.start_paste(
+            selection,
+            atom_targets,
+            OnPasteDone::Targets(Box::new(on_targets)),
+        );
+    }
+
+    /// Copies every target of the bundle on the selections. This returns once we own them,
+    /// and they're served from [`ClipboardDriver::dispatch`] until another client takes them.
+    pub fn set(
+        &mut self,
+        selections: &[Selection],
+        bundle: &TargetBundle,
+    ) -> Result<(), ClipboxError> {
+        let clipboard = &self.clipboard;
+
+        let offers = bundle
+            .ordered()
+            .into_iter()
+            .map(|(target, data)| {
+                let data = match &clipboard.payload_limit {
+                    Some(limit) => limit.apply(target, data)?,
+                    None => data,
+                };
+
+                Ok((clipboard.intern(target), Payload::Shared(data.into())))
+            })
+            .collect::<Result<Vec<_>, SetSelectionError>>()?;
+
+        let (time, owned) = clipboard.acquire_with_retry(selections)?;
+
+        // Taking a selection from ourselves doesn't get us a SelectionClear
+        for serving in &mut self.servings {
+            serving
+                .served
+                .selections
+                .retain(|(selection, _)| !selections.contains(selection));
+        }
+        self.servings
+            .retain(|serving| !serving.served.selections.is_empty());
+
+        let serving = clipboard.start_serving(owned, time, offers);
+        self.servings.push(serving);
+        Ok(())
+    }
+
+    /// Whether we still serve a copy on the selection
+    pub fn is_serving(&self, selection: Selection) -> bool {
+        let atom_selection = self.clipboard.selection_atom(selection);
+        self.servings
+            .iter()
+            .any(|serving| serving.served.selection(atom_selection).is_some())
+    }
+
+    /// Watches the selections with XFixes, calling `on_change` from
+    /// [`ClipboardDriver::dispatch`] for each change until it returns `false`.
+    ///
+    /// Gives `false` if we don't have XFixes: polling would paste every selection every so
+    /// often, use [`X11Clipboard::watch_with`] for that.
+    pub fn watch(
+        &mut self,
+        selections: &[Selection],
+        on_change: impl FnMut(ChangeEvent) -> bool + 'static,
+    ) -> bool {
+        let clipboard = &self.clipboard;
+        let Some(xfixes) = clipboard.xfixes() else {
+            return false;
+        };
+
+        let selections = selections
+            .iter()
+            .map(|&selection| (selection, clipboard.selection_atom(selection)))
+            .collect::<Vec<_>>();
+
+        for &(_, atom_selection) in &selections {
+            clipboard.select_owner_changes(xfixes, atom_selection, selection_mask::ALL);
+        }
+
+        self.watchers.push(DriverWatcher {
+            selections,
+            on_change: Box::new(on_change),
+        });
+        true
+    }
+
+    /// Asks for a timestamp to paste with, the rest happens as events come
+    fn start_paste(&mut self, selection: Selection, atom_target: Atom, on_done: OnPasteDone) {
+        let clipboard = &self.clipboard;
+        clipboard.request_timestamp();
+
+        self.pastes.push(Paste {
+            atom_selection: clipboard.selection_atom(selection),
+            atom_target,
+            property: clipboard.take_reply_property(),
+            step: PasteStep::Timestamp,
+            deadline: deadline_in(clipboard.timeouts.get().conversion),
+            on_done,
+        });
+    }
+
+    /// The copy we serve on the selection, if we still do
+    fn serving(&mut self, selection: Selection) -> Option<&mut Serving<'static>> {
+        let atom_selection = self.clipboard.selection_atom(selection);
+        self.servings
+            .iter_mut()
+            .find(|serving| serving.served.selection(atom_selection).is_some())
+    }
+
+    fn handle_timeouts(&mut self) {
+        let clipboard = &self.clipboard;
+        let now = Instant::now();
+
+        self.servings
+            .retain_mut(|serving| match clipboard.serve_deadline(serving) {
+                Some(deadline) if deadline <= now => !clipboard.serve_timed_out(serving),
+                _ => true,
+            });
+
+        while let Some(index) = self
+            .pastes
+            .iter()
+            .position(|paste| paste.deadline.is_some_and(|deadline| deadline <= now))
+        {
+            let paste = self.pastes.remove(index);
+            paste.finish(clipboard, Err(GetSelectionError::Timeout));
+        }
+    }
+
+    fn handle_event(&mut self, xevent: XEvent) {
+        let clipboard = &self.clipboard;
+
+        // Our own window only hears about pastes
+        if clipboard.is_reader_event(&xevent) {
+            self.handle_paste_event(&xevent);
+            return;
+        }
+
+        if let Some(notify) = clipboard
+            .xfixes()
+            .and_then(|xfixes| xfixes.selection_notify(&xevent))
+        {
+            self.notify_watchers(notify.selection, notify.owner, notify.selection_timestamp);
+            return;
+        }
+
+        // Requests and clears are for whoever serves that selection...
+        let atom_selection = unsafe {
+            match xevent.type_id {
+                et::SELECTION_REQUEST => Some(xevent.xselectionrequest.selection),
+                et::SELECTION_CLEAR => Some(xlib::selection_clear(&xevent).selection),
+                _ => None,
+            }
+        };
+
+        let Some(atom_selection) = atom_selection else {
+            // ...while transfers are going on in any of them
+            self.servings
+                .retain_mut(|serving| !clipboard.serve_event(serving, xevent));
+            return;
+        };
+
+        let serving = self
+            .servings
+            .iter()
+            .position(|serving| serving.served.selection(atom_selection).is_some());
+
+        match serving {
+            Some(index) => {
+                let over = clipboard.serve_event(&mut self.servings[index], xevent);
+                if over {
+                    self.servings.remove(index);
+                }
+            }
+            None if unsafe { xevent.type_id } == et::SELECTION_REQUEST => {
+                let request = unsafe { xevent.xselectionrequest };
+                if request.owner == clipboard.owner_window {
+                    clipboard.refuse_conversion(&request, "we're not serving that selection");
+                }
+            }
+            None => {}
+        }
+    }
+
+    fn handle_paste_event(&mut self, xevent: &XEvent) {
+        let clipboard = &self.clipboard;
+
+        if let Some(time) = clipboard.timestamp_of(xevent) {
+            // Any timestamp will do, so the oldest paste gets the first one
+            let paste = self
+                .pastes
+                .iter_mut()
+                .find(|paste| matches!(paste.step, PasteStep::Timestamp));
+
+            if let Some(paste) = paste {
+                clipboard.convert_selection(
+                    paste.atom_selection,
+                    paste.atom_target,
+                    paste.property,
+                    time,
+                );
+                paste.step = PasteStep::Conversion;
+                paste.deadline = deadline_in(clipboard.timeouts.get().conversion);
+            }
+            return;
+        }
+
+        let over = self
+            .pastes
+            .iter_mut()
+            .enumerate()
+            .find_map(|(index, paste)| Some((index, paste.handle_event(clipboard, xevent)?)));
+
+        if let Some((index, result)) = over {
+            let paste = self.pastes.remove(index);
+            paste.finish(clipboard, result);
+        }
+    }
+
+    fn notify_watchers(&mut self, atom_selection: Atom, owner: XWindow, acquired_at: c_ulong) {
+        let clipboard = &self.clipboard;
+        let owner = Some(owner).filter(|&owner| owner != 0);
+
+        // Fetched once for every watcher of the selection
+        let mut targets = None;
+
+        let watchers = self.watchers.len();
+        self.watchers.retain_mut(|watcher| {
+            let watched = watcher
+                .selections
+                .iter()
+                .find(|&&(_, atom)| atom == atom_selection);
+
+            let Some(&(selection, _)) = watched else {
+                return true;
+            };
+
+            let targets = targets
+                .get_or_insert_with(|| clipboard.change_targets(selection, owner))
+                .clone();
+
+            (watcher.on_change)(ChangeEvent {
+                selection,
+                owner,
+                acquired_at: Some(acquired_at),
+                targets: Some(targets),
+                preview: None,
+            })
+        });
+
+        if self.watchers.len() == watchers {
+            return;
+        }
+
+        // Stop hearing about what nobody watches anymore
+        let Some(xfixes) = clipboard.xfixes() else {
+            return;
+        };
+
+        let still_watched = self.watchers.iter().any(|watcher| {
+            watcher
+                .selections
+                .iter()
+                .any(|&(_, atom)| atom == atom_selection)
+        });
+
+        if !still_watched {
+            clipboard.select_owner_changes(xfixes, atom_selection, 0);
+        }
+    }
+}
+
+impl Paste {
+    /// Moves the paste along if the event is about it, giving what it got once it's over
+    fn handle_event(
+        &mut self,
+        clipboard: &X11Clipboard,
+        xevent: &XEvent,
+    ) -> Option<Result<Pasted, GetSelectionError>> {
+        match self.step {
+            PasteStep::Timestamp => None,
+            PasteStep::Conversion => {
+                let reply = clipboard.selection_reply(
+                    xevent,
+                    self.atom_selection,
+                    self.atom_target,
+                    self.property,
+                )?;
+
+                reply.and_then(|_| self.read_reply(clipboard)).transpose()
+            }
+            PasteStep::Incr(_) => {
+                if !clipboard.is_incr_chunk(xevent, self.property) {
+                    return None;
+                }
+
+                self.read_chunk(clipboard).transpose()
+            }
+        }
+    }
+
+    /// Reads what the owner converted the selection into, unless it only started an INCR
+    /// transfer
+    fn read_reply(
+        &mut self,
+        clipboard: &X11Clipboard,
+    ) -> Result<Option<Pasted>, GetSelectionError> {
+        let prop = clipboard.get_window_property(clipboard.window, self.property)?;
+
+        if let OnPasteDone::Targets(_) = self.on_done {
+            let atoms = prop.into_vec32()?;
+            let atoms = atoms.into_iter().map(|atom| atom as Atom).collect();
+            return Ok(Some(Pasted::Targets(atoms)));
+        }
+
+        if prop.ty == clipboard.atoms.incr {
+            self.step = PasteStep::Incr(IncrReceiver::new());
+            self.ask_for_chunk(clipboard);
+            return Ok(None);
+        }
+
+        Ok(Some(Pasted::Data(prop.into_vec()?)))
+    }
+
+    /// Reads the chunk the owner just sent, unless it's the empty one that ends the transfer
+    fn read_chunk(
+        &mut self,
+        clipboard: &X11Clipboard,
+    ) -> Result<Option<Pasted>, GetSelectionError> {
+        let chunk = clipboard
+            .get_window_property(clipboard.window, self.property)?
+            .into_vec::<u8>()?;
+
+        let PasteStep::Incr(receiver) = &mut self.step else {
+            return Ok(None);
+        };
+
+        if !receiver.push(&chunk) {
+            self.ask_for_chunk(clipboard);
+            return Ok(None);
+        }
+
+        let receiver = mem::take(receiver);
+        Ok(Some(Pasted::Data(receiver.finish())))
+    }
+
+    /// Tells the owner we're ready for the next chunk of the INCR transfer
+    fn ask_for_chunk(&mut self, clipboard: &X11Clipboard) {
+        unsafe {
+            (clipboard.x.XDeleteProperty)(
+                clipboard.display.as_ptr(),
+                clipboard.window,
+                self.property,
+            )
+        };
+
+        self.deadline = deadline_in(clipboard.timeouts.get().incr_chunk);
+    }
+
+    /// Hands what the paste got to whoever asked for it
+    fn finish(self, clipboard: &X11Clipboard, result: Result<Pasted, GetSelectionError>) {
+        clipboard.free_reply_property(self.property);
+
+        match (self.on_done, result) {
+            (OnPasteDone::Data(on_pasted), Ok(Pasted::Data(data))) => on_pasted(Ok(data)),
+            (OnPasteDone::Targets(on_targets), Ok(Pasted::Targets(atoms))) => {
+                let names = atoms
+                    .into_iter()
+                    .filter_map(|atom| clipboard.atom_name(atom))
+                    .collect();
+                on_targets(Ok(names));
+            }
+            (OnPasteDone::Data(on_pasted), Err(err)) => on_pasted(Err(err.into())),
+            (OnPasteDone::Targets(on_targets), Err(err)) => on_targets(Err(err.into())),
+            (_, Ok(_)) => unreachable!("pastes are read as what they're for"),
+        }
+    }
+}
+
+/// When something that may take `timeout` should be given up on, if ever
+fn deadline_in(timeout: Option<Duration>) -> Option<Instant> {
+    timeout.map(|timeout| Instant::now() + timeout)
+}
+
+impl AsRawFd for ClipboardDriver {
+    fn as_raw_fd(&self) -> RawFd {
+        self.clipboard.as_raw_fd()
+    }
+}
+
+impl AsFd for ClipboardDriver {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.clipboard.as_fd()
+    }
+}
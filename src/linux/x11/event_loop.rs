@@ -0,0 +1,423 @@
+//! A thread that owns a connection and does everything on it, driven through handles.
+//!
+//! An [`X11Clipboard`] only reads events while one of its methods waits for something: a copy
+//! is only served while `set_*` blocks, and a watcher misses the requests that come in while it
+//! waits for changes. The [`ClipboardThread`] reads every event as it comes instead, and
+//! dispatches it to whatever it's about: copies it serves, watchers, pastes in progress.
+//! Handles send it commands over a channel, from any thread, so that several copies, pastes and
+//! watchers can go on at the same time.
+
+use std::ffi::{CStr, CString};
+use std::fs::File;
+use std::io::{self, Write};
+use std::os::fd::AsRawFd;
+use std::panic;
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError, TrySendError};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Instant;
+
+use super::{
+    atom_names, poll, ChangeEvent, ClipboardDriver, TargetBundle, X11Clipboard, UTF8_TEXT_TARGETS,
+};
+use crate::convert;
+use crate::error::{ClipboxError, ErrorContext, Phase, ResultExt};
+use crate::event::ClipboardEvent;
+use crate::selection::Selection;
+
+type Job = Box<dyn FnOnce(&X11Clipboard) + Send>;
+
+/// Where a watcher's changes go, until it says nobody listens anymore
+type OnChange = Box<dyn FnMut(ChangeEvent) -> bool + Send>;
+
+/// What handles ask the event thread to do
+enum Command {
+    /// Runs on the clipboard, between two events
+    Run(Job),
+    Get {
+        selection: Selection,
+        target: CString,
+        reply: Sender<Result<Vec<u8>, ClipboxError>>,
+    },
+    Set {
+        selections: Vec<Selection>,
+        bundle: TargetBundle,
+        reply: Sender<Result<(), ClipboxError>>,
+    },
+    Targets {
+        selection: Selection,
+        reply: Sender<Result<Vec<CString>, ClipboxError>>,
+    },
+    IsServing {
+        selection: Selection,
+        reply: Sender<bool>,
+    },
+    Watch {
+        selections: Vec<Selection>,
+        on_change: OnChange,
+        reply: Sender<bool>,
+    },
+    Shutdown,
+}
+
+/// The event thread, see the [module docs](self). Dropping it stops the thread, handing what
+/// it copied to the clipboard manager on the way out.
+pub struct ClipboardThread {
+    handle: ClipboardHandle,
+    thread: Option<JoinHandle<()>>,
+}
+
+/// Sends commands to a [`ClipboardThread`]. Handles are cheap to clone and can be sent to other
+/// threads. They fail with [`ClipboxError::Io`] once the thread is gone.
+#[derive(Clone)]
+pub struct ClipboardHandle {
+    commands: Sender<Command>,
+    /// Wakes the thread up when it's waiting for events
+    waker: Arc<File>,
+}
+
+/// What the event thread keeps track of
+struct EventLoop {
+    driver: ClipboardDriver,
+}
+
+impl ClipboardThread {
+    /// Connects to the default display from a new event thread
+    pub fn spawn() -> Result<Self, ClipboxError> {
+        Self::open(None)
+    }
+
+    pub fn spawn_with_display_name(display_name: &CStr) -> Result<Self, ClipboxError> {
+        Self::open(Some(display_name.to_owned()))
+    }
+
+    fn open(display_name: Option<CString>) -> Result<Self, ClipboxError> {
+        let (commands_tx, commands) = mpsc::channel();
+        let (wake, waker) = poll::pipe().map_err(ClipboxError::Io)?;
+
+        let (ready_tx, ready_rx) = mpsc::channel();
+        let thread = thread::Builder::new()
+            .name("clipbox-events".to_string())
+            .spawn(move || {
+                let clipboard = match &display_name {
+                    Some(display_name) => X11Clipboard::init_with_display_name(display_name),
+                    None => X11Clipboard::init(),
+                };
+
+                match clipboard {
+                    Ok(clipboard) => {
+                        let _ = ready_tx.send(Ok(()));
+                        EventLoop::new(clipboard).run(commands, wake);
+                    }
+                    Err(err) => {
+                        let _ = ready_tx.send(Err(err));
+                    }
+                }
+            })
+            .map_err(ClipboxError::Io)?;
+
+        match ready_rx.recv() {
+            Ok(Ok(())) => Ok(Self {
+                handle: ClipboardHandle {
+                    commands: commands_tx,
+                    waker: Arc::new(waker),
+                },
+                thread: Some(thread),
+            }),
+            Ok(Err(err)) => Err(err),
+            Err(_) => {
+                join(thread);
+                Err(gone())
+            }
+        }
+    }
+
+    /// A new handle on the thread
+    pub fn handle(&self) -> ClipboardHandle {
+        self.handle.clone()
+    }
+
+    /// Whether the thread is still running. It only stops by itself if it can't wait on the
+    /// connection anymore.
+    pub fn is_running(&self) -> bool {
+        self.thread
+            .as_ref()
+            .is_some_and(|thread| !thread.is_finished())
+    }
+}
+
+impl std::ops::Deref for ClipboardThread {
+    type Target = ClipboardHandle;
+
+    fn deref(&self) -> &ClipboardHandle {
+        &self.handle
+    }
+}
+
+impl Drop for ClipboardThread {
+    fn drop(&mut self) {
+        let _ = self.handle.send(Command::Shutdown);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl ClipboardHandle {
+    fn send(&self, command: Command) -> Result<(), ClipboxError> {
+        self.commands.send(command).map_err(|_| gone())?;
+        // A full pipe wakes the thread up just as well
+        let _ = (&*self.waker).write(&[0]);
+        Ok(())
+    }
+
+    /// Sends a command and waits for its reply
+    fn request<T>(&self, command: impl FnOnce(Sender<T>) -> Command) -> Result<T, ClipboxError> {
+        let (reply, result) = mpsc::channel();
+        self.send(command(reply))?;
+        result.recv().map_err(|_| gone())
+    }
+
+    /// Runs `f` on the thread's clipboard, between two events, and gives what it returns.
+    ///
+    /// The thread doesn't serve its copies while `f` runs, so pasting one of them from there
+    /// only times out: use [`ClipboardHandle::get`] for that. Same for copying, which would
+    /// serve from `f` until it's over: use [`ClipboardHandle::set`].
+    pub fn with<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&X11Clipboard) -> T + Send + 'static,
+    ) -> Result<T, ClipboxError> {
+        self.request(|reply| {
+            Command::Run(Box::new(move |clipboard| {
+                let _ = reply.send(f(clipboard));
+            }))
+        })
+    }
+
+    /// Gets the selection, straight from what we copied if the thread serves it
+    pub fn get(&self, selection: Selection, target: &CStr) -> Result<Vec<u8>, ClipboxError> {
+        self.request(|reply| Command::Get {
+            selection,
+            target: target.to_owned(),
+            reply,
+        })?
+        .context(|| {
+            ErrorContext::new(Phase::Pasting)
+                .selection(selection)
+                .target(target)
+        })
+    }
+
+    /// Gets the selection as UTF-8 text, like [`X11Clipboard::get_text`] but without keeping
+    /// the thread from serving while the owner answers
+    pub fn get_text(&self, selection: Selection) -> Result<String, ClipboxError> {
+        let targets = self.targets(selection)?;
+        let has = |target: &CStr| targets.iter().any(|t| t.as_c_str() == target);
+
+        if let Some(&target) = UTF8_TEXT_TARGETS.iter().find(|&&target| has(target)) {
+            let text = self.get(selection, target)?;
+            return String::from_utf8(text).map_err(|err| ClipboxError::Format(Box::new(err)));
+        }
+
+        if has(atom_names::COMPOUND_TEXT) {
+            let text = self.get(selection, atom_names::COMPOUND_TEXT)?;
+            return Ok(convert::compound_text_to_string(&text));
+        }
+
+        if has(atom_names::STRING) {
+            let text = self.get(selection, atom_names::STRING)?;
+            return Ok(convert::latin1_to_string(&text));
+        }
+
+        Err(ClipboxError::NotAvailable)
+    }
+
+    /// Copies every target of the bundle on the selections. This returns once the thread owns
+    /// them, and it serves them until another client takes them.
+    pub fn set(&self, selections: &[Selection], bundle: TargetBundle) -> Result<(), ClipboxError> {
+        self.request(|reply| Command::Set {
+            selections: selections.to_vec(),
+            bundle,
+            reply,
+        })?
+        .context(|| ErrorContext {
+            selection: selections.first().copied(),
+            ..ErrorContext::new(Phase::Copying)
+        })
+    }
+
+    pub fn set_text(&self, selection: Selection, text: &str) -> Result<(), ClipboxError> {
+        self.set(&[selection], TargetBundle::new().with_text(text))
+    }
+
+    pub fn clear(&self, selection: Selection) -> Result<(), ClipboxError> {
+        self.with(move |clipboard| clipboard.clear(selection))?
+    }
+
+    /// The targets of the selection, straight from what we copied if the thread serves it
+    pub fn targets(&self, selection: Selection) -> Result<Vec<CString>, ClipboxError> {
+        self.request(|reply| Command::Targets { selection, reply })?
+            .context(|| ErrorContext::new(Phase::ListingTargets).selection(selection))
+    }
+
+    /// Whether the thread still serves a copy on the selection
+    pub fn is_serving(&self, selection: Selection) -> Result<bool, ClipboxError> {
+        self.request(|reply| Command::IsServing { selection, reply })
+    }
+
+    /// Watches the selections with XFixes, sending each change to the receiver until it's
+    /// dropped. Unlike [`X11Clipboard::watch`], this doesn't keep the thread from serving.
+    ///
+    /// Each call is a subscription of its own, with its own receiver: watching `CLIPBOARD`
+    /// from one place and `PRIMARY` from another goes through the same connection without
+    /// either one seeing the other's changes. Nothing bounds the receiver, see
+    /// [`ClipboardHandle::watch_bounded`] for consumers that may fall behind.
+    ///
+    /// Gives `None` if we don't have XFixes: polling would paste every selection from the
+    /// thread every so often, use [`X11Clipboard::watch_with`] for that.
+    pub fn watch(
+        &self,
+        selections: &[Selection],
+    ) -> Result<Option<Receiver<ChangeEvent>>, ClipboxError> {
+        let (changes, receiver) = mpsc::channel();
+        let watching = self.watch_boxed(
+            selections,
+            Box::new(move |change| changes.send(change).is_ok()),
+        )?;
+
+        Ok(watching.then_some(receiver))
+    }
+
+    /// Like [`ClipboardHandle::watch`], but at most `capacity` changes wait to be read: newer
+    /// ones are dropped until the receiver catches up, the thread never waits for it
+    pub fn watch_bounded(
+        &self,
+        selections: &[Selection],
+        capacity: usize,
+    ) -> Result<Option<Receiver<ChangeEvent>>, ClipboxError> {
+        let (changes, receiver) = mpsc::sync_channel(capacity);
+        let watching = self.watch_boxed(
+            selections,
+            Box::new(move |change| {
+                !matches!(changes.try_send(change), Err(TrySendError::Disconnected(_)))
+            }),
+        )?;
+
+        Ok(watching.then_some(receiver))
+    }
+
+    fn watch_boxed(
+        &self,
+        selections: &[Selection],
+        on_change: OnChange,
+    ) -> Result<bool, ClipboxError> {
+        self.request(|reply| Command::Watch {
+            selections: selections.to_vec(),
+            on_change,
+            reply,
+        })
+    }
+
+    /// What happens in the thread (requests, transfers...), see [`X11Clipboard::subscribe`]
+    pub fn subscribe(&self) -> Result<Receiver<ClipboardEvent>, ClipboxError> {
+        self.with(|clipboard| clipboard.subscribe())
+    }
+}
+
+impl EventLoop {
+    fn new(clipboard: X11Clipboard) -> Self {
+        Self {
+            driver: ClipboardDriver::new(clipboard),
+        }
+    }
+
+    fn run(mut self, commands: Receiver<Command>, mut wake: File) {
+        let connection = self.driver.as_raw_fd();
+
+        loop {
+            loop {
+                match commands.try_recv() {
+                    Ok(Command::Shutdown) | Err(TryRecvError::Disconnected) => return,
+                    Ok(command) => self.handle_command(command),
+                    Err(TryRecvError::Empty) => break,
+                }
+            }
+
+            self.driver.dispatch();
+
+            let timeout = self
+                .driver
+                .deadline()
+                .map(|deadline| deadline.saturating_duration_since(Instant::now()));
+
+            match poll::wait_readable([connection, wake.as_raw_fd()], timeout) {
+                Ok([_, true]) => {
+                    // Commands are in the channel, the bytes only woke us up. All of them, or
+                    // the leftovers would wake us up again right away.
+                    let _ = poll::drain(&mut wake);
+                }
+                Ok(_) => {}
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => {
+                    self.driver.clipboard().emit(|| ClipboardEvent::Error {
+                        message: format!("Can't wait for events anymore: {}", err),
+                    });
+                    return;
+                }
+            }
+        }
+    }
+
+    fn handle_command(&mut self, command: Command) {
+        let driver = &mut self.driver;
+
+        match command {
+            Command::Run(job) => job(driver.clipboard()),
+            Command::Get {
+                selection,
+                target,
+                reply,
+            } => {
+                driver.get(selection, &target, move |data| {
+                    let _ = reply.send(data);
+                });
+            }
+            Command::Set {
+                selections,
+                bundle,
+                reply,
+            } => {
+                let _ = reply.send(driver.set(&selections, &bundle));
+            }
+            Command::Targets { selection, reply } => {
+                driver.targets(selection, move |targets| {
+                    let _ = reply.send(targets);
+                });
+            }
+            Command::IsServing { selection, reply } => {
+                let _ = reply.send(driver.is_serving(selection));
+            }
+            Command::Watch {
+                selections,
+                on_change,
+                reply,
+            } => {
+                let _ = reply.send(driver.watch(&selections, on_change));
+            }
+            Command::Shutdown => unreachable!("the thread stops before handling it"),
+        }
+    }
+}
+
+fn gone() -> ClipboxError {
+    ClipboxError::Io(io::Error::new(
+        io::ErrorKind::BrokenPipe,
+        "the clipboard thread is gone",
+    ))
+}
+
+fn join(thread: JoinHandle<()>) {
+    if let Err(payload) = thread.join() {
+        panic::resume_unwind(payload);
+    }
+}
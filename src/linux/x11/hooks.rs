@@ -0,0 +1,260 @@
+//! Running commands when a selection changes, to upload copied images, rewrite URLs... without
+//! writing any Rust.
+//!
+//! A [`ChangeHook`] runs its command for each change of the selections it watches, with the
+//! new contents on its standard input and what we know about them in its environment:
+//!
+//! - `CLIPBOX_SELECTION`: the selection's name, like `CLIPBOARD`
+//! - `CLIPBOX_TARGET`: the target the contents are in, like `UTF8_STRING` or `image/png`
+//! - `CLIPBOX_MIME`: its MIME type, if it has one
+//! - `CLIPBOX_OWNER_WINDOW`: the window owning the selection, in hexadecimal
+//! - `CLIPBOX_OWNER_CLASS`, `CLIPBOX_OWNER_INSTANCE`, `CLIPBOX_OWNER_NAME` and
+//!   `CLIPBOX_OWNER_PID`: what the owner says about itself, when it does
+//!
+//! Commands run in the background, and hooks never wait for them. Cleared selections, our own
+//! copies and secrets (see [`X11Clipboard::is_secret`]) don't run them.
+
+use std::ffi::{CStr, CString, OsStr, OsString};
+use std::io::Write;
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::Duration;
+
+use super::{
+    is_data_target, ChangeEvent, TargetInfo, X11Clipboard, DEFAULT_SETTLE_TIME, UTF8_TEXT_TARGETS,
+};
+use crate::cancel::CancellationToken;
+use crate::error::{ClipboxError, ErrorContext, Phase, ResultExt};
+use crate::event::ClipboardEvent;
+use crate::selection::Selection;
+
+/// Runs a command for each change of some selections, see the [module docs](self).
+///
+/// Either [run](ChangeHook::run) it on a connection of its own, or hand it the changes of an
+/// existing watcher with [`ChangeHook::handle_change`].
+pub struct ChangeHook {
+    program: OsString,
+    args: Vec<OsString>,
+    selections: Vec<Selection>,
+    /// In order of preference, none meaning text or else anything
+    targets: Vec<CString>,
+    settle_time: Duration,
+    cancellation: Option<CancellationToken>,
+    /// The commands we started that may still be running
+    children: Vec<Child>,
+}
+
+impl ChangeHook {
+    /// Runs `program` for each change of `CLIPBOARD`
+    pub fn new(program: impl AsRef<OsStr>) -> Self {
+        Self {
+            program: program.as_ref().to_owned(),
+            args: Vec::new(),
+            selections: vec![Selection::Clipboard],
+            targets: Vec::new(),
+            settle_time: DEFAULT_SETTLE_TIME,
+            cancellation: None,
+            children: Vec::new(),
+        }
+    }
+
+    pub fn arg(mut self, arg: impl AsRef<OsStr>) -> Self {
+        self.args.push(arg.as_ref().to_owned());
+        self
+    }
+
+    pub fn args(mut self, args: impl IntoIterator<Item = impl AsRef<OsStr>>) -> Self {
+        self.args
+            .extend(args.into_iter().map(|arg| arg.as_ref().to_owned()));
+        self
+    }
+
+    /// The selections whose changes run the command, only `CLIPBOARD` by default
+    pub fn selections(mut self, selections: &[Selection]) -> Self {
+        self.selections = selections.to_vec();
+        self
+    }
+
+    /// Only runs for contents offered as this target, with that target on standard input.
+    /// Several of them are tried in the order they were added.
+    ///
+    /// Without any, the command gets the contents as UTF-8 text if it can, and as their first
+    /// target otherwise.
+    pub fn target(mut self, target: &CStr) -> Self {
+        self.targets.push(target.to_owned());
+        self
+    }
+
+    /// How long a selection has to stay the same before the command runs when running, see
+    /// [`Watcher::debounce`](super::Watcher::debounce). [`DEFAULT_SETTLE_TIME`] by default.
+    pub fn settle_time(mut self, settle_time: Duration) -> Self {
+        self.settle_time = settle_time;
+        self
+    }
+
+    /// Stops running once `token` is cancelled
+    pub fn cancel_on(mut self, token: CancellationToken) -> Self {
+        self.cancellation = Some(token);
+        self
+    }
+
+    pub fn watched_selections(&self) -> &[Selection] {
+        &self.selections
+    }
+
+    /// Watches the selections and runs the command for each change until cancelled. The
+    /// clipboard serves nothing in the meantime, so give it a connection of its own.
+    ///
+    /// Commands that can't be run (or changes whose contents can't be fetched) are reported
+    /// as [`ClipboardEvent::Error`]s and skipped.
+    pub fn run(&mut self, clipboard: &X11Clipboard) {
+        let mut watcher = clipboard
+            .watch(&self.selections)
+            .prefetch_targets(true)
+            .debounce(self.settle_time)
+            .skip_secrets(true);
+
+        if let Some(token) = &self.cancellation {
+            watcher = watcher.cancel_on(token.clone());
+        }
+
+        for change in watcher {
+            if let Err(err) = self.handle_change(clipboard, &change) {
+                clipboard.emit(|| ClipboardEvent::Error {
+                    message: format!("Couldn't run the hook for {}: {}", change.selection, err),
+                });
+            }
+        }
+    }
+
+    /// Runs the command for a selection that just changed, giving whether it did. Changes of
+    /// other selections, of cleared selections, of those we own, of secrets and of contents
+    /// not offered as any of the hook's targets are left alone.
+    ///
+    /// `clipboard` is the one the change was noticed on.
+    pub fn handle_change(
+        &mut self,
+        clipboard: &X11Clipboard,
+        change: &ChangeEvent,
+    ) -> Result<bool, ClipboxError> {
+        // The commands that are done don't have to be waited for anymore
+        self.children
+            .retain_mut(|child| matches!(child.try_wait(), Ok(None)));
+
+        if !self.selections.contains(&change.selection) {
+            return Ok(false);
+        }
+
+        let Some(owner) = change.owner else {
+            return Ok(false);
+        };
+        if owner == clipboard.owner_window {
+            return Ok(false);
+        }
+
+        let fetched;
+        let targets = match &change.targets {
+            Some(targets) => targets,
+            None => {
+                fetched = clipboard.get_target_names(change.selection)?.0;
+                &fetched
+            }
+        };
+
+        if clipboard.offers_secret(change.selection, targets.iter().map(CString::as_c_str)) {
+            return Ok(false);
+        }
+
+        let Some(target) = self.pick_target(targets) else {
+            return Ok(false);
+        };
+
+        let data = clipboard
+            .get_selection(change.selection, target)
+            .context(|| {
+                ErrorContext::new(Phase::Pasting)
+                    .selection(change.selection)
+                    .target(target)
+            })?;
+
+        let mut command = Command::new(&self.program);
+        command
+            .args(&self.args)
+            .stdin(Stdio::piped())
+            .env("CLIPBOX_SELECTION", change.selection.name())
+            .env("CLIPBOX_TARGET", target.to_string_lossy().as_ref())
+            .env("CLIPBOX_OWNER_WINDOW", format!("{:#x}", owner));
+
+        let mime = TargetInfo::new(0, target.to_owned()).mime;
+        let info = clipboard.selection_owner_info(change.selection);
+        let details = [
+            ("CLIPBOX_MIME", mime.map(|mime| mime.to_string())),
+            (
+                "CLIPBOX_OWNER_CLASS",
+                info.as_ref().and_then(|info| info.class.clone()),
+            ),
+            (
+                "CLIPBOX_OWNER_INSTANCE",
+                info.as_ref().and_then(|info| info.instance.clone()),
+            ),
+            (
+                "CLIPBOX_OWNER_NAME",
+                info.as_ref().and_then(|info| info.name.clone()),
+            ),
+            (
+                "CLIPBOX_OWNER_PID",
+                info.and_then(|info| info.pid).map(|pid| pid.to_string()),
+            ),
+        ];
+
+        // Not inherited from a hook that runs us, since they'd be about something else
+        for (name, value) in details {
+            match value {
+                Some(value) => command.env(name, value),
+                None => command.env_remove(name),
+            };
+        }
+
+        let mut child = command
+            .spawn()
+            .map_err(ClipboxError::Io)
+            .context(|| ErrorContext::new(Phase::RunningHook).selection(change.selection))?;
+
+        // A command that doesn't read its input mustn't block us
+        if let Some(mut stdin) = child.stdin.take() {
+            thread::Builder::new()
+                .name("clipbox-hook".to_string())
+                .spawn(move || {
+                    let _ = stdin.write_all(&data);
+                })
+                .map_err(ClipboxError::Io)?;
+        }
+
+        self.children.push(child);
+        Ok(true)
+    }
+
+    /// The target whose data the command gets, see [`ChangeHook::target`]
+    fn pick_target<'t>(&self, targets: &'t [CString]) -> Option<&'t CStr> {
+        let offered = |wanted: &CStr| {
+            targets
+                .iter()
+                .find(|target| target.as_c_str() == wanted)
+                .map(CString::as_c_str)
+        };
+
+        if !self.targets.is_empty() {
+            return self.targets.iter().find_map(|wanted| offered(wanted));
+        }
+
+        UTF8_TEXT_TARGETS
+            .iter()
+            .find_map(|&wanted| offered(wanted))
+            .or_else(|| {
+                targets
+                    .iter()
+                    .find(|target| is_data_target(target))
+                    .map(CString::as_c_str)
+            })
+    }
+}
@@ -0,0 +1,117 @@
+//! Finding the X libraries, which are loaded at runtime rather than linked.
+//!
+//! By default, they're looked up by their usual sonames (`libX11.so.6`, `libXfixes.so.3`) in
+//! the places the dynamic loader knows about. Systems that keep them elsewhere (Nix, some BSDs,
+//! bundled apps...) can point us at them with [`set_library_paths`], or with the
+//! [`LIBX11_ENV_VAR`] and [`LIBXFIXES_ENV_VAR`] environment variables.
+//!
+//! loki-linux loads libX11 by its soname itself. A libX11 loaded from a path beforehand is the
+//! one it gets, but only if that library's soname is `libX11.so.6`: one named differently
+//! (say, `libX11.so.18.0` on OpenBSD) can't stand in for it.
+
+use std::env;
+use std::ffi::{c_char, c_int, c_void, CStr, CString, OsStr};
+use std::os::unix::ffi::OsStrExt;
+use std::path::PathBuf;
+use std::ptr::NonNull;
+use std::sync::Mutex;
+
+use loki_linux::x11::LibX11;
+
+/// Paths (or names) of libX11 to try before the usual ones, separated by `:`
+pub const LIBX11_ENV_VAR: &str = "CLIPBOX_LIBX11";
+
+/// Paths (or names) of libXfixes to try before the usual ones, separated by `:`
+pub const LIBXFIXES_ENV_VAR: &str = "CLIPBOX_LIBXFIXES";
+
+const X11_NAMES: &[&CStr] = &[c"libX11.so.6", c"libX11.so"];
+const XFIXES_NAMES: &[&CStr] = &[c"libXfixes.so.3", c"libXfixes.so"];
+
+// `dlopen` and friends are in libc on every platform we support
+extern "C" {
+    fn dlopen(filename: *const c_char, flags: c_int) -> *mut c_void;
+    fn dlsym(handle: *mut c_void, symbol: *const c_char) -> *mut c_void;
+}
+
+const RTLD_NOW: c_int = 2;
+
+/// Set through [`set_library_paths`], taken from the environment otherwise
+static LIBRARY_PATHS: Mutex<Option<LibraryPaths>> = Mutex::new(None);
+
+/// Where to find the X libraries, see the [module docs](self). Each one is an absolute path
+/// or a library name for the dynamic loader, tried in order before the usual sonames.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LibraryPaths {
+    pub x11: Vec<PathBuf>,
+    pub xfixes: Vec<PathBuf>,
+}
+
+impl LibraryPaths {
+    /// From [`LIBX11_ENV_VAR`] and [`LIBXFIXES_ENV_VAR`], empty where they aren't set
+    pub fn from_env() -> Self {
+        let paths = |var| {
+            env::var_os(var)
+                .map(|paths| {
+                    env::split_paths(&paths)
+                        .filter(|path| !path.as_os_str().is_empty())
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+
+        Self {
+            x11: paths(LIBX11_ENV_VAR),
+            xfixes: paths(LIBXFIXES_ENV_VAR),
+        }
+    }
+}
+
+/// Changes where the X libraries are loaded from for the connections opened from now on,
+/// instead of the environment variables. Libraries loaded already stay loaded.
+pub fn set_library_paths(paths: LibraryPaths) {
+    *LIBRARY_PATHS.lock().unwrap_or_else(|err| err.into_inner()) = Some(paths);
+}
+
+fn library_paths() -> LibraryPaths {
+    LIBRARY_PATHS
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .clone()
+        .unwrap_or_else(LibraryPaths::from_env)
+}
+
+/// Loads libX11 through loki-linux, once the configured one is loaded for it to find
+pub(super) fn load_x11() -> Result<LibX11, String> {
+    let configured = library_paths().x11;
+    if !configured.is_empty() && open_library(&configured, &[]).is_none() {
+        return Err(format!("Couldn't load libX11 from any of {:?}", configured));
+    }
+
+    LibX11::new().map_err(|err| err.to_string())
+}
+
+/// The libX11 loki-linux uses, to look up what it doesn't bind. It's found by soname the same
+/// way, so that we never mix two of them.
+pub(super) fn open_x11() -> Option<NonNull<c_void>> {
+    open_library(&[], X11_NAMES)
+}
+
+pub(super) fn open_xfixes() -> Option<NonNull<c_void>> {
+    open_library(&library_paths().xfixes, XFIXES_NAMES)
+}
+
+/// Loads the first of these libraries that's installed. They're never unloaded.
+fn open_library(configured: &[PathBuf], names: &[&CStr]) -> Option<NonNull<c_void>> {
+    let configured = configured
+        .iter()
+        .filter_map(|path| CString::new(OsStr::as_bytes(path.as_os_str())).ok());
+
+    configured
+        .chain(names.iter().map(|&name| name.to_owned()))
+        .find_map(|name| NonNull::new(unsafe { dlopen(name.as_ptr(), RTLD_NOW) }))
+}
+
+pub(super) unsafe fn symbol(handle: NonNull<c_void>, name: &CStr) -> Option<*mut c_void> {
+    let symbol = dlsym(handle.as_ptr(), name.as_ptr());
+    (!symbol.is_null()).then_some(symbol)
+}
@@ -0,0 +1,146 @@
+//! Being the clipboard manager, for apps that want to keep the clipboard alive themselves.
+//!
+//! ICCCM lets the owner of `CLIPBOARD` ask the owner of `CLIPBOARD_MANAGER` to save the
+//! clipboard (through the `SAVE_TARGETS` target) before it exits. Becoming that owner is all
+//! a clipboard manager needs on the X11 side: what it does with the saved contents (history,
+//! persistence...) is up to it.
+
+use loki_linux::x11::{et, prop_mode, Atom, XSelectionRequestEvent};
+
+use super::{atom_names, xlib, TargetBundle, TargetOrder, X11Clipboard};
+use crate::error::ClipboxError;
+use crate::event::ClipboardEvent;
+use crate::selection::Selection;
+
+impl X11Clipboard {
+    /// Becomes the clipboard manager, saving the clipboard whenever its owner asks us to
+    /// before exiting. This blocks until another client becomes the clipboard manager.
+    ///
+    /// Each saved clipboard is handed to `on_save` before its owner is told we're done, so that
+    /// we can take over the clipboard in time, for example with
+    /// [`X11Clipboard::set_many_in_background`].
+    pub fn run_clipboard_manager(
+        &self,
+        mut on_save: impl FnMut(TargetBundle),
+    ) -> Result<(), ClipboxError> {
+        let atom_manager = self.intern(atom_names::CLIPBOARD_MANAGER);
+        let atom_save_targets = self.intern(atom_names::SAVE_TARGETS);
+
+        let time = self
+            .get_compliant_timestamp(self.timeouts.get().ownership)
+            .ok_or_else(|| self.gave_up_copying())?;
+
+        unsafe {
+            (self.x.XSetSelectionOwner)(
+                self.display.as_ptr(),
+                atom_manager,
+                self.owner_window,
+                time,
+            );
+
+            let owner = (self.x.XGetSelectionOwner)(self.display.as_ptr(), atom_manager);
+            if owner != self.owner_window {
+                // There's a clipboard manager already
+                return Err(ClipboxError::Ownership);
+            }
+        }
+
+        self.emit(|| ClipboardEvent::OwnerChanged {
+            selection: self.atom_name_lossy(atom_manager),
+        });
+
+        loop {
+            // Saving the clipboard pastes it, which puts our own requests aside until then
+            let Some(xevent) = self.next_owner_event_until(None) else {
+                continue;
+            };
+            let type_id = unsafe { xevent.type_id };
+
+            if type_id == et::SELECTION_REQUEST {
+                let mut request = unsafe { xevent.xselectionrequest };
+                if request.owner != self.owner_window || request.selection != atom_manager {
+                    continue;
+                }
+
+                // Obsolete clients leave the property out, see `serve_owned`
+                request.property = match request.property {
+                    0 => request.target,
+                    _ => request.property,
+                };
+
+                if request.target == self.atoms.targets {
+                    let target_atoms = [self.atoms.targets, atom_save_targets];
+
+                    unsafe {
+                        (self.x.XChangeProperty)(
+                            self.display.as_ptr(),
+                            request.requestor,
+                            request.property,
+                            self.atoms.atom,
+                            32,
+                            prop_mode::REPLACE,
+                            target_atoms.as_ptr().cast(),
+                            target_atoms.len() as i32,
+                        );
+                    }
+                } else if request.target == atom_save_targets {
+                    match self.save_clipboard(&request) {
+                        Ok(bundle) => on_save(bundle),
+                        Err(err) => {
+                            let reason = format!("couldn't save the clipboard: {}", err);
+                            self.refuse_conversion(&request, &reason);
+                            continue;
+                        }
+                    }
+                } else {
+                    self.refuse_conversion(&request, "unavailable target");
+                    continue;
+                }
+
+                self.send_selection_notify(&request, request.property);
+            } else if type_id == et::SELECTION_CLEAR {
+                let xevent = unsafe { xlib::selection_clear(&xevent) };
+                if xevent.selection == atom_manager {
+                    // Someone else manages the clipboard now (._. )
+                    self.emit(|| ClipboardEvent::OwnershipLost {
+                        selection: self.atom_name_lossy(atom_manager),
+                    });
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    /// Fetches the clipboard for a `SAVE_TARGETS` request, keeping the targets its owner
+    /// listed in the request's property, or all of them if it didn't list any
+    fn save_clipboard(
+        &self,
+        request: &XSelectionRequestEvent,
+    ) -> Result<TargetBundle, ClipboxError> {
+        let snapshot = self.snapshot(Selection::Clipboard)?;
+
+        let listed = self
+            .get_window_property(request.requestor, request.property)
+            .and_then(|prop| Ok(prop.into_vec32()?))
+            .unwrap_or_default();
+
+        if listed.is_empty() {
+            return Ok(snapshot);
+        }
+
+        let listed = listed
+            .into_iter()
+            .filter_map(|atom| self.atom_name(Atom::from(atom)))
+            .collect::<Vec<_>>();
+
+        let bundle = snapshot
+            .iter()
+            .filter(|&(target, _)| listed.iter().any(|name| name.as_c_str() == target))
+            .fold(
+                TargetBundle::new().order(TargetOrder::AsAdded),
+                |bundle, (target, data)| bundle.with(target, data),
+            );
+
+        Ok(bundle)
+    }
+}
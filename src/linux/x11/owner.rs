@@ -0,0 +1,99 @@
+//! Finding out which app owns a selection, for "copied from Firefox" and per-app policies.
+//!
+//! Toolkits usually own selections from a hidden window that says nothing about itself, so we
+//! also look at its client leader, the window that speaks for the whole app.
+
+use std::ffi::CStr;
+
+use loki_linux::x11::XWindow;
+
+use super::X11Clipboard;
+use crate::selection::Selection;
+
+/// What we know about the owner of a selection. Apps don't have to tell, so anything but the
+/// window can be missing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OwnerInfo {
+    /// The window that owns the selection
+    pub window: XWindow,
+    /// The instance part of `WM_CLASS`, like `Navigator`
+    pub instance: Option<String>,
+    /// The class part of `WM_CLASS`, like `firefox`
+    pub class: Option<String>,
+    /// `_NET_WM_NAME`, or `WM_NAME` if there isn't one
+    pub name: Option<String>,
+    /// `_NET_WM_PID`, only meaningful if the app runs on this machine
+    pub pid: Option<u32>,
+    /// `WM_CLIENT_MACHINE`, the machine the app runs on. That's not always the one the X
+    /// server runs on: apps can be forwarded over SSH.
+    pub machine: Option<String>,
+}
+
+impl OwnerInfo {
+    /// Fills in what we don't know from another window of the same app
+    fn or(self, other: Self) -> Self {
+        Self {
+            window: self.window,
+            instance: self.instance.or(other.instance),
+            class: self.class.or(other.class),
+            name: self.name.or(other.name),
+            pid: self.pid.or(other.pid),
+            machine: self.machine.or(other.machine),
+        }
+    }
+}
+
+impl X11Clipboard {
+    /// Describes the app that owns the selection, or gives `None` if nobody owns it
+    pub fn selection_owner_info(&self, selection: Selection) -> Option<OwnerInfo> {
+        let window = self.selection_owner(selection)?;
+        let info = self.window_info(window);
+
+        let leader = self
+            .window_property_u32(window, c"WM_CLIENT_LEADER")
+            .map(XWindow::from)
+            .filter(|&leader| leader != window && leader != 0);
+
+        Some(match leader {
+            Some(leader) => info.or(self.window_info(leader)),
+            None => info,
+        })
+    }
+
+    fn window_info(&self, window: XWindow) -> OwnerInfo {
+        // WM_CLASS is the instance and the class, each one ending with a NUL
+        let wm_class = self.window_property_string(window, c"WM_CLASS");
+        let mut wm_class = wm_class.as_deref().unwrap_or_default().split('\0');
+        let mut next_part = || {
+            wm_class
+                .next()
+                .filter(|part| !part.is_empty())
+                .map(str::to_string)
+        };
+
+        OwnerInfo {
+            window,
+            instance: next_part(),
+            class: next_part(),
+            name: self
+                .window_property_string(window, c"_NET_WM_NAME")
+                .or_else(|| self.window_property_string(window, c"WM_NAME"))
+                .filter(|name| !name.is_empty()),
+            pid: self.window_property_u32(window, c"_NET_WM_PID"),
+            machine: self
+                .window_property_string(window, c"WM_CLIENT_MACHINE")
+                .filter(|machine| !machine.is_empty()),
+        }
+    }
+
+    fn window_property_string(&self, window: XWindow, name: &CStr) -> Option<String> {
+        let prop = self.get_window_property(window, self.intern(name)).ok()?;
+        let bytes = prop.into_vec::<u8>().ok()?;
+        Some(String::from_utf8_lossy(&bytes).into_owned())
+    }
+
+    fn window_property_u32(&self, window: XWindow, name: &CStr) -> Option<u32> {
+        let prop = self.get_window_property(window, self.intern(name)).ok()?;
+        prop.into_vec32().ok()?.first().copied()
+    }
+}
@@ -0,0 +1,99 @@
+//! Waiting for the X connection (and whatever else) to become readable, without spinning.
+
+use std::ffi::{c_int, c_short};
+use std::fs::File;
+use std::io::{self, Read};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::time::Duration;
+
+#[cfg(target_os = "linux")]
+type Nfds = std::ffi::c_ulong;
+#[cfg(not(target_os = "linux"))]
+type Nfds = std::ffi::c_uint;
+
+#[repr(C)]
+struct PollFd {
+    fd: c_int,
+    events: c_short,
+    revents: c_short,
+}
+
+const POLLIN: c_short = 0x1;
+
+const F_GETFL: c_int = 3;
+const F_SETFL: c_int = 4;
+#[cfg(target_os = "linux")]
+const O_NONBLOCK: c_int = 0o4000;
+#[cfg(not(target_os = "linux"))]
+const O_NONBLOCK: c_int = 0x4;
+
+// `poll`, `pipe` and `fcntl` are in libc on every platform we support
+extern "C" {
+    fn poll(fds: *mut PollFd, nfds: Nfds, timeout: c_int) -> c_int;
+    #[link_name = "pipe"]
+    fn libc_pipe(fds: *mut c_int) -> c_int;
+    fn fcntl(fd: c_int, cmd: c_int, ...) -> c_int;
+}
+
+/// Sleeps until one of the fds is readable or the timeout is over (if there is one), giving
+/// which ones are readable
+pub(super) fn wait_readable<const N: usize>(
+    fds: [RawFd; N],
+    timeout: Option<Duration>,
+) -> io::Result<[bool; N]> {
+    // Rounding up, or we'd wake up right before the deadline and spin until it's there
+    let timeout_ms = match timeout {
+        Some(timeout) => timeout.as_micros().div_ceil(1000).min(c_int::MAX as u128) as c_int,
+        None => -1,
+    };
+
+    let mut pollfds = fds.map(|fd| PollFd {
+        fd,
+        events: POLLIN,
+        revents: 0,
+    });
+
+    if unsafe { poll(pollfds.as_mut_ptr(), N as Nfds, timeout_ms) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(pollfds.map(|pollfd| pollfd.revents != 0))
+}
+
+/// A non-blocking pipe, as its read end and write end: reading an empty one or writing to a
+/// full one fails with [`io::ErrorKind::WouldBlock`]
+pub(super) fn pipe() -> io::Result<(File, File)> {
+    let mut fds = [0; 2];
+    if unsafe { libc_pipe(fds.as_mut_ptr()) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    // We're the only ones with these fds
+    let [read, write] = fds.map(|fd| File::from(unsafe { OwnedFd::from_raw_fd(fd) }));
+    set_nonblocking(read.as_raw_fd())?;
+    set_nonblocking(write.as_raw_fd())?;
+    Ok((read, write))
+}
+
+/// Reads everything there is in a non-blocking pipe (or until it's closed)
+pub(super) fn drain(pipe: &mut File) -> io::Result<()> {
+    let mut buf = [0; 64];
+    loop {
+        match pipe.read(&mut buf) {
+            Ok(0) => return Ok(()),
+            Ok(_) => {}
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+fn set_nonblocking(fd: RawFd) -> io::Result<()> {
+    let flags = unsafe { fcntl(fd, F_GETFL) };
+    if flags == -1 || unsafe { fcntl(fd, F_SETFL, flags | O_NONBLOCK) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,148 @@
+//! Peeking at what a selection holds, for watchers and pickers that show each change without
+//! pasting all of it.
+//!
+//! Only the beginning of text is read, straight from the property the owner converted it into,
+//! and INCR transfers are given up once we have enough. Anything else is only measured.
+
+use std::ffi::CStr;
+
+use super::{
+    atom_names, is_data_target, GetSelectionError, TargetNames, X11Clipboard, UTF8_TEXT_TARGETS,
+};
+use crate::convert::{self, Mime};
+use crate::error::ClipboxError;
+use crate::selection::Selection;
+
+/// Previews never read more than this, whatever they're asked for
+pub const MAX_PREVIEW_SIZE: usize = 64 * 1024;
+
+/// A glimpse of a selection, see [`X11Clipboard::get_preview`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Preview {
+    /// The beginning of its text, and whether there's more of it
+    Text { text: String, truncated: bool },
+    /// Something that isn't text, as its first target
+    Binary {
+        target: String,
+        mime: Option<Mime>,
+        /// In bytes. For big ones sent in chunks, that's the lower bound the owner gives.
+        size: usize,
+    },
+}
+
+impl X11Clipboard {
+    /// Gets the first `max_bytes` (at most [`MAX_PREVIEW_SIZE`]) of the selection's text, or the
+    /// MIME type and size of its first target if it has no text. Owners that don't answer are
+    /// given up on like for any other paste, see [`Timeouts`](super::Timeouts).
+    ///
+    /// Gives [`ClipboxError::NotAvailable`] if the owner offers no data at all.
+    pub fn get_preview(
+        &self,
+        selection: Selection,
+        max_bytes: usize,
+    ) -> Result<Preview, ClipboxError> {
+        let targets = self.get_target_names(selection)?;
+        self.preview_with_targets(selection, &targets, max_bytes)
+    }
+
+    pub(super) fn preview_with_targets(
+        &self,
+        selection: Selection,
+        targets: &TargetNames,
+        max_bytes: usize,
+    ) -> Result<Preview, ClipboxError> {
+        let max_bytes = max_bytes.min(MAX_PREVIEW_SIZE);
+
+        if let Some(&target) = UTF8_TEXT_TARGETS.iter().find(|t| targets.contains(t)) {
+            let (mut text, truncated) = self.get_prefix(selection, target, max_bytes)?;
+
+            // Don't leave half a character at the end
+            if let Err(err) = std::str::from_utf8(&text) {
+                if truncated && err.error_len().is_none() {
+                    text.truncate(err.valid_up_to());
+                }
+            }
+
+            return Ok(Preview::Text {
+                text: String::from_utf8_lossy(&text).into_owned(),
+                truncated,
+            });
+        }
+
+        if targets.contains(atom_names::STRING) {
+            let (text, truncated) = self.get_prefix(selection, atom_names::STRING, max_bytes)?;
+            return Ok(Preview::Text {
+                text: convert::latin1_to_string(&text),
+                truncated,
+            });
+        }
+
+        let target = targets
+            .0
+            .iter()
+            .find(|target| is_data_target(target))
+            .ok_or(ClipboxError::NotAvailable)?;
+
+        let size = self.get_size(selection, target)?;
+        let target = target.to_string_lossy().into_owned();
+        Ok(Preview::Binary {
+            mime: Mime::parse(&target),
+            target,
+            size,
+        })
+    }
+
+    /// The first `max_bytes` of the selection, and whether there's more
+    fn get_prefix(
+        &self,
+        selection: Selection,
+        target: &CStr,
+        max_bytes: usize,
+    ) -> Result<(Vec<u8>, bool), GetSelectionError> {
+        let (property, clipbox_prop) =
+            self.request_selection_prefix(selection, target, max_bytes)?;
+
+        if clipbox_prop.ty != self.atoms.incr {
+            // Read by 4 bytes, so there can be a few too many
+            let remaining = clipbox_prop.bytes_remaining;
+            let mut data = clipbox_prop.into_vec::<u8>()?;
+            let truncated = remaining > 0 || data.len() > max_bytes;
+            data.truncate(max_bytes);
+            return Ok((data, truncated));
+        }
+
+        // Dropping the property halfway leaves the owner waiting until it gives up on us
+        let mut data = Vec::new();
+        while data.len() < max_bytes {
+            self.wait_for_incr_chunk(&property)?;
+
+            let chunk = property.read_prefix(max_bytes - data.len())?;
+            if chunk.nitems == 0 {
+                return Ok((data, false));
+            }
+
+            let more_in_chunk = chunk.bytes_remaining > 0;
+            chunk.write_into_vec(&mut data)?;
+            if more_in_chunk {
+                break;
+            }
+        }
+
+        data.truncate(max_bytes);
+        Ok((data, true))
+    }
+
+    /// How big the selection is, without reading any of it
+    fn get_size(&self, selection: Selection, target: &CStr) -> Result<usize, GetSelectionError> {
+        // Enough for the lower bound of an INCR transfer, which is all there is to its property
+        let (_property, clipbox_prop) = self.request_selection_prefix(selection, target, 4)?;
+
+        if clipbox_prop.ty == self.atoms.incr {
+            let lower_bound = clipbox_prop.into_vec32()?.first().copied();
+            return Ok(lower_bound.unwrap_or_default() as usize);
+        }
+
+        let item_size = clipbox_prop.format.max(8) as usize / 8;
+        Ok(clipbox_prop.nitems as usize * item_size + clipbox_prop.bytes_remaining as usize)
+    }
+}
@@ -0,0 +1,200 @@
+//! The X11 selection plumbing clipbox is built on, without its policy layer.
+//!
+//! [`X11Clipboard`] decides which targets to offer, when to switch to INCR transfers, and when
+//! an operation is over. Niche protocols built on selections (legacy IPC schemes, custom
+//! clipboard managers...) usually need to make those decisions themselves, so this module only
+//! exposes the building blocks: atoms, properties, selection conversions and replies.
+//!
+//! Everything here goes through the clipboard's own connection and hidden window.
+
+use std::ffi::{c_int, c_long, c_short, CStr, CString};
+
+use loki_linux::x11::prop_mode;
+pub use loki_linux::x11::{Atom, XEvent, XSelectionRequestEvent, XWindow};
+
+use super::{xlib, Atoms, X11Clipboard};
+use crate::error::ClipboxError;
+
+/// The items of a property, depending on its format
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PropertyData {
+    Format8(Vec<u8>),
+    Format16(Vec<u16>),
+    Format32(Vec<u32>),
+}
+
+/// An owned copy of a window property
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Property {
+    /// The type of the property (an atom like `ATOM`, `UTF8_STRING` or `INCR`)
+    pub ty: Atom,
+    pub data: PropertyData,
+}
+
+/// How to write a property when it already exists
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PropertyMode {
+    Replace,
+    Prepend,
+    Append,
+}
+
+impl PropertyMode {
+    fn to_x11(self) -> c_int {
+        match self {
+            Self::Replace => prop_mode::REPLACE,
+            Self::Prepend => xlib::PROP_MODE_PREPEND,
+            Self::Append => prop_mode::APPEND,
+        }
+    }
+}
+
+/// Raw access to the X11 connection of an [`X11Clipboard`].
+///
+/// Get one with [`X11Clipboard::raw`].
+pub struct Connection<'a> {
+    clipboard: &'a X11Clipboard,
+}
+
+impl X11Clipboard {
+    /// Gives raw access to the selection plumbing of this clipboard, see the [`raw`](self) module.
+    pub fn raw(&self) -> Connection<'_> {
+        Connection { clipboard: self }
+    }
+}
+
+impl<'a> Connection<'a> {
+    /// The atoms clipbox interns when connecting
+    pub fn atoms(&self) -> &'a Atoms {
+        &self.clipboard.atoms
+    }
+
+    /// Our hidden window for pasting, which receives the owners' replies
+    pub fn window(&self) -> XWindow {
+        self.clipboard.window
+    }
+
+    /// Our hidden window for copying, which owns our selections and receives the requests
+    pub fn owner_window(&self) -> XWindow {
+        self.clipboard.owner_window
+    }
+
+    /// Gets the atom for a name, creating it if needed. This goes through the atom cache.
+    pub fn intern_atom(&self, name: &CStr) -> Atom {
+        self.clipboard.intern(name)
+    }
+
+    /// Gets the name of an atom, or `None` if the atom doesn't exist
+    pub fn atom_name(&self, atom: Atom) -> Option<CString> {
+        self.clipboard.atom_name(atom)
+    }
+
+    /// Reads a property from any window
+    pub fn read_property(&self, window: XWindow, property: Atom) -> Result<Property, ClipboxError> {
+        let prop = self.clipboard.get_window_property(window, property)?;
+        let ty = prop.ty;
+
+        let data = match prop.format {
+            16 => PropertyData::Format16(prop.into_vec()?),
+            32 => PropertyData::Format32(prop.into_vec32()?),
+            _ => PropertyData::Format8(prop.into_vec()?),
+        };
+
+        Ok(Property { ty, data })
+    }
+
+    /// Writes a property on any window
+    pub fn write_property(
+        &self,
+        window: XWindow,
+        property: Atom,
+        ty: Atom,
+        data: &PropertyData,
+        mode: PropertyMode,
+    ) {
+        let x = &self.clipboard.x;
+        let display = self.clipboard.display.as_ptr();
+
+        unsafe {
+            match data {
+                PropertyData::Format8(items) => (x.XChangeProperty)(
+                    display,
+                    window,
+                    property,
+                    ty,
+                    8,
+                    mode.to_x11(),
+                    items.as_ptr().cast(),
+                    items.len() as c_int,
+                ),
+                PropertyData::Format16(items) => {
+                    // Xlib wants shorts for 16-bit properties
+                    let items: Vec<c_short> = items.iter().map(|&item| item as c_short).collect();
+                    (x.XChangeProperty)(
+                        display,
+                        window,
+                        property,
+                        ty,
+                        16,
+                        mode.to_x11(),
+                        items.as_ptr().cast(),
+                        items.len() as c_int,
+                    )
+                }
+                PropertyData::Format32(items) => {
+                    // ...and longs for 32-bit properties, even on 64-bit platforms
+                    let items: Vec<c_long> = items.iter().map(|&item| item as c_long).collect();
+                    (x.XChangeProperty)(
+                        display,
+                        window,
+                        property,
+                        ty,
+                        32,
+                        mode.to_x11(),
+                        items.as_ptr().cast(),
+                        items.len() as c_int,
+                    )
+                }
+            };
+        }
+    }
+
+    pub fn delete_property(&self, window: XWindow, property: Atom) {
+        unsafe {
+            (self.clipboard.x.XDeleteProperty)(self.clipboard.display.as_ptr(), window, property);
+        }
+    }
+
+    /// Asks the owner of `selection` to convert it to `target` and store it in `property`
+    /// on our window. The owner answers with a `SelectionNotify` event.
+    pub fn convert_selection(&self, selection: Atom, target: Atom, property: Atom) {
+        unsafe {
+            // Fall back to CurrentTime rather than not converting at all
+            let timeout = self.clipboard.timeouts().conversion;
+            let time = self.clipboard.get_compliant_timestamp(timeout).unwrap_or(0);
+
+            (self.clipboard.x.XConvertSelection)(
+                self.clipboard.display.as_ptr(),
+                selection,
+                target,
+                property,
+                self.clipboard.window,
+                time,
+            );
+        }
+    }
+
+    /// Replies to a `SelectionRequest`, telling the requestor that the data is in `property`.
+    /// Pass `None` to refuse the conversion.
+    pub fn send_selection_notify(&self, request: &XSelectionRequestEvent, property: Option<Atom>) {
+        self.clipboard
+            .send_selection_notify(request, property.unwrap_or(0));
+    }
+
+    /// Blocks until the next event arrives on the connection. Events clipbox put aside while
+    /// pasting come first.
+    pub fn next_event(&self) -> XEvent {
+        let deferred = self.clipboard.deferred_events.borrow_mut().pop_front();
+        deferred.unwrap_or_else(|| self.clipboard.next_event())
+    }
+}
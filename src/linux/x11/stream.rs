@@ -0,0 +1,94 @@
+//! Clipboard changes as a [`Stream`], for async apps.
+//!
+//! The watching happens on a thread of its own (see [`X11Clipboard::watch_with`]), which wakes
+//! up the stream's task whenever there's a change to hand out.
+
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+use futures_core::Stream;
+
+use super::{ChangeEvent, WatchGuard, X11Clipboard};
+use crate::error::ClipboxError;
+use crate::selection::Selection;
+
+#[derive(Default)]
+struct Shared {
+    changes: VecDeque<ChangeEvent>,
+    waker: Option<Waker>,
+    /// Whether the watcher thread is gone, which ends the stream
+    closed: bool,
+}
+
+/// Clipboard changes as they happen, see [`X11Clipboard::change_stream`].
+///
+/// Watching stops when the stream is dropped.
+pub struct ChangeStream {
+    shared: Arc<Mutex<Shared>>,
+    _guard: WatchGuard,
+}
+
+/// Lives in the watcher thread's callback, so that the stream ends once the thread does
+struct Notifier {
+    shared: Arc<Mutex<Shared>>,
+}
+
+impl Notifier {
+    fn push(&self, change: ChangeEvent) {
+        let mut shared = self.shared.lock().unwrap();
+        shared.changes.push_back(change);
+        if let Some(waker) = shared.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl Drop for Notifier {
+    fn drop(&mut self) {
+        let mut shared = self.shared.lock().unwrap();
+        shared.closed = true;
+        if let Some(waker) = shared.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl X11Clipboard {
+    /// Streams the changes of the selections: `while let Some(change) = stream.next().await`.
+    ///
+    /// The stream only ends if the watcher thread fails.
+    pub fn change_stream(&self, selections: &[Selection]) -> Result<ChangeStream, ClipboxError> {
+        let shared = Arc::new(Mutex::new(Shared::default()));
+
+        let notifier = Notifier {
+            shared: shared.clone(),
+        };
+        let guard = self.watch_with(selections, move |change| notifier.push(change))?;
+
+        Ok(ChangeStream {
+            shared,
+            _guard: guard,
+        })
+    }
+}
+
+impl Stream for ChangeStream {
+    type Item = ChangeEvent;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<ChangeEvent>> {
+        let mut shared = self.shared.lock().unwrap();
+
+        if let Some(change) = shared.changes.pop_front() {
+            return Poll::Ready(Some(change));
+        }
+
+        if shared.closed {
+            return Poll::Ready(None);
+        }
+
+        shared.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
@@ -0,0 +1,237 @@
+//! Keeping `PRIMARY` and `CLIPBOARD` in sync, like autocutsel or parcellite do: selected text
+//! can be pasted with Ctrl+V, and/or what's copied can be pasted with a middle click.
+//!
+//! Whenever a synced selection gets a new owner, a [`SelectionSync`] takes a snapshot of it and
+//! copies that to the other selection from a background thread. It knows the windows of the
+//! threads it started, so its own copies don't bounce back when syncing both ways.
+//!
+//! Apps usually own `PRIMARY` as soon as something is selected, and only change what it holds
+//! while the selection grows. The sync waits for the selection to settle before taking its
+//! snapshot, but a selection that keeps growing past that isn't synced again.
+
+use std::ffi::{CStr, CString};
+use std::time::Duration;
+
+use super::{BackgroundCopy, ChangeEvent, X11Clipboard};
+use crate::cancel::CancellationToken;
+use crate::error::{ClipboxError, ErrorContext, Phase, ResultExt};
+use crate::event::ClipboardEvent;
+use crate::linux::session::Session;
+use crate::selection::Selection;
+
+/// How long a selection has to stay the same before it's synced, by default
+pub const DEFAULT_SETTLE_TIME: Duration = Duration::from_millis(300);
+
+/// Which selection is copied into which
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SyncDirection {
+    /// Selecting text copies it, like autocutsel
+    #[default]
+    PrimaryToClipboard,
+    /// Copying also makes it pastable with a middle click
+    ClipboardToPrimary,
+    Both,
+}
+
+/// Mirrors `PRIMARY` and `CLIPBOARD` into one another, see the [module docs](self).
+///
+/// Either [run](SelectionSync::run) it on a connection of its own, or hand it the changes of
+/// an existing watcher with [`SelectionSync::sync_change`].
+pub struct SelectionSync {
+    direction: SyncDirection,
+    /// Lowercase
+    ignored_apps: Vec<String>,
+    ignored_targets: Vec<CString>,
+    settle_time: Duration,
+    cancellation: Option<CancellationToken>,
+    /// The copies we made, which may still own a selection
+    copies: Vec<BackgroundCopy>,
+    /// Nothing is synced while it's inactive, see [`SelectionSync::pause_when_inactive`]
+    session: Option<Session>,
+}
+
+impl SelectionSync {
+    pub fn new(direction: SyncDirection) -> Self {
+        Self {
+            direction,
+            ignored_apps: Vec::new(),
+            ignored_targets: Vec::new(),
+            settle_time: DEFAULT_SETTLE_TIME,
+            cancellation: None,
+            copies: Vec::new(),
+            session: Session::current(),
+        }
+    }
+
+    pub fn direction(&self) -> SyncDirection {
+        self.direction
+    }
+
+    /// Doesn't sync what this app owns, by the class or instance part of its `WM_CLASS` (like
+    /// `firefox`), case-insensitively. Terminals that keep both selections themselves are
+    /// good candidates.
+    pub fn ignore_app(mut self, app: &str) -> Self {
+        self.ignored_apps.push(app.to_lowercase());
+        self
+    }
+
+    /// Doesn't sync selections offering this target, like `image/png` to only sync text.
+    ///
+    /// Selections whose owner says they're a secret (see [`X11Clipboard::is_secret`]) are
+    /// never synced.
+    pub fn ignore_target(mut self, target: &CStr) -> Self {
+        self.ignored_targets.push(target.to_owned());
+        self
+    }
+
+    /// How long a selection has to stay the same before it's synced when running, see
+    /// [`Watcher::debounce`](super::Watcher::debounce). [`DEFAULT_SETTLE_TIME`] by default.
+    pub fn settle_time(mut self, settle_time: Duration) -> Self {
+        self.settle_time = settle_time;
+        self
+    }
+
+    /// Whether to stop syncing while our login session isn't the active one (another user
+    /// switched to theirs), which it does by default. See [`Session::is_active`].
+    pub fn pause_when_inactive(mut self, pause: bool) -> Self {
+        self.session = pause.then(Session::current).flatten();
+        self
+    }
+
+    /// Stops running once `token` is cancelled
+    pub fn cancel_on(mut self, token: CancellationToken) -> Self {
+        self.cancellation = Some(token);
+        self
+    }
+
+    /// The selections whose changes get synced
+    pub fn watched_selections(&self) -> &'static [Selection] {
+        match self.direction {
+            SyncDirection::PrimaryToClipboard => &[Selection::Primary],
+            SyncDirection::ClipboardToPrimary => &[Selection::Clipboard],
+            SyncDirection::Both => &[Selection::Primary, Selection::Clipboard],
+        }
+    }
+
+    /// Watches the selections and syncs them until cancelled. The clipboard serves nothing in
+    /// the meantime, so give it a connection of its own.
+    ///
+    /// Changes that can't be synced (their owner went away, another app copied first...) are
+    /// reported as [`ClipboardEvent::Error`]s and skipped.
+    pub fn run(&mut self, clipboard: &X11Clipboard) {
+        let mut watcher = clipboard
+            .watch(self.watched_selections())
+            .prefetch_targets(true)
+            .debounce(self.settle_time);
+
+        if let Some(token) = &self.cancellation {
+            watcher = watcher.cancel_on(token.clone());
+        }
+
+        for change in watcher {
+            if let Err(err) = self.sync_change(clipboard, &change) {
+                clipboard.emit(|| ClipboardEvent::Error {
+                    message: format!("Couldn't sync {}: {}", change.selection, err),
+                });
+            }
+        }
+    }
+
+    /// Syncs a selection that just changed, giving whether it was. Changes of a selection that
+    /// isn't synced that way, of cleared selections, of those we own, of ignored ones and those
+    /// made while our session is inactive are left alone.
+    ///
+    /// `clipboard` is the one the change was noticed on.
+    pub fn sync_change(
+        &mut self,
+        clipboard: &X11Clipboard,
+        change: &ChangeEvent,
+    ) -> Result<bool, ClipboxError> {
+        let Some(mirror) = self.mirror(change.selection) else {
+            return Ok(false);
+        };
+
+        // The threads that lost their selection are done
+        self.copies.retain(BackgroundCopy::is_serving);
+
+        if self
+            .session
+            .as_ref()
+            .is_some_and(|session| !session.is_active())
+        {
+            return Ok(false);
+        }
+
+        let Some(owner) = change.owner else {
+            return Ok(false);
+        };
+        if owner == clipboard.owner_window || self.copies.iter().any(|copy| copy.window() == owner)
+        {
+            return Ok(false);
+        }
+
+        if self.ignores_owner(clipboard, change.selection) {
+            return Ok(false);
+        }
+
+        let fetched;
+        let targets = match &change.targets {
+            Some(targets) => targets,
+            None => {
+                fetched = clipboard.get_target_names(change.selection)?.0;
+                &fetched
+            }
+        };
+
+        let ignored = targets
+            .iter()
+            .any(|target| self.ignored_targets.contains(target));
+        if ignored
+            || clipboard.offers_secret(change.selection, targets.iter().map(CString::as_c_str))
+        {
+            return Ok(false);
+        }
+
+        let bundle = clipboard
+            .snapshot(change.selection)
+            .context(|| ErrorContext::new(Phase::Pasting).selection(change.selection))?;
+        if bundle.is_empty() {
+            return Ok(false);
+        }
+
+        // Our previous copy on the mirror loses it to the new one, and winds down by itself
+        let copy = clipboard
+            .copy_in_background(&[mirror], bundle, None)
+            .context(|| ErrorContext::new(Phase::Copying).selection(mirror))?;
+        self.copies.push(copy);
+        Ok(true)
+    }
+
+    /// Where changes of the selection are copied to
+    fn mirror(&self, selection: Selection) -> Option<Selection> {
+        match (self.direction, selection) {
+            (SyncDirection::PrimaryToClipboard | SyncDirection::Both, Selection::Primary) => {
+                Some(Selection::Clipboard)
+            }
+            (SyncDirection::ClipboardToPrimary | SyncDirection::Both, Selection::Clipboard) => {
+                Some(Selection::Primary)
+            }
+            _ => None,
+        }
+    }
+
+    fn ignores_owner(&self, clipboard: &X11Clipboard, selection: Selection) -> bool {
+        if self.ignored_apps.is_empty() {
+            return false;
+        }
+
+        clipboard
+            .selection_owner_info(selection)
+            .is_some_and(|info| {
+                [info.class, info.instance]
+                    .into_iter()
+                    .flatten()
+                    .any(|name| self.ignored_apps.contains(&name.to_lowercase()))
+            })
+    }
+}
@@ -0,0 +1,678 @@
+//! Watching selections for changes, which is what clipboard managers are made of.
+//!
+//! The XFixes extension tells us right away when a selection changes owner. Without it, we
+//! poll: every so often, we look at each selection's owner, targets and contents, and compare
+//! them to what they were last time. Both go through the same [`Watcher`].
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::VecDeque;
+use std::ffi::{c_ulong, CString};
+use std::hash::{Hash, Hasher};
+use std::panic;
+use std::sync::mpsc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use loki_linux::x11::{Atom, XWindow};
+
+use super::xfixes::{selection_mask, XFixes};
+use super::{is_data_target, Preview, TargetNames, X11Clipboard};
+use crate::cancel::CancellationToken;
+use crate::error::ClipboxError;
+use crate::selection::Selection;
+
+/// How often [`X11Clipboard::watch`] polls when it can't use XFixes
+pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// A selection got a new owner, or lost its owner (the app quit, or someone cleared it)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChangeEvent {
+    pub selection: Selection,
+    /// The window owning the selection now, if anyone has it
+    pub owner: Option<XWindow>,
+    /// When the owner acquired the selection, in X server time (milliseconds). Only XFixes
+    /// tells us that.
+    pub acquired_at: Option<c_ulong>,
+    /// What the new owner can convert the selection to, if the watcher fetched it (see
+    /// [`Watcher::prefetch_targets`]). Empty if nobody owns it, or if it's us: we already know
+    /// what we copied.
+    pub targets: Option<Vec<CString>>,
+    /// A glimpse of the new contents, if the watcher was asked for one (see
+    /// [`Watcher::preview`]) and the owner gave it. Never there for our own copies.
+    pub preview: Option<Preview>,
+}
+
+/// Stops watching when dropped, see [`X11Clipboard::watch_with`]
+pub struct WatchGuard {
+    stop: CancellationToken,
+    thread: Option<JoinHandle<Result<(), ClipboxError>>>,
+}
+
+/// How a [`Watcher`] finds out about changes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchStrategy {
+    /// The X server tells us, see [`X11Clipboard::watch_xfixes`]
+    XFixes,
+    /// We look every so often, see [`X11Clipboard::watch_polling`]
+    Polling(Duration),
+}
+
+/// What a selection looked like when we last polled it
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Fingerprint {
+    owner: Option<XWindow>,
+    targets: Vec<Atom>,
+    /// Of the data of the first actual target
+    content_hash: Option<u64>,
+}
+
+/// Tells when watched selections change, see [`X11Clipboard::watch`]. It's also an endless
+/// iterator over the changes.
+///
+/// The watcher reads events from the clipboard's connection: while waiting for changes, that
+/// connection doesn't serve anything. Copy from a background thread (or another connection)
+/// in the meantime.
+///
+/// Its changes come in one stream for all its selections. To watch them independently from
+/// one connection, each with its own receiver, go through
+/// [`ClipboardHandle::watch_bounded`](super::ClipboardHandle::watch_bounded).
+pub struct Watcher<'a> {
+    clipboard: &'a X11Clipboard,
+    selections: Vec<(Selection, Atom)>,
+    strategy: WatchStrategy,
+    /// When polling, what each selection looked like last time
+    fingerprints: Vec<Fingerprint>,
+    next_poll: Instant,
+    /// Changes found by a poll that weren't handed out yet
+    pending: VecDeque<ChangeEvent>,
+    /// See [`Watcher::cancel_on`]
+    cancellation: Option<CancellationToken>,
+    /// See [`Watcher::prefetch_targets`]
+    prefetch_targets: bool,
+    /// See [`Watcher::debounce`]
+    debounce: Option<Duration>,
+    /// When debouncing, the last change of each selection still settling, and when it came
+    settling: Vec<(ChangeEvent, Instant)>,
+    /// See [`Watcher::skip_secrets`]
+    skip_secrets: bool,
+    /// See [`Watcher::deduplicate`]
+    deduplicate: bool,
+    /// When deduplicating, the contents of each selection we last handed out a change for
+    last_contents: Vec<(Selection, Option<u64>)>,
+    /// See [`Watcher::preview`]
+    preview: Option<usize>,
+}
+
+impl X11Clipboard {
+    /// Watches the selections the best way we can: with XFixes if we have it, polling every
+    /// [`DEFAULT_POLL_INTERVAL`] otherwise
+    pub fn watch(&self, selections: &[Selection]) -> Watcher<'_> {
+        self.watch_xfixes(selections)
+            .unwrap_or_else(|| self.watch_polling(selections, DEFAULT_POLL_INTERVAL))
+    }
+
+    /// Watches the selections with the XFixes extension, which tells us as soon as one of
+    /// them changes owner.
+    ///
+    /// Gives `None` if `libXfixes` isn't installed or the X server doesn't have the extension.
+    pub fn watch_xfixes(&self, selections: &[Selection]) -> Option<Watcher<'_>> {
+        let xfixes = self.xfixes()?;
+
+        let watcher = Watcher::new(self, selections, WatchStrategy::XFixes);
+        for &(_, atom_selection) in &watcher.selections {
+            self.select_owner_changes(xfixes, atom_selection, selection_mask::ALL);
+        }
+
+        Some(watcher)
+    }
+
+    /// Watches the selections by looking at them every `interval`: a change of owner, of
+    /// targets or of contents counts as a change. Only the first actual target is fetched to
+    /// compare contents, but that's still a paste per selection each time.
+    pub fn watch_polling(&self, selections: &[Selection], interval: Duration) -> Watcher<'_> {
+        let mut watcher = Watcher::new(self, selections, WatchStrategy::Polling(interval));
+        watcher.fingerprints = watcher
+            .selections
+            .iter()
+            .map(|&(selection, _)| self.fingerprint(selection))
+            .collect();
+
+        watcher
+    }
+
+    /// Watches the selections from a background thread, calling `on_change` there for each
+    /// change, until the returned guard is dropped.
+    ///
+    /// The thread opens its own connection to the same display, so this clipboard stays free
+    /// for anything else.
+    pub fn watch_with(
+        &self,
+        selections: &[Selection],
+        mut on_change: impl FnMut(ChangeEvent) + Send + 'static,
+    ) -> Result<WatchGuard, ClipboxError> {
+        let display_name = self.display_name();
+        let timeouts = self.timeouts();
+        let selections = selections.to_vec();
+
+        let stop = CancellationToken::new();
+        let thread_stop = stop.clone();
+
+        let (ready_tx, ready_rx) = mpsc::channel();
+        let thread = thread::Builder::new()
+            .name("clipbox-watcher".to_string())
+            .spawn(move || -> Result<(), ClipboxError> {
+                let mut clipboard = X11Clipboard::init_with_display_name(&display_name)?;
+                clipboard.set_timeouts(timeouts);
+
+                let watcher = clipboard.watch(&selections).cancel_on(thread_stop);
+                let _ = ready_tx.send(());
+
+                for change in watcher {
+                    on_change(change);
+                }
+
+                Ok(())
+            })
+            .map_err(ClipboxError::Io)?;
+
+        if ready_rx.recv().is_err() {
+            // It couldn't connect
+            return Err(join(thread).err().unwrap_or(ClipboxError::Timeout));
+        }
+
+        Ok(WatchGuard {
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    /// XFixes on our display, if we have it
+    pub(super) fn xfixes(&self) -> Option<&XFixes> {
+        self.xfixes
+            .get_or_init(|| XFixes::init(self.display))
+            .as_ref()
+    }
+
+    /// Picks which changes of owner of the selection XFixes tells us about, `0` for none
+    pub(super) fn select_owner_changes(
+        &self,
+        xfixes: &XFixes,
+        atom_selection: Atom,
+        mask: c_ulong,
+    ) {
+        unsafe {
+            (xfixes.lib.XFixesSelectSelectionInput)(
+                self.display.as_ptr(),
+                self.window,
+                atom_selection,
+                mask,
+            );
+        }
+    }
+
+    /// What a new owner offers, if it isn't us
+    pub(super) fn change_targets(
+        &self,
+        selection: Selection,
+        owner: Option<XWindow>,
+    ) -> Vec<CString> {
+        if owner.is_none() || owner == Some(self.owner_window) {
+            return Vec::new();
+        }
+
+        self.get_target_names(selection)
+            .map(|names| names.0)
+            .unwrap_or_default()
+    }
+
+    fn fingerprint(&self, selection: Selection) -> Fingerprint {
+        let owner = self.selection_owner(selection);
+        if owner.is_none() || owner == Some(self.owner_window) {
+            // Nothing to look at, or it's ours and nobody is serving it while we poll
+            return Fingerprint {
+                owner,
+                targets: Vec::new(),
+                content_hash: None,
+            };
+        }
+
+        let targets = self.get_target_atoms(selection).unwrap_or_default();
+        let content_hash = self.content_hash(selection, &targets);
+
+        Fingerprint {
+            owner,
+            targets,
+            content_hash,
+        }
+    }
+
+    /// Hashes the data of the first actual target, if there is one
+    fn content_hash(&self, selection: Selection, targets: &[Atom]) -> Option<u64> {
+        targets
+            .iter()
+            .filter_map(|&atom| self.atom_name(atom))
+            .find(|target| is_data_target(target))
+            .and_then(|target| self.get_selection(selection, &target).ok())
+            .map(|data| {
+                let mut hasher = DefaultHasher::new();
+                data.hash(&mut hasher);
+                hasher.finish()
+            })
+    }
+}
+
+impl<'a> Watcher<'a> {
+    fn new(clipboard: &'a X11Clipboard, selections: &[Selection], strategy: WatchStrategy) -> Self {
+        let selections = selections
+            .iter()
+            .map(|&selection| (selection, clipboard.selection_atom(selection)))
+            .collect();
+
+        Self {
+            clipboard,
+            selections,
+            strategy,
+            fingerprints: Vec::new(),
+            next_poll: Instant::now(),
+            pending: VecDeque::new(),
+            cancellation: None,
+            prefetch_targets: true,
+            debounce: None,
+            settling: Vec::new(),
+            skip_secrets: false,
+            deduplicate: false,
+            last_contents: Vec::new(),
+            preview: None,
+        }
+    }
+
+    pub fn strategy(&self) -> WatchStrategy {
+        self.strategy
+    }
+
+    /// Whether to ask each new owner for its targets right away, which they are by default.
+    /// That's a round trip to the owner per change, which consumers that only care about the
+    /// owner can skip. Polling always has them, it compares them.
+    pub fn prefetch_targets(mut self, prefetch: bool) -> Self {
+        self.prefetch_targets = prefetch;
+        self
+    }
+
+    /// Only hands out the settled value of a selection: a change waits for `window` to pass
+    /// without another one before it's handed out, and the ones it replaces are dropped.
+    ///
+    /// Selecting text updates `PRIMARY` dozens of times per second, for example, where most
+    /// consumers only care about where the selection ended up.
+    pub fn debounce(mut self, window: Duration) -> Self {
+        self.debounce = Some(window);
+        self
+    }
+
+    /// Drops the changes whose owner says they're secrets, like the passwords KeePassXC copies
+    /// (see [`X11Clipboard::is_secret`]). That's a paste for each change offering the hint.
+    pub fn skip_secrets(mut self, skip: bool) -> Self {
+        self.skip_secrets = skip;
+        self
+    }
+
+    /// Fetches the contents of each change (its first actual target) and drops the changes
+    /// that have the same contents as the last one handed out for that selection: copying the
+    /// same text twice, or an app taking the selection back without changing it.
+    ///
+    /// That's a paste per change, so it goes well with [`Watcher::debounce`]. Our own copies
+    /// can't be fetched while we wait for changes, so they always come through.
+    pub fn deduplicate(mut self, deduplicate: bool) -> Self {
+        self.deduplicate = deduplicate;
+        self
+    }
+
+    /// Gives each change a [preview](ChangeEvent::preview) of at most `max_bytes` (and never
+    /// more than [`MAX_PREVIEW_SIZE`](super::MAX_PREVIEW_SIZE)) of its text, or what it is and
+    /// how big for anything else. That's a paste per change, cut short however big the
+    /// contents are.
+    pub fn preview(mut self, max_bytes: usize) -> Self {
+        self.preview = Some(max_bytes);
+        self
+    }
+
+    /// Stops watching once `token` is cancelled: waiting for changes gives up, and the
+    /// iterator ends
+    pub fn cancel_on(mut self, token: CancellationToken) -> Self {
+        self.cancellation = Some(token);
+        self
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancellation
+            .as_ref()
+            .is_some_and(CancellationToken::is_cancelled)
+    }
+
+    /// Blocks until one of the selections changes, or gives `None` once cancelled (see
+    /// [`Watcher::cancel_on`])
+    pub fn next_change(&mut self) -> Option<ChangeEvent> {
+        loop {
+            if self.is_cancelled() {
+                return None;
+            }
+
+            if let Some(change) = self.next_change_until(None) {
+                return Some(change);
+            }
+        }
+    }
+
+    /// Waits for one of the selections to change, giving up after `timeout` or once cancelled
+    pub fn next_change_timeout(&mut self, timeout: Duration) -> Option<ChangeEvent> {
+        self.next_change_until(Some(Instant::now() + timeout))
+    }
+
+    fn next_change_until(&mut self, deadline: Option<Instant>) -> Option<ChangeEvent> {
+        let Some(token) = self.cancellation.clone() else {
+            return self.next_previewed_change(deadline);
+        };
+
+        let clipboard = self.clipboard;
+        clipboard.with_cancellation(&token, |_| self.next_previewed_change(deadline))
+    }
+
+    /// The next change with its preview, see [`Watcher::preview`]
+    fn next_previewed_change(&mut self, deadline: Option<Instant>) -> Option<ChangeEvent> {
+        let mut change = self.next_wanted_change(deadline)?;
+
+        if let (Some(max_bytes), Some(owner)) = (self.preview, change.owner) {
+            let clipboard = self.clipboard;
+            if owner != clipboard.owner_window {
+                let preview = match &change.targets {
+                    Some(targets) => {
+                        let targets = TargetNames(targets.clone());
+                        clipboard.preview_with_targets(change.selection, &targets, max_bytes)
+                    }
+                    None => clipboard.get_preview(change.selection, max_bytes),
+                };
+                change.preview = preview.ok();
+            }
+        }
+
+        Some(change)
+    }
+
+    /// The next change that isn't filtered out, see [`Watcher::skip_secrets`] and
+    /// [`Watcher::deduplicate`]
+    fn next_wanted_change(&mut self, deadline: Option<Instant>) -> Option<ChangeEvent> {
+        loop {
+            let change = self.next_settled_change(deadline)?;
+            if self.skip_secrets && self.is_secret(&change) {
+                continue;
+            }
+
+            if !self.deduplicate {
+                return Some(change);
+            }
+
+            let clipboard = self.clipboard;
+            let contents = match change.owner {
+                Some(owner) if owner == clipboard.owner_window => {
+                    // Ours, nothing to compare the next change with either
+                    self.forget_contents(change.selection);
+                    return Some(change);
+                }
+                Some(_) => {
+                    let targets = clipboard
+                        .get_target_atoms(change.selection)
+                        .unwrap_or_default();
+                    clipboard.content_hash(change.selection, &targets)
+                }
+                None => None,
+            };
+
+            let last = self
+                .last_contents
+                .iter_mut()
+                .find(|(selection, _)| *selection == change.selection);
+
+            match last {
+                Some((_, last)) if *last == contents => continue,
+                Some((_, last)) => *last = contents,
+                None => self.last_contents.push((change.selection, contents)),
+            }
+
+            return Some(change);
+        }
+    }
+
+    fn is_secret(&self, change: &ChangeEvent) -> bool {
+        let clipboard = self.clipboard;
+
+        match (&change.owner, &change.targets) {
+            (None, _) => false,
+            (Some(owner), _) if *owner == clipboard.owner_window => false,
+            (Some(_), Some(targets)) => {
+                clipboard.offers_secret(change.selection, targets.iter().map(CString::as_c_str))
+            }
+            (Some(_), None) => clipboard.is_secret(change.selection),
+        }
+    }
+
+    fn forget_contents(&mut self, selection: Selection) {
+        self.last_contents
+            .retain(|&(last_selection, _)| last_selection != selection);
+    }
+
+    /// The next change once it settled, see [`Watcher::debounce`]
+    fn next_settled_change(&mut self, deadline: Option<Instant>) -> Option<ChangeEvent> {
+        let Some(window) = self.debounce else {
+            return self.next_strategy_change(deadline);
+        };
+
+        loop {
+            let now = Instant::now();
+            let settled = self
+                .settling
+                .iter()
+                .position(|&(_, changed_at)| changed_at + window <= now);
+
+            if let Some(index) = settled {
+                let (mut change, _) = self.settling.remove(index);
+                if self.prefetch_targets && change.targets.is_none() {
+                    // Only now, the changes it replaced didn't need them
+                    change.targets = Some(
+                        self.clipboard
+                            .change_targets(change.selection, change.owner),
+                    );
+                }
+                return Some(change);
+            }
+
+            let settle_at = self
+                .settling
+                .iter()
+                .map(|&(_, changed_at)| changed_at + window)
+                .min();
+
+            let wake_up = match (settle_at, deadline) {
+                (Some(settle_at), Some(deadline)) => Some(settle_at.min(deadline)),
+                (settle_at, deadline) => settle_at.or(deadline),
+            };
+
+            match self.next_strategy_change(wake_up) {
+                Some(change) => {
+                    let changed_at = Instant::now();
+                    let settling = self
+                        .settling
+                        .iter_mut()
+                        .find(|(settling, _)| settling.selection == change.selection);
+
+                    match settling {
+                        Some(settling) => *settling = (change, changed_at),
+                        None => self.settling.push((change, changed_at)),
+                    }
+                }
+                None if self.clipboard.is_cancelled() => return None,
+                None if deadline.is_some_and(|deadline| Instant::now() >= deadline) => return None,
+                // Something is about to settle
+                None => {}
+            }
+        }
+    }
+
+    fn next_strategy_change(&mut self, deadline: Option<Instant>) -> Option<ChangeEvent> {
+        match self.strategy {
+            WatchStrategy::XFixes => self.next_xfixes_change(deadline),
+            WatchStrategy::Polling(interval) => self.next_polled_change(interval, deadline),
+        }
+    }
+
+    /// Sleeps until `until`, or gives `false` if we're cancelled before
+    fn sleep_until(&self, until: Instant) -> bool {
+        // How long we may take to notice we're cancelled
+        const CANCEL_CHECK_INTERVAL: Duration = Duration::from_millis(50);
+
+        loop {
+            if self.is_cancelled() {
+                return false;
+            }
+
+            let remaining = until.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return true;
+            }
+
+            thread::sleep(remaining.min(CANCEL_CHECK_INTERVAL));
+        }
+    }
+
+    fn next_xfixes_change(&self, deadline: Option<Instant>) -> Option<ChangeEvent> {
+        let clipboard = self.clipboard;
+        let xfixes = clipboard.xfixes()?;
+
+        loop {
+            let xevent = clipboard.next_owner_event_until(deadline)?;
+
+            // Anything else is for a serve loop that isn't running, so it's lost anyway
+            let Some(xevent) = xfixes.selection_notify(&xevent) else {
+                continue;
+            };
+
+            let watched = self
+                .selections
+                .iter()
+                .find(|&&(_, atom_selection)| atom_selection == xevent.selection);
+
+            if let Some(&(selection, _)) = watched {
+                let owner = Some(xevent.owner).filter(|&owner| owner != 0);
+                // When debouncing, only the settled change gets them
+                let targets = (self.prefetch_targets && self.debounce.is_none())
+                    .then(|| clipboard.change_targets(selection, owner));
+
+                return Some(ChangeEvent {
+                    selection,
+                    owner,
+                    acquired_at: Some(xevent.selection_timestamp),
+                    targets,
+                    preview: None,
+                });
+            }
+        }
+    }
+
+    fn next_polled_change(
+        &mut self,
+        interval: Duration,
+        deadline: Option<Instant>,
+    ) -> Option<ChangeEvent> {
+        loop {
+            if let Some(change) = self.pending.pop_front() {
+                return Some(change);
+            }
+
+            let poll_at = self.next_poll.max(Instant::now());
+            if let Some(deadline) = deadline.filter(|&deadline| deadline < poll_at) {
+                self.sleep_until(deadline);
+                return None;
+            }
+
+            if !self.sleep_until(poll_at) {
+                return None;
+            }
+            self.next_poll = poll_at + interval;
+
+            for (&(selection, _), last) in self.selections.iter().zip(&mut self.fingerprints) {
+                let fingerprint = self.clipboard.fingerprint(selection);
+                if fingerprint != *last {
+                    let targets = fingerprint
+                        .targets
+                        .iter()
+                        .filter_map(|&atom| self.clipboard.atom_name(atom))
+                        .collect();
+
+                    self.pending.push_back(ChangeEvent {
+                        selection,
+                        owner: fingerprint.owner,
+                        acquired_at: None,
+                        targets: Some(targets),
+                        preview: None,
+                    });
+                    *last = fingerprint;
+                }
+            }
+        }
+    }
+}
+
+/// Changes, one at a time: `next` blocks until the next change. This only ends once
+/// cancelled, see [`Watcher::cancel_on`].
+impl<'a> Iterator for Watcher<'a> {
+    type Item = ChangeEvent;
+
+    fn next(&mut self) -> Option<ChangeEvent> {
+        self.next_change()
+    }
+}
+
+impl<'a> Drop for Watcher<'a> {
+    fn drop(&mut self) {
+        if self.strategy != WatchStrategy::XFixes {
+            return;
+        }
+
+        let Some(xfixes) = self.clipboard.xfixes() else {
+            return;
+        };
+
+        for &(_, atom_selection) in &self.selections {
+            self.clipboard
+                .select_owner_changes(xfixes, atom_selection, 0);
+        }
+    }
+}
+
+impl WatchGuard {
+    /// Whether the thread is still watching. It only stops by itself if it fails.
+    pub fn is_watching(&self) -> bool {
+        self.thread
+            .as_ref()
+            .is_some_and(|thread| !thread.is_finished())
+    }
+
+    /// Stops watching, giving the error that stopped the thread early if there was one
+    pub fn stop(mut self) -> Result<(), ClipboxError> {
+        self.stop.cancel();
+        self.thread.take().map_or(Ok(()), join)
+    }
+}
+
+impl Drop for WatchGuard {
+    fn drop(&mut self) {
+        self.stop.cancel();
+        if let Some(thread) = self.thread.take() {
+            let _ = join(thread);
+        }
+    }
+}
+
+fn join(thread: JoinHandle<Result<(), ClipboxError>>) -> Result<(), ClipboxError> {
+    thread
+        .join()
+        .unwrap_or_else(|payload| panic::resume_unwind(payload))
+}
@@ -0,0 +1,154 @@
+//! Driving the clipboard from a [winit](::winit) event loop.
+//!
+//! winit can't wait on other file descriptors, so a [`WinitClipboard`] has a small thread do
+//! it: when the X connection is readable, it sends the loop a user event and waits for the
+//! next dispatch. Everything else happens on the loop's thread, the clipboard never leaves it.
+//!
+//! Dispatch it when that user event comes, and let it pick the control flow when the loop is
+//! about to wait:
+//!
+//! ```ignore
+//! fn user_event(&mut self, _: &ActiveEventLoop, _: Wake) {
+//!     self.clipboard.dispatch();
+//! }
+//!
+//! fn about_to_wait(&mut self, event_loop: &ActiveEventLoop) {
+//!     self.clipboard.dispatch();
+//!     event_loop.set_control_flow(self.clipboard.control_flow());
+//! }
+//! ```
+
+use std::fs::File;
+use std::io;
+use std::ops::{Deref, DerefMut};
+use std::os::fd::{AsRawFd, RawFd};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::thread::{self, JoinHandle};
+
+use ::winit::event_loop::{ControlFlow, EventLoopProxy};
+
+use super::{poll, ClipboardDriver, X11Clipboard};
+
+/// A clipboard driven by a winit event loop, see the [module docs](self)
+pub struct WinitClipboard {
+    driver: ClipboardDriver,
+    waker: Option<Waker>,
+}
+
+/// The thread waking the loop up
+struct Waker {
+    /// Lets the thread wait on the connection again, once we've read what woke it up
+    dispatched: SyncSender<()>,
+    /// Dropping it stops the thread
+    stop: File,
+    thread: JoinHandle<()>,
+}
+
+impl WinitClipboard {
+    /// Has `event` sent through the proxy whenever the clipboard has to be dispatched
+    pub fn new<T: Clone + Send + 'static>(
+        clipboard: X11Clipboard,
+        proxy: EventLoopProxy<T>,
+        event: T,
+    ) -> io::Result<Self> {
+        Self::from_driver(ClipboardDriver::new(clipboard), proxy, event)
+    }
+
+    pub fn from_driver<T: Clone + Send + 'static>(
+        driver: ClipboardDriver,
+        proxy: EventLoopProxy<T>,
+        event: T,
+    ) -> io::Result<Self> {
+        let connection = driver.as_raw_fd();
+        let (dispatched, dispatches) = mpsc::sync_channel(1);
+        let (stopped, stop) = poll::pipe()?;
+
+        let thread = thread::Builder::new()
+            .name("clipbox-winit".to_string())
+            .spawn(move || wake_up(connection, stopped, dispatches, proxy, event))?;
+
+        Ok(Self {
+            driver,
+            waker: Some(Waker {
+                dispatched,
+                stop,
+                thread,
+            }),
+        })
+    }
+
+    /// Dispatches the driver, see [`ClipboardDriver::dispatch`]
+    pub fn dispatch(&mut self) {
+        self.driver.dispatch();
+
+        if let Some(waker) = &self.waker {
+            // It's already been told if it's full
+            let _ = waker.dispatched.try_send(());
+        }
+    }
+
+    /// How long the loop can wait before dispatching again, if nothing wakes it up
+    pub fn control_flow(&self) -> ControlFlow {
+        if self.driver.has_pending_events() {
+            return ControlFlow::Poll;
+        }
+
+        match self.driver.deadline() {
+            Some(deadline) => ControlFlow::WaitUntil(deadline),
+            None => ControlFlow::Wait,
+        }
+    }
+}
+
+impl Deref for WinitClipboard {
+    type Target = ClipboardDriver;
+
+    fn deref(&self) -> &ClipboardDriver {
+        &self.driver
+    }
+}
+
+impl DerefMut for WinitClipboard {
+    fn deref_mut(&mut self) -> &mut ClipboardDriver {
+        &mut self.driver
+    }
+}
+
+impl Drop for WinitClipboard {
+    fn drop(&mut self) {
+        // Before the connection closes under the thread
+        if let Some(waker) = self.waker.take() {
+            drop(waker.dispatched);
+            drop(waker.stop);
+            let _ = waker.thread.join();
+        }
+    }
+}
+
+/// What the waker thread does, until it's stopped or the loop is gone
+fn wake_up<T: Clone + 'static>(
+    connection: RawFd,
+    stopped: File,
+    dispatches: Receiver<()>,
+    proxy: EventLoopProxy<T>,
+    event: T,
+) {
+    loop {
+        match poll::wait_readable([connection, stopped.as_raw_fd()], None) {
+            Ok([_, true]) => return,
+            Ok([true, _]) => {}
+            Ok(_) => continue,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(_) => return,
+        }
+
+        if proxy.send_event(event.clone()).is_err() {
+            return;
+        }
+
+        // The connection stays readable until the events are read
+        if dispatches.recv().is_err() {
+            return;
+        }
+    }
+}
@@ -0,0 +1,108 @@
+//! The few bits of the XFixes extension we need, loaded at runtime like Xlib itself.
+//!
+//! XFixes tells clients when selections change owner, which is all a clipboard watcher needs.
+//! It lives in its own library that isn't always installed, so not finding it isn't an error.
+
+use std::ffi::{c_int, c_ulong, c_void};
+use std::mem;
+use std::ptr::NonNull;
+
+use loki_linux::x11::{Atom, Bool, XDisplay, XEvent, XWindow};
+
+use super::loader::{self, symbol};
+
+/// Which changes of owner we want to hear about
+pub(super) mod selection_mask {
+    use std::ffi::c_ulong;
+
+    pub const SET_SELECTION_OWNER: c_ulong = 1 << 0;
+    pub const SELECTION_WINDOW_DESTROY: c_ulong = 1 << 1;
+    pub const SELECTION_CLIENT_CLOSE: c_ulong = 1 << 2;
+    pub const ALL: c_ulong =
+        SET_SELECTION_OWNER | SELECTION_WINDOW_DESTROY | SELECTION_CLIENT_CLOSE;
+}
+
+/// Offset of `XFixesSelectionNotify` from the extension's first event
+const SELECTION_NOTIFY: c_int = 0;
+
+/// What XFixes sends when a selection changes owner
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub(super) struct XFixesSelectionNotifyEvent {
+    pub type_id: c_int,
+    pub serial: c_ulong,
+    pub send_event: Bool,
+    pub display: *mut XDisplay,
+    pub window: XWindow,
+    pub subtype: c_int,
+    pub owner: XWindow,
+    pub selection: Atom,
+    pub timestamp: c_ulong,
+    pub selection_timestamp: c_ulong,
+}
+
+type QueryExtension = unsafe extern "C" fn(*mut XDisplay, *mut c_int, *mut c_int) -> Bool;
+type SelectSelectionInput = unsafe extern "C" fn(*mut XDisplay, XWindow, Atom, c_ulong);
+
+/// Named like the C functions, the same way as [`LibX11`](loki_linux::x11::LibX11)
+#[allow(non_snake_case)]
+pub(super) struct LibXfixes {
+    pub XFixesQueryExtension: QueryExtension,
+    pub XFixesSelectSelectionInput: SelectSelectionInput,
+}
+
+impl LibXfixes {
+    /// Loads the library, or gives `None` if it isn't installed.
+    ///
+    /// It's never unloaded: Xlib calls back into it when the display closes.
+    pub fn open() -> Option<Self> {
+        let handle = loader::open_xfixes()?;
+
+        unsafe {
+            let query_extension = symbol(handle, c"XFixesQueryExtension")?;
+            let select_selection_input = symbol(handle, c"XFixesSelectSelectionInput")?;
+
+            Some(Self {
+                XFixesQueryExtension: mem::transmute::<*mut c_void, QueryExtension>(
+                    query_extension,
+                ),
+                XFixesSelectSelectionInput: mem::transmute::<*mut c_void, SelectSelectionInput>(
+                    select_selection_input,
+                ),
+            })
+        }
+    }
+}
+
+/// XFixes on a given display
+pub(super) struct XFixes {
+    pub lib: LibXfixes,
+    /// Where the extension's events start, the server decides
+    event_base: c_int,
+}
+
+impl XFixes {
+    /// Gives `None` if the library is missing or the server doesn't have the extension
+    pub fn init(display: NonNull<XDisplay>) -> Option<Self> {
+        let lib = LibXfixes::open()?;
+
+        let mut event_base = 0;
+        let mut error_base = 0;
+        let present = unsafe {
+            (lib.XFixesQueryExtension)(display.as_ptr(), &mut event_base, &mut error_base)
+        };
+
+        (present != 0).then_some(Self { lib, event_base })
+    }
+
+    /// Reads an event as a change of selection owner, if that's what it is
+    pub fn selection_notify(&self, xevent: &XEvent) -> Option<XFixesSelectionNotifyEvent> {
+        if unsafe { xevent.type_id } != self.event_base + SELECTION_NOTIFY {
+            return None;
+        }
+
+        // Xlib's XEvent is big enough for every event, including extension ones
+        let xevent = unsafe { *(xevent as *const XEvent).cast::<XFixesSelectionNotifyEvent>() };
+        Some(xevent)
+    }
+}
@@ -0,0 +1,116 @@
+//! The bits of Xlib we need on top of what loki-linux binds, loaded at runtime from libX11
+//! itself.
+//!
+//! loki-linux only binds what its own windowing needs. Functions it may not have are looked up
+//! in the libX11 it already loaded, and the few protocol constants and event layouts it may not
+//! have are defined here, straight from `X.h` and `Xlib.h`.
+
+use std::ffi::{c_char, c_int, c_long, c_ulong, c_void};
+use std::mem;
+
+use loki_linux::x11::{Atom, Bool, XDisplay, XEvent, XSelectionRequestEvent, XWindow};
+
+use super::loader::{self, symbol};
+
+/// `DestroyNotify`
+pub(super) const DESTROY_NOTIFY: c_int = 17;
+
+/// `StructureNotifyMask`, to hear about a window being destroyed
+pub(super) const STRUCTURE_NOTIFY_MASK: c_long = 1 << 17;
+
+/// `PropModePrepend`
+pub(super) const PROP_MODE_PREPEND: c_int = 1;
+
+/// What the X server sends when another client takes a selection we own
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub(super) struct XSelectionClearEvent {
+    pub type_id: c_int,
+    pub serial: c_ulong,
+    pub send_event: Bool,
+    pub display: *mut XDisplay,
+    pub window: XWindow,
+    pub selection: Atom,
+    pub time: c_ulong,
+}
+
+/// What the X server sends when a window whose structure we watch is destroyed
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub(super) struct XDestroyWindowEvent {
+    pub type_id: c_int,
+    pub serial: c_ulong,
+    pub send_event: Bool,
+    pub display: *mut XDisplay,
+    pub event: XWindow,
+    pub window: XWindow,
+}
+
+// Both are read out of an XEvent, which is at least as big as a selection request
+const _: () =
+    assert!(mem::size_of::<XSelectionClearEvent>() <= mem::size_of::<XSelectionRequestEvent>());
+const _: () =
+    assert!(mem::size_of::<XDestroyWindowEvent>() <= mem::size_of::<XSelectionRequestEvent>());
+
+type ConnectionNumber = unsafe extern "C" fn(*mut XDisplay) -> c_int;
+type DestroyWindow = unsafe extern "C" fn(*mut XDisplay, XWindow) -> c_int;
+type DisplayString = unsafe extern "C" fn(*mut XDisplay) -> *mut c_char;
+type ExtendedMaxRequestSize = unsafe extern "C" fn(*mut XDisplay) -> c_long;
+type QueryExtension =
+    unsafe extern "C" fn(*mut XDisplay, *const c_char, *mut c_int, *mut c_int, *mut c_int) -> Bool;
+
+/// Named like the C functions, the same way as [`LibX11`](loki_linux::x11::LibX11)
+#[allow(non_snake_case)]
+pub(super) struct LibX11Extras {
+    pub XConnectionNumber: ConnectionNumber,
+    pub XDestroyWindow: DestroyWindow,
+    pub XDisplayString: DisplayString,
+    pub XExtendedMaxRequestSize: ExtendedMaxRequestSize,
+    pub XQueryExtension: QueryExtension,
+}
+
+impl LibX11Extras {
+    /// Looks the functions up in libX11, giving `None` if it's missing one of them. It's never
+    /// unloaded, like loki-linux's.
+    pub fn open() -> Option<Self> {
+        let handle = loader::open_x11()?;
+
+        unsafe {
+            let connection_number = symbol(handle, c"XConnectionNumber")?;
+            let destroy_window = symbol(handle, c"XDestroyWindow")?;
+            let display_string = symbol(handle, c"XDisplayString")?;
+            let extended_max_request_size = symbol(handle, c"XExtendedMaxRequestSize")?;
+            let query_extension = symbol(handle, c"XQueryExtension")?;
+
+            Some(Self {
+                XConnectionNumber: mem::transmute::<*mut c_void, ConnectionNumber>(
+                    connection_number,
+                ),
+                XDestroyWindow: mem::transmute::<*mut c_void, DestroyWindow>(destroy_window),
+                XDisplayString: mem::transmute::<*mut c_void, DisplayString>(display_string),
+                XExtendedMaxRequestSize: mem::transmute::<*mut c_void, ExtendedMaxRequestSize>(
+                    extended_max_request_size,
+                ),
+                XQueryExtension: mem::transmute::<*mut c_void, QueryExtension>(query_extension),
+            })
+        }
+    }
+}
+
+/// Reads a `SelectionClear` event
+///
+/// # Safety
+///
+/// The event has to be one.
+pub(super) unsafe fn selection_clear(xevent: &XEvent) -> XSelectionClearEvent {
+    *(xevent as *const XEvent).cast::<XSelectionClearEvent>()
+}
+
+/// Reads a `DestroyNotify` event
+///
+/// # Safety
+///
+/// The event has to be one.
+pub(super) unsafe fn destroy_window(xevent: &XEvent) -> XDestroyWindowEvent {
+    *(xevent as *const XEvent).cast::<XDestroyWindowEvent>()
+}
@@ -1,35 +1,174 @@
+use std::env;
 use std::error::Error;
 use std::fs;
 
-use clipbox::linux::x11::{atom_names, mime_types, X11Clipboard};
+#[cfg(feature = "png")]
+use clipbox::convert;
+use clipbox::convert::ClipboardContent;
+use clipbox::history::{Provenance, SensitivityRules};
+#[cfg(feature = "dbus")]
+use clipbox::linux::dbus::DbusService;
+use clipbox::linux::doctor;
+use clipbox::linux::x11::{
+    atom_names, ChangeHook, Preview, SelectionSync, SyncDirection, X11Clipboard,
+};
+use clipbox::transform::{Pipeline, Stage, StripTrackingParams, TrimWhitespace};
+use clipbox::{Clipboard, Selection};
 
 const MYSELF: &[u8] = "hello I'm really new (I swear) UTF8 text: 日本語".as_bytes();
 // const IMAGE: &[u8] = include_bytes!("../image.png");
 
 fn main() -> Result<(), Box<dyn Error>> {
+    if env::args().nth(1).as_deref() == Some("doctor") {
+        // Meant to be pasted as-is in bug reports
+        print!("{}", doctor::run());
+        return Ok(());
+    }
+
+    if env::args().nth(1).as_deref() == Some("watch") {
+        // One line per change, until Ctrl+C. `--preview N` adds the first N bytes of each one.
+        let preview = match env::args().nth(2).as_deref() {
+            Some("--preview") => Some(
+                env::args()
+                    .nth(3)
+                    .and_then(|n| n.parse().ok())
+                    .unwrap_or(80),
+            ),
+            _ => None,
+        };
+
+        let clipboard = Clipboard::new()?;
+        let mut changes = clipboard.changes()?;
+        if let Some(max_bytes) = preview {
+            changes = changes.preview(max_bytes);
+        }
+
+        for change in changes {
+            let Some(owner) = change.owner else {
+                println!("{}: nobody owns it anymore", change.selection);
+                continue;
+            };
+
+            let x11 = clipboard.x11()?;
+            let mut provenance = Provenance::new(x11.selection_owner_info(change.selection));
+            provenance.sensitive = x11.is_secret(change.selection)
+                || match &change.preview {
+                    Some(Preview::Text { text, .. }) => SensitivityRules::default().is_sensitive(
+                        &ClipboardContent::Text(text.clone()),
+                        provenance.app.as_ref(),
+                    ),
+                    _ => false,
+                };
+
+            let targets = change
+                .targets
+                .iter()
+                .flatten()
+                .map(|target| target.to_string_lossy())
+                .collect::<Vec<_>>();
+
+            println!(
+                "{}: copied from {} (window {:#x}), targets: {}",
+                change.selection,
+                provenance,
+                owner,
+                targets.join(", ")
+            );
+
+            match change.preview {
+                Some(Preview::Text { text, truncated }) => {
+                    println!("  {:?}{}", text, if truncated { "..." } else { "" })
+                }
+                Some(Preview::Binary { target, size, .. }) => {
+                    println!("  {} ({} bytes)", target, size)
+                }
+                None => {}
+            }
+        }
+
+        return Ok(());
+    }
+
+    if env::args().nth(1).as_deref() == Some("sync") {
+        // PRIMARY into CLIPBOARD (or the other way, or both), until Ctrl+C
+        let direction = match env::args().nth(2).as_deref() {
+            Some("clipboard") => SyncDirection::ClipboardToPrimary,
+            Some("both") => SyncDirection::Both,
+            _ => SyncDirection::PrimaryToClipboard,
+        };
+
+        SelectionSync::new(direction).run(&X11Clipboard::init()?);
+        return Ok(());
+    }
+
+    if env::args().nth(1).as_deref() == Some("clean") {
+        // Copies the clipboard text again, without tracking parameters in links and stray
+        // whitespace around it
+        let mut clipboard = Clipboard::new()?;
+        clipboard.set_transforms(
+            Pipeline::new()
+                .rule(Stage::Paste, StripTrackingParams::default())
+                .rule(Stage::Copy, TrimWhitespace),
+        );
+
+        let text = clipboard.get_text()?;
+        clipboard.set_text(&text)?;
+        return Ok(());
+    }
+
+    if env::args().nth(1).as_deref() == Some("hook") {
+        // Runs the command for each change, with the contents on its stdin, until Ctrl+C
+        let Some(program) = env::args().nth(2) else {
+            return Err("usage: clipbox hook <command> [args...]".into());
+        };
+
+        ChangeHook::new(program)
+            .args(env::args().skip(3))
+            .run(&X11Clipboard::init()?);
+        return Ok(());
+    }
+
+    #[cfg(feature = "dbus")]
+    if env::args().nth(1).as_deref() == Some("dbus") {
+        // Serves the clipboard on the session bus until killed
+        let _service = DbusService::start()?;
+        loop {
+            std::thread::park();
+        }
+    }
+
     println!("[[Init X11 clipboard]]");
     let clipboard = X11Clipboard::init()?;
 
     println!("[[Getting targets]]");
-    let targets = clipboard.get_targets(atom_names::CLIPBOARD)?;
+    let targets = clipboard.get_targets(Selection::Clipboard)?;
     dbg!(&targets);
 
-    println!("[[Getting selection]]");
-    if targets.contains(&mime_types::IMAGE_PNG) {
-        let selection = clipboard.get_selection(atom_names::CLIPBOARD, mime_types::IMAGE_PNG)?;
-        println!("[[Writing image]]");
-        fs::write("image.png", selection)?;
-    } else {
-        let selection = clipboard.get_selection(atom_names::CLIPBOARD, atom_names::UTF8_STRING)?;
-        println!("[[Writing text]]");
-        fs::write("string.txt", selection)?;
+    println!("[[Getting content]]");
+    match clipboard.get_content(Selection::Clipboard)? {
+        #[cfg(feature = "png")]
+        ClipboardContent::Image(image) => {
+            println!("[[Writing image]]");
+            fs::write("image.png", convert::encode_png(&image)?)?;
+        }
+        ClipboardContent::Html(html) => {
+            println!("[[Writing HTML]]");
+            fs::write("clipboard.html", html)?;
+        }
+        ClipboardContent::Text(text) => {
+            println!("[[Writing text]]");
+            fs::write("string.txt", text)?;
+        }
+        content => {
+            dbg!(&content);
+        }
     }
 
     println!("[[Copying myself into clipboard]]");
-    clipboard.set_selection(atom_names::CLIPBOARD, atom_names::UTF8_STRING, MYSELF)?;
+    clipboard.set_selection(Selection::Clipboard, atom_names::UTF8_STRING, MYSELF)?;
 
     // println!("[[Copying image into clipboard]]");
-    // clipboard.set_selection(atom_names::CLIPBOARD, mime_types::IMAGE_PNG, IMAGE)?;
+    // clipboard.set_selection(Selection::Clipboard, mime_types::IMAGE_PNG, IMAGE)?;
 
     // 👍
     Ok(())
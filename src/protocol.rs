@@ -0,0 +1,349 @@
+//! Selection transfer logic that doesn't depend on any particular transport.
+//!
+//! These are plain state machines: a backend feeds them what happened (a chunk arrived, the
+//! requestor consumed a chunk, time passed) and does whatever they say next. Only X11 drives
+//! them for now, but nothing in here knows about X11.
+
+use std::fmt;
+use std::io::{self, Read};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+/// Whether a payload has to be sent incrementally, given the biggest chunk the transport takes
+pub(crate) fn needs_incr(len: usize, max_chunk_size: usize) -> bool {
+    len >= max_chunk_size
+}
+
+/// Where an incremental transfer gets its data from
+enum Source<'d> {
+    Slice(&'d [u8]),
+    Shared(Rc<[u8]>),
+    Reader(&'d mut dyn Read),
+}
+
+impl<'d> fmt::Debug for Source<'d> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Slice(data) => write!(f, "Slice({} bytes)", data.len()),
+            Self::Shared(data) => write!(f, "Shared({} bytes)", data.len()),
+            Self::Reader(_) => write!(f, "Reader"),
+        }
+    }
+}
+
+/// Where an incremental transfer is at, on the sending side
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum IncrState {
+    /// The requestor hasn't consumed what we last gave it yet (the announcement of the
+    /// transfer, then each chunk)
+    AwaitingDelete,
+    /// The requestor consumed it, the next chunk can go out
+    SendChunk,
+    /// The empty chunk went out, there's nothing left to do
+    Finished,
+}
+
+/// Sending side of an incremental transfer.
+///
+/// Data goes out in chunks, each one after the requestor consumed the previous one,
+/// and an empty chunk marks the end of the transfer:
+///
+/// ```text
+/// AwaitingDelete --consumed()--> SendChunk --next_chunk()--> AwaitingDelete
+///                                          --next_chunk() (empty)--> Finished
+/// ```
+#[derive(Debug)]
+pub(crate) struct IncrSender<'d> {
+    source: Source<'d>,
+    chunk_size: usize,
+    /// The current chunk, when reading from a stream
+    buf: Vec<u8>,
+    sent: usize,
+    state: IncrState,
+}
+
+impl<'d> IncrSender<'d> {
+    pub fn new(data: &'d [u8], chunk_size: usize) -> Self {
+        Self::with_source(Source::Slice(data), chunk_size)
+    }
+
+    /// Sends data that is also kept around elsewhere, for other requestors
+    pub fn shared(data: Rc<[u8]>, chunk_size: usize) -> Self {
+        Self::with_source(Source::Shared(data), chunk_size)
+    }
+
+    /// Sends data as it's read from a stream, one chunk in memory at a time
+    pub fn from_reader(reader: &'d mut dyn Read, chunk_size: usize) -> Self {
+        Self::with_source(Source::Reader(reader), chunk_size)
+    }
+
+    fn with_source(source: Source<'d>, chunk_size: usize) -> Self {
+        Self {
+            source,
+            chunk_size,
+            buf: Vec::new(),
+            sent: 0,
+            state: IncrState::AwaitingDelete,
+        }
+    }
+
+    /// The requestor consumed what we last gave it. Returns whether we were waiting for
+    /// that: if not, there's no chunk to send.
+    pub fn consumed(&mut self) -> bool {
+        if self.state != IncrState::AwaitingDelete {
+            return false;
+        }
+
+        self.state = IncrState::SendChunk;
+        true
+    }
+
+    /// Gives the next chunk to send, once the requestor consumed the previous one.
+    ///
+    /// The last chunk is empty. `None` comes when it's not time to send anything: the
+    /// requestor didn't consume the previous chunk yet, or the transfer is finished.
+    pub fn next_chunk(&mut self) -> Option<io::Result<&[u8]>> {
+        if self.state != IncrState::SendChunk {
+            return None;
+        }
+
+        let chunk = match &mut self.source {
+            Source::Slice(data) => {
+                let end = (self.sent + self.chunk_size).min(data.len());
+                &data[self.sent..end]
+            }
+            Source::Shared(data) => {
+                let end = (self.sent + self.chunk_size).min(data.len());
+                &data[self.sent..end]
+            }
+            Source::Reader(reader) => {
+                self.buf.resize(self.chunk_size, 0);
+
+                // Fill the whole chunk unless the stream ends
+                let mut len = 0;
+                while len < self.chunk_size {
+                    match reader.read(&mut self.buf[len..]) {
+                        Ok(0) => break,
+                        Ok(n) => len += n,
+                        Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                        Err(err) => return Some(Err(err)),
+                    }
+                }
+
+                &self.buf[..len]
+            }
+        };
+
+        self.sent += chunk.len();
+        self.state = match chunk.is_empty() {
+            true => IncrState::Finished,
+            false => IncrState::AwaitingDelete,
+        };
+        Some(Ok(chunk))
+    }
+
+    /// How many bytes were handed out so far
+    pub fn sent(&self) -> usize {
+        self.sent
+    }
+
+    /// How many bytes there are in total, if we know it (we don't for streams)
+    pub fn total(&self) -> Option<usize> {
+        match &self.source {
+            Source::Slice(data) => Some(data.len()),
+            Source::Shared(data) => Some(data.len()),
+            Source::Reader(_) => None,
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.state == IncrState::Finished
+    }
+}
+
+/// Receiving side of an incremental transfer
+#[derive(Debug, Default)]
+pub(crate) struct IncrReceiver {
+    data: Vec<u8>,
+    complete: bool,
+}
+
+impl IncrReceiver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Takes in a chunk, returning whether the transfer is complete (the chunk was empty)
+    pub fn push(&mut self, chunk: &[u8]) -> bool {
+        if chunk.is_empty() {
+            self.complete = true;
+        }
+
+        self.data.extend_from_slice(chunk);
+        self.complete
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+/// Expires when nothing happened for a while, or never without a timeout
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct IdleTimeout {
+    timeout: Option<Duration>,
+    last_activity: Instant,
+}
+
+impl IdleTimeout {
+    pub fn new(timeout: Option<Duration>) -> Self {
+        Self {
+            timeout,
+            last_activity: Instant::now(),
+        }
+    }
+
+    /// Something happened, start waiting again
+    pub fn touch(&mut self) {
+        self.last_activity = Instant::now();
+    }
+
+    /// How long until it expires, `None` if it never does
+    pub fn remaining(&self) -> Option<Duration> {
+        let timeout = self.timeout?;
+        Some(timeout.saturating_sub(self.last_activity.elapsed()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs a sender to the end, giving the chunks it sent (with the empty one)
+    fn send_all(sender: &mut IncrSender) -> Vec<Vec<u8>> {
+        let mut chunks = Vec::new();
+        while !sender.is_finished() {
+            assert!(sender.consumed());
+            let chunk = sender.next_chunk().unwrap().unwrap().to_vec();
+            chunks.push(chunk);
+        }
+        chunks
+    }
+
+    #[test]
+    fn sender_waits_for_each_delete() {
+        let data = b"abcdefgh";
+        let mut sender = IncrSender::new(data, 3);
+
+        // The announcement of the transfer wasn't consumed yet
+        assert!(sender.next_chunk().is_none());
+
+        assert!(sender.consumed());
+        assert_eq!(sender.next_chunk().unwrap().unwrap(), b"abc");
+        assert!(sender.next_chunk().is_none());
+        assert_eq!(sender.sent(), 3);
+
+        assert!(sender.consumed());
+        assert_eq!(sender.next_chunk().unwrap().unwrap(), b"def");
+        assert!(sender.consumed());
+        assert_eq!(sender.next_chunk().unwrap().unwrap(), b"gh");
+        assert!(!sender.is_finished());
+
+        assert!(sender.consumed());
+        assert!(sender.next_chunk().unwrap().unwrap().is_empty());
+        assert!(sender.is_finished());
+        assert_eq!(sender.sent(), data.len());
+        assert_eq!(sender.total(), Some(data.len()));
+    }
+
+    #[test]
+    fn sender_ends_with_an_empty_chunk() {
+        // Even when the data splits evenly into chunks
+        let mut sender = IncrSender::new(b"abcdef", 3);
+        assert_eq!(send_all(&mut sender), [&b"abc"[..], b"def", b""]);
+
+        let mut sender = IncrSender::shared(Rc::from(&b"abcdefg"[..]), 3);
+        assert_eq!(send_all(&mut sender), [&b"abc"[..], b"def", b"g", b""]);
+
+        let mut sender = IncrSender::new(b"", 3);
+        assert_eq!(send_all(&mut sender), [b""]);
+    }
+
+    #[test]
+    fn sender_reads_streams_in_full_chunks() {
+        // Hands out one byte per read, like a slow pipe
+        struct Trickle<'a>(&'a [u8]);
+
+        impl Read for Trickle<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                let Some((&byte, rest)) = self.0.split_first() else {
+                    return Ok(0);
+                };
+                buf[0] = byte;
+                self.0 = rest;
+                Ok(1)
+            }
+        }
+
+        let mut reader = Trickle(b"abcdefg");
+        let mut sender = IncrSender::from_reader(&mut reader, 3);
+        assert_eq!(sender.total(), None);
+        assert_eq!(send_all(&mut sender), [&b"abc"[..], b"def", b"g", b""]);
+        assert_eq!(sender.sent(), 7);
+    }
+
+    #[test]
+    fn sender_ignores_deletes_once_finished() {
+        let mut sender = IncrSender::new(b"abc", 3);
+        send_all(&mut sender);
+
+        assert!(!sender.consumed());
+        assert!(sender.next_chunk().is_none());
+        assert!(sender.is_finished());
+    }
+
+    #[test]
+    fn sender_ignores_repeated_deletes() {
+        let mut sender = IncrSender::new(b"abcdef", 3);
+        assert!(sender.consumed());
+        assert!(!sender.consumed());
+
+        assert_eq!(sender.next_chunk().unwrap().unwrap(), b"abc");
+        assert!(sender.next_chunk().is_none());
+    }
+
+    #[test]
+    fn incr_starts_at_the_max_chunk_size() {
+        assert!(!needs_incr(0, 1024));
+        assert!(!needs_incr(1023, 1024));
+        assert!(needs_incr(1024, 1024));
+        assert!(needs_incr(1025, 1024));
+    }
+
+    #[test]
+    fn receiver_completes_on_an_empty_chunk() {
+        let mut receiver = IncrReceiver::new();
+        assert!(!receiver.push(b"abc"));
+        assert!(!receiver.push(b"def"));
+        assert!(receiver.push(b""));
+        assert_eq!(receiver.finish(), b"abcdef");
+
+        let mut receiver = IncrReceiver::new();
+        assert!(receiver.push(b""));
+        assert!(receiver.finish().is_empty());
+    }
+
+    #[test]
+    fn idle_timeout_counts_down_from_the_last_activity() {
+        assert_eq!(IdleTimeout::new(None).remaining(), None);
+
+        let mut idle = IdleTimeout::new(Some(Duration::from_secs(60)));
+        idle.touch();
+        let remaining = idle.remaining().unwrap();
+        assert!(remaining > Duration::from_secs(59));
+        assert!(remaining <= Duration::from_secs(60));
+
+        let idle = IdleTimeout::new(Some(Duration::ZERO));
+        assert_eq!(idle.remaining(), Some(Duration::ZERO));
+    }
+}
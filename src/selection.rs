@@ -0,0 +1,37 @@
+//! Which clipboard to work with.
+
+use std::fmt;
+
+/// A clipboard, called a selection on X11.
+///
+/// Most apps only ever use [`Selection::Clipboard`]. The other ones are X11 things:
+/// `Primary` holds the last selected text (pasted with a middle click), and nobody really
+/// knows what `Secondary` is for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Selection {
+    /// The actual clipboard that most apps use
+    #[default]
+    Clipboard,
+    Primary,
+    Secondary,
+    /// Any other selection, by name. The name can't contain NUL bytes.
+    Custom(&'static str),
+}
+
+impl Selection {
+    /// The name of the selection, like `CLIPBOARD`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Clipboard => "CLIPBOARD",
+            Self::Primary => "PRIMARY",
+            Self::Secondary => "SECONDARY",
+            Self::Custom(name) => name,
+        }
+    }
+}
+
+impl fmt::Display for Selection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
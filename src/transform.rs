@@ -0,0 +1,340 @@
+//! Text transformations that can be applied to clipboard contents on copy or on paste.
+//!
+//! Filters are small and composable: a [`Pipeline`] is just an ordered list of rules,
+//! each rule being a [`Filter`] and the [`Stage`] it applies to.
+
+/// A transformation over clipboard text.
+pub trait Filter {
+    fn apply(&self, text: &str) -> String;
+}
+
+impl<F: Fn(&str) -> String> Filter for F {
+    fn apply(&self, text: &str) -> String {
+        self(text)
+    }
+}
+
+/// When a rule applies
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    /// When we put text into the clipboard
+    Copy,
+    /// When we read text from the clipboard
+    Paste,
+    /// Both on copy and on paste
+    Both,
+}
+
+impl Stage {
+    fn includes(self, stage: Stage) -> bool {
+        self == Stage::Both || self == stage
+    }
+}
+
+struct Rule {
+    stage: Stage,
+    filter: Box<dyn Filter>,
+}
+
+/// An ordered list of filters, each applied on copy, on paste, or both.
+#[derive(Default)]
+pub struct Pipeline {
+    rules: Vec<Rule>,
+}
+
+impl Pipeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends a rule to the pipeline. Rules are applied in the order they were added.
+    pub fn rule(mut self, stage: Stage, filter: impl Filter + 'static) -> Self {
+        self.rules.push(Rule {
+            stage,
+            filter: Box::new(filter),
+        });
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Runs every rule matching `stage` over the text.
+    pub fn apply(&self, stage: Stage, text: &str) -> String {
+        let mut text = text.to_string();
+
+        for rule in &self.rules {
+            if rule.stage.includes(stage) {
+                text = rule.filter.apply(&text);
+            }
+        }
+
+        text
+    }
+}
+
+/// Trims leading and trailing whitespace.
+#[derive(Debug, Clone, Copy)]
+pub struct TrimWhitespace;
+
+impl Filter for TrimWhitespace {
+    fn apply(&self, text: &str) -> String {
+        text.trim().to_string()
+    }
+}
+
+/// Replaces typographic quotes (and apostrophes) with their ASCII counterparts.
+#[derive(Debug, Clone, Copy)]
+pub struct StraightenQuotes;
+
+impl Filter for StraightenQuotes {
+    fn apply(&self, text: &str) -> String {
+        text.chars()
+            .map(|c| match c {
+                '\u{2018}' | '\u{2019}' | '\u{201A}' | '\u{201B}' | '\u{2032}' => '\'',
+                '\u{201C}' | '\u{201D}' | '\u{201E}' | '\u{201F}' | '\u{2033}' => '"',
+                c => c,
+            })
+            .collect()
+    }
+}
+
+/// Query parameters that only exist to track people around.
+pub const DEFAULT_TRACKING_PARAMS: &[&str] = &[
+    "utm_*",
+    "fbclid",
+    "gclid",
+    "dclid",
+    "gbraid",
+    "wbraid",
+    "msclkid",
+    "yclid",
+    "mc_cid",
+    "mc_eid",
+    "igshid",
+    "_hsenc",
+    "_hsmi",
+    "mkt_tok",
+    "oly_anon_id",
+    "oly_enc_id",
+    "vero_id",
+    "ref_src",
+];
+
+/// Removes tracking query parameters from every http(s) URL in the text.
+///
+/// Parameter names ending with `*` match any parameter starting with that prefix. Punctuation
+/// right after a URL (like the period ending a sentence) is left out of it.
+#[derive(Debug, Clone)]
+pub struct StripTrackingParams {
+    pub params: Vec<String>,
+}
+
+impl Default for StripTrackingParams {
+    fn default() -> Self {
+        Self {
+            params: DEFAULT_TRACKING_PARAMS
+                .iter()
+                .map(|param| param.to_string())
+                .collect(),
+        }
+    }
+}
+
+impl StripTrackingParams {
+    fn is_tracking_param(&self, key: &str) -> bool {
+        self.params
+            .iter()
+            .any(|param| match param.strip_suffix('*') {
+                Some(prefix) => key.starts_with(prefix),
+                None => key == param,
+            })
+    }
+
+    fn strip_url(&self, url: &str) -> String {
+        let (url, fragment) = match url.find('#') {
+            Some(i) => url.split_at(i),
+            None => (url, ""),
+        };
+
+        let Some((base, query)) = url.split_once('?') else {
+            return format!("{}{}", url, fragment);
+        };
+
+        let query = query
+            .split('&')
+            .filter(|pair| {
+                let key = pair.split_once('=').map_or(*pair, |(key, _)| key);
+                !key.is_empty() && !self.is_tracking_param(key)
+            })
+            .collect::<Vec<_>>()
+            .join("&");
+
+        match query.is_empty() {
+            true => format!("{}{}", base, fragment),
+            false => format!("{}?{}{}", base, query, fragment),
+        }
+    }
+}
+
+impl Filter for StripTrackingParams {
+    fn apply(&self, text: &str) -> String {
+        let mut result = String::with_capacity(text.len());
+        let mut rest = text;
+
+        while let Some(start) = find_url_start(rest) {
+            result.push_str(&rest[..start]);
+            rest = &rest[start..];
+
+            let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            let end = url_end(&rest[..end]);
+            result.push_str(&self.strip_url(&rest[..end]));
+            rest = &rest[end..];
+        }
+
+        result.push_str(rest);
+        result
+    }
+}
+
+/// Where a URL stops once the punctuation after it is left out. Closing brackets are part of
+/// it when it has the opening one, like Wikipedia's `Rust_(programming_language)`.
+fn url_end(url: &str) -> usize {
+    let mut end = url.len();
+
+    while let Some(last) = url[..end].chars().next_back() {
+        let opening = match last {
+            ')' => '(',
+            ']' => '[',
+            '.' | ',' | ';' | ':' | '!' | '?' | '\'' | '"' => {
+                end -= 1;
+                continue;
+            }
+            _ => break,
+        };
+
+        let url = &url[..end];
+        if url.matches(opening).count() >= url.matches(last).count() {
+            break;
+        }
+        end -= 1;
+    }
+
+    end
+}
+
+fn find_url_start(text: &str) -> Option<usize> {
+    let http = text.find("http://");
+    let https = text.find("https://");
+
+    match (http, https) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strip(text: &str) -> String {
+        StripTrackingParams::default().apply(text)
+    }
+
+    #[test]
+    fn strips_tracking_params_only() {
+        assert_eq!(
+            strip("https://example.com/a?id=3&fbclid=xyz&page=2"),
+            "https://example.com/a?id=3&page=2"
+        );
+        assert_eq!(
+            strip("http://example.com/?gclid=1&q=rust"),
+            "http://example.com/?q=rust"
+        );
+    }
+
+    #[test]
+    fn prefixes_match_every_param_they_start() {
+        assert_eq!(
+            strip("https://example.com/?utm_source=a&utm_medium=b&utm_campaign=c&utmost=1"),
+            "https://example.com/?utmost=1"
+        );
+    }
+
+    #[test]
+    fn empty_query_is_dropped() {
+        assert_eq!(
+            strip("https://example.com/?utm_source=a&fbclid=b"),
+            "https://example.com/"
+        );
+        assert_eq!(strip("https://example.com/?&"), "https://example.com/");
+        assert_eq!(
+            strip("https://example.com/path"),
+            "https://example.com/path"
+        );
+    }
+
+    #[test]
+    fn fragments_are_kept() {
+        assert_eq!(
+            strip("https://example.com/a?utm_source=a&id=3#section"),
+            "https://example.com/a?id=3#section"
+        );
+        assert_eq!(
+            strip("https://example.com/a?utm_source=a#section"),
+            "https://example.com/a#section"
+        );
+        // A `?` in the fragment isn't a query
+        assert_eq!(
+            strip("https://example.com/a#what?fbclid=1"),
+            "https://example.com/a#what?fbclid=1"
+        );
+    }
+
+    #[test]
+    fn punctuation_after_urls_is_kept() {
+        assert_eq!(
+            strip("Look: https://example.com/?utm_source=a. Nice, right?"),
+            "Look: https://example.com/. Nice, right?"
+        );
+        assert_eq!(
+            strip("(see https://example.com/?fbclid=1), then"),
+            "(see https://example.com/), then"
+        );
+        assert_eq!(
+            strip("https://en.wikipedia.org/wiki/Rust_(language)?utm_source=a"),
+            "https://en.wikipedia.org/wiki/Rust_(language)"
+        );
+        assert_eq!(
+            strip("\"https://example.com/?id=1&gclid=2\"!"),
+            "\"https://example.com/?id=1\"!"
+        );
+    }
+
+    #[test]
+    fn text_around_urls_is_untouched() {
+        assert_eq!(
+            strip("a https://a.com/?fbclid=1 b http://b.com/?utm_x=2\tc"),
+            "a https://a.com/ b http://b.com/\tc"
+        );
+        assert_eq!(strip("no links here?"), "no links here?");
+    }
+
+    #[test]
+    fn pipelines_run_rules_of_their_stage_in_order() {
+        let pipeline = Pipeline::new()
+            .rule(Stage::Copy, TrimWhitespace)
+            .rule(Stage::Both, StraightenQuotes)
+            .rule(Stage::Paste, |text: &str| format!("{text}!"));
+
+        assert_eq!(
+            pipeline.apply(Stage::Copy, " \u{201C}hi\u{201D} "),
+            "\"hi\""
+        );
+        assert_eq!(
+            pipeline.apply(Stage::Paste, " \u{2018}hi\u{2019} "),
+            " 'hi' !"
+        );
+    }
+}
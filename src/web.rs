@@ -0,0 +1,206 @@
+//! Browser clipboard backend, using the async [Clipboard API].
+//!
+//! Browsers only expose a single clipboard, and every call has to be awaited since it all goes
+//! through promises. Most browsers will also refuse any access unless the page is focused
+//! and the call originates from a user gesture (click, key press...).
+//!
+//! That's why the `Clipboard` facade isn't there on the web: it blocks, which pages can't do.
+//! Use [`WebClipboard`] (also at the crate root) instead, which has the same methods, only
+//! async and without a selection to pick.
+//!
+//! [Clipboard API]: https://developer.mozilla.org/en-US/docs/Web/API/Clipboard_API
+
+use std::ffi::{CStr, CString};
+
+use js_sys::{Array, Object, Reflect, Uint8Array};
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{Blob, BlobPropertyBag, Clipboard, ClipboardItem};
+
+use crate::error::{ClipboxError, ErrorContext, Phase, ResultExt};
+use crate::selection::Selection;
+
+/// The clipboard of the page's browser, see the [module docs](self)
+pub struct WebClipboard {
+    clipboard: Clipboard,
+}
+
+impl WebClipboard {
+    /// Gets the clipboard of the page's window. Fails with [`ClipboxError::Init`] in workers,
+    /// which have none.
+    pub fn init() -> Result<Self, ClipboxError> {
+        let Some(window) = web_sys::window() else {
+            return Err(ClipboxError::Init(
+                "No window (are we running in a worker?)".into(),
+            ));
+        };
+        let clipboard = window.navigator().clipboard();
+        Ok(Self { clipboard })
+    }
+
+    /// Reads the clipboard as text (`navigator.clipboard.readText()`)
+    pub async fn get_text(&self) -> Result<String, ClipboxError> {
+        let text = JsFuture::from(self.clipboard.read_text())
+            .await
+            .context(pasting)?;
+        Ok(text.as_string().unwrap_or_default())
+    }
+
+    /// Writes text into the clipboard (`navigator.clipboard.writeText()`)
+    pub async fn set_text(&self, text: &str) -> Result<(), ClipboxError> {
+        JsFuture::from(self.clipboard.write_text(text))
+            .await
+            .context(|| ErrorContext::new(Phase::Copying).selection(Selection::Clipboard))?;
+        Ok(())
+    }
+
+    /// Reads every clipboard item, that is the available mime types and their blobs.
+    async fn read_items(&self) -> Result<Vec<ClipboardItem>, ClipboxError> {
+        let items = JsFuture::from(self.clipboard.read()).await?;
+        let items = items.dyn_into::<Array>()?;
+
+        items
+            .iter()
+            .map(|item| Ok(item.dyn_into::<ClipboardItem>()?))
+            .collect()
+    }
+
+    /// The MIME types the clipboard can be pasted as
+    pub async fn get_targets(&self) -> Result<Vec<CString>, ClipboxError> {
+        let items = self
+            .read_items()
+            .await
+            .context(|| ErrorContext::new(Phase::ListingTargets).selection(Selection::Clipboard))?;
+
+        let targets = items
+            .iter()
+            .flat_map(|item| item.types().iter().filter_map(|ty| ty.as_string()))
+            .filter_map(|ty| CString::new(ty).ok())
+            .collect();
+
+        Ok(targets)
+    }
+
+    /// Gets the clipboard contents as `target`, a MIME type
+    pub async fn get_selection(&self, target: &CStr) -> Result<Vec<u8>, ClipboxError> {
+        self.read_target(target)
+            .await
+            .context(|| pasting().target(target))
+    }
+
+    async fn read_target(&self, target: &CStr) -> Result<Vec<u8>, ClipboxError> {
+        let target = target.to_str().map_err(|_| ClipboxError::NotAvailable)?;
+
+        for item in self.read_items().await? {
+            if !item.types().includes(&JsValue::from_str(target), 0) {
+                continue;
+            }
+
+            let blob = JsFuture::from(item.get_type(target)).await?;
+            let blob = blob.dyn_into::<Blob>()?;
+
+            let buffer = JsFuture::from(blob.array_buffer()).await?;
+            return Ok(Uint8Array::new(&buffer).to_vec());
+        }
+
+        Err(ClipboxError::NotAvailable)
+    }
+
+    /// Writes arbitrary data into the clipboard.
+    ///
+    /// Note that browsers only accept a handful of mime types here (usually `text/plain`,
+    /// `text/html` and `image/png`). Anything else will be rejected with a JS error.
+    pub async fn set_selection(&self, target: &CStr, data: &[u8]) -> Result<(), ClipboxError> {
+        self.write_target(target, data).await.context(|| {
+            ErrorContext::new(Phase::Copying)
+                .selection(Selection::Clipboard)
+                .target(target)
+        })
+    }
+
+    async fn write_target(&self, target: &CStr, data: &[u8]) -> Result<(), ClipboxError> {
+        let target = target
+            .to_str()
+            .map_err(|err| ClipboxError::Format(Box::new(err)))?;
+        let blob_parts = Array::of1(&Uint8Array::from(data));
+
+        let blob_options = BlobPropertyBag::new();
+        blob_options.set_type(target);
+        let blob = Blob::new_with_u8_array_sequence_and_options(&blob_parts, &blob_options)?;
+
+        let record = Object::new();
+        Reflect::set(&record, &JsValue::from_str(target), &blob)?;
+
+        let item = ClipboardItem::new_with_record_from_str_to_blob_promise(&record)?;
+        JsFuture::from(self.clipboard.write(&Array::of1(&item))).await?;
+
+        Ok(())
+    }
+}
+
+/// What pasting from the clipboard was about, for errors
+fn pasting() -> ErrorContext {
+    ErrorContext::new(Phase::Pasting).selection(Selection::Clipboard)
+}
+
+/// What promises reject with, a `DOMException` most of the time (`NotAllowedError` when the
+/// page isn't focused or the user said no)
+impl From<JsValue> for ClipboxError {
+    fn from(value: JsValue) -> Self {
+        let message = match value.dyn_ref::<js_sys::Error>() {
+            Some(err) => format!(
+                "{}: {}",
+                String::from(err.name()),
+                String::from(err.message())
+            ),
+            None => match value.as_string() {
+                Some(message) => message,
+                None => format!("{:?}", value),
+            },
+        };
+
+        Self::Protocol(format!("JS error: {}", message).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    use super::*;
+
+    fn message(value: impl Into<JsValue>) -> String {
+        match ClipboxError::from(value.into()) {
+            ClipboxError::Protocol(err) => err.to_string(),
+            err => panic!("expected a protocol error, got {:?}", err),
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn js_errors_keep_their_name_and_message() {
+        let err = js_sys::Error::new("Document is not focused.");
+        err.set_name("NotAllowedError");
+
+        assert_eq!(
+            message(err),
+            "JS error: NotAllowedError: Document is not focused."
+        );
+    }
+
+    #[wasm_bindgen_test]
+    fn other_rejections_are_described() {
+        assert_eq!(message("nope"), "JS error: nope");
+        assert!(message(JsValue::from_f64(42.0)).contains("42"));
+    }
+
+    #[wasm_bindgen_test]
+    fn errors_say_what_we_were_doing() {
+        let err =
+            ClipboxError::from(JsValue::from_str("nope")).context(pasting().target(c"text/html"));
+        assert_eq!(
+            err.to_string(),
+            "Pasting CLIPBOARD as text/html: JS error: nope"
+        );
+        assert!(matches!(err.root(), ClipboxError::Protocol(_)));
+    }
+}